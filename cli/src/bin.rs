@@ -13,9 +13,15 @@ use clap::Arg;
 use clap::App;
 use clap::SubCommand;
 use clap::ArgMatches;
+use cli::CliErr;
+use cli::OutputFormat;
 use cli::commands;
+use cli::commands::CheckParams;
+use cli::commands::DiffParams;
+use cli::commands::GenerateLockfileParams;
 use cli::commands::SnapshotNowParams;
 use cli::commands::QueryParams;
+use cli::commands::SyncIndexParams;
 use cli::commands::TryAddingParams;
 use std::path::PathBuf;
 
@@ -40,6 +46,11 @@ fn main() {
                 .arg(Arg::with_name("repo_directory")
                      .long("repo_directory")
                      .takes_value(true)))
+    .subcommand(SubCommand::with_name("sync_index")
+                .about("Fetch the latest indexes and snapshots into the local cache.")
+                .arg(Arg::with_name("repo_directory")
+                     .long("repo_directory")
+                     .takes_value(true)))
     .subcommand(SubCommand::with_name("query")
                 .about("Retrieve details about the given crate in the provided snapshot.")
                 .arg(Arg::with_name("snapshot_version")
@@ -48,8 +59,51 @@ fn main() {
                 .arg(Arg::with_name("repo_directory")
                      .long("repo_directory")
                      .takes_value(true))
+                .arg(Arg::with_name("format")
+                     .long("format")
+                     .takes_value(true)
+                     .possible_values(&["text", "json"])
+                     .default_value("text"))
                 .arg(Arg::with_name("crate_name")
                      .required(true)))
+    .subcommand(SubCommand::with_name("generate_lockfile")
+                .about("Write a Cargo.lock that pins the project's dependencies to the provided snapshot.")
+                .arg(Arg::with_name("repo_directory")
+                     .long("repo_directory")
+                     .takes_value(true))
+                .arg(Arg::with_name("snapshot_version")
+                     .long("snapshot_version")
+                     .takes_value(true)))
+    .subcommand(SubCommand::with_name("check")
+                .about("Audit the project's Cargo.lock against the provided snapshot.")
+                .arg(Arg::with_name("repo_directory")
+                     .long("repo_directory")
+                     .takes_value(true))
+                .arg(Arg::with_name("snapshot_version")
+                     .long("snapshot_version")
+                     .takes_value(true))
+                .arg(Arg::with_name("format")
+                     .long("format")
+                     .takes_value(true)
+                     .possible_values(&["text", "json"])
+                     .default_value("text")))
+    .subcommand(SubCommand::with_name("diff")
+                .about("Show the changes between two snapshots, with changelog excerpts where available.")
+                .arg(Arg::with_name("repo_directory")
+                     .long("repo_directory")
+                     .takes_value(true))
+                .arg(Arg::with_name("lcs_directory")
+                     .long("lcs_directory")
+                     .takes_value(true))
+                .arg(Arg::with_name("format")
+                     .long("format")
+                     .takes_value(true)
+                     .possible_values(&["text", "json"])
+                     .default_value("text"))
+                .arg(Arg::with_name("from_version")
+                     .required(true))
+                .arg(Arg::with_name("to_version")
+                     .required(true)))
     .subcommand(SubCommand::with_name("try_adding")
                 .about("Attempt a naive preview of an addition of a crate into the current snapshot.")
                 .arg(Arg::with_name("repo_directory")
@@ -64,8 +118,12 @@ fn main() {
 
   match matches.subcommand() {
     ("snapshot_now", Some(sub_matches)) => run_snapshot_now(sub_matches),
+    ("sync_index", Some(sub_matches)) => run_sync_index(sub_matches),
     ("query", Some(sub_matches)) => run_query(sub_matches),
     ("try_adding", Some(sub_matches)) => run_try_adding(sub_matches),
+    ("generate_lockfile", Some(sub_matches)) => run_generate_lockfile(sub_matches),
+    ("check", Some(sub_matches)) => run_check(sub_matches),
+    ("diff", Some(sub_matches)) => run_diff(sub_matches),
     _ => println!("No command matched.")
   }
 }
@@ -75,7 +133,15 @@ fn run_snapshot_now(arg_matches: &ArgMatches) {
     repo_directory: arg_matches.value_of("repo_directory").map(PathBuf::from),
   };
 
-  commands::snapshot_now(params);
+  exit_on_error(commands::snapshot_now(params));
+}
+
+fn run_sync_index(arg_matches: &ArgMatches) {
+  let params = SyncIndexParams {
+    repo_directory: arg_matches.value_of("repo_directory").map(PathBuf::from),
+  };
+
+  exit_on_error(commands::sync_index(params));
 }
 
 fn run_query(arg_matches: &ArgMatches) {
@@ -83,17 +149,62 @@ fn run_query(arg_matches: &ArgMatches) {
     snapshot_version: arg_matches.value_of("snapshot_version").map(ToOwned::to_owned),
     repo_directory: arg_matches.value_of("repo_directory").map(PathBuf::from),
     crate_name: arg_matches.value_of("crate_name").map(ToOwned::to_owned).unwrap(),
+    format: arg_matches.value_of("format").and_then(OutputFormat::parse).unwrap(),
   };
 
-  commands::query(params);
+  exit_on_error(commands::query(params));
 }
 
 fn run_try_adding(arg_matches: &ArgMatches) {
   let params = TryAddingParams {
-    snapshot_version: arg_matches.value_of("snapshot_versin").map(ToOwned::to_owned),
+    snapshot_version: arg_matches.value_of("snapshot_version").map(ToOwned::to_owned),
     repo_directory: arg_matches.value_of("repo_directory").map(PathBuf::from),
     crate_name: arg_matches.value_of("crate_name").map(ToOwned::to_owned).unwrap(),
   };
 
-  commands::try_adding(params);
+  exit_on_error(commands::try_adding(params));
+}
+
+fn run_generate_lockfile(arg_matches: &ArgMatches) {
+  let params = GenerateLockfileParams {
+    snapshot_version: arg_matches.value_of("snapshot_version").map(ToOwned::to_owned),
+    repo_directory: arg_matches.value_of("repo_directory").map(PathBuf::from),
+  };
+
+  exit_on_error(commands::generate_lockfile(params));
+}
+
+fn run_check(arg_matches: &ArgMatches) {
+  let params = CheckParams {
+    snapshot_version: arg_matches.value_of("snapshot_version").map(ToOwned::to_owned),
+    repo_directory: arg_matches.value_of("repo_directory").map(PathBuf::from),
+    format: arg_matches.value_of("format").and_then(OutputFormat::parse).unwrap(),
+  };
+
+  exit_on_error(commands::check(params));
+}
+
+fn run_diff(arg_matches: &ArgMatches) {
+  let params = DiffParams {
+    from_version: arg_matches.value_of("from_version").map(ToOwned::to_owned).unwrap(),
+    to_version: arg_matches.value_of("to_version").map(ToOwned::to_owned).unwrap(),
+    repo_directory: arg_matches.value_of("repo_directory").map(PathBuf::from),
+    lcs_directory: arg_matches.value_of("lcs_directory").map(PathBuf::from),
+    format: arg_matches.value_of("format").and_then(OutputFormat::parse).unwrap(),
+  };
+
+  exit_on_error(commands::diff(params));
+}
+
+/** Reports a failed command and exits with a non-zero status. */
+fn exit_on_error(result: Result<(), CliErr>) {
+  match result {
+    Ok(()) => {},
+    // The command has already reported why
+    Err(CliErr::CheckFailed(_)) => std::process::exit(1),
+    Err(err) => {
+      eprintln!("Error: {:?}", err);
+      std::process::exit(1);
+    },
+  }
 }
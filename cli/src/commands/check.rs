@@ -0,0 +1,313 @@
+use ::CliErr;
+use ::OutputFormat;
+use common::index::IndexEntrySource;
+use common::lockfile::Lockfile;
+use common::manifest::CargoManifest;
+use common::manifest::DependencyKind;
+use common::snapshot::WorkspaceSnapshot;
+use common::snapshot_diff;
+use project::LocalCache;
+use project::Project;
+use project;
+use serde_json;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::path::PathBuf;
+
+pub struct CheckParams {
+  pub snapshot_version: Option<String>,
+  pub repo_directory: Option<PathBuf>,
+  pub format: OutputFormat,
+}
+
+/** How well a project's locked registry packages agree with a snapshot. */
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct CheckReport {
+  pub snapshot_version: String,
+  // The number of registry packages in the lockfile
+  pub checked_packages: usize,
+  pub findings: Vec<Finding>,
+}
+
+/** A single way in which a project strays from its snapshot. */
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Finding {
+  // A crate that is locked, or that a member depends on directly, which the snapshot lacks
+  MissingFromSnapshot {
+    crate_name: String,
+    locked_versions: Vec<String>,
+    required_by: Vec<String>,
+  },
+  VersionMismatch {
+    crate_name: String,
+    locked_version: String,
+    snapshot_version: String,
+  },
+  // Several semver-incompatible versions of a crate are locked at once
+  DuplicateVersions {
+    crate_name: String,
+    versions: Vec<String>,
+  },
+  // The locked version has been yanked from the index
+  YankedPin {
+    crate_name: String,
+    version: String,
+  },
+}
+
+/**
+ * Audits the project's Cargo.lock (and the manifests of its workspace members) against the
+ * selected snapshot.
+ *
+ * Fails with CheckFailed if there are any findings, so that the exit code can gate CI.
+ */
+pub fn check(params: CheckParams) -> Result<(), CliErr> {
+  let project = try!(Project::load(params.repo_directory));
+  let cache = try!(LocalCache::locate());
+  let snapshot = try!(project::load_snapshot(&project,
+                                             &cache,
+                                             params.snapshot_version.as_ref().map(|v| v.as_str())));
+  let index = try!(cache.open_crates_io_index());
+  let members = try!(project.read_workspace_manifests());
+
+  let lockfile_path = project.get_lockfile_path();
+  let mut contents = String::new();
+  match File::open(&lockfile_path) {
+    Ok(mut file) => try!(file.read_to_string(&mut contents)),
+    Err(ref err) if err.kind() == ErrorKind::NotFound => {
+      return Err(CliErr::OtherErr(format!("No lockfile was found at {:?}, run generate_lockfile first",
+                                          lockfile_path)))
+    },
+    Err(err) => return Err(CliErr::IoErr(err)),
+  };
+  let lockfile = try!(Lockfile::from_str(&contents));
+
+  let report = try!(check_project(&members, &lockfile, &snapshot, &index));
+  match params.format {
+    OutputFormat::Text => print!("{}", render_text(&report)),
+    OutputFormat::Json => println!("{}", try!(serde_json::to_string_pretty(&report))),
+  }
+  if report.findings.is_empty() {
+    Ok(())
+  } else {
+    Err(CliErr::CheckFailed(report.findings.len()))
+  }
+}
+
+/**
+ * Compares every registry package in the lockfile (and every registry dependency of the members)
+ * to the snapshot.
+ *
+ * Findings are grouped by kind, in crate name order.
+ */
+pub fn check_project(members: &[CargoManifest],
+                     lockfile: &Lockfile,
+                     snapshot: &WorkspaceSnapshot,
+                     index: &IndexEntrySource) -> Result<CheckReport, CliErr> {
+  let pinned_versions = snapshot.details.iter()
+    .map(|details| (details.name.as_str(), details.version.as_str()))
+    .collect::<HashMap<_, _>>();
+  let registry_packages = lockfile.packages.iter()
+    .filter(|package| package.source.as_ref().map(|s| s.starts_with("registry+")).unwrap_or(false))
+    .collect::<Vec<_>>();
+
+  // Crate name -> (locked versions, members that depend on it directly)
+  let mut missing_crates = BTreeMap::new();
+  for member in members.iter() {
+    let member_name = member.get_package().map(|p| p.name.clone()).unwrap_or_default();
+    for &kind in [DependencyKind::Normal, DependencyKind::Build, DependencyKind::Dev].iter() {
+      for entry in member.get_registry_dependency_entries(kind).into_iter() {
+        if !pinned_versions.contains_key(entry.name.as_str()) {
+          missing_crates.entry(entry.name.clone())
+            .or_insert_with(|| (BTreeSet::new(), BTreeSet::new()))
+            .1.insert(member_name.clone());
+        }
+      }
+    }
+  }
+
+  let mut version_mismatches = Vec::new();
+  let mut yanked_pins = Vec::new();
+  let mut locked_versions = BTreeMap::new();
+  for package in registry_packages.iter() {
+    locked_versions.entry(package.name.clone()).or_insert_with(Vec::new).push(package.version.clone());
+    match pinned_versions.get(package.name.as_str()) {
+      None => {
+        missing_crates.entry(package.name.clone())
+          .or_insert_with(|| (BTreeSet::new(), BTreeSet::new()))
+          .0.insert(package.version.clone());
+      },
+      Some(pinned_version) if *pinned_version != package.version => {
+        version_mismatches.push(Finding::VersionMismatch {
+          crate_name: package.name.clone(),
+          locked_version: package.version.clone(),
+          snapshot_version: pinned_version.to_string(),
+        });
+      },
+      Some(_) => {},
+    }
+
+    let yanked = try!(index.get_entries(&package.name)).iter()
+      .any(|entry| entry.vers == package.version && entry.yanked.unwrap_or(false));
+    if yanked {
+      yanked_pins.push(Finding::YankedPin {
+        crate_name: package.name.clone(),
+        version: package.version.clone(),
+      });
+    }
+  }
+
+  let mut findings = missing_crates.into_iter()
+    .map(|(crate_name, (versions, required_by))| Finding::MissingFromSnapshot {
+      crate_name: crate_name,
+      locked_versions: versions.into_iter().collect(),
+      required_by: required_by.into_iter().collect(),
+    })
+    .collect::<Vec<_>>();
+  findings.extend(version_mismatches.into_iter());
+  for (crate_name, versions) in locked_versions.into_iter() {
+    let compatibility_classes = versions.iter()
+      .map(|version| snapshot_diff::get_compatibility_class(version))
+      .collect::<BTreeSet<_>>();
+    if compatibility_classes.len() > 1 {
+      findings.push(Finding::DuplicateVersions {
+        crate_name: crate_name,
+        versions: versions,
+      });
+    }
+  }
+  findings.extend(yanked_pins.into_iter());
+
+  Ok(CheckReport {
+    snapshot_version: snapshot.version.clone(),
+    checked_packages: registry_packages.len(),
+    findings: findings,
+  })
+}
+
+fn render_text(report: &CheckReport) -> String {
+  let mut lines = Vec::new();
+  lines.push(format!("Checked {} registry packages against snapshot {}",
+                     report.checked_packages,
+                     report.snapshot_version));
+  for finding in report.findings.iter() {
+    lines.push(format!("  {}", describe_finding(finding)));
+  }
+  if report.findings.is_empty() {
+    lines.push("No problems found".to_owned());
+  } else {
+    lines.push(format!("Found {} problems", report.findings.len()));
+  }
+  lines.push(String::new());
+  lines.join("\n")
+}
+
+fn describe_finding(finding: &Finding) -> String {
+  match *finding {
+    Finding::MissingFromSnapshot { ref crate_name, ref locked_versions, ref required_by } => {
+      let mut details = Vec::new();
+      if !locked_versions.is_empty() {
+        details.push(format!("locked at {}", locked_versions.join(", ")));
+      }
+      if !required_by.is_empty() {
+        details.push(format!("required by {}", required_by.join(", ")));
+      }
+      format!("missing from snapshot: {} ({})", crate_name, details.join("; "))
+    },
+    Finding::VersionMismatch { ref crate_name, ref locked_version, ref snapshot_version } =>
+      format!("version mismatch: {} is locked at {}, but the snapshot pins {}",
+              crate_name,
+              locked_version,
+              snapshot_version),
+    Finding::DuplicateVersions { ref crate_name, ref versions } =>
+      format!("incompatible duplicates: {} is locked at {}", crate_name, versions.join(", ")),
+    Finding::YankedPin { ref crate_name, ref version } =>
+      format!("yanked: {} {} has been yanked from the index", crate_name, version),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use commands::check::Finding;
+  use commands::check;
+  use common::index::testing::FakeIndex;
+  use common::index::testing::index_entry;
+  use common::lockfile::Lockfile;
+  use common::manifest::CargoManifest;
+  use common::snapshot::WorkspaceSnapshot;
+  use common::snapshot::testing::crate_snapshot;
+
+  fn locked_package(name: &str, version: &str) -> String {
+    format!("[[package]]\nname = \"{}\"\nversion = \"{}\"\n\
+             source = \"registry+https://github.com/rust-lang/crates.io-index\"\n\n",
+            name,
+            version)
+  }
+
+  #[test]
+  fn test_reports_every_kind_of_finding() {
+    let snapshot = WorkspaceSnapshot {
+      version: "lts-1.0".to_owned(),
+      members: Vec::new(),
+      details: vec![
+        crate_snapshot("libc", "0.2.30", &[]),
+        crate_snapshot("memchr", "2.0.1", &[]),
+        crate_snapshot("old", "0.1.0", &[]),
+        crate_snapshot("rand", "0.4.1", &[]),
+      ],
+    };
+    let mut yanked_entry = index_entry("old", "0.1.0", &[]);
+    yanked_entry.yanked = Some(true);
+    let index = FakeIndex::new(vec![yanked_entry]);
+    let members = vec![CargoManifest::from_str(r#"
+      [package]
+      name = "example"
+      version = "0.1.0"
+
+      [dependencies]
+      memchr = "2.0"
+      regex = "0.2"
+      sibling = { path = "../sibling" }
+    "#).unwrap()];
+    let lockfile = Lockfile::from_str(&format!("[[package]]\nname = \"example\"\nversion = \"0.1.0\"\n\n{}{}{}{}{}{}",
+                                               locked_package("libc", "0.2.29"),
+                                               locked_package("memchr", "2.0.1"),
+                                               locked_package("old", "0.1.0"),
+                                               locked_package("rand", "0.3.18"),
+                                               locked_package("rand", "0.4.1"),
+                                               locked_package("regex", "0.2.2"))).unwrap();
+
+    let report = check::check_project(&members, &lockfile, &snapshot, &index).unwrap();
+    assert_eq!(report.checked_packages, 6);
+    assert_eq!(report.findings, vec![
+      Finding::MissingFromSnapshot {
+        crate_name: "regex".to_owned(),
+        locked_versions: vec!["0.2.2".to_owned()],
+        required_by: vec!["example".to_owned()],
+      },
+      Finding::VersionMismatch {
+        crate_name: "libc".to_owned(),
+        locked_version: "0.2.29".to_owned(),
+        snapshot_version: "0.2.30".to_owned(),
+      },
+      Finding::VersionMismatch {
+        crate_name: "rand".to_owned(),
+        locked_version: "0.3.18".to_owned(),
+        snapshot_version: "0.4.1".to_owned(),
+      },
+      Finding::DuplicateVersions {
+        crate_name: "rand".to_owned(),
+        versions: vec!["0.3.18".to_owned(), "0.4.1".to_owned()],
+      },
+      Finding::YankedPin {
+        crate_name: "old".to_owned(),
+        version: "0.1.0".to_owned(),
+      },
+    ]);
+  }
+}
@@ -0,0 +1,198 @@
+use ::CliErr;
+use ::OutputFormat;
+use common::index;
+use common::snapshot::ResolutionType;
+use common::snapshot_diff::BumpKind;
+use common::snapshot_diff::ChangelogExcerpt;
+use common::snapshot_diff::SnapshotDiff;
+use common::snapshot_diff;
+use flate2::read::GzDecoder;
+use project::LocalCache;
+use project::Project;
+use project;
+use serde_json;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+use tar::Archive;
+
+// Root files that are checked for release notes, in order of preference
+const CHANGELOG_FILE_NAMES: [&'static str; 4] = ["changelog.md", "changes.md", "releases.md", "release-notes.md"];
+
+pub struct DiffParams {
+  pub from_version: String,
+  pub to_version: String,
+  pub repo_directory: Option<PathBuf>,
+  // A local copy of the LCS, for reading changelogs out of crate tarballs
+  pub lcs_directory: Option<PathBuf>,
+  pub format: OutputFormat,
+}
+
+/**
+ * Shows what changed between two snapshots.
+ *
+ * If a local LCS is provided, the changelog of each bumped crate is excerpted from its new tarball.
+ */
+pub fn diff(params: DiffParams) -> Result<(), CliErr> {
+  let project = try!(Project::load(params.repo_directory));
+  let cache = try!(LocalCache::locate());
+  let from_snapshot = try!(project::load_snapshot(&project, &cache, Some(&params.from_version)));
+  let to_snapshot = try!(project::load_snapshot(&project, &cache, Some(&params.to_version)));
+
+  let mut diff = snapshot_diff::diff_snapshots(&from_snapshot, &to_snapshot);
+  if let Some(ref lcs_directory) = params.lcs_directory {
+    for change in diff.version_changes.iter_mut() {
+      let crate_path = lcs_directory.join(index::get_lcs_path_for_crate(&change.name, &change.to));
+      change.changelog = match try!(read_changelog(&crate_path)) {
+        Some((file_name, contents)) => snapshot_diff::extract_changelog_excerpt(&contents, &change.from, &change.to)
+          .map(|excerpt| ChangelogExcerpt {
+            file_name: file_name,
+            excerpt: excerpt,
+          }),
+        None => None,
+      };
+    }
+  }
+
+  match params.format {
+    OutputFormat::Text => print!("{}", render_text(&diff)),
+    OutputFormat::Json => println!("{}", try!(serde_json::to_string_pretty(&diff))),
+  }
+  Ok(())
+}
+
+/**
+ * Reads the first changelog-like file at the root of a crate tarball, yielding its name and
+ * contents.
+ *
+ * Yields None if the tarball is not present, or has no such file.
+ */
+pub fn read_changelog(crate_path: &Path) -> Result<Option<(String, String)>, CliErr> {
+  let file = match File::open(crate_path) {
+    Ok(file) => file,
+    Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+    Err(err) => return Err(CliErr::IoErr(err)),
+  };
+  let gz = try!(GzDecoder::new(file));
+  let mut tar = Archive::new(gz);
+
+  let mut candidates = Vec::new();
+  for entry_res in try!(tar.entries()) {
+    let mut entry = try!(entry_res);
+    if !entry.header().entry_type().is_file() {
+      continue
+    }
+    // Crate tarballs nest everything within a "$NAME-$VERSION/" directory
+    let path_in_crate = try!(entry.path()).components().skip(1).collect::<PathBuf>();
+    if path_in_crate.components().count() != 1 {
+      continue
+    }
+    let file_name = path_in_crate.to_string_lossy().into_owned();
+    let priority = match CHANGELOG_FILE_NAMES.iter().position(|name| *name == file_name.to_lowercase()) {
+      Some(priority) => priority,
+      None => continue,
+    };
+    let mut contents = String::new();
+    if entry.read_to_string(&mut contents).is_ok() {
+      candidates.push((priority, file_name, contents));
+    }
+  }
+
+  candidates.sort_by_key(|&(priority, _, _)| priority);
+  Ok(candidates.into_iter().next().map(|(_, file_name, contents)| (file_name, contents)))
+}
+
+fn render_text(diff: &SnapshotDiff) -> String {
+  let mut lines = Vec::new();
+  lines.push(format!("Changes from snapshot {} to {}", diff.from_version, diff.to_version));
+
+  lines.push(format!("  added ({}):", diff.added.len()));
+  lines.extend(diff.added.iter().map(|c| format!("    {} {}", c.name, c.version)));
+  lines.push(format!("  removed ({}):", diff.removed.len()));
+  lines.extend(diff.removed.iter().map(|c| format!("    {} {}", c.name, c.version)));
+
+  lines.push(format!("  version changes ({}):", diff.version_changes.len()));
+  for change in diff.version_changes.iter() {
+    lines.push(format!("    {} {} -> {} ({})", change.name, change.from, change.to, describe_bump(change.kind)));
+    if let Some(ref changelog) = change.changelog {
+      lines.push(format!("      from {}:", changelog.file_name));
+      lines.extend(changelog.excerpt.lines().map(|line| format!("        {}", line)));
+    }
+  }
+
+  lines.push(format!("  maintainer changes ({}):", diff.maintainer_changes.len()));
+  lines.extend(diff.maintainer_changes.iter().map(|c| format!("    {}: {} -> {}",
+                                                              c.name,
+                                                              c.from.as_ref().map(|m| m.as_str()).unwrap_or("(none)"),
+                                                              c.to.as_ref().map(|m| m.as_str()).unwrap_or("(none)"))));
+  lines.push(format!("  resolution changes ({}):", diff.resolution_changes.len()));
+  lines.extend(diff.resolution_changes.iter().map(|c| format!("    {}: {} -> {}",
+                                                              c.name,
+                                                              describe_resolution(&c.from),
+                                                              describe_resolution(&c.to))));
+  lines.push(String::new());
+  lines.join("\n")
+}
+
+fn describe_bump(kind: BumpKind) -> &'static str {
+  match kind {
+    BumpKind::Patch => "patch",
+    BumpKind::Minor => "minor",
+    BumpKind::Major => "semver-major",
+    BumpKind::Downgrade => "downgrade",
+    BumpKind::Unknown => "unknown",
+  }
+}
+
+fn describe_resolution(resolution_type: &Option<ResolutionType>) -> String {
+  match *resolution_type {
+    Some(ResolutionType { git: Some(ref git), .. }) => format!("git {}#{}", git.repository, git.revision),
+    Some(ResolutionType { path: Some(ref path), .. }) => format!("path {}", path),
+    Some(ResolutionType { crates_io: Some(true), .. }) => "crates.io".to_owned(),
+    _ => "(unspecified)".to_owned(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use commands::diff;
+  use common::index;
+  use flate2::Compression;
+  use flate2::write::GzEncoder;
+  use std::fs::File;
+  use tar::Builder;
+  use tar::Header;
+  use tempdir::TempDir;
+
+  fn append_file(builder: &mut Builder<GzEncoder<File>>, path: &str, contents: &str) {
+    let mut header = Header::new_gnu();
+    header.set_path(path).unwrap();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append(&header, contents.as_bytes()).unwrap();
+  }
+
+  #[test]
+  fn test_reads_root_changelog_from_crate_tarball() {
+    let tempdir = TempDir::new("diff").unwrap();
+    let crate_path = tempdir.path().join(index::get_lcs_path_for_crate("Serde", "1.1.0"));
+    assert_eq!(crate_path, tempdir.path().join("se/rd/Serde-1.1.0.crate"));
+
+    ::std::fs::create_dir_all(crate_path.parent().unwrap()).unwrap();
+    {
+      let encoder = GzEncoder::new(File::create(&crate_path).unwrap(), Compression::Default);
+      let mut builder = Builder::new(encoder);
+      append_file(&mut builder, "Serde-1.1.0/src/CHANGELOG.md", "## 9.9.9\n");
+      append_file(&mut builder, "Serde-1.1.0/RELEASES.md", "## 1.1.0\n- Releases\n");
+      append_file(&mut builder, "Serde-1.1.0/CHANGELOG.md", "## 1.1.0\n- Changelog\n");
+      builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    assert_eq!(diff::read_changelog(&crate_path).unwrap(),
+               Some(("CHANGELOG.md".to_owned(), "## 1.1.0\n- Changelog\n".to_owned())));
+    assert_eq!(diff::read_changelog(&tempdir.path().join("missing.crate")).unwrap(), None);
+  }
+}
@@ -0,0 +1,51 @@
+use ::CliErr;
+use common::lockfile::LockfileErr;
+use common::lockfile;
+use project::LocalCache;
+use project::PROJECT_LOCKFILE;
+use project::Project;
+use project;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+pub struct GenerateLockfileParams {
+  pub snapshot_version: Option<String>,
+  pub repo_directory: Option<PathBuf>,
+}
+
+/**
+ * Writes a Cargo.lock for the project (every member of its workspace) that pins every registry
+ * dependency to the selected snapshot, with checksums from the synced crates.io index.
+ *
+ * Nothing is written if the project needs anything that the snapshot does not provide.
+ */
+pub fn generate_lockfile(params: GenerateLockfileParams) -> Result<(), CliErr> {
+  let project = try!(Project::load(params.repo_directory));
+  let cache = try!(LocalCache::locate());
+  let snapshot = try!(project::load_snapshot(&project,
+                                             &cache,
+                                             params.snapshot_version.as_ref().map(|v| v.as_str())));
+  let index = try!(cache.open_crates_io_index());
+  let members = try!(project.read_workspace_members());
+
+  let lockfile = match lockfile::generate_lockfile(&members, &snapshot, &index) {
+    Ok(lockfile) => lockfile,
+    Err(LockfileErr::OutsideSnapshot(problems)) => {
+      println!("The project needs crates outside of snapshot {}:", snapshot.version);
+      for problem in problems.iter() {
+        println!("  {}", problem);
+      }
+      return Err(CliErr::OtherErr(format!("No {} was written", PROJECT_LOCKFILE)))
+    },
+    Err(err) => return Err(CliErr::LockfileErr(err)),
+  };
+
+  let lockfile_path = project.get_lockfile_path();
+  try!(try!(File::create(&lockfile_path)).write_all(lockfile.to_cargo_lock_string().as_bytes()));
+  println!("Pinned {} packages to snapshot {} in {:?}",
+           lockfile.packages.len(),
+           snapshot.version,
+           lockfile_path);
+  Ok(())
+}
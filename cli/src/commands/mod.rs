@@ -0,0 +1,22 @@
+pub mod check;
+pub mod diff;
+pub mod generate_lockfile;
+pub mod query;
+pub mod snapshot_now;
+pub mod sync_index;
+pub mod try_adding;
+
+pub use commands::check::CheckParams;
+pub use commands::check::check;
+pub use commands::diff::DiffParams;
+pub use commands::diff::diff;
+pub use commands::generate_lockfile::GenerateLockfileParams;
+pub use commands::generate_lockfile::generate_lockfile;
+pub use commands::query::QueryParams;
+pub use commands::query::query;
+pub use commands::snapshot_now::SnapshotNowParams;
+pub use commands::snapshot_now::snapshot_now;
+pub use commands::sync_index::SyncIndexParams;
+pub use commands::sync_index::sync_index;
+pub use commands::try_adding::TryAddingParams;
+pub use commands::try_adding::try_adding;
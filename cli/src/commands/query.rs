@@ -0,0 +1,194 @@
+use ::CliErr;
+use ::OutputFormat;
+use common::snapshot::CrateSnapshot;
+use common::snapshot::ResolutionType;
+use common::snapshot::WorkspaceSnapshot;
+use project::LocalCache;
+use project::Project;
+use project;
+use serde_json;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+pub struct QueryParams {
+  pub snapshot_version: Option<String>,
+  pub repo_directory: Option<PathBuf>,
+  pub crate_name: String,
+  pub format: OutputFormat,
+}
+
+/** Everything the snapshot records about a single crate. */
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct QueryResult {
+  pub snapshot_version: String,
+  pub crate_name: String,
+  pub version: String,
+  pub maintainer: Option<String>,
+  pub resolution_type: Option<ResolutionType>,
+  pub dev_only: bool,
+  pub dependencies: Vec<ResolvedDependency>,
+  pub dev_dependencies: Vec<ResolvedDependency>,
+  // The members of the snapshot that need this crate to build, directly or transitively
+  pub depending_members: Vec<String>,
+}
+
+/** A dependency, alongside the version the snapshot pins it to. */
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct ResolvedDependency {
+  pub name: String,
+  // Only absent if the snapshot is inconsistent
+  pub version: Option<String>,
+}
+
+/** Prints the details of a crate in the selected snapshot. */
+pub fn query(params: QueryParams) -> Result<(), CliErr> {
+  let project = try!(Project::load(params.repo_directory));
+  let cache = try!(LocalCache::locate());
+  let snapshot = try!(project::load_snapshot(&project,
+                                             &cache,
+                                             params.snapshot_version.as_ref().map(|v| v.as_str())));
+
+  let result = try!(query_snapshot(&snapshot, &params.crate_name));
+  match params.format {
+    OutputFormat::Text => print!("{}", render_text(&result)),
+    OutputFormat::Json => println!("{}", try!(serde_json::to_string_pretty(&result))),
+  }
+  Ok(())
+}
+
+/** Looks up the crate in the snapshot, resolving its dependencies and dependents. */
+pub fn query_snapshot(snapshot: &WorkspaceSnapshot, crate_name: &str) -> Result<QueryResult, CliErr> {
+  let crates_by_name = snapshot.details.iter()
+    .map(|details| (details.name.as_str(), details))
+    .collect::<HashMap<_, _>>();
+  let details = try!(crates_by_name.get(crate_name)
+    .ok_or_else(|| CliErr::UnknownCrate(crate_name.to_owned())));
+
+  let resolve = |names: &Vec<String>| {
+    names.iter()
+      .map(|name| ResolvedDependency {
+        name: name.clone(),
+        version: crates_by_name.get(name.as_str()).map(|d| d.version.clone()),
+      })
+      .collect::<Vec<_>>()
+  };
+
+  let depending_members = snapshot.members.iter()
+    .filter(|member| member.as_str() != crate_name)
+    .filter(|member| get_transitive_dependencies(&crates_by_name, member).contains(crate_name))
+    .cloned()
+    .collect();
+
+  Ok(QueryResult {
+    snapshot_version: snapshot.version.clone(),
+    crate_name: details.name.clone(),
+    version: details.version.clone(),
+    maintainer: details.maintainer.clone(),
+    resolution_type: details.resolution_type.clone(),
+    dev_only: details.dev_only,
+    dependencies: resolve(&details.dependencies),
+    dev_dependencies: resolve(&details.dev_dependencies),
+    depending_members: depending_members,
+  })
+}
+
+/** Yields the names of every crate needed to build the provided crate, excluding dev dependencies. */
+fn get_transitive_dependencies<'a>(crates_by_name: &HashMap<&'a str, &'a CrateSnapshot>, crate_name: &str) -> BTreeSet<&'a str> {
+  let mut visited = BTreeSet::new();
+  let mut pending = match crates_by_name.get(crate_name) {
+    Some(details) => details.dependencies.iter().map(|name| name.as_str()).collect::<Vec<_>>(),
+    None => Vec::new(),
+  };
+  while let Some(name) = pending.pop() {
+    if !visited.insert(name) {
+      continue
+    }
+    if let Some(details) = crates_by_name.get(name) {
+      pending.extend(details.dependencies.iter().map(|name| name.as_str()));
+    }
+  }
+  visited
+}
+
+fn render_text(result: &QueryResult) -> String {
+  let mut lines = Vec::new();
+  lines.push(format!("{} {} (in snapshot {})", result.crate_name, result.version, result.snapshot_version));
+  lines.push(format!("  maintainer: {}", result.maintainer.as_ref().map(|m| m.as_str()).unwrap_or("none")));
+  lines.push(format!("  resolution: {}", describe_resolution_type(&result.resolution_type)));
+  if result.dev_only {
+    lines.push("  only needed to build the tests of members".to_owned());
+  }
+  lines.push(format!("  dependencies:{}", render_dependencies(&result.dependencies)));
+  if !result.dev_dependencies.is_empty() {
+    lines.push(format!("  dev dependencies:{}", render_dependencies(&result.dev_dependencies)));
+  }
+  if result.depending_members.is_empty() {
+    lines.push("  depended on by members: none".to_owned());
+  } else {
+    lines.push(format!("  depended on by members: {}", result.depending_members.join(", ")));
+  }
+  lines.push(String::new());
+  lines.join("\n")
+}
+
+fn render_dependencies(dependencies: &[ResolvedDependency]) -> String {
+  if dependencies.is_empty() {
+    return " none".to_owned()
+  }
+  dependencies.iter()
+    .map(|dependency| format!("\n    {} {}",
+                              dependency.name,
+                              dependency.version.as_ref().map(|v| v.as_str()).unwrap_or("(not in snapshot)")))
+    .collect::<Vec<_>>()
+    .join("")
+}
+
+fn describe_resolution_type(resolution_type: &Option<ResolutionType>) -> String {
+  match *resolution_type {
+    Some(ResolutionType { git: Some(ref git), .. }) => format!("git {} at {}", git.repository, git.revision),
+    Some(ResolutionType { path: Some(ref path), .. }) => format!("path {}", path),
+    Some(ResolutionType { crates_io: Some(true), .. }) => "crates.io".to_owned(),
+    _ => "unknown".to_owned(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use commands::query::ResolvedDependency;
+  use commands::query;
+  use common::snapshot::CrateSnapshot;
+  use common::snapshot::WorkspaceSnapshot;
+  use common::snapshot::testing::crate_snapshot;
+
+
+  #[test]
+  fn test_query_resolves_dependencies_and_dependents() {
+    let snapshot = WorkspaceSnapshot {
+      version: "lts-1.0".to_owned(),
+      members: vec!["regex".to_owned(), "libc".to_owned()],
+      details: vec![
+        CrateSnapshot {
+          maintainer: Some("libc-team".to_owned()),
+          ..crate_snapshot("libc", "0.2.30", &[])
+        },
+        crate_snapshot("memchr", "2.0.1", &["libc"]),
+        CrateSnapshot {
+          maintainer: Some("regex-team".to_owned()),
+          ..crate_snapshot("regex", "0.2.2", &["memchr"])
+        },
+      ],
+    };
+
+    let result = query::query_snapshot(&snapshot, "memchr").unwrap();
+    assert_eq!(result.version, "2.0.1");
+    assert_eq!(result.dependencies, vec![ResolvedDependency {
+      name: "libc".to_owned(),
+      version: Some("0.2.30".to_owned()),
+    }]);
+    assert_eq!(result.depending_members, vec!["regex".to_owned()]);
+    assert_eq!(query::query_snapshot(&snapshot, "libc").unwrap().depending_members,
+               vec!["regex".to_owned()]);
+    assert!(query::query_snapshot(&snapshot, "missing").is_err());
+  }
+}
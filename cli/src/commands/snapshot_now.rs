@@ -0,0 +1,220 @@
+use ::CliErr;
+use common::cargo::DependencyEntry;
+use common::manifest::CargoManifest;
+use common::manifest::DependencyKind;
+use common::resolver::CandidateSource;
+use common::resolver::Requirement;
+use common::resolver::ResolutionErr;
+use common::resolver;
+use common::snapshot::CrateSnapshot;
+use common::snapshot::ResolutionType;
+use common::snapshot::WorkspaceSnapshot;
+use project::LocalCache;
+use project::Project;
+use semver::VersionReq;
+use serde_yaml;
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+pub struct SnapshotNowParams {
+  pub repo_directory: Option<PathBuf>,
+}
+
+/**
+ * Resolves the newest compatible versions of the project's dependencies against the synced
+ * crates.io index, and writes them out as the project-local snapshot.
+ */
+pub fn snapshot_now(params: SnapshotNowParams) -> Result<(), CliErr> {
+  let project = try!(Project::load(params.repo_directory));
+  let cache = try!(LocalCache::locate());
+  let metadata = try!(try!(cache.read_metadata())
+    .ok_or_else(|| CliErr::OtherErr("The local indexes have never been synced, run sync_index first".to_owned())));
+
+  let manifest = try!(project.read_manifest());
+
+  // The snapshot is named for the index revision that it was resolved against
+  let snapshot_version = format!("local-{}", metadata.crates_io_index_revision.chars().take(12).collect::<String>());
+  let source = try!(cache.open_crates_io_index());
+  let snapshot = match plan_project_snapshot(&manifest, &source, &snapshot_version) {
+    Ok(snapshot) => snapshot,
+    Err(CliErr::UnresolvableDependencies(reasons)) => {
+      println!("The dependencies in {:?} cannot be resolved:", project.get_manifest_path());
+      for reason in reasons.iter() {
+        println!("  {}", reason);
+      }
+      return Err(CliErr::OtherErr("No snapshot was written".to_owned()))
+    },
+    Err(err) => return Err(err),
+  };
+
+  let snapshot_path = project.get_snapshot_path();
+  try!(try!(File::create(&snapshot_path)).write_all(try!(serde_yaml::to_string(&snapshot)).as_bytes()));
+  println!("Resolved {} crates ({} direct dependencies) into {:?}",
+           snapshot.details.len(),
+           snapshot.members.len(),
+           snapshot_path);
+  Ok(())
+}
+
+/**
+ * Resolves the registry dependencies of a manifest (of every kind) into a snapshot, whose members
+ * are the direct dependencies.
+ *
+ * Path and git dependencies are left out. Crates that are only needed through dev dependencies are
+ * marked as dev-only. If the dependencies cannot be resolved, the error lists the reasons.
+ */
+pub fn plan_project_snapshot(manifest: &CargoManifest,
+                             source: &CandidateSource,
+                             snapshot_version: &str) -> Result<WorkspaceSnapshot, CliErr> {
+  let mut reasons = Vec::new();
+  let mut roots = Vec::new();
+  let mut non_dev_roots = BTreeSet::new();
+  for &kind in [DependencyKind::Normal, DependencyKind::Build, DependencyKind::Dev].iter() {
+    for entry in manifest.get_registry_dependency_entries(kind).into_iter() {
+      match to_requirement(&entry) {
+        Ok(requirement) => {
+          if kind != DependencyKind::Dev {
+            non_dev_roots.insert(requirement.name.clone());
+          }
+          roots.push(requirement);
+        },
+        Err(reason) => reasons.push(reason),
+      }
+    }
+  }
+
+  let members = roots.iter().map(|r| r.name.clone()).collect::<BTreeSet<_>>();
+  for name in members.iter() {
+    if source.get_candidates(name).is_empty() {
+      reasons.push(format!("{} is not in the crates.io index", name));
+    }
+  }
+  if !reasons.is_empty() {
+    return Err(CliErr::UnresolvableDependencies(reasons))
+  }
+
+  let resolution = match resolver::resolve(source, &roots) {
+    Ok(resolution) => resolution,
+    Err(ResolutionErr::NoSolution(report)) => return Err(CliErr::UnresolvableDependencies(report.explanation)),
+    Err(err) => return Err(CliErr::ResolutionErr(err)),
+  };
+
+  // Anything reachable from a normal or build dependency is needed to build the project
+  let mut non_dev_crates = BTreeSet::new();
+  let mut pending_crates = non_dev_roots.into_iter().collect::<Vec<_>>();
+  while let Some(crate_name) = pending_crates.pop() {
+    if !non_dev_crates.insert(crate_name.clone()) {
+      continue
+    }
+    if let Some(dependency_names) = resolution.dependencies.get(&crate_name) {
+      pending_crates.extend(dependency_names.iter().cloned());
+    }
+  }
+
+  let details = resolution.versions.iter()
+    .map(|(name, version)| CrateSnapshot {
+      name: name.clone(),
+      version: version.to_string(),
+      maintainer: None,
+      dependencies: resolution.dependencies.get(name)
+        .map(|names| names.iter().cloned().collect())
+        .unwrap_or_else(Vec::new),
+      dev_dependencies: Vec::new(),
+      dev_only: !non_dev_crates.contains(name),
+      features: Vec::new(),
+      resolution_type: Some(ResolutionType {
+        crates_io: Some(true),
+        git: None,
+        path: None,
+      }),
+    })
+    .collect();
+
+  Ok(WorkspaceSnapshot {
+    version: snapshot_version.to_owned(),
+    members: members.into_iter().collect(),
+    details: details,
+  })
+}
+
+fn to_requirement(entry: &DependencyEntry) -> Result<Requirement, String> {
+  match VersionReq::parse(&entry.req) {
+    Ok(req) => Ok(Requirement {
+      name: entry.name.clone(),
+      req: req,
+    }),
+    Err(_) => Err(format!("{} has an invalid version requirement: {:?}", entry.name, entry.req)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use ::CliErr;
+  use commands::snapshot_now;
+  use common::manifest::CargoManifest;
+  use common::resolver::testing::FakeCandidateSource;
+
+  #[test]
+  fn test_resolves_newest_compatible_versions_and_reports_failures() {
+    let source = FakeCandidateSource::new(&[
+      ("libc", "0.2.20", &[]),
+      ("libc", "0.2.30", &[]),
+      ("libc", "0.3.0", &[]),
+      ("memchr", "2.0.1", &[("libc", "^0.2")]),
+      ("rand", "0.3.18", &[("libc", "^0.2")]),
+    ]);
+    let manifest = CargoManifest::from_str(r#"
+      [package]
+      name = "example"
+      version = "0.1.0"
+
+      [dependencies]
+      memchr = "2.0"
+      sibling = { path = "../sibling" }
+
+      [dev-dependencies]
+      rand = "0.3"
+    "#).unwrap();
+
+    let snapshot = snapshot_now::plan_project_snapshot(&manifest, &source, "local-test").unwrap();
+    assert_eq!(snapshot.members, vec!["memchr".to_owned(), "rand".to_owned()]);
+    let summary = snapshot.details.iter()
+      .map(|details| (details.name.as_str(), details.version.as_str(), details.dev_only))
+      .collect::<Vec<_>>();
+    assert_eq!(summary, vec![
+      ("libc", "0.2.30", false),
+      ("memchr", "2.0.1", false),
+      ("rand", "0.3.18", true),
+    ]);
+
+    let manifest = CargoManifest::from_str(r#"
+      [package]
+      name = "example"
+      version = "0.1.0"
+
+      [dependencies]
+      memchr = "2.0"
+      libc = "0.3"
+    "#).unwrap();
+    match snapshot_now::plan_project_snapshot(&manifest, &source, "local-test") {
+      Err(CliErr::UnresolvableDependencies(reasons)) => assert!(!reasons.is_empty()),
+      other => panic!("Expected unresolvable dependencies, got {:?}", other),
+    }
+
+    let manifest = CargoManifest::from_str(r#"
+      [package]
+      name = "example"
+      version = "0.1.0"
+
+      [dependencies]
+      missing = "1.0"
+    "#).unwrap();
+    match snapshot_now::plan_project_snapshot(&manifest, &source, "local-test") {
+      Err(CliErr::UnresolvableDependencies(reasons)) =>
+        assert_eq!(reasons, vec!["missing is not in the crates.io index".to_owned()]),
+      other => panic!("Expected unresolvable dependencies, got {:?}", other),
+    }
+  }
+}
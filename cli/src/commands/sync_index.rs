@@ -0,0 +1,227 @@
+use ::CliErr;
+use chrono::Utc;
+use common::WorkspaceMetadata;
+use common::snapshot_store;
+use git2::Oid;
+use git2::Repository;
+use git2::build::CheckoutBuilder;
+use project::LocalCache;
+use project::PROJECT_CONFIGURATION_FILE;
+use project::Project;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+/** The upstream that the crates.io index is synced from, unless the project picks another. */
+pub const DEFAULT_CRATES_IO_INDEX_URL: &'static str = "https://github.com/rust-lang/crates.io-index";
+/** As above, for the augmented index. */
+pub const DEFAULT_AUGMENTED_INDEX_URL: &'static str = "https://github.com/acmcarther/stockpile-index";
+
+pub struct SyncIndexParams {
+  pub repo_directory: Option<PathBuf>,
+}
+
+/** What syncing did to a single local checkout. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncOutcome {
+  // There was no checkout, so one was cloned
+  Cloned { revision: Oid },
+  FastForwarded {
+    from: Oid,
+    to: Oid,
+    new_commits: usize,
+    // The number of files that differ between the two revisions
+    changed_files: usize,
+  },
+  UpToDate { revision: Oid },
+}
+
+impl SyncOutcome {
+  pub fn get_revision(&self) -> Oid {
+    match *self {
+      SyncOutcome::Cloned { revision } => revision,
+      SyncOutcome::FastForwarded { to, .. } => to,
+      SyncOutcome::UpToDate { revision } => revision,
+    }
+  }
+}
+
+/**
+ * Brings the crates.io index, the augmented index, and the snapshot repository in the local cache
+ * up to date with their upstreams, and records when that happened.
+ *
+ * The indices' upstreams are the project's, if it configures them, or the public ones otherwise.
+ * There is no public snapshot repository, so the project must name one.
+ */
+pub fn sync_index(params: SyncIndexParams) -> Result<(), CliErr> {
+  let project = try!(Project::load(params.repo_directory));
+  let cache = try!(LocalCache::locate());
+  try!(fs::create_dir_all(&cache.directory));
+  let configuration = &project.configuration;
+  let snapshot_repository_url = try!(configuration.snapshot_repository_url.clone()
+    .ok_or_else(|| CliErr::OtherErr(format!("No snapshot_repository_url is set in the {} of {:?}, so there is no \
+                                             snapshot repository to sync. Set it to the repository your \
+                                             snapshots are published to.",
+                                            PROJECT_CONFIGURATION_FILE,
+                                            project.directory))));
+
+  match try!(cache.read_metadata()) {
+    Some(WorkspaceMetadata { last_index_time: Some(ref time), .. }) => println!("Last synced at {}", time),
+    _ => println!("Never synced before"),
+  }
+
+  let crates_io_outcome = try!(sync_repository(
+    configuration.crates_io_index_url.as_ref().map(|u| u.as_str()).unwrap_or(DEFAULT_CRATES_IO_INDEX_URL),
+    &cache.get_crates_io_index_path()));
+  println!("crates.io index: {}", describe_outcome(&crates_io_outcome));
+
+  let augmented_outcome = try!(sync_repository(
+    configuration.augmented_index_url.as_ref().map(|u| u.as_str()).unwrap_or(DEFAULT_AUGMENTED_INDEX_URL),
+    &cache.get_augmented_index_path()));
+  println!("augmented index: {}", describe_outcome(&augmented_outcome));
+
+  let snapshot_repository_path = cache.get_snapshot_repository_path();
+  let previous_snapshots = try!(snapshot_store::list_snapshots(&snapshot_repository_path));
+  let snapshot_outcome = try!(sync_repository(&snapshot_repository_url, &snapshot_repository_path));
+  println!("snapshot repository: {}", describe_outcome(&snapshot_outcome));
+  let new_snapshots = try!(snapshot_store::list_snapshots(&snapshot_repository_path)).into_iter()
+    .filter(|name| !previous_snapshots.contains(name))
+    .map(|name| name.to_string())
+    .collect::<Vec<_>>();
+  if !new_snapshots.is_empty() {
+    println!("  new snapshots: {}", new_snapshots.join(", "));
+  }
+
+  try!(cache.write_metadata(&WorkspaceMetadata {
+    last_index_time: Some(Utc::now()),
+    crates_io_index_revision: crates_io_outcome.get_revision().to_string(),
+  }));
+  Ok(())
+}
+
+/**
+ * Clones the upstream into the path if there is no checkout there yet, or fast-forwards the
+ * checked out branch to the upstream's branch of the same name otherwise.
+ *
+ * Checkouts that have diverged from their upstream are left alone, and reported as an error.
+ */
+pub fn sync_repository<P: AsRef<Path>>(url: &str, path: P) -> Result<SyncOutcome, CliErr> {
+  let path = path.as_ref();
+  if !path.join(".git").exists() {
+    let repository = try!(Repository::clone(url, path));
+    let revision = try!(get_head_revision(&repository));
+    return Ok(SyncOutcome::Cloned { revision: revision })
+  }
+
+  let repository = try!(Repository::open(path));
+  let head = try!(repository.head());
+  if !head.is_branch() {
+    return Err(CliErr::OtherErr(format!("The checkout at {:?} is not on a branch", path)))
+  }
+  let branch_name = try!(head.shorthand()
+    .map(ToOwned::to_owned)
+    .ok_or_else(|| CliErr::OtherErr(format!("The checkout at {:?} has an unreadable HEAD", path))));
+  let head_name = try!(head.name()
+    .map(ToOwned::to_owned)
+    .ok_or_else(|| CliErr::OtherErr(format!("The checkout at {:?} has an unreadable HEAD", path))));
+  let current_revision = try!(get_head_revision(&repository));
+
+  let upstream_ref_name = format!("refs/remotes/origin/{}", branch_name);
+  let refspec = format!("+refs/heads/{}:{}", branch_name, upstream_ref_name);
+  try!(try!(repository.remote_anonymous(url)).fetch(&[refspec.as_str()], None, None));
+  let upstream_revision = try!(try!(repository.find_reference(&upstream_ref_name)).target()
+    .ok_or_else(|| CliErr::OtherErr(format!("{} has no revision", upstream_ref_name))));
+
+  if upstream_revision == current_revision {
+    return Ok(SyncOutcome::UpToDate { revision: current_revision })
+  }
+  let (ahead, behind) = try!(repository.graph_ahead_behind(current_revision, upstream_revision));
+  if ahead > 0 {
+    return Err(CliErr::OtherErr(format!("The checkout at {:?} has diverged from {}, so it cannot be \
+                                         fast-forwarded", path, url)))
+  }
+
+  let changed_files = {
+    let current_tree = try!(try!(repository.find_commit(current_revision)).tree());
+    let upstream_tree = try!(try!(repository.find_commit(upstream_revision)).tree());
+    try!(repository.diff_tree_to_tree(Some(&current_tree), Some(&upstream_tree), None)).deltas().count()
+  };
+  try!(try!(repository.find_reference(&head_name))
+    .set_target(upstream_revision, &format!("sync: fast-forward to {}", upstream_revision)));
+  try!(repository.checkout_head(Some(CheckoutBuilder::new().force())));
+
+  Ok(SyncOutcome::FastForwarded {
+    from: current_revision,
+    to: upstream_revision,
+    new_commits: behind,
+    changed_files: changed_files,
+  })
+}
+
+fn get_head_revision(repository: &Repository) -> Result<Oid, CliErr> {
+  try!(repository.head()).target()
+    .ok_or_else(|| CliErr::OtherErr("The checkout has no HEAD revision".to_owned()))
+}
+
+fn describe_outcome(outcome: &SyncOutcome) -> String {
+  match *outcome {
+    SyncOutcome::Cloned { revision } => format!("cloned at {}", revision),
+    SyncOutcome::FastForwarded { from, to, new_commits, changed_files } =>
+      format!("updated {} -> {} ({} new commits, {} files changed)", from, to, new_commits, changed_files),
+    SyncOutcome::UpToDate { revision } => format!("already up to date at {}", revision),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use commands::sync_index::SyncOutcome;
+  use commands::sync_index;
+  use git2::Oid;
+  use git2::Repository;
+  use git2::Signature;
+  use std::fs::File;
+  use std::io::Write;
+  use std::path::Path;
+  use tempdir::TempDir;
+
+  fn commit_file(repository: &Repository, file_name: &str, contents: &str) -> Oid {
+    let workdir = repository.workdir().unwrap().to_path_buf();
+    File::create(workdir.join(file_name)).unwrap().write_all(contents.as_bytes()).unwrap();
+    let mut index = repository.index().unwrap();
+    index.add_path(Path::new(file_name)).unwrap();
+    index.write().unwrap();
+    let tree = repository.find_tree(index.write_tree().unwrap()).unwrap();
+    let signature = Signature::now("test", "test@localhost").unwrap();
+    let parent = repository.head().ok()
+      .and_then(|head| head.target())
+      .map(|oid| repository.find_commit(oid).unwrap());
+    let parents = parent.iter().collect::<Vec<_>>();
+    repository.commit(Some("HEAD"), &signature, &signature, "Update", &tree, parents.as_slice()).unwrap()
+  }
+
+  #[test]
+  fn test_sync_repository_clones_then_fast_forwards() {
+    let upstream_dir = TempDir::new("upstream").unwrap();
+    let upstream = Repository::init(upstream_dir.path()).unwrap();
+    let first_revision = commit_file(&upstream, "a", "1");
+    let upstream_url = upstream_dir.path().to_str().unwrap();
+
+    let checkout_dir = TempDir::new("checkout").unwrap();
+    let checkout_path = checkout_dir.path().join("index");
+    assert_eq!(sync_index::sync_repository(upstream_url, &checkout_path).unwrap(),
+               SyncOutcome::Cloned { revision: first_revision });
+    assert_eq!(sync_index::sync_repository(upstream_url, &checkout_path).unwrap(),
+               SyncOutcome::UpToDate { revision: first_revision });
+
+    commit_file(&upstream, "a", "2");
+    let latest_revision = commit_file(&upstream, "b", "1");
+    assert_eq!(sync_index::sync_repository(upstream_url, &checkout_path).unwrap(),
+               SyncOutcome::FastForwarded {
+                 from: first_revision,
+                 to: latest_revision,
+                 new_commits: 2,
+                 changed_files: 2,
+               });
+    assert!(checkout_path.join("b").exists());
+  }
+}
@@ -0,0 +1,215 @@
+use ::CliErr;
+use common::index;
+use common::resolver::CandidateSource;
+use common::resolver::Requirement;
+use common::resolver::ResolutionErr;
+use common::resolver;
+use common::snapshot::ResolutionType;
+use common::snapshot::WorkspaceSnapshot;
+use project::LocalCache;
+use project::Project;
+use project;
+use semver::Version;
+use semver::VersionReq;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+pub struct TryAddingParams {
+  pub snapshot_version: Option<String>,
+  pub repo_directory: Option<PathBuf>,
+  pub crate_name: String,
+}
+
+/** What adding a crate to a snapshot would do to it. */
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct TryAddingResult {
+  pub snapshot_version: String,
+  pub crate_name: String,
+  // Whether any selection of versions includes the crate
+  pub fits: bool,
+  pub chosen_version: Option<String>,
+  // Crates that are not in the snapshot, but that the crate needs, directly or transitively
+  pub new_crates: Vec<AddedCrate>,
+  // Crates in the snapshot that would need a different version
+  pub moved_pins: Vec<MovedPin>,
+  // Only present if the crate does not fit
+  pub conflict_explanation: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct AddedCrate {
+  pub name: String,
+  pub version: String,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct MovedPin {
+  pub name: String,
+  pub from: String,
+  pub to: String,
+}
+
+/** Previews the addition of a crate to the selected snapshot, using the synced crates.io index. */
+pub fn try_adding(params: TryAddingParams) -> Result<(), CliErr> {
+  if !index::is_valid_crate_name(&params.crate_name) {
+    return Err(CliErr::OtherErr(format!("{:?} is not a valid crate name", params.crate_name)))
+  }
+  let project = try!(Project::load(params.repo_directory));
+  let cache = try!(LocalCache::locate());
+  let snapshot = try!(project::load_snapshot(&project,
+                                             &cache,
+                                             params.snapshot_version.as_ref().map(|v| v.as_str())));
+  let index = try!(cache.open_crates_io_index());
+
+  let result = try!(try_adding_to_snapshot(&snapshot, &index, &params.crate_name));
+  print!("{}", render_text(&result));
+  Ok(())
+}
+
+/**
+ * Resolves the snapshot's members alongside the crate, keeping each existing pin wherever possible.
+ *
+ * Crates that the snapshot resolves from git or a local path are not in the index, so they are left
+ * out entirely.
+ */
+pub fn try_adding_to_snapshot(snapshot: &WorkspaceSnapshot,
+                              source: &CandidateSource,
+                              crate_name: &str) -> Result<TryAddingResult, CliErr> {
+  let mut pinned_versions = BTreeMap::new();
+  for details in snapshot.details.iter() {
+    match details.resolution_type {
+      Some(ResolutionType { git: Some(_), .. }) | Some(ResolutionType { path: Some(_), .. }) => continue,
+      _ => {},
+    }
+    if let Ok(version) = Version::parse(&details.version) {
+      pinned_versions.insert(details.name.clone(), version);
+    }
+  }
+
+  let mut roots = snapshot.members.iter()
+    .filter(|member| pinned_versions.contains_key(member.as_str()))
+    .map(|member| any_version_of(member))
+    .collect::<Vec<_>>();
+  roots.push(any_version_of(crate_name));
+
+  let mut result = TryAddingResult {
+    snapshot_version: snapshot.version.clone(),
+    crate_name: crate_name.to_owned(),
+    fits: false,
+    chosen_version: None,
+    new_crates: Vec::new(),
+    moved_pins: Vec::new(),
+    conflict_explanation: Vec::new(),
+  };
+
+  let resolution = match resolver::resolve_with_preferences(source, &roots, &pinned_versions) {
+    Ok(resolution) => resolution,
+    Err(ResolutionErr::NoSolution(report)) => {
+      result.conflict_explanation = report.explanation;
+      return Ok(result)
+    },
+    Err(err) => return Err(CliErr::ResolutionErr(err)),
+  };
+
+  result.fits = true;
+  result.chosen_version = resolution.versions.get(crate_name).map(|v| v.to_string());
+  for (name, version) in resolution.versions.iter() {
+    match pinned_versions.get(name) {
+      Some(pinned_version) if pinned_version != version => result.moved_pins.push(MovedPin {
+        name: name.clone(),
+        from: pinned_version.to_string(),
+        to: version.to_string(),
+      }),
+      Some(_) => {},
+      None if name == crate_name => {},
+      None => result.new_crates.push(AddedCrate {
+        name: name.clone(),
+        version: version.to_string(),
+      }),
+    }
+  }
+  Ok(result)
+}
+
+fn any_version_of(crate_name: &str) -> Requirement {
+  Requirement {
+    name: crate_name.to_owned(),
+    req: VersionReq::any(),
+  }
+}
+
+fn render_text(result: &TryAddingResult) -> String {
+  let mut lines = Vec::new();
+  if !result.fits {
+    lines.push(format!("{} does not fit in snapshot {}", result.crate_name, result.snapshot_version));
+    lines.extend(result.conflict_explanation.iter().map(|line| format!("  {}", line)));
+    lines.push(String::new());
+    return lines.join("\n")
+  }
+
+  lines.push(format!("{} {} fits in snapshot {}",
+                     result.crate_name,
+                     result.chosen_version.as_ref().map(|v| v.as_str()).unwrap_or("(unknown version)"),
+                     result.snapshot_version));
+  if result.new_crates.is_empty() {
+    lines.push("  new crates: none".to_owned());
+  } else {
+    lines.push("  new crates:".to_owned());
+    lines.extend(result.new_crates.iter().map(|c| format!("    {} {}", c.name, c.version)));
+  }
+  if result.moved_pins.is_empty() {
+    lines.push("  moved pins: none".to_owned());
+  } else {
+    lines.push("  moved pins:".to_owned());
+    lines.extend(result.moved_pins.iter().map(|p| format!("    {} {} -> {}", p.name, p.from, p.to)));
+  }
+  lines.push(String::new());
+  lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+  use commands::try_adding::AddedCrate;
+  use commands::try_adding::MovedPin;
+  use commands::try_adding;
+  use common::resolver::testing::FakeCandidateSource;
+  use common::snapshot::WorkspaceSnapshot;
+  use common::snapshot::testing::crate_snapshot;
+
+  #[test]
+  fn test_try_adding_reports_new_crates_moved_pins_and_conflicts() {
+    let snapshot = WorkspaceSnapshot {
+      version: "lts-1.0".to_owned(),
+      members: vec!["regex".to_owned(), "serde".to_owned()],
+      details: vec![
+        crate_snapshot("libc", "0.2.20", &[]),
+        crate_snapshot("regex", "0.2.2", &["libc"]),
+        crate_snapshot("serde", "1.0.0", &[]),
+      ],
+    };
+    let source = FakeCandidateSource::new(&[
+      ("libc", "0.2.20", &[]),
+      ("libc", "0.2.30", &[]),
+      ("regex", "0.2.2", &[("libc", "^0.2")]),
+      ("serde", "1.0.0", &[]),
+      ("itoa", "0.3.4", &[]),
+      ("mio", "0.6.10", &[("libc", ">= 0.2.30"), ("itoa", "^0.3")]),
+      ("old-serde-user", "0.1.0", &[("serde", "^0.9")]),
+    ]);
+
+    let result = try_adding::try_adding_to_snapshot(&snapshot, &source, "mio").unwrap();
+    assert!(result.fits);
+    assert_eq!(result.chosen_version, Some("0.6.10".to_owned()));
+    assert_eq!(result.new_crates, vec![AddedCrate { name: "itoa".to_owned(), version: "0.3.4".to_owned() }]);
+    assert_eq!(result.moved_pins, vec![MovedPin {
+      name: "libc".to_owned(),
+      from: "0.2.20".to_owned(),
+      to: "0.2.30".to_owned(),
+    }]);
+
+    let result = try_adding::try_adding_to_snapshot(&snapshot, &source, "old-serde-user").unwrap();
+    assert!(!result.fits);
+    assert_eq!(result.chosen_version, None);
+    assert!(!result.conflict_explanation.is_empty());
+  }
+}
@@ -1,44 +1,67 @@
+extern crate chrono;
+#[macro_use] extern crate common;
+extern crate flate2;
+extern crate git2;
 extern crate log;
+extern crate semver;
 extern crate serde;
 #[macro_use(Serialize, Deserialize)]
 extern crate serde_derive;
+extern crate serde_json;
 extern crate serde_yaml;
+extern crate tar;
+#[cfg(test)]
+extern crate tempdir;
 
-pub mod commands {
-  use std::path::PathBuf;
-  use std::env;
-
-  pub struct SnapshotNowParams {
-    pub repo_directory: Option<PathBuf>,
-  }
-
-  pub fn snapshot_now(params: SnapshotNowParams) {
-  }
-
-  pub struct SyncIndexParams{
-    pub repo_directory: Option<PathBuf>,
-  }
-
-
-  pub fn sync_index(params: SyncIndexParams) {
-  }
+use common::lockfile::LockfileErr;
+use common::resolver::ResolutionErr;
+use common::snapshot_store::SnapshotStoreErr;
+use std::io;
 
-  pub struct QueryParams {
-    pub snapshot_version: Option<String>,
-    pub repo_directory: Option<PathBuf>,
-    pub crate_name: String,
-  }
+pub mod commands;
+pub mod project;
 
-  pub fn query(params: QueryParams) {
-  }
+#[derive(Debug)]
+pub enum CliErr {
+  IoErr(io::Error),
+  SerdeJsonErr(serde_json::Error),
+  YamlErr(serde_yaml::Error),
+  SnapshotStoreErr(SnapshotStoreErr),
+  ResolutionErr(ResolutionErr),
+  GitErr(git2::Error),
+  LockfileErr(LockfileErr),
+  // The crate is not in the selected snapshot
+  UnknownCrate(String),
+  // Why the dependencies of a project could not be resolved, one reason per line
+  UnresolvableDependencies(Vec<String>),
+  // The project strays from its snapshot in this many ways, which have already been reported
+  CheckFailed(usize),
+  OtherErr(String),
+}
+define_from_error_boilerplate!(io::Error, CliErr, CliErr::IoErr);
+define_from_error_boilerplate!(serde_json::Error, CliErr, CliErr::SerdeJsonErr);
+define_from_error_boilerplate!(serde_yaml::Error, CliErr, CliErr::YamlErr);
+define_from_error_boilerplate!(SnapshotStoreErr, CliErr, CliErr::SnapshotStoreErr);
+define_from_error_boilerplate!(ResolutionErr, CliErr, CliErr::ResolutionErr);
+define_from_error_boilerplate!(git2::Error, CliErr, CliErr::GitErr);
+define_from_error_boilerplate!(LockfileErr, CliErr, CliErr::LockfileErr);
 
-  pub struct TryAddingParams {
-    pub snapshot_version: Option<String>,
-    pub repo_directory: Option<PathBuf>,
-    pub crate_name: String,
-  }
+/** How a command should print its results. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+  // Human readable text
+  Text,
+  // A single JSON document, for scripting
+  Json,
+}
 
-  pub fn try_adding(params: TryAddingParams) {
+impl OutputFormat {
+  pub fn parse(raw: &str) -> Option<OutputFormat> {
+    match raw {
+      "text" => Some(OutputFormat::Text),
+      "json" => Some(OutputFormat::Json),
+      _ => None,
+    }
   }
 }
 
@@ -0,0 +1,264 @@
+use ::CliErr;
+use common::WorkspaceMetadata;
+use common::index::LocalIndex;
+use common::manifest::CargoManifest;
+use common::signing::PublicKey;
+use common::snapshot::WorkspaceSnapshot;
+use common::snapshot_store;
+use serde_yaml;
+use std::env;
+use std::fs::File;
+use std::fs;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+/** The name of the Cargo manifest, at the root of a project. */
+pub const PROJECT_MANIFEST_FILE: &'static str = "Cargo.toml";
+/** The name of Cargo's lockfile, at the root of a project. */
+pub const PROJECT_LOCKFILE: &'static str = "Cargo.lock";
+/** The name of the project configuration file, at the root of a project. */
+pub const PROJECT_CONFIGURATION_FILE: &'static str = "stockpile.yaml";
+/** The name of the snapshot that snapshot_now writes for a project, at the root of the project. */
+pub const PROJECT_SNAPSHOT_FILE: &'static str = "stockpile-snapshot.yaml";
+/** The environment variable that overrides the location of the local cache. */
+pub const CACHE_DIRECTORY_VAR: &'static str = "STOCKPILE_CACHE_DIRECTORY";
+
+/** The snapshot used when neither the command line nor the project configuration picks one. */
+const DEFAULT_SNAPSHOT_VERSION: &'static str = "lts";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ProjectConfiguration {
+  // The snapshot name or alias (such as "lts") used when none is requested
+  #[serde(default)]
+  pub snapshot_version: Option<String>,
+  // Hex encoded public keys. If any are listed, cached snapshots must be signed by one of them.
+  #[serde(default)]
+  pub trusted_keys: Vec<String>,
+  // Overrides for the upstreams that sync_index pulls from
+  #[serde(default)]
+  pub crates_io_index_url: Option<String>,
+  #[serde(default)]
+  pub augmented_index_url: Option<String>,
+  // The repository that snapshots are published to, which sync_index requires
+  #[serde(default)]
+  pub snapshot_repository_url: Option<String>,
+}
+
+/** A user's project: a directory with a Cargo.toml, and possibly a stockpile.yaml. */
+pub struct Project {
+  pub directory: PathBuf,
+  pub configuration: ProjectConfiguration,
+}
+
+impl Project {
+  /** Loads the project at the provided directory, or the working directory. */
+  pub fn load(repo_directory: Option<PathBuf>) -> Result<Project, CliErr> {
+    let directory = match repo_directory {
+      Some(directory) => directory,
+      None => try!(env::current_dir()),
+    };
+
+    let mut contents = String::new();
+    let configuration = match File::open(directory.join(PROJECT_CONFIGURATION_FILE)) {
+      Ok(mut file) => {
+        try!(file.read_to_string(&mut contents));
+        try!(serde_yaml::from_str::<ProjectConfiguration>(&contents))
+      },
+      Err(ref err) if err.kind() == ErrorKind::NotFound => ProjectConfiguration::default(),
+      Err(err) => return Err(CliErr::IoErr(err)),
+    };
+
+    Ok(Project {
+      directory: directory,
+      configuration: configuration,
+    })
+  }
+
+  pub fn get_manifest_path(&self) -> PathBuf {
+    self.directory.join(PROJECT_MANIFEST_FILE)
+  }
+
+  pub fn get_lockfile_path(&self) -> PathBuf {
+    self.directory.join(PROJECT_LOCKFILE)
+  }
+
+  pub fn read_manifest(&self) -> Result<CargoManifest, CliErr> {
+    read_manifest_at(&self.directory)
+  }
+
+  /**
+   * Reads the manifest of every package in the project: the root package (unless the manifest is
+   * virtual) and the members of its [workspace]. A member ending in "*" includes every package in
+   * that directory.
+   */
+  pub fn read_workspace_manifests(&self) -> Result<Vec<CargoManifest>, CliErr> {
+    let members = try!(self.read_workspace_members());
+    Ok(members.into_iter().map(|(_, manifest)| manifest).collect())
+  }
+
+  /** As read_workspace_manifests, with the directory of each package. */
+  pub fn read_workspace_members(&self) -> Result<Vec<(PathBuf, CargoManifest)>, CliErr> {
+    let root_manifest = try!(self.read_manifest());
+    let mut member_directories = Vec::new();
+    if let Some(ref workspace) = root_manifest.workspace {
+      for member in workspace.members.iter() {
+        if !member.ends_with('*') {
+          member_directories.push(self.directory.join(member));
+          continue
+        }
+        for entry in try!(fs::read_dir(self.directory.join(member.trim_right_matches('*')))) {
+          let path = try!(entry).path();
+          if path.join(PROJECT_MANIFEST_FILE).exists() {
+            member_directories.push(path);
+          }
+        }
+      }
+      member_directories.retain(|directory| {
+        !workspace.exclude.iter().any(|excluded| *directory == self.directory.join(excluded))
+      });
+    }
+    member_directories.sort();
+
+    let mut members = Vec::new();
+    for directory in member_directories.into_iter() {
+      let manifest = try!(read_manifest_at(&directory));
+      members.push((directory, manifest));
+    }
+    if root_manifest.get_package().is_some() {
+      members.insert(0, (self.directory.clone(), root_manifest));
+    }
+    Ok(members)
+  }
+
+  pub fn get_snapshot_path(&self) -> PathBuf {
+    self.directory.join(PROJECT_SNAPSHOT_FILE)
+  }
+
+  /** Reads the project-local snapshot written by snapshot_now, if there is one. */
+  pub fn read_local_snapshot(&self) -> Result<Option<WorkspaceSnapshot>, CliErr> {
+    let mut contents = String::new();
+    match File::open(self.get_snapshot_path()) {
+      Ok(mut file) => try!(file.read_to_string(&mut contents)),
+      Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+      Err(err) => return Err(CliErr::IoErr(err)),
+    };
+    Ok(Some(try!(serde_yaml::from_str::<WorkspaceSnapshot>(&contents))))
+  }
+
+  pub fn get_trusted_keys(&self) -> Result<Vec<PublicKey>, CliErr> {
+    let mut trusted_keys = Vec::new();
+    for raw_key in self.configuration.trusted_keys.iter() {
+      trusted_keys.push(try!(PublicKey::from_hex(raw_key)
+        .map_err(|_| CliErr::OtherErr(format!("{} in {} is not a valid public key",
+                                              raw_key,
+                                              PROJECT_CONFIGURATION_FILE)))));
+    }
+    Ok(trusted_keys)
+  }
+}
+
+fn read_manifest_at(directory: &Path) -> Result<CargoManifest, CliErr> {
+  let manifest_path = directory.join(PROJECT_MANIFEST_FILE);
+  let mut contents = String::new();
+  try!(File::open(&manifest_path).and_then(|mut f| f.read_to_string(&mut contents)));
+  CargoManifest::from_str(&contents)
+    .map_err(|err| CliErr::OtherErr(format!("Could not parse {:?}: {}", manifest_path, err)))
+}
+
+/**
+ * The directory that synced indexes and snapshots are kept in.
+ *
+ * This is $STOCKPILE_CACHE_DIRECTORY if set, or ~/.stockpile otherwise.
+ */
+pub struct LocalCache {
+  pub directory: PathBuf,
+}
+
+impl LocalCache {
+  pub fn locate() -> Result<LocalCache, CliErr> {
+    if let Some(directory) = env::var_os(CACHE_DIRECTORY_VAR) {
+      return Ok(LocalCache { directory: PathBuf::from(directory) })
+    }
+    match env::home_dir() {
+      Some(home_directory) => Ok(LocalCache { directory: home_directory.join(".stockpile") }),
+      None => Err(CliErr::OtherErr(format!("Could not find a home directory, set {} instead",
+                                           CACHE_DIRECTORY_VAR))),
+    }
+  }
+
+  /** The checkout of the snapshot repository. */
+  pub fn get_snapshot_repository_path(&self) -> PathBuf {
+    self.directory.join("snapshot-repository")
+  }
+
+  /** The checkout of the crates.io index. */
+  pub fn get_crates_io_index_path(&self) -> PathBuf {
+    self.directory.join("crates.io-index")
+  }
+
+  /** Opens the checkout of the crates.io index, which must have been synced. */
+  pub fn open_crates_io_index(&self) -> Result<LocalIndex, CliErr> {
+    let index_path = self.get_crates_io_index_path();
+    if !index_path.exists() {
+      return Err(CliErr::OtherErr(format!("No crates.io index was found at {:?}, run sync_index first",
+                                          index_path)))
+    }
+    Ok(LocalIndex::new(index_path))
+  }
+
+  /** The checkout of the augmented index. */
+  pub fn get_augmented_index_path(&self) -> PathBuf {
+    self.directory.join("augmented-index")
+  }
+
+  pub fn get_metadata_path(&self) -> PathBuf {
+    self.directory.join("metadata.yaml")
+  }
+
+  /** Reads the record of the last sync, if there has been one. */
+  pub fn read_metadata(&self) -> Result<Option<WorkspaceMetadata>, CliErr> {
+    let mut contents = String::new();
+    match File::open(self.get_metadata_path()) {
+      Ok(mut file) => try!(file.read_to_string(&mut contents)),
+      Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+      Err(err) => return Err(CliErr::IoErr(err)),
+    };
+    Ok(Some(try!(serde_yaml::from_str::<WorkspaceMetadata>(&contents))))
+  }
+
+  pub fn write_metadata(&self, metadata: &WorkspaceMetadata) -> Result<(), CliErr> {
+    let mut file = try!(File::create(self.get_metadata_path()));
+    try!(file.write_all(try!(serde_yaml::to_string(metadata)).as_bytes()));
+    Ok(())
+  }
+}
+
+/**
+ * Loads the snapshot that a command should use.
+ *
+ * The snapshot version is the requested one, or the project's default. The project-local snapshot
+ * is used if it has that version (or if no version was picked at all). Otherwise, the snapshot is
+ * read from the local cache, by name or alias, falling back to the latest LTS snapshot.
+ */
+pub fn load_snapshot(project: &Project,
+                     cache: &LocalCache,
+                     requested_version: Option<&str>) -> Result<WorkspaceSnapshot, CliErr> {
+  let snapshot_version = requested_version.or(project.configuration.snapshot_version.as_ref().map(|v| v.as_str()));
+  if let Some(local_snapshot) = try!(project.read_local_snapshot()) {
+    if snapshot_version.map(|v| v == local_snapshot.version).unwrap_or(true) {
+      return Ok(local_snapshot)
+    }
+  }
+
+  let snapshot_version = snapshot_version.unwrap_or(DEFAULT_SNAPSHOT_VERSION);
+  let trusted_keys = try!(project.get_trusted_keys());
+  let repository_path = cache.get_snapshot_repository_path();
+  if trusted_keys.is_empty() {
+    Ok(try!(snapshot_store::read_snapshot(&repository_path, snapshot_version)))
+  } else {
+    Ok(try!(snapshot_store::read_verified_snapshot(&repository_path, snapshot_version, &trusted_keys)))
+  }
+}
@@ -0,0 +1,22 @@
+use ring::digest;
+
+/** Hex encodes the SHA-256 of the bytes, in the form that crates.io-index checksums take. */
+pub fn get_sha256(bytes: &[u8]) -> String {
+  to_hex(digest::digest(&digest::SHA256, bytes).as_ref())
+}
+
+/** Hex encodes the bytes in lowercase, two characters per byte. */
+pub fn to_hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join("")
+}
+
+#[cfg(test)]
+mod tests {
+  use checksum;
+
+  #[test]
+  fn test_sha256_is_lowercase_hex() {
+    assert_eq!(checksum::get_sha256(b"abc"),
+               "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+  }
+}
@@ -0,0 +1,526 @@
+use index::IndexEntrySource;
+use semver::Version;
+use semver::VersionReq;
+use serde_yaml;
+use snapshot::ResolutionType;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::io;
+use std::path::Path;
+use yaml_rust::parser::Event;
+use yaml_rust::parser::MarkedEventReceiver;
+use yaml_rust::parser::Parser;
+use yaml_rust::scanner::Marker;
+
+/** The crates that a workspace (such as the one in manifest.yaml) wants snapshotted. */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkspaceConfiguration {
+  pub crate_sets: Vec<MaintainerConfiguration>,
+  // Crates whose tests do not need to build, so their dev dependencies are left out of snapshots
+  pub skip_dev_dependencies: Vec<String>,
+  // Test failures that should not fail the verification of a snapshot, such as known flakes
+  #[serde(default)]
+  pub expected_test_failures: Vec<ExpectedTestFailure>,
+}
+
+/** A set of crates that a single maintainer is responsible for. */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintainerConfiguration {
+  // An email address
+  pub maintainer: String,
+  pub crates: Vec<CrateEntry>,
+}
+
+/** A crate in a crate set, written either as just its name or as its name with constraints. */
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum CrateEntry {
+  Name(String),
+  Constrained(CrateConstraints),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct CrateConstraints {
+  pub name: String,
+  // A semver requirement, such as "^1.2", or an exact pin, such as "=1.2.3"
+  #[serde(default)]
+  pub version: Option<String>,
+  // Features to enable (alongside the defaults) when verifying the crate
+  #[serde(default)]
+  pub features: Vec<String>,
+  // Where the crate comes from, if not crates.io. Crates from git or a path (relative to this
+  // configuration) are packaged from there, so they need not be published.
+  #[serde(default)]
+  pub resolution: Option<ResolutionType>,
+  // Free-form, for humans only
+  #[serde(default)]
+  pub notes: Option<String>,
+}
+
+impl CrateEntry {
+  pub fn get_name(&self) -> &str {
+    match *self {
+      CrateEntry::Name(ref name) => name,
+      CrateEntry::Constrained(ref constraints) => &constraints.name,
+    }
+  }
+
+  pub fn get_version_req(&self) -> Option<&str> {
+    match *self {
+      CrateEntry::Name(_) => None,
+      CrateEntry::Constrained(ref constraints) => constraints.version.as_ref().map(|v| v.as_str()),
+    }
+  }
+
+  pub fn get_features(&self) -> &[String] {
+    match *self {
+      CrateEntry::Name(_) => &[],
+      CrateEntry::Constrained(ref constraints) => &constraints.features,
+    }
+  }
+
+  pub fn get_resolution(&self) -> Option<&ResolutionType> {
+    match *self {
+      CrateEntry::Name(_) => None,
+      CrateEntry::Constrained(ref constraints) => constraints.resolution.as_ref(),
+    }
+  }
+}
+
+/** A member's tests that are known to fail. */
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ExpectedTestFailure {
+  pub name: String,
+  // A test binary, as named in verification reports (such as "lib" or "test/integration"). If
+  // unset, every test of the member (and building them) may fail.
+  #[serde(default)]
+  pub test: Option<String>,
+  // Free-form, for humans only
+  #[serde(default)]
+  pub notes: Option<String>,
+}
+
+impl WorkspaceConfiguration {
+  /**
+   * Whether the member's test binary is expected to fail, or whether building its tests is, if no
+   * binary is provided.
+   */
+  pub fn is_test_failure_expected(&self, crate_name: &str, test: Option<&str>) -> bool {
+    self.expected_test_failures.iter()
+      .filter(|failure| failure.name == crate_name)
+      .any(|failure| failure.test.is_none() || failure.test.as_ref().map(|t| t.as_str()) == test)
+  }
+}
+
+#[derive(Debug)]
+pub enum ConfigurationErr {
+  IoErr(io::Error),
+  YamlErr(serde_yaml::Error),
+  InvalidConfiguration(Vec<ConfigurationProblem>),
+}
+define_from_error_boilerplate!(io::Error, ConfigurationErr, ConfigurationErr::IoErr);
+define_from_error_boilerplate!(serde_yaml::Error, ConfigurationErr, ConfigurationErr::YamlErr);
+
+/** A single reason that a configuration was rejected, and where it was found. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigurationProblem {
+  pub source_name: String,
+  // Both the line and column are 1-indexed
+  pub line: usize,
+  pub column: usize,
+  pub message: String,
+}
+
+impl fmt::Display for ConfigurationProblem {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}:{}:{}: {}", self.source_name, self.line, self.column, self.message)
+  }
+}
+
+/** Reads the configuration at the path, and validates it against the index. */
+pub fn load_configuration<P: AsRef<Path>>(path: P,
+                                          index: &IndexEntrySource) -> Result<WorkspaceConfiguration, ConfigurationErr> {
+  let mut contents = String::new();
+  try!(File::open(path.as_ref()).and_then(|mut f| f.read_to_string(&mut contents)));
+  parse_configuration(&path.as_ref().to_string_lossy(), &contents, index)
+}
+
+/**
+ * Reads the configuration at the path without validating it, for jobs that act on a snapshot that
+ * was already planned from it.
+ */
+pub fn read_unvalidated_configuration<P: AsRef<Path>>(path: P) -> Result<WorkspaceConfiguration, ConfigurationErr> {
+  let mut contents = String::new();
+  try!(File::open(path.as_ref()).and_then(|mut f| f.read_to_string(&mut contents)));
+  Ok(try!(serde_yaml::from_str::<WorkspaceConfiguration>(&contents)))
+}
+
+/**
+ * Parses and validates a configuration.
+ *
+ * Every problem is reported at once, in the order that they appear in the source. The source name
+ * is only used to describe where problems are.
+ */
+pub fn parse_configuration(source_name: &str,
+                           contents: &str,
+                           index: &IndexEntrySource) -> Result<WorkspaceConfiguration, ConfigurationErr> {
+  let configuration = try!(serde_yaml::from_str::<WorkspaceConfiguration>(contents));
+
+  let mut recorder = PositionRecorder::new();
+  if let Err(err) = Parser::new(contents.chars()).load(&mut recorder, false) {
+    // serde_yaml accepted the same document, so this is not expected
+    return Err(ConfigurationErr::InvalidConfiguration(vec![ConfigurationProblem {
+      source_name: source_name.to_owned(),
+      line: err.marker().line(),
+      column: err.marker().col() + 1,
+      message: err.to_string(),
+    }]))
+  }
+
+  let mut problems = Vec::new();
+  {
+    let mut add_problem = |path: String, message: String| {
+      let (line, column) = recorder.positions.get(&path).cloned().unwrap_or((0, 0));
+      problems.push(ConfigurationProblem {
+        source_name: source_name.to_owned(),
+        line: line,
+        column: column,
+        message: message,
+      });
+    };
+
+    // Crate name -> (index of the claiming crate set, path of the claim)
+    let mut claims: HashMap<&str, (usize, String)> = HashMap::new();
+    for (set_idx, crate_set) in configuration.crate_sets.iter().enumerate() {
+      let set_path = format!("crate_sets[{}]", set_idx);
+      if !is_valid_contact(&crate_set.maintainer) {
+        add_problem(format!("{}.maintainer", set_path),
+                    format!("maintainer contact \"{}\" is not an email address", crate_set.maintainer));
+      }
+
+      for (crate_idx, entry) in crate_set.crates.iter().enumerate() {
+        let crate_name = entry.get_name();
+        let crate_path = format!("{}.crates[{}]", set_path, crate_idx);
+        let name_path = match *entry {
+          CrateEntry::Name(_) => crate_path.clone(),
+          CrateEntry::Constrained(_) => format!("{}.name", crate_path),
+        };
+        if let Some(&(claiming_set_idx, ref claim_path)) = claims.get(crate_name) {
+          let claim_line = recorder.positions.get(claim_path).map(|&(line, _)| line).unwrap_or(0);
+          if claiming_set_idx == set_idx {
+            add_problem(name_path,
+                        format!("{} is listed more than once in this crate set (first at line {})",
+                                crate_name,
+                                claim_line));
+          } else {
+            add_problem(name_path,
+                        format!("{} is already claimed by {} (at line {})",
+                                crate_name,
+                                configuration.crate_sets[claiming_set_idx].maintainer,
+                                claim_line));
+          }
+          continue
+        }
+        claims.insert(crate_name, (set_idx, name_path.clone()));
+
+        let version_path = format!("{}.version", crate_path);
+        let version_req = match entry.get_version_req() {
+          Some(raw_req) => match VersionReq::parse(raw_req) {
+            Ok(req) => Some(req),
+            Err(_) => {
+              add_problem(version_path.clone(),
+                          format!("{} has an invalid version requirement \"{}\"", crate_name, raw_req));
+              None
+            },
+          },
+          None => None,
+        };
+
+        if let Some(resolution) = entry.get_resolution() {
+          let is_crates_io = resolution.crates_io.unwrap_or(false);
+          let source_count = [is_crates_io, resolution.git.is_some(), resolution.path.is_some()].iter()
+            .filter(|is_set| **is_set)
+            .count();
+          if source_count != 1 {
+            add_problem(format!("{}.resolution", crate_path),
+                        format!("{} must resolve from exactly one of crates_io, git, or path", crate_name));
+          }
+        }
+
+        // Crates from git or a path are packaged from there, so they need not be published
+        if entry.get_resolution().map(|r| r.git.is_some() || r.path.is_some()).unwrap_or(false) {
+          continue
+        }
+        let index_entries = try!(index.get_entries(crate_name));
+        if index_entries.is_empty() {
+          add_problem(name_path, format!("{} is not in the crates.io index", crate_name));
+        } else if let Some(req) = version_req {
+          let has_match = index_entries.iter()
+            .filter(|index_entry| !index_entry.yanked.unwrap_or(false))
+            .filter_map(|index_entry| Version::parse(&index_entry.vers).ok())
+            .any(|version| req.matches(&version));
+          if !has_match {
+            add_problem(version_path,
+                        format!("no published version of {} matches {}", crate_name, req));
+          }
+        }
+      }
+    }
+
+    for (skip_idx, crate_name) in configuration.skip_dev_dependencies.iter().enumerate() {
+      if !claims.contains_key(crate_name.as_str()) {
+        add_problem(format!("skip_dev_dependencies[{}]", skip_idx),
+                    format!("{} is in skip_dev_dependencies, but not in any crate set", crate_name));
+      }
+    }
+
+    for (failure_idx, failure) in configuration.expected_test_failures.iter().enumerate() {
+      let failure_path = format!("expected_test_failures[{}].name", failure_idx);
+      if !claims.contains_key(failure.name.as_str()) {
+        add_problem(failure_path,
+                    format!("{} is in expected_test_failures, but not in any crate set", failure.name));
+      } else if configuration.skip_dev_dependencies.contains(&failure.name) {
+        add_problem(failure_path,
+                    format!("{} is in expected_test_failures, but its tests are skipped", failure.name));
+      }
+    }
+  }
+
+  if problems.is_empty() {
+    Ok(configuration)
+  } else {
+    problems.sort_by_key(|problem| (problem.line, problem.column));
+    Err(ConfigurationErr::InvalidConfiguration(problems))
+  }
+}
+
+/** Accepts plain email addresses, like "someone@example.com". */
+fn is_valid_contact(contact: &str) -> bool {
+  if contact.chars().any(|c| c.is_whitespace()) {
+    return false
+  }
+  let mut parts = contact.split('@');
+  match (parts.next(), parts.next(), parts.next()) {
+    (Some(local), Some(domain), None) =>
+      !local.is_empty() && domain.contains('.') && !domain.starts_with('.') && !domain.ends_with('.'),
+    _ => false,
+  }
+}
+
+/** An open mapping or sequence within a YAML document, with the path to it. */
+enum Container {
+  // Mappings are positioned at their first key, once it has been seen
+  Mapping { path: String, key: Option<String>, is_empty: bool },
+  Sequence { path: String, next_idx: usize },
+}
+
+/**
+ * Records the (line, column) of each value in a YAML document by its path, such as
+ * "crate_sets[0].crates[1]".
+ *
+ * serde_yaml discards positions once a document is deserialized, so they are recovered from the
+ * parser's events instead.
+ */
+struct PositionRecorder {
+  containers: Vec<Container>,
+  positions: HashMap<String, (usize, usize)>,
+}
+
+impl PositionRecorder {
+  fn new() -> PositionRecorder {
+    PositionRecorder {
+      containers: Vec::new(),
+      positions: HashMap::new(),
+    }
+  }
+
+  /** Yields the path of the next value in the innermost container. */
+  fn get_value_path(&self) -> String {
+    match self.containers.last() {
+      Some(&Container::Mapping { ref path, key: Some(ref key), .. }) if path.is_empty() => key.clone(),
+      Some(&Container::Mapping { ref path, key: Some(ref key), .. }) => format!("{}.{}", path, key),
+      Some(&Container::Mapping { ref path, key: None, .. }) => path.clone(),
+      Some(&Container::Sequence { ref path, next_idx }) => format!("{}[{}]", path, next_idx),
+      None => String::new(),
+    }
+  }
+
+  /** Moves the innermost container past the value that was just read. */
+  fn finish_value(&mut self) {
+    match self.containers.last_mut() {
+      Some(&mut Container::Mapping { ref mut key, .. }) => *key = None,
+      Some(&mut Container::Sequence { ref mut next_idx, .. }) => *next_idx += 1,
+      None => {},
+    }
+  }
+
+  fn record(&mut self, path: String, mark: &Marker) {
+    self.positions.insert(path, (mark.line(), mark.col() + 1));
+  }
+}
+
+impl MarkedEventReceiver for PositionRecorder {
+  fn on_event(&mut self, event: Event, mark: Marker) {
+    match event {
+      Event::Scalar(value, _, _, _) => {
+        let is_key = match self.containers.last() {
+          Some(&Container::Mapping { key: None, .. }) => true,
+          _ => false,
+        };
+        if is_key {
+          let mut first_key_path = None;
+          if let Some(&mut Container::Mapping { ref path, ref mut key, ref mut is_empty }) = self.containers.last_mut() {
+            if *is_empty {
+              first_key_path = Some(path.clone());
+              *is_empty = false;
+            }
+            *key = Some(value);
+          }
+          if let Some(path) = first_key_path {
+            self.record(path, &mark);
+          }
+        } else {
+          let path = self.get_value_path();
+          self.record(path, &mark);
+          self.finish_value();
+        }
+      },
+      Event::MappingStart(_) => {
+        let path = self.get_value_path();
+        self.record(path.clone(), &mark);
+        self.containers.push(Container::Mapping { path: path, key: None, is_empty: true });
+      },
+      Event::SequenceStart(_) => {
+        let path = self.get_value_path();
+        self.record(path.clone(), &mark);
+        self.containers.push(Container::Sequence { path: path, next_idx: 0 });
+      },
+      Event::MappingEnd | Event::SequenceEnd => {
+        self.containers.pop();
+        self.finish_value();
+      },
+      Event::Alias(_) => self.finish_value(),
+      _ => {},
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use configuration::ConfigurationErr;
+  use configuration::CrateConstraints;
+  use configuration::CrateEntry;
+  use configuration;
+  use index::testing::FakeIndex;
+  use index::testing::index_entry;
+
+  #[test]
+  fn test_reports_every_problem_with_its_position() {
+    let index = FakeIndex::new(vec![
+      index_entry("libc", "0.2.30", &[]),
+      index_entry("memchr", "2.0.1", &[]),
+      index_entry("regex", "0.2.2", &[]),
+    ]);
+    let valid = "crate_sets:\n  - maintainer: a@example.com\n    crates:\n      - libc\n      - memchr\n\
+                 skip_dev_dependencies:\n  - memchr\n\
+                 expected_test_failures:\n  - name: libc\n    test: test/flaky\n";
+    let configuration = configuration::parse_configuration("manifest.yaml", valid, &index).unwrap();
+    assert_eq!(configuration.crate_sets[0].crates,
+               vec![CrateEntry::Name("libc".to_owned()), CrateEntry::Name("memchr".to_owned())]);
+    assert!(configuration.is_test_failure_expected("libc", Some("test/flaky")));
+    assert!(!configuration.is_test_failure_expected("libc", Some("lib")));
+    assert!(!configuration.is_test_failure_expected("libc", None));
+
+    let invalid = "crate_sets:\n\
+                   \x20 - maintainer: a@example.com\n\
+                   \x20   crates:\n\
+                   \x20     - libc\n\
+                   \x20     - nonexistent\n\
+                   \x20     - libc\n\
+                   \x20 - maintainer: not an email\n\
+                   \x20   crates: [regex, memchr]\n\
+                   \x20 - maintainer: c@example.com\n\
+                   \x20   crates:\n\
+                   \x20     - memchr\n\
+                   skip_dev_dependencies:\n\
+                   \x20 - memchr\n\
+                   \x20 - unclaimed\n\
+                   expected_test_failures:\n\
+                   \x20 - name: memchr\n";
+    let problems = match configuration::parse_configuration("manifest.yaml", invalid, &index) {
+      Err(ConfigurationErr::InvalidConfiguration(problems)) => problems,
+      other => panic!("Expected the configuration to be invalid, but got {:?}", other),
+    };
+    let rendered = problems.iter().map(|problem| problem.to_string()).collect::<Vec<_>>();
+    assert_eq!(rendered, vec![
+      "manifest.yaml:5:9: nonexistent is not in the crates.io index".to_owned(),
+      "manifest.yaml:6:9: libc is listed more than once in this crate set (first at line 4)".to_owned(),
+      "manifest.yaml:7:17: maintainer contact \"not an email\" is not an email address".to_owned(),
+      "manifest.yaml:11:9: memchr is already claimed by not an email (at line 8)".to_owned(),
+      "manifest.yaml:14:5: unclaimed is in skip_dev_dependencies, but not in any crate set".to_owned(),
+      "manifest.yaml:16:11: memchr is in expected_test_failures, but its tests are skipped".to_owned(),
+    ]);
+  }
+
+  #[test]
+  fn test_crate_entries_accept_constraints() {
+    let index = FakeIndex::new(vec![
+      index_entry("libc", "0.2.30", &[]),
+      index_entry("regex", "0.2.2", &[]),
+      index_entry("serde", "1.0.15", &[]),
+    ]);
+    let contents = "crate_sets:\n\
+                    \x20 - maintainer: a@example.com\n\
+                    \x20   crates:\n\
+                    \x20     - libc\n\
+                    \x20     - name: serde\n\
+                    \x20       version: \"^1.0\"\n\
+                    \x20       features: [derive]\n\
+                    \x20       notes: Needed for the derive macros\n\
+                    \x20     - name: regex\n\
+                    \x20       version: \"=0.2.2\"\n\
+                    \x20       resolution:\n\
+                    \x20         path: vendor/regex\n\
+                    skip_dev_dependencies: []\n";
+    let configuration = configuration::parse_configuration("manifest.yaml", contents, &index).unwrap();
+    let crates = &configuration.crate_sets[0].crates;
+    assert_eq!(crates[0], CrateEntry::Name("libc".to_owned()));
+    assert_eq!(crates[1], CrateEntry::Constrained(CrateConstraints {
+      name: "serde".to_owned(),
+      version: Some("^1.0".to_owned()),
+      features: vec!["derive".to_owned()],
+      resolution: None,
+      notes: Some("Needed for the derive macros".to_owned()),
+    }));
+    assert_eq!(crates[2].get_resolution().and_then(|r| r.path.clone()), Some("vendor/regex".to_owned()));
+
+    let invalid = "crate_sets:\n\
+                   \x20 - maintainer: a@example.com\n\
+                   \x20   crates:\n\
+                   \x20     - name: serde\n\
+                   \x20       version: \"^2.0\"\n\
+                   \x20     - name: regex\n\
+                   \x20       resolution:\n\
+                   \x20         crates_io: true\n\
+                   \x20         path: vendor/regex\n\
+                   \x20     - name: unpublished-fork\n\
+                   \x20       resolution:\n\
+                   \x20         git:\n\
+                   \x20           repository: https://example.com/fork\n\
+                   \x20           revision: abc123\n\
+                   skip_dev_dependencies: []\n";
+    let problems = match configuration::parse_configuration("manifest.yaml", invalid, &index) {
+      Err(ConfigurationErr::InvalidConfiguration(problems)) => problems,
+      other => panic!("Expected the configuration to be invalid, but got {:?}", other),
+    };
+    let rendered = problems.iter().map(|problem| problem.to_string()).collect::<Vec<_>>();
+    assert_eq!(rendered, vec![
+      "manifest.yaml:5:18: no published version of serde matches ^2.0".to_owned(),
+      "manifest.yaml:8:11: regex must resolve from exactly one of crates_io, git, or path".to_owned(),
+    ]);
+  }
+}
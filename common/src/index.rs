@@ -0,0 +1,207 @@
+use cargo;
+use resolver::Candidate;
+use resolver::CandidateSource;
+use resolver::candidates;
+use serde_json;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+/**
+ * Constructs an "index-like" directory path for the given crate name.
+ *
+ * If the crate name is one character, the path is 1/$CRATE_NAME
+ * If the crate name is two characters, the path is 2/$CRATE_NAME
+ * If the crate name is three characters, the path is 3/$CRATE_NAME
+ * If the crate name is four or more characters, the path is
+ *   $FIRST_TWO_CHARS/$NEXT_TWO_CHARS/$CRATE_NAME
+ *
+ * Characters are counted as chars rather than bytes, so names that are not valid crate names (see
+ * is_valid_crate_name) still yield a path instead of panicking.
+ */
+pub fn get_path_for_crate(crate_name: &str) -> PathBuf {
+  let chars = crate_name.chars().collect::<Vec<_>>();
+  match chars.len() {
+    0 => panic!("Can't generate a path for an empty string"),
+    1 => PathBuf::from(format!("1/{}", crate_name)),
+    2 => PathBuf::from(format!("2/{}", crate_name)),
+    3 => PathBuf::from(format!("3/{}", crate_name)),
+    _ => PathBuf::from(format!("{}/{}/{}",
+                               chars[0..2].iter().collect::<String>(),
+                               chars[2..4].iter().collect::<String>(),
+                               crate_name)),
+  }
+}
+
+/**
+ * Whether the name could be a crate's: a letter followed by ASCII letters, digits, '-' or '_', as
+ * crates.io requires.
+ *
+ * Names from users (rather than from an index) should be checked before being turned into paths.
+ */
+pub fn is_valid_crate_name(crate_name: &str) -> bool {
+  let is_ascii_letter = |c: char| (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z');
+  match crate_name.chars().next() {
+    Some(first) if is_ascii_letter(first) => {
+      crate_name.chars().all(|c| is_ascii_letter(c) || (c >= '0' && c <= '9') || c == '-' || c == '_')
+    },
+    _ => false,
+  }
+}
+
+/**
+ * Constructs the path of a crate's tarball within a local crate store: $NAME-$VERSION.crate, in
+ * the directory that the index path of the (lowercased) crate name would be in.
+ */
+pub fn get_lcs_path_for_crate(crate_name: &str, version: &str) -> PathBuf {
+  let index_path = get_path_for_crate(&crate_name.to_lowercase());
+  let directory = index_path.parent().map(|parent| parent.to_path_buf()).unwrap_or_default();
+  directory.join(format!("{}-{}.crate", crate_name, version))
+}
+
+/** A trait for anything that can yield the entries of a crates.io-like index, crate by crate. */
+pub trait IndexEntrySource {
+  /** Yields every entry for the crate, or nothing if the index does not know of it. */
+  fn get_entries(&self, crate_name: &str) -> io::Result<Vec<cargo::IndexEntry>>;
+}
+
+/**
+ * A read-only view of a checked out crates.io-like index, which reads crate files on demand.
+ *
+ * Unlike the job indexes, this never loads the whole index, so it is suitable for the CLI.
+ */
+pub struct LocalIndex {
+  root: PathBuf,
+}
+
+impl LocalIndex {
+  pub fn new<P: AsRef<Path>>(root: P) -> LocalIndex {
+    LocalIndex {
+      root: root.as_ref().to_path_buf(),
+    }
+  }
+}
+
+impl IndexEntrySource for LocalIndex {
+  fn get_entries(&self, crate_name: &str) -> io::Result<Vec<cargo::IndexEntry>> {
+    // Anything else can't be in the index, and may not even map to a path within it
+    if !is_valid_crate_name(crate_name) {
+      return Ok(Vec::new())
+    }
+    let mut contents = String::new();
+    // Index paths are always lowercase, though crate names may not be
+    match File::open(self.root.join(get_path_for_crate(&crate_name.to_lowercase()))) {
+      Ok(mut file) => try!(file.read_to_string(&mut contents)),
+      Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+      Err(err) => return Err(err),
+    };
+
+    let mut entries = Vec::new();
+    for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+      entries.push(try!(serde_json::from_str::<cargo::IndexEntry>(line)
+        .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))));
+    }
+    Ok(entries)
+  }
+}
+
+/**
+ * Candidates are the non-yanked versions in the index, without dev dependencies.
+ *
+ * Unreadable crate files are treated as having no versions.
+ */
+impl CandidateSource for LocalIndex {
+  fn get_candidates(&self, crate_name: &str) -> Vec<Candidate> {
+    self.get_entries(crate_name).unwrap_or_default().iter()
+      .filter(|entry| !entry.yanked.unwrap_or(false))
+      .filter_map(|entry| candidates::to_candidate(entry, Vec::new()))
+      .collect()
+  }
+}
+
+pub mod testing {
+  use cargo;
+  use index::IndexEntrySource;
+  use std::collections::HashMap;
+  use std::io;
+
+  /** An IndexEntrySource backed by an in-memory list of entries. */
+  pub struct FakeIndex {
+    entries: HashMap<String, Vec<cargo::IndexEntry>>,
+  }
+
+  impl FakeIndex {
+    pub fn new(entries: Vec<cargo::IndexEntry>) -> FakeIndex {
+      let mut entries_by_name = HashMap::new();
+      for entry in entries.into_iter() {
+        entries_by_name.entry(entry.name.clone()).or_insert_with(Vec::new).push(entry);
+      }
+      FakeIndex {
+        entries: entries_by_name,
+      }
+    }
+  }
+
+  impl IndexEntrySource for FakeIndex {
+    fn get_entries(&self, crate_name: &str) -> io::Result<Vec<cargo::IndexEntry>> {
+      Ok(self.entries.get(crate_name).cloned().unwrap_or_default())
+    }
+  }
+
+  /** Constructs an entry with normal dependencies on each (name, requirement). */
+  pub fn index_entry(name: &str, version: &str, deps: &[(&str, &str)]) -> cargo::IndexEntry {
+    cargo::IndexEntry {
+      name: name.to_owned(),
+      vers: version.to_owned(),
+      deps: deps.iter()
+        .map(|&(dep_name, dep_req)| dependency_entry(dep_name, dep_req))
+        .collect(),
+      cksum: format!("{}-{}-checksum", name, version),
+      features: HashMap::new(),
+      yanked: None,
+    }
+  }
+
+  /**
+   * Constructs a normal, non-optional dependency with default features. Tests that need anything
+   * else override the fields they care about.
+   */
+  pub fn dependency_entry(name: &str, req: &str) -> cargo::DependencyEntry {
+    cargo::DependencyEntry {
+      name: name.to_owned(),
+      req: req.to_owned(),
+      features: Vec::new(),
+      optional: false,
+      default_features: true,
+      target: None,
+      kind: Some("normal".to_owned()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use index::IndexEntrySource;
+  use index::LocalIndex;
+  use index;
+  use std::path::PathBuf;
+
+  #[test]
+  fn test_invalid_crate_names_have_no_entries() {
+    let local_index = LocalIndex::new("/nonexistent-stockpile-index");
+    for crate_name in ["", "日本語", "ü", "../../etc", "1abc", "a b"].iter() {
+      assert!(!index::is_valid_crate_name(crate_name), "{} should be invalid", crate_name);
+      assert_eq!(local_index.get_entries(crate_name).unwrap(), Vec::new());
+    }
+    assert!(index::is_valid_crate_name("serde_json-2"));
+  }
+
+  #[test]
+  fn test_paths_count_chars_rather_than_bytes() {
+    assert_eq!(index::get_path_for_crate("日本語"), PathBuf::from("3/日本語"));
+    assert_eq!(index::get_path_for_crate("日本語です"), PathBuf::from("日本/語で/日本語です"));
+  }
+}
@@ -7,14 +7,28 @@ extern crate serde_yaml;
 extern crate zcfg_flag_parser;
 extern crate chrono;
 extern crate fern;
+extern crate ring;
+extern crate semver;
+extern crate serde_json;
+extern crate toml;
+extern crate untrusted;
+extern crate yaml_rust;
 
 use chrono::DateTime;
 use chrono::Utc;
 use std::env;
 use zcfg_flag_parser::FlagParser;
 
+pub mod checksum;
+pub mod index;
+pub mod license;
+pub mod manifest;
+pub mod resolver;
+pub mod snapshot_diff;
+
 pub mod cargo {
   use super::*;
+  use std::collections::BTreeMap;
   use std::collections::HashMap;
   // Mostly a copy from github/rust-lang/crates.io/src/git.rs
   // WARNING: On sync from upstream crates.io-index, all modifications
@@ -53,6 +67,98 @@ pub mod cargo {
     pub name: String,
     pub vers: String,
     pub dev_dependencies: Option<Vec<DependencyEntry>>,
+    pub source_statistics: Option<SourceStatistics>,
+    pub license: Option<LicenseInfo>,
+  }
+
+  // Statistics derived from a lexical scan of a crate's source tarball
+  #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+  pub struct SourceStatistics {
+    pub compressed_size_bytes: u64,
+    pub uncompressed_size_bytes: u64,
+    pub file_counts_by_extension: BTreeMap<String, u64>,
+    pub rust_lines_of_code: u64,
+    pub unsafe_usage: UnsafeUsage,
+    pub forbids_unsafe_code: bool,
+  }
+
+  // Occurrences of the `unsafe` keyword, by what it qualifies
+  #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+  pub struct UnsafeUsage {
+    pub blocks: u64,
+    pub functions: u64,
+    pub impls: u64,
+    pub traits: u64,
+  }
+
+  // A crate version from the crates.io-index, alongside whatever the augmented index knows of it
+  #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+  pub struct JoinedIndexEntry {
+    pub index_entry: IndexEntry,
+    pub augmented_entry: Option<AugmentedIndexEntry>,
+  }
+
+  impl JoinedIndexEntry {
+    pub fn get_deps(&self) -> &Vec<DependencyEntry> {
+      &self.index_entry.deps
+    }
+
+    /** Yields the dev dependencies, if they have been backfilled. */
+    pub fn get_dev_dependencies(&self) -> Option<&Vec<DependencyEntry>> {
+      self.augmented_entry.as_ref().and_then(|e| e.dev_dependencies.as_ref())
+    }
+
+    pub fn get_source_statistics(&self) -> Option<&SourceStatistics> {
+      self.augmented_entry.as_ref().and_then(|e| e.source_statistics.as_ref())
+    }
+
+    pub fn get_license(&self) -> Option<&LicenseInfo> {
+      self.augmented_entry.as_ref().and_then(|e| e.license.as_ref())
+    }
+
+    pub fn is_yanked(&self) -> bool {
+      self.index_entry.yanked.unwrap_or(false)
+    }
+  }
+
+  // License terms declared in a crate's manifest, and detected within its tarball
+  #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+  pub struct LicenseInfo {
+    pub declared: Option<DeclaredLicense>,
+    // The "license-file" from the manifest, if any
+    pub license_file: Option<String>,
+    pub detected_files: Vec<DetectedLicenseFile>,
+  }
+
+  // The manifest's "license" field, alongside its SPDX interpretation
+  #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+  pub struct DeclaredLicense {
+    pub raw: String,
+    pub spdx_expression: Option<String>,
+    pub confidence: LicenseConfidence,
+  }
+
+  // A license-like file found in the crate tarball
+  #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+  pub struct DetectedLicenseFile {
+    pub path: String,
+    pub spdx_id: Option<String>,
+    pub confidence: LicenseConfidence,
+  }
+
+  // How much trust to place in a license identification
+  #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+  #[serde(rename_all = "snake_case")]
+  pub enum LicenseConfidence {
+    // Declared as a valid SPDX expression, used verbatim
+    Exact,
+    // Declared informally, and rewritten into a valid SPDX expression
+    Normalized,
+    // Identified by the license text itself
+    High,
+    // Identified only by a file name, such as LICENSE-MIT
+    Low,
+    Unknown,
   }
 
   // TODO(acmcarther): Derive these from Ref T, not T
@@ -76,57 +182,90 @@ pub mod cargo {
       }
     }
   }
-}
-
 
-pub mod configuration {
-  #[derive(Debug, Clone, Serialize, Deserialize)]
-  pub struct WorkspaceConfiguration {
-    crate_sets: Vec<MaintainerConfiguration>,
-    skip_dev_dependencies: Vec<String>,
-  }
-
-  #[derive(Debug, Clone, Serialize, Deserialize)]
-  pub struct MaintainerConfiguration {
-    maintainer: String,
-    crates: Vec<String>,
+  impl ::std::convert::From<JoinedIndexEntry> for CrateKey {
+    fn from(e: JoinedIndexEntry) -> CrateKey {
+      CrateKey::from(e.index_entry)
+    }
   }
 }
 
+
 pub mod snapshot {
-  #[derive(Debug, Clone, Serialize, Deserialize)]
+  #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
   pub struct WorkspaceSnapshot {
-    version: String,
-    members: Vec<String>,
-    details: Vec<CrateSnapshot>,
+    pub version: String,
+    // The crates named in the workspace configuration
+    pub members: Vec<String>,
+    // Every crate in the snapshot, including transitive dependencies of members
+    pub details: Vec<CrateSnapshot>,
   }
 
-  #[derive(Debug, Clone, Serialize, Deserialize)]
+  #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
   pub struct CrateSnapshot {
-    name: String,
-    version: String,
-    maintainer: String,
-    dependencies: String,
-    resolution_type: Option<ResolutionType>,
+    pub name: String,
+    pub version: String,
+    // Only members have a maintainer
+    pub maintainer: Option<String>,
+    // The names of the crates this crate depends on, each of which is also in the snapshot
+    pub dependencies: Vec<String>,
+    // As above, but only for members whose tests are built
+    #[serde(default)]
+    pub dev_dependencies: Vec<String>,
+    // Whether the crate is only in the snapshot to build the tests of members
+    #[serde(default)]
+    pub dev_only: bool,
+    // Features to enable (alongside the defaults) when verifying a member
+    #[serde(default)]
+    pub features: Vec<String>,
+    pub resolution_type: Option<ResolutionType>,
   }
 
-  #[derive(Debug, Clone, Serialize, Deserialize)]
+  // Exactly one of these should be set
+  #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
   pub struct ResolutionType {
-    crates_io: Option<bool>,
-    git: Option<GitResolution>,
+    pub crates_io: Option<bool>,
+    pub git: Option<GitResolution>,
+    // A local directory containing the crate
+    pub path: Option<String>,
   }
 
-  #[derive(Debug, Clone, Serialize, Deserialize)]
+  #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
   pub struct GitResolution {
-    repository: String,
-    revision: String,
+    pub repository: String,
+    pub revision: String,
+  }
+
+  pub mod testing {
+    use snapshot::CrateSnapshot;
+    use snapshot::ResolutionType;
+
+    /** Constructs a crates.io resolved, non-member crate depending on each named crate. */
+    pub fn crate_snapshot(name: &str, version: &str, dependencies: &[&str]) -> CrateSnapshot {
+      CrateSnapshot {
+        name: name.to_owned(),
+        version: version.to_owned(),
+        maintainer: None,
+        dependencies: dependencies.iter().map(|d| d.to_string()).collect(),
+        dev_dependencies: Vec::new(),
+        dev_only: false,
+        features: Vec::new(),
+        resolution_type: Some(ResolutionType {
+          crates_io: Some(true),
+          git: None,
+          path: None,
+        }),
+      }
+    }
   }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WorkspaceMetadata {
-  last_index_time: Option<DateTime<Utc>>,
-  crates_io_index_revision: String,
+  // When the local indexes were last synced
+  pub last_index_time: Option<DateTime<Utc>>,
+  // The commit of the crates.io index that was checked out by that sync
+  pub crates_io_index_revision: String,
 }
 
 
@@ -216,6 +355,13 @@ macro_rules! define_from_error_boilerplate {
   }
 }
 
+// Declared after the macros above, which they rely on
+pub mod configuration;
+pub mod lockfile;
+pub mod signing;
+pub mod snapshot_store;
+pub mod verification;
+
 #[cfg(test)]
 mod tests {
   #[test]
@@ -0,0 +1,390 @@
+use cargo::DeclaredLicense;
+use cargo::DetectedLicenseFile;
+use cargo::LicenseConfidence;
+
+/** License identifiers from the SPDX license list that are in common use among crates. */
+const KNOWN_SPDX_LICENSE_IDS: &'static [&'static str] = &[
+  "0BSD", "AFL-3.0", "AGPL-3.0", "AGPL-3.0-only", "AGPL-3.0-or-later", "Apache-1.1", "Apache-2.0",
+  "Artistic-2.0", "BSD-2-Clause", "BSD-3-Clause", "BSD-4-Clause", "BSL-1.0", "CC-BY-3.0",
+  "CC-BY-4.0", "CC-BY-SA-4.0", "CC0-1.0", "CDDL-1.0", "EPL-1.0", "EPL-2.0", "EUPL-1.2", "GPL-2.0",
+  "GPL-2.0-only", "GPL-2.0-or-later", "GPL-3.0", "GPL-3.0-only", "GPL-3.0-or-later", "ISC",
+  "LGPL-2.0", "LGPL-2.1", "LGPL-2.1-only", "LGPL-2.1-or-later", "LGPL-3.0", "LGPL-3.0-only",
+  "LGPL-3.0-or-later", "MIT", "MIT-0", "MPL-1.1", "MPL-2.0", "MS-PL", "NCSA", "OFL-1.1", "OpenSSL",
+  "PostgreSQL", "Python-2.0", "Unicode-DFS-2016", "Unlicense", "UPL-1.0", "WTFPL", "X11", "Zlib",
+  "zlib-acknowledgement",
+];
+
+/** License exception identifiers from the SPDX exception list, for use after WITH. */
+const KNOWN_SPDX_EXCEPTION_IDS: &'static [&'static str] = &[
+  "Autoconf-exception-3.0", "Bison-exception-2.2", "Classpath-exception-2.0", "GCC-exception-3.1",
+  "LLVM-exception", "OpenSSL-exception",
+];
+
+/** Informal spellings of licenses seen in the wild, and the SPDX identifier they mean. */
+const LICENSE_ALIASES: &'static [(&'static str, &'static str)] = &[
+  ("apache2", "Apache-2.0"), ("apache-2", "Apache-2.0"), ("apache2.0", "Apache-2.0"),
+  ("asl2", "Apache-2.0"), ("asl-2.0", "Apache-2.0"), ("bsd2", "BSD-2-Clause"),
+  ("bsd-2", "BSD-2-Clause"), ("bsd3", "BSD-3-Clause"), ("bsd-3", "BSD-3-Clause"),
+  ("boost", "BSL-1.0"), ("cc0", "CC0-1.0"), ("gplv2", "GPL-2.0"), ("gpl2", "GPL-2.0"),
+  ("gpl-2", "GPL-2.0"), ("gplv3", "GPL-3.0"), ("gpl3", "GPL-3.0"), ("gpl-3", "GPL-3.0"),
+  ("lgplv2.1", "LGPL-2.1"), ("lgplv3", "LGPL-3.0"), ("lgpl3", "LGPL-3.0"), ("mpl2", "MPL-2.0"),
+  ("mpl-2", "MPL-2.0"), ("mpl2.0", "MPL-2.0"), ("unlicensed", "Unlicense"),
+];
+
+/** Multi-word spellings that are rewritten before tokenizing the expression. */
+const PHRASE_ALIASES: &'static [(&'static str, &'static str)] = &[
+  ("apache license, version 2.0", "Apache-2.0"), ("apache license version 2.0", "Apache-2.0"),
+  ("apache license 2.0", "Apache-2.0"), ("apache 2.0", "Apache-2.0"), ("mit license", "MIT"),
+  ("mozilla public license 2.0", "MPL-2.0"), ("boost software license 1.0", "BSL-1.0"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ExpressionToken {
+  License(String),
+  Exception(String),
+  And,
+  Or,
+  With,
+  Open,
+  Close,
+}
+
+/**
+ * Interprets a `license` field from a Cargo.toml as an SPDX license expression.
+ *
+ * Expressions that are already valid are kept verbatim with Exact confidence. Common deviations
+ * (lowercase identifiers or operators, "/" as a separator for dual licensing, informal names like
+ * "Apache 2.0") are rewritten into a valid expression with Normalized confidence. Anything that
+ * still contains an unrecognized identifier, or is not a well formed expression, is kept with no
+ * SPDX expression at all.
+ */
+pub fn normalize_declared_license(raw: &str) -> DeclaredLicense {
+  let spdx_expression = tokenize_expression(raw)
+    .and_then(|tokens| {
+      if is_well_formed(&tokens) {
+        Some(render_expression(&tokens))
+      } else {
+        None
+      }
+    });
+
+  let confidence = match spdx_expression {
+    Some(ref expression) if expression == raw => LicenseConfidence::Exact,
+    Some(_) => LicenseConfidence::Normalized,
+    None => LicenseConfidence::Unknown,
+  };
+
+  DeclaredLicense {
+    raw: raw.to_owned(),
+    spdx_expression: spdx_expression,
+    confidence: confidence,
+  }
+}
+
+/** Splits an expression into tokens, resolving every identifier to its canonical SPDX form. */
+fn tokenize_expression(raw: &str) -> Option<Vec<ExpressionToken>> {
+  let mut rewritten = raw.to_owned();
+  for &(phrase, id) in PHRASE_ALIASES.iter() {
+    rewritten = replace_ignoring_ascii_case(&rewritten, phrase, id);
+  }
+
+  let spaced = rewritten
+    .replace("(", " ( ")
+    .replace(")", " ) ")
+    .replace("/", " OR ")
+    .replace(",", " OR ");
+
+  let mut tokens = Vec::new();
+  for word in spaced.split_whitespace() {
+    let token = match word.to_lowercase().as_str() {
+      "(" => ExpressionToken::Open,
+      ")" => ExpressionToken::Close,
+      "and" => ExpressionToken::And,
+      "or" => ExpressionToken::Or,
+      "with" => ExpressionToken::With,
+      _ if tokens.last() == Some(&ExpressionToken::With) => match canonical_exception_id(word) {
+        Some(id) => ExpressionToken::Exception(id),
+        None => return None,
+      },
+      _ => match canonical_license_id(word) {
+        Some(id) => ExpressionToken::License(id),
+        None => return None,
+      },
+    };
+    tokens.push(token);
+  }
+
+  if tokens.is_empty() {
+    return None
+  }
+  Some(tokens)
+}
+
+/** Replaces each occurrence of a lowercase phrase, regardless of how it is cased in the text. */
+fn replace_ignoring_ascii_case(text: &str, lowercase_phrase: &str, replacement: &str) -> String {
+  let lowercase_text = text.to_ascii_lowercase();
+  let mut replaced = String::new();
+  let mut remaining_start = 0;
+  while let Some(offset) = lowercase_text[remaining_start..].find(lowercase_phrase) {
+    let phrase_start = remaining_start + offset;
+    replaced.push_str(&text[remaining_start..phrase_start]);
+    replaced.push_str(replacement);
+    remaining_start = phrase_start + lowercase_phrase.len();
+  }
+  replaced.push_str(&text[remaining_start..]);
+  replaced
+}
+
+/** Resolves a (possibly miscased or informal) license identifier, honoring a trailing "+". */
+fn canonical_license_id(word: &str) -> Option<String> {
+  let (base, plus) = if word.ends_with('+') {
+    (&word[0..word.len() - 1], "+")
+  } else {
+    (word, "")
+  };
+  let lower_base = base.to_lowercase();
+
+  if lower_base.starts_with("licenseref-") {
+    return Some(format!("LicenseRef-{}{}", &base["licenseref-".len()..], plus))
+  }
+
+  for id in KNOWN_SPDX_LICENSE_IDS.iter() {
+    if id.to_lowercase() == lower_base {
+      return Some(format!("{}{}", id, plus))
+    }
+  }
+  for &(alias, id) in LICENSE_ALIASES.iter() {
+    if alias == lower_base {
+      return Some(format!("{}{}", id, plus))
+    }
+  }
+  None
+}
+
+fn canonical_exception_id(word: &str) -> Option<String> {
+  let lower_word = word.to_lowercase();
+  KNOWN_SPDX_EXCEPTION_IDS.iter()
+    .find(|id| id.to_lowercase() == lower_word)
+    .map(|id| (*id).to_owned())
+}
+
+/**
+ * Checks that the tokens form an expression matching:
+ *   expression := term ((AND | OR) term)*
+ *   term := LICENSE [WITH EXCEPTION] | "(" expression ")"
+ */
+fn is_well_formed(tokens: &[ExpressionToken]) -> bool {
+  let mut position = 0;
+  parse_expression(tokens, &mut position) && position == tokens.len()
+}
+
+fn parse_expression(tokens: &[ExpressionToken], position: &mut usize) -> bool {
+  if !parse_term(tokens, position) {
+    return false
+  }
+  while *position < tokens.len() {
+    match tokens[*position] {
+      ExpressionToken::And | ExpressionToken::Or => {
+        *position = *position + 1;
+        if !parse_term(tokens, position) {
+          return false
+        }
+      },
+      _ => return true,
+    }
+  }
+  true
+}
+
+fn parse_term(tokens: &[ExpressionToken], position: &mut usize) -> bool {
+  match tokens.get(*position) {
+    Some(&ExpressionToken::License(_)) => {
+      *position = *position + 1;
+      if tokens.get(*position) == Some(&ExpressionToken::With) {
+        match tokens.get(*position + 1) {
+          Some(&ExpressionToken::Exception(_)) => *position = *position + 2,
+          _ => return false,
+        }
+      }
+      true
+    },
+    Some(&ExpressionToken::Open) => {
+      *position = *position + 1;
+      if !parse_expression(tokens, position) {
+        return false
+      }
+      if tokens.get(*position) != Some(&ExpressionToken::Close) {
+        return false
+      }
+      *position = *position + 1;
+      true
+    },
+    _ => false,
+  }
+}
+
+fn render_expression(tokens: &[ExpressionToken]) -> String {
+  let mut rendered = String::new();
+  for (idx, token) in tokens.iter().enumerate() {
+    let previous_was_open = idx > 0 && tokens[idx - 1] == ExpressionToken::Open;
+    let needs_space = idx > 0 && !previous_was_open && *token != ExpressionToken::Close;
+    if needs_space {
+      rendered.push(' ');
+    }
+    match *token {
+      ExpressionToken::License(ref id) | ExpressionToken::Exception(ref id) => rendered.push_str(id),
+      ExpressionToken::And => rendered.push_str("AND"),
+      ExpressionToken::Or => rendered.push_str("OR"),
+      ExpressionToken::With => rendered.push_str("WITH"),
+      ExpressionToken::Open => rendered.push('('),
+      ExpressionToken::Close => rendered.push(')'),
+    }
+  }
+  rendered
+}
+
+/** Phrases that identify a license text, checked in order against whitespace-collapsed text. */
+const LICENSE_TEXT_MARKERS: &'static [(&'static [&'static str], &'static str)] = &[
+  (&["gnu affero general public license"], "AGPL-3.0"),
+  (&["gnu lesser general public license", "version 3"], "LGPL-3.0"),
+  (&["gnu lesser general public license", "version 2.1"], "LGPL-2.1"),
+  (&["gnu general public license", "version 3"], "GPL-3.0"),
+  (&["gnu general public license", "version 2"], "GPL-2.0"),
+  (&["apache license", "version 2.0"], "Apache-2.0"),
+  (&["mozilla public license version 2.0"], "MPL-2.0"),
+  (&["mozilla public license, version 2.0"], "MPL-2.0"),
+  (&["boost software license - version 1.0"], "BSL-1.0"),
+  (&["this is free and unencumbered software released into the public domain"], "Unlicense"),
+  (&["cc0 1.0 universal"], "CC0-1.0"),
+  (&["do what the fuck you want to public license"], "WTFPL"),
+  (&["permission is hereby granted, free of charge, to any person obtaining a copy"], "MIT"),
+  (&["permission to use, copy, modify, and/or distribute this software for any purpose"], "ISC"),
+  (&["redistribution and use in source and binary forms", "neither the name"], "BSD-3-Clause"),
+  (&["redistribution and use in source and binary forms"], "BSD-2-Clause"),
+  (&["provided 'as-is', without any express or implied warranty"], "Zlib"),
+];
+
+/** Whether the file name looks like it holds license text (LICENSE-MIT, COPYING, etc). */
+pub fn is_license_file_name(file_name: &str) -> bool {
+  let upper_name = file_name.to_uppercase();
+  ["LICENSE", "LICENCE", "COPYING", "UNLICENSE", "COPYRIGHT"].iter()
+    .any(|prefix| upper_name.starts_with(prefix))
+}
+
+/** Guesses the license from a file name suffix, such as LICENSE-APACHE. */
+fn license_id_from_file_name(file_name: &str) -> Option<String> {
+  let upper_name = file_name.to_uppercase();
+  if upper_name.starts_with("UNLICENSE") {
+    return Some("Unlicense".to_owned())
+  }
+
+  let stem = upper_name.split('.').next().unwrap_or("");
+  let suffix = match stem.find(|c| c == '-' || c == '_') {
+    Some(idx) => &stem[idx + 1..],
+    None => return None,
+  };
+  match suffix {
+    "APACHE" | "APACHE2" | "APACHE-2.0" => Some("Apache-2.0".to_owned()),
+    "BOOST" => Some("BSL-1.0".to_owned()),
+    _ => canonical_license_id(suffix),
+  }
+}
+
+/**
+ * Identifies the license within a license file.
+ *
+ * A match on the license text itself yields High confidence. Failing that, a license named by the
+ * file name (as in LICENSE-MIT) yields Low confidence.
+ */
+pub fn detect_license_file(path_in_crate: &str, contents: &str) -> DetectedLicenseFile {
+  let collapsed = contents.split_whitespace()
+    .collect::<Vec<_>>()
+    .join(" ")
+    .to_lowercase();
+  let from_text = LICENSE_TEXT_MARKERS.iter()
+    .find(|&&(markers, _)| markers.iter().all(|marker| collapsed.contains(marker)))
+    .map(|&(_, id)| id.to_owned());
+
+  let file_name = path_in_crate.rsplit('/').next().unwrap_or(path_in_crate);
+  let (spdx_id, confidence) = match from_text {
+    Some(id) => (Some(id), LicenseConfidence::High),
+    None => match license_id_from_file_name(file_name) {
+      Some(id) => (Some(id), LicenseConfidence::Low),
+      None => (None, LicenseConfidence::Unknown),
+    },
+  };
+
+  DetectedLicenseFile {
+    path: path_in_crate.to_owned(),
+    spdx_id: spdx_id,
+    confidence: confidence,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use cargo::LicenseConfidence;
+  use license;
+
+  fn normalized(raw: &str) -> (Option<String>, LicenseConfidence) {
+    let declared = license::normalize_declared_license(raw);
+    (declared.spdx_expression, declared.confidence)
+  }
+
+  #[test]
+  fn test_valid_expressions_are_exact() {
+    assert_eq!(normalized("MIT"), (Some("MIT".to_owned()), LicenseConfidence::Exact));
+    assert_eq!(normalized("MIT OR Apache-2.0"),
+               (Some("MIT OR Apache-2.0".to_owned()), LicenseConfidence::Exact));
+    assert_eq!(normalized("(MIT OR Apache-2.0) AND BSD-3-Clause"),
+               (Some("(MIT OR Apache-2.0) AND BSD-3-Clause".to_owned()), LicenseConfidence::Exact));
+    assert_eq!(normalized("Apache-2.0 WITH LLVM-exception"),
+               (Some("Apache-2.0 WITH LLVM-exception".to_owned()), LicenseConfidence::Exact));
+  }
+
+  #[test]
+  fn test_common_deviations_are_normalized() {
+    assert_eq!(normalized("MIT/Apache-2.0"),
+               (Some("MIT OR Apache-2.0".to_owned()), LicenseConfidence::Normalized));
+    assert_eq!(normalized("mit or apache-2.0"),
+               (Some("MIT OR Apache-2.0".to_owned()), LicenseConfidence::Normalized));
+    assert_eq!(normalized("Apache 2.0"),
+               (Some("Apache-2.0".to_owned()), LicenseConfidence::Normalized));
+    assert_eq!(normalized("GPLv3+"),
+               (Some("GPL-3.0+".to_owned()), LicenseConfidence::Normalized));
+  }
+
+  #[test]
+  fn test_unrecognized_expressions_have_no_spdx_form() {
+    assert_eq!(normalized("Proprietary"), (None, LicenseConfidence::Unknown));
+    assert_eq!(normalized("MIT OR"), (None, LicenseConfidence::Unknown));
+    assert_eq!(normalized("(MIT"), (None, LicenseConfidence::Unknown));
+    assert_eq!(normalized(""), (None, LicenseConfidence::Unknown));
+  }
+
+  #[test]
+  fn test_license_files_are_detected_by_text_then_name() {
+    let mit = license::detect_license_file(
+      "LICENSE-MIT",
+      "Permission is hereby granted, free of charge, to any\n  person obtaining a copy of this software");
+    assert_eq!(mit.spdx_id, Some("MIT".to_owned()));
+    assert_eq!(mit.confidence, LicenseConfidence::High);
+
+    let apache = license::detect_license_file("LICENSE-APACHE", "See the website.");
+    assert_eq!(apache.spdx_id, Some("Apache-2.0".to_owned()));
+    assert_eq!(apache.confidence, LicenseConfidence::Low);
+
+    let unknown = license::detect_license_file("COPYING", "All rights reserved.");
+    assert_eq!(unknown.spdx_id, None);
+    assert_eq!(unknown.confidence, LicenseConfidence::Unknown);
+  }
+
+  #[test]
+  fn test_license_file_names_are_recognized() {
+    assert!(license::is_license_file_name("LICENSE"));
+    assert!(license::is_license_file_name("license-mit.txt"));
+    assert!(license::is_license_file_name("COPYING"));
+    assert!(license::is_license_file_name("UNLICENSE"));
+    assert!(!license::is_license_file_name("README.md"));
+  }
+}
@@ -0,0 +1,558 @@
+use cargo::DependencyEntry;
+use index::IndexEntrySource;
+use manifest::CargoManifest;
+use manifest::DependencyKind;
+use manifest::ManifestDependency;
+use resolver::candidates;
+use semver::Version;
+use semver::VersionReq;
+use snapshot::CrateSnapshot;
+use snapshot::ResolutionType;
+use snapshot::WorkspaceSnapshot;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Read;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use toml;
+
+/** The source that Cargo records for crates from the crates.io index. */
+pub const CRATES_IO_SOURCE: &'static str = "registry+https://github.com/rust-lang/crates.io-index";
+
+/** A single [[package]] of a Cargo.lock. */
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LockedPackage {
+  pub name: String,
+  pub version: String,
+  // Only registry packages have a source
+  pub source: Option<String>,
+  pub checksum: Option<String>,
+  // The ids of the packages this package depends on
+  pub dependencies: BTreeSet<String>,
+}
+
+/** The contents of a Cargo.lock, with packages in name (then version) order. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lockfile {
+  pub packages: Vec<LockedPackage>,
+}
+
+/** A Cargo.lock as written to disk, before checksums are joined with their packages. */
+#[derive(Debug, Clone, Deserialize)]
+struct RawLockfile {
+  // Older versions of Cargo write the root package separately
+  root: Option<RawLockedPackage>,
+  package: Option<Vec<RawLockedPackage>>,
+  metadata: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct RawLockedPackage {
+  name: String,
+  version: String,
+  source: Option<String>,
+  #[serde(default)]
+  dependencies: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum LockfileErr {
+  IoErr(io::Error),
+  TomlErr(toml::de::Error),
+  // The manifest at this path has no [package] section
+  MissingPackage(PathBuf),
+  // Everything the project needs that the snapshot does not provide, one problem per line
+  OutsideSnapshot(Vec<String>),
+}
+
+define_from_error_boilerplate!(io::Error, LockfileErr, LockfileErr::IoErr);
+define_from_error_boilerplate!(toml::de::Error, LockfileErr, LockfileErr::TomlErr);
+
+impl LockedPackage {
+  /** The id that Cargo uses to refer to the package: "$NAME $VERSION", then " ($SOURCE)" if any. */
+  pub fn get_id(&self) -> String {
+    get_package_id(&self.name, &self.version, self.source.as_ref().map(|s| s.as_str()))
+  }
+}
+
+impl Lockfile {
+  /** Parses the contents of a Cargo.lock. */
+  pub fn from_str(contents: &str) -> Result<Lockfile, LockfileErr> {
+    let raw_lockfile = try!(toml::from_str::<RawLockfile>(contents));
+    let metadata = raw_lockfile.metadata.unwrap_or_default();
+    let mut packages = raw_lockfile.root.into_iter()
+      .chain(raw_lockfile.package.unwrap_or_default().into_iter())
+      .map(|raw_package| {
+        let mut package = LockedPackage {
+          name: raw_package.name,
+          version: raw_package.version,
+          source: raw_package.source,
+          checksum: None,
+          dependencies: raw_package.dependencies.into_iter().collect(),
+        };
+        // Cargo writes "<none>" for packages that it could not checksum
+        package.checksum = match metadata.get(&format!("checksum {}", package.get_id())) {
+          Some(checksum) if checksum != "<none>" => Some(checksum.clone()),
+          _ => None,
+        };
+        package
+      })
+      .collect::<Vec<_>>();
+    packages.sort();
+    Ok(Lockfile {
+      packages: packages,
+    })
+  }
+
+  /** Renders the lockfile the way that Cargo writes it. */
+  pub fn to_cargo_lock_string(&self) -> String {
+    let mut lines = Vec::new();
+    for package in self.packages.iter() {
+      lines.push("[[package]]".to_owned());
+      lines.push(format!("name = {}", quote(&package.name)));
+      lines.push(format!("version = {}", quote(&package.version)));
+      if let Some(ref source) = package.source {
+        lines.push(format!("source = {}", quote(source)));
+      }
+      if !package.dependencies.is_empty() {
+        lines.push("dependencies = [".to_owned());
+        lines.extend(package.dependencies.iter().map(|id| format!(" {},", quote(id))));
+        lines.push("]".to_owned());
+      }
+      lines.push(String::new());
+    }
+
+    let checksummed_packages = self.packages.iter()
+      .filter(|package| package.checksum.is_some())
+      .collect::<Vec<_>>();
+    if !checksummed_packages.is_empty() {
+      lines.push("[metadata]".to_owned());
+      for package in checksummed_packages.into_iter() {
+        lines.push(format!("{} = {}",
+                           quote(&format!("checksum {}", package.get_id())),
+                           quote(package.checksum.as_ref().unwrap())));
+      }
+    }
+    lines.push(String::new());
+    lines.join("\n")
+  }
+}
+
+/**
+ * Generates the lockfile for the workspace whose members (each with its directory) are provided,
+ * pinning every registry package to its version in the snapshot.
+ *
+ * The members' dev dependencies are included, and path dependencies are read from disk (relative
+ * to the member that declares them) and locked as well. As in Cargo, local packages are locked with every
+ * feature enabled. Registry packages depend on whatever they need to build with the union of the
+ * features that their dependents ask of them, and their checksums are taken from the index.
+ *
+ * Fails if any package needs a crate that is not in the snapshot (or a version that the snapshot
+ * does not pin), listing every such problem.
+ */
+pub fn generate_lockfile(members: &[(PathBuf, CargoManifest)],
+                         snapshot: &WorkspaceSnapshot,
+                         index: &IndexEntrySource) -> Result<Lockfile, LockfileErr> {
+  let mut generator = LockfileGenerator {
+    pins: snapshot.details.iter().map(|details| (details.name.as_str(), details)).collect(),
+    index: index,
+    packages: Vec::new(),
+    registry_packages: BTreeMap::new(),
+    pending_registry_crates: Vec::new(),
+    feature_requests: HashMap::new(),
+    visited_local_packages: HashSet::new(),
+    problems: Vec::new(),
+  };
+
+  // Members are locked with their dev dependencies, even when another member depends on them
+  for &(ref directory, ref manifest) in members.iter() {
+    let package = try!(manifest.get_package()
+      .ok_or_else(|| LockfileErr::MissingPackage(directory.join("Cargo.toml"))));
+    generator.visited_local_packages.insert(package.name.clone());
+  }
+  for &(ref directory, ref manifest) in members.iter() {
+    try!(generator.lock_local_package(manifest, directory, true /* include_dev */));
+  }
+  try!(generator.lock_registry_packages());
+  if !generator.problems.is_empty() {
+    return Err(LockfileErr::OutsideSnapshot(generator.problems))
+  }
+
+  let mut packages = generator.packages;
+  packages.extend(generator.registry_packages.into_iter().map(|(_, package)| package));
+  packages.sort();
+  Ok(Lockfile {
+    packages: packages,
+  })
+}
+
+/** Reads the Cargo.toml in the provided directory. */
+pub fn read_manifest<P: AsRef<Path>>(directory: P) -> Result<CargoManifest, LockfileErr> {
+  let mut contents = String::new();
+  try!(File::open(directory.as_ref().join("Cargo.toml")).and_then(|mut f| f.read_to_string(&mut contents)));
+  Ok(try!(CargoManifest::from_str(&contents)))
+}
+
+struct LockfileGenerator<'a> {
+  pins: HashMap<&'a str, &'a CrateSnapshot>,
+  index: &'a IndexEntrySource,
+  // Local packages
+  packages: Vec<LockedPackage>,
+  // Registry packages, which are locked again whenever they are asked for more features
+  registry_packages: BTreeMap<String, LockedPackage>,
+  pending_registry_crates: Vec<String>,
+  feature_requests: HashMap<String, FeatureRequest>,
+  visited_local_packages: HashSet<String>,
+  problems: Vec<String>,
+}
+
+/** The union of the features that dependents ask of a registry crate. */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct FeatureRequest {
+  default_features: bool,
+  features: BTreeSet<String>,
+}
+
+impl<'a> LockfileGenerator<'a> {
+  /** Locks a package that lives on disk, and (recursively) its path dependencies. */
+  fn lock_local_package(&mut self, manifest: &CargoManifest, directory: &Path, include_dev: bool) -> Result<(), LockfileErr> {
+    let package = try!(manifest.get_package()
+      .ok_or_else(|| LockfileErr::MissingPackage(directory.join("Cargo.toml"))));
+    self.visited_local_packages.insert(package.name.clone());
+
+    // Every feature is enabled, so every "dep/feature" applies
+    let dependency_features = manifest.features.iter()
+      .flat_map(|features| features.values())
+      .flat_map(|members| members.iter())
+      .filter_map(|member| member.find('/').map(|slash_idx| (&member[0..slash_idx], &member[slash_idx + 1..])))
+      .collect::<Vec<_>>();
+
+    let mut kinds = vec![DependencyKind::Normal, DependencyKind::Build];
+    if include_dev {
+      kinds.push(DependencyKind::Dev);
+    }
+
+    let mut dependencies = BTreeSet::new();
+    for kind in kinds.into_iter() {
+      for (name, dependency, _) in manifest.get_dependencies(kind).into_iter() {
+        match *dependency {
+          ManifestDependency::Detailed(ref details) if details.path.is_some() => {
+            let dependency_directory = directory.join(details.path.as_ref().unwrap());
+            let dependency_manifest = try!(read_manifest(&dependency_directory));
+            let dependency_package = try!(dependency_manifest.get_package()
+              .ok_or_else(|| LockfileErr::MissingPackage(dependency_directory.join("Cargo.toml"))));
+            dependencies.insert(get_package_id(&dependency_package.name, &dependency_package.version, None));
+            if !self.visited_local_packages.contains(&dependency_package.name) {
+              try!(self.lock_local_package(&dependency_manifest, &dependency_directory, false /* include_dev */));
+            }
+          },
+          ManifestDependency::Detailed(ref details) if details.git.is_some() => {
+            self.add_problem(format!("{} depends on {} from git, which the snapshot cannot pin",
+                                     package.name,
+                                     name));
+          },
+          _ => {
+            let entry = dependency.to_dependency_entry(name, kind, None);
+            let extra_features = dependency_features.iter()
+              .filter(|&&(dependency_name, _)| dependency_name == name.as_str())
+              .map(|&(_, feature)| feature.to_owned())
+              .collect::<Vec<_>>();
+            if let Some(id) = self.lock_registry_dependency(&package.name, &entry, &extra_features) {
+              dependencies.insert(id);
+            }
+          },
+        }
+      }
+    }
+
+    self.packages.push(LockedPackage {
+      name: package.name.clone(),
+      version: package.version.clone(),
+      source: None,
+      checksum: None,
+      dependencies: dependencies,
+    });
+    Ok(())
+  }
+
+  /**
+   * Locks every registry crate that has been depended on, and everything they depend on with the
+   * features that are asked of them.
+   */
+  fn lock_registry_packages(&mut self) -> Result<(), LockfileErr> {
+    while let Some(crate_name) = self.pending_registry_crates.pop() {
+      let pin = match self.pins.get(crate_name.as_str()).cloned() {
+        Some(pin) => pin,
+        None => continue,
+      };
+      let entry = match try!(self.index.get_entries(&crate_name)).into_iter().find(|e| e.vers == pin.version) {
+        Some(entry) => entry,
+        None => {
+          self.add_problem(format!("The snapshot pins {} {}, but the index has no such version",
+                                   crate_name,
+                                   pin.version));
+          continue
+        },
+      };
+
+      let request = self.feature_requests.get(&crate_name).cloned().unwrap_or_default();
+      let features = request.features.into_iter().collect::<Vec<_>>();
+      let mut dependencies = BTreeSet::new();
+      for (dependency, extra_features) in candidates::get_requested_dependencies(&entry,
+                                                                                 request.default_features,
+                                                                                 &features).into_iter() {
+        if let Some(id) = self.lock_registry_dependency(&crate_name, dependency, &extra_features) {
+          dependencies.insert(id);
+        }
+      }
+      self.registry_packages.insert(crate_name.clone(), LockedPackage {
+        name: crate_name.clone(),
+        version: pin.version.clone(),
+        source: Some(CRATES_IO_SOURCE.to_owned()),
+        checksum: Some(entry.cksum.clone()),
+        dependencies: dependencies,
+      });
+    }
+    Ok(())
+  }
+
+  /**
+   * Yields the id of the snapshot's pin for a dependency, and queues the pin to be locked (again,
+   * if the dependency asks for features that it was not locked with).
+   *
+   * Records a problem instead if the snapshot has no usable pin that satisfies the requirement.
+   */
+  fn lock_registry_dependency(&mut self,
+                              dependent: &str,
+                              dependency: &DependencyEntry,
+                              extra_features: &[String]) -> Option<String> {
+    let crate_name = dependency.name.as_str();
+    let raw_req = dependency.req.as_str();
+    let pin = match self.pins.get(crate_name).cloned() {
+      Some(pin) => pin,
+      None => {
+        self.add_problem(format!("{} depends on {} {}, which is not in the snapshot", dependent, crate_name, raw_req));
+        return None
+      },
+    };
+    match pin.resolution_type {
+      Some(ResolutionType { git: Some(_), .. }) => {
+        self.add_problem(format!("{} depends on {}, which the snapshot resolves from git", dependent, crate_name));
+        return None
+      },
+      Some(ResolutionType { path: Some(_), .. }) => {
+        self.add_problem(format!("{} depends on {}, which the snapshot resolves from a local path",
+                                 dependent,
+                                 crate_name));
+        return None
+      },
+      _ => {},
+    }
+
+    let satisfied = match (VersionReq::parse(raw_req), Version::parse(&pin.version)) {
+      (Ok(req), Ok(version)) => req.matches(&version),
+      _ => false,
+    };
+    if !satisfied {
+      self.add_problem(format!("{} depends on {} {}, but the snapshot pins {} {}",
+                               dependent,
+                               crate_name,
+                               raw_req,
+                               crate_name,
+                               pin.version));
+      return None
+    }
+
+    let is_new_crate = !self.feature_requests.contains_key(crate_name);
+    let request = self.feature_requests.entry(crate_name.to_owned()).or_insert_with(FeatureRequest::default);
+    let previous_request = request.clone();
+    request.default_features |= dependency.default_features;
+    request.features.extend(dependency.features.iter().chain(extra_features.iter()).cloned());
+    if (is_new_crate || *request != previous_request) && !self.pending_registry_crates.iter().any(|c| c == crate_name) {
+      self.pending_registry_crates.push(crate_name.to_owned());
+    }
+    Some(get_package_id(crate_name, &pin.version, Some(CRATES_IO_SOURCE)))
+  }
+
+  /** Records a problem, unless it was already found (such as when a crate is locked again). */
+  fn add_problem(&mut self, problem: String) {
+    if !self.problems.contains(&problem) {
+      self.problems.push(problem);
+    }
+  }
+}
+
+fn get_package_id(name: &str, version: &str, source: Option<&str>) -> String {
+  match source {
+    Some(source) => format!("{} {} ({})", name, version, source),
+    None => format!("{} {}", name, version),
+  }
+}
+
+fn quote(raw: &str) -> String {
+  format!("\"{}\"", raw.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+  use index::testing::FakeIndex;
+  use index::testing::index_entry;
+  use lockfile::Lockfile;
+  use lockfile::LockfileErr;
+  use lockfile;
+  use manifest::CargoManifest;
+  use snapshot::WorkspaceSnapshot;
+  use snapshot::testing::crate_snapshot;
+  use std::path::PathBuf;
+
+  #[test]
+  fn test_pins_the_project_to_the_snapshot() {
+    let snapshot = WorkspaceSnapshot {
+      version: "lts-1.0".to_owned(),
+      members: vec!["memchr".to_owned()],
+      details: vec![crate_snapshot("libc", "0.2.30", &[]), crate_snapshot("memchr", "2.0.1", &[])],
+    };
+    let index = FakeIndex::new(vec![
+      index_entry("libc", "0.2.29", &[]),
+      index_entry("libc", "0.2.30", &[]),
+      index_entry("memchr", "2.0.1", &[("libc", "^0.2")]),
+    ]);
+    let manifest = CargoManifest::from_str(r#"
+      [package]
+      name = "example"
+      version = "0.1.0"
+
+      [dependencies]
+      memchr = "2.0"
+    "#).unwrap();
+
+    let lockfile = lockfile::generate_lockfile(&[(PathBuf::from("."), manifest)], &snapshot, &index).unwrap();
+    assert_eq!(lockfile.to_cargo_lock_string(), r#"[[package]]
+name = "example"
+version = "0.1.0"
+dependencies = [
+ "memchr 2.0.1 (registry+https://github.com/rust-lang/crates.io-index)",
+]
+
+[[package]]
+name = "libc"
+version = "0.2.30"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "memchr"
+version = "2.0.1"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+dependencies = [
+ "libc 0.2.30 (registry+https://github.com/rust-lang/crates.io-index)",
+]
+
+[metadata]
+"checksum libc 0.2.30 (registry+https://github.com/rust-lang/crates.io-index)" = "libc-0.2.30-checksum"
+"checksum memchr 2.0.1 (registry+https://github.com/rust-lang/crates.io-index)" = "memchr-2.0.1-checksum"
+"#);
+    assert_eq!(Lockfile::from_str(&lockfile.to_cargo_lock_string()).unwrap(), lockfile);
+
+    let manifest = CargoManifest::from_str(r#"
+      [package]
+      name = "example"
+      version = "0.1.0"
+
+      [dependencies]
+      libc = "0.3"
+      regex = "0.2"
+    "#).unwrap();
+    match lockfile::generate_lockfile(&[(PathBuf::from("."), manifest)], &snapshot, &index) {
+      Err(LockfileErr::OutsideSnapshot(problems)) => assert_eq!(problems, vec![
+        "example depends on libc 0.3, but the snapshot pins libc 0.2.30".to_owned(),
+        "example depends on regex 0.2, which is not in the snapshot".to_owned(),
+      ]),
+      other => panic!("Expected the project to need crates outside the snapshot, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn test_registry_packages_are_locked_with_the_features_asked_of_them() {
+    let snapshot = WorkspaceSnapshot {
+      version: "lts-1.0".to_owned(),
+      members: vec!["memchr".to_owned()],
+      details: vec![crate_snapshot("memchr", "2.0.1", &[])],
+    };
+    let mut memchr = index_entry("memchr", "2.0.1", &[("libc", "^0.2")]);
+    memchr.deps[0].optional = true;
+    memchr.features.insert("default".to_owned(), vec!["use_std".to_owned()]);
+    memchr.features.insert("use_std".to_owned(), vec!["libc".to_owned()]);
+    let index = FakeIndex::new(vec![memchr]);
+
+    let manifest = CargoManifest::from_str(r#"
+      [package]
+      name = "example"
+      version = "0.1.0"
+
+      [dependencies]
+      memchr = { version = "2.0", default-features = false }
+    "#).unwrap();
+    let lockfile = lockfile::generate_lockfile(&[(PathBuf::from("."), manifest)], &snapshot, &index).unwrap();
+    assert_eq!(lockfile.packages.len(), 2);
+
+    let manifest = CargoManifest::from_str(r#"
+      [package]
+      name = "example"
+      version = "0.1.0"
+
+      [dependencies]
+      memchr = { version = "2.0", default-features = false, features = ["use_std"] }
+    "#).unwrap();
+    match lockfile::generate_lockfile(&[(PathBuf::from("."), manifest)], &snapshot, &index) {
+      Err(LockfileErr::OutsideSnapshot(problems)) => assert_eq!(problems, vec![
+        "memchr depends on libc ^0.2, which is not in the snapshot".to_owned(),
+      ]),
+      other => panic!("Expected the feature to need a crate outside the snapshot, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn test_locks_every_workspace_member_with_its_dev_dependencies() {
+    let snapshot = WorkspaceSnapshot {
+      version: "lts-1.0".to_owned(),
+      members: vec!["libc".to_owned(), "memchr".to_owned()],
+      details: vec![crate_snapshot("libc", "0.2.30", &[]), crate_snapshot("memchr", "2.0.1", &[])],
+    };
+    let index = FakeIndex::new(vec![
+      index_entry("libc", "0.2.30", &[]),
+      index_entry("memchr", "2.0.1", &[]),
+    ]);
+    let app = CargoManifest::from_str(r#"
+      [package]
+      name = "app"
+      version = "0.1.0"
+
+      [dependencies]
+      memchr = "2.0"
+    "#).unwrap();
+    let support = CargoManifest::from_str(r#"
+      [package]
+      name = "support"
+      version = "0.1.0"
+
+      [dev-dependencies]
+      libc = "0.2"
+    "#).unwrap();
+
+    let members = vec![(PathBuf::from("app"), app), (PathBuf::from("support"), support)];
+    let lockfile = lockfile::generate_lockfile(&members, &snapshot, &index).unwrap();
+    let locked = lockfile.packages.iter()
+      .map(|package| (package.name.as_str(), package.dependencies.iter().cloned().collect::<Vec<_>>()))
+      .collect::<Vec<_>>();
+    assert_eq!(locked, vec![
+      ("app", vec!["memchr 2.0.1 (registry+https://github.com/rust-lang/crates.io-index)".to_owned()]),
+      ("libc", vec![]),
+      ("memchr", vec![]),
+      ("support", vec!["libc 0.2.30 (registry+https://github.com/rust-lang/crates.io-index)".to_owned()]),
+    ]);
+  }
+}
@@ -0,0 +1,289 @@
+use cargo::DependencyEntry;
+use cargo::IndexEntry;
+use std::collections::BTreeMap;
+use toml;
+
+/**
+ * The subset of a crate's Cargo.toml that stockpile cares about.
+ *
+ * Cargo's own TomlManifest keeps its fields private, so the fields we need are mirrored here
+ * instead. Unknown keys are ignored.
+ */
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct CargoManifest {
+  pub package: Option<ManifestPackage>,
+  // The legacy name for "package"
+  pub project: Option<ManifestPackage>,
+  pub dependencies: Option<BTreeMap<String, ManifestDependency>>,
+  #[serde(rename = "dev-dependencies")]
+  pub dev_dependencies: Option<BTreeMap<String, ManifestDependency>>,
+  #[serde(rename = "dev_dependencies")]
+  pub dev_dependencies_legacy: Option<BTreeMap<String, ManifestDependency>>,
+  #[serde(rename = "build-dependencies")]
+  pub build_dependencies: Option<BTreeMap<String, ManifestDependency>>,
+  #[serde(rename = "build_dependencies")]
+  pub build_dependencies_legacy: Option<BTreeMap<String, ManifestDependency>>,
+  pub target: Option<BTreeMap<String, ManifestTarget>>,
+  pub features: Option<BTreeMap<String, Vec<String>>>,
+  pub workspace: Option<ManifestWorkspace>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ManifestPackage {
+  pub name: String,
+  pub version: String,
+  pub license: Option<String>,
+  #[serde(rename = "license-file")]
+  pub license_file: Option<String>,
+}
+
+/** The [workspace] section, whose members are paths (or globs) relative to the manifest. */
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct ManifestWorkspace {
+  #[serde(default)]
+  pub members: Vec<String>,
+  #[serde(default)]
+  pub exclude: Vec<String>,
+}
+
+/** The platform specific dependency tables under [target.'cfg(...)']. */
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct ManifestTarget {
+  pub dependencies: Option<BTreeMap<String, ManifestDependency>>,
+  #[serde(rename = "dev-dependencies")]
+  pub dev_dependencies: Option<BTreeMap<String, ManifestDependency>>,
+  #[serde(rename = "dev_dependencies")]
+  pub dev_dependencies_legacy: Option<BTreeMap<String, ManifestDependency>>,
+  #[serde(rename = "build-dependencies")]
+  pub build_dependencies: Option<BTreeMap<String, ManifestDependency>>,
+  #[serde(rename = "build_dependencies")]
+  pub build_dependencies_legacy: Option<BTreeMap<String, ManifestDependency>>,
+}
+
+/** A dependency, either as a bare version requirement or as a detailed table. */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ManifestDependency {
+  Simple(String),
+  Detailed(DetailedManifestDependency),
+}
+
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct DetailedManifestDependency {
+  pub version: Option<String>,
+  pub path: Option<String>,
+  pub git: Option<String>,
+  pub branch: Option<String>,
+  pub tag: Option<String>,
+  pub rev: Option<String>,
+  pub features: Option<Vec<String>>,
+  pub optional: Option<bool>,
+  #[serde(rename = "default-features")]
+  pub default_features: Option<bool>,
+  #[serde(rename = "default_features")]
+  pub default_features_legacy: Option<bool>,
+}
+
+/** The kind of a dependency, spelled the way the crates.io-index spells it. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DependencyKind {
+  Normal,
+  Build,
+  Dev,
+}
+
+impl DependencyKind {
+  pub fn as_index_str(&self) -> &'static str {
+    match *self {
+      DependencyKind::Normal => "normal",
+      DependencyKind::Build => "build",
+      DependencyKind::Dev => "dev",
+    }
+  }
+}
+
+impl CargoManifest {
+  /** Parses the contents of a Cargo.toml. */
+  pub fn from_str(contents: &str) -> Result<CargoManifest, toml::de::Error> {
+    toml::from_str::<CargoManifest>(contents)
+  }
+
+  /** Yields the [package] section, falling back to the legacy [project] section. */
+  pub fn get_package(&self) -> Option<&ManifestPackage> {
+    self.package.as_ref().or(self.project.as_ref())
+  }
+
+  /**
+   * Builds the crates.io-index entry that the crate would have if it were published with the
+   * provided checksum. Yields None if the manifest has no package.
+   */
+  pub fn to_index_entry(&self, cksum: &str) -> Option<IndexEntry> {
+    let package = match self.get_package() {
+      Some(package) => package,
+      None => return None,
+    };
+    let mut deps = Vec::new();
+    for &kind in [DependencyKind::Normal, DependencyKind::Build, DependencyKind::Dev].iter() {
+      deps.extend(self.get_dependency_entries(kind).into_iter());
+    }
+    Some(IndexEntry {
+      name: package.name.clone(),
+      vers: package.version.clone(),
+      deps: deps,
+      cksum: cksum.to_owned(),
+      features: self.features.clone().map(|f| f.into_iter().collect()).unwrap_or_default(),
+      yanked: None,
+    })
+  }
+
+  /** Yields every dependency of the given kind, including platform specific ones. */
+  pub fn get_dependency_entries(&self, kind: DependencyKind) -> Vec<DependencyEntry> {
+    self.get_dependencies(kind).into_iter()
+      .map(|(name, dependency, target)| dependency.to_dependency_entry(name, kind, target.cloned()))
+      .collect()
+  }
+
+  /** As above, but omits path and git dependencies, which no registry index can resolve. */
+  pub fn get_registry_dependency_entries(&self, kind: DependencyKind) -> Vec<DependencyEntry> {
+    self.get_dependencies(kind).into_iter()
+      .filter(|&(_, dependency, _)| dependency.is_from_registry())
+      .map(|(name, dependency, target)| dependency.to_dependency_entry(name, kind, target.cloned()))
+      .collect()
+  }
+
+  /**
+   * Yields every dependency of the given kind as written in the manifest, alongside its name and
+   * the target it is specific to (if any).
+   */
+  pub fn get_dependencies(&self, kind: DependencyKind) -> Vec<(&String, &ManifestDependency, Option<&String>)> {
+    let mut dependencies = Vec::new();
+    let tables = match kind {
+      DependencyKind::Normal => vec![&self.dependencies],
+      DependencyKind::Build => vec![&self.build_dependencies, &self.build_dependencies_legacy],
+      DependencyKind::Dev => vec![&self.dev_dependencies, &self.dev_dependencies_legacy],
+    };
+    for table in tables.into_iter() {
+      append_dependencies(&mut dependencies, table, None);
+    }
+
+    if let Some(ref targets) = self.target {
+      for (target_name, target) in targets.iter() {
+        let tables = match kind {
+          DependencyKind::Normal => vec![&target.dependencies],
+          DependencyKind::Build => vec![&target.build_dependencies, &target.build_dependencies_legacy],
+          DependencyKind::Dev => vec![&target.dev_dependencies, &target.dev_dependencies_legacy],
+        };
+        for table in tables.into_iter() {
+          append_dependencies(&mut dependencies, table, Some(target_name));
+        }
+      }
+    }
+
+    dependencies
+  }
+}
+
+fn append_dependencies<'a>(dependencies: &mut Vec<(&'a String, &'a ManifestDependency, Option<&'a String>)>,
+                           table: &'a Option<BTreeMap<String, ManifestDependency>>,
+                           target: Option<&'a String>) {
+  if let Some(ref table) = *table {
+    for (name, dependency) in table.iter() {
+      dependencies.push((name, dependency, target));
+    }
+  }
+}
+
+impl ManifestDependency {
+  /** Whether the dependency is fetched from a registry, rather than from a path or git. */
+  pub fn is_from_registry(&self) -> bool {
+    match *self {
+      ManifestDependency::Simple(_) => true,
+      ManifestDependency::Detailed(ref details) => details.path.is_none() && details.git.is_none(),
+    }
+  }
+
+  /**
+   * Converts this dependency into the form used by the crates.io-index.
+   *
+   * Dependencies without a version (such as path or git dependencies) accept any version.
+   */
+  pub fn to_dependency_entry(&self, name: &str, kind: DependencyKind, target: Option<String>) -> DependencyEntry {
+    match *self {
+      ManifestDependency::Simple(ref req) => DependencyEntry {
+        name: name.to_owned(),
+        req: req.clone(),
+        features: Vec::new(),
+        optional: false,
+        default_features: true,
+        target: target,
+        kind: Some(kind.as_index_str().to_owned()),
+      },
+      ManifestDependency::Detailed(ref details) => DependencyEntry {
+        name: name.to_owned(),
+        req: details.version.clone().unwrap_or_else(|| "*".to_owned()),
+        features: details.features.clone().unwrap_or_default(),
+        optional: details.optional.unwrap_or(false),
+        default_features: details.default_features
+          .or(details.default_features_legacy)
+          .unwrap_or(true),
+        target: target,
+        kind: Some(kind.as_index_str().to_owned()),
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use manifest::CargoManifest;
+  use manifest::DependencyKind;
+
+  #[test]
+  fn test_reads_dependencies_of_each_kind() {
+    let manifest = CargoManifest::from_str(r#"
+      [package]
+      name = "example"
+      version = "0.1.0"
+
+      [dependencies]
+      libc = "0.2"
+
+      [dev-dependencies]
+      rand = { version = "0.3", default-features = false, features = ["std"] }
+
+      [target.'cfg(unix)'.dev-dependencies]
+      tempdir = "0.3"
+    "#).unwrap();
+
+    assert_eq!(manifest.get_package().unwrap().name, "example".to_owned());
+
+    let normal = manifest.get_dependency_entries(DependencyKind::Normal);
+    assert_eq!(normal.len(), 1);
+    assert_eq!(normal[0].req, "0.2".to_owned());
+    assert_eq!(normal[0].kind, Some("normal".to_owned()));
+
+    let dev = manifest.get_dependency_entries(DependencyKind::Dev);
+    assert_eq!(dev.len(), 2);
+    assert_eq!(dev[0].name, "rand".to_owned());
+    assert_eq!(dev[0].default_features, false);
+    assert_eq!(dev[0].features, vec!["std".to_owned()]);
+    assert_eq!(dev[1].name, "tempdir".to_owned());
+    assert_eq!(dev[1].target, Some("cfg(unix)".to_owned()));
+  }
+
+  #[test]
+  fn test_dependencies_without_versions_accept_anything() {
+    let manifest = CargoManifest::from_str(r#"
+      [package]
+      name = "example"
+      version = "0.1.0"
+
+      [dependencies]
+      sibling = { path = "../sibling" }
+    "#).unwrap();
+
+    let normal = manifest.get_dependency_entries(DependencyKind::Normal);
+    assert_eq!(normal[0].req, "*".to_owned());
+    assert!(manifest.get_registry_dependency_entries(DependencyKind::Normal).is_empty());
+  }
+}
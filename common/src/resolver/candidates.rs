@@ -0,0 +1,204 @@
+use cargo;
+use resolver::Candidate;
+use resolver::Requirement;
+use semver::Version;
+use semver::VersionReq;
+use std::collections::HashSet;
+
+/**
+ * Converts an index entry into a resolver candidate, with the provided dev dependencies.
+ *
+ * Yields None if the version or any requirement cannot be parsed as semver.
+ */
+pub fn to_candidate(entry: &cargo::IndexEntry, dev_dependencies: Vec<&cargo::DependencyEntry>) -> Option<Candidate> {
+  to_candidate_with_features(entry, dev_dependencies, &[])
+}
+
+/** As to_candidate, but with the provided features enabled alongside the defaults. */
+pub fn to_candidate_with_features(entry: &cargo::IndexEntry,
+                                  dev_dependencies: Vec<&cargo::DependencyEntry>,
+                                  features: &[String]) -> Option<Candidate> {
+  let version = match Version::parse(&entry.vers) {
+    Ok(version) => version,
+    Err(_) => return None,
+  };
+
+  let dependencies = match to_requirements(get_active_dependencies_with_features(entry, features)) {
+    Some(dependencies) => dependencies,
+    None => return None,
+  };
+  let dev_dependencies = match to_requirements(dev_dependencies) {
+    Some(dev_dependencies) => dev_dependencies,
+    None => return None,
+  };
+
+  Some(Candidate {
+    version: version,
+    dependencies: dependencies,
+    dev_dependencies: dev_dependencies,
+  })
+}
+
+fn to_requirements(dependencies: Vec<&cargo::DependencyEntry>) -> Option<Vec<Requirement>> {
+  let mut requirements = Vec::new();
+  for dependency in dependencies.into_iter() {
+    match VersionReq::parse(&dependency.req) {
+      Ok(req) => requirements.push(Requirement {
+        name: dependency.name.clone(),
+        req: req,
+      }),
+      Err(_) => return None,
+    }
+  }
+  Some(requirements)
+}
+
+/**
+ * Yields the dependencies needed to build the crate with its default features.
+ *
+ * These are the non-optional normal and build dependencies (for every target), plus the optional
+ * dependencies enabled by the "default" feature. Features requested by dependents are not
+ * considered.
+ */
+pub fn get_active_dependencies(entry: &cargo::IndexEntry) -> Vec<&cargo::DependencyEntry> {
+  get_active_dependencies_with_features(entry, &[])
+}
+
+/**
+ * As get_active_dependencies, but with the provided features enabled alongside the defaults.
+ *
+ * Features may also name optional dependencies directly, as they can in Cargo.
+ */
+pub fn get_active_dependencies_with_features<'a>(entry: &'a cargo::IndexEntry,
+                                                 features: &[String]) -> Vec<&'a cargo::DependencyEntry> {
+  get_requested_dependencies(entry, true /* default_features */, features).into_iter()
+    .map(|(dependency, _)| dependency)
+    .collect()
+}
+
+/**
+ * As get_active_dependencies_with_features, but the defaults are only enabled if default_features
+ * is set. Each dependency comes with the features that the crate's enabled features ask of it (as
+ * "dep/feature"), beyond those listed in the dependency itself.
+ */
+pub fn get_requested_dependencies<'a>(entry: &'a cargo::IndexEntry,
+                                      default_features: bool,
+                                      features: &[String]) -> Vec<(&'a cargo::DependencyEntry, Vec<String>)> {
+  let enabled_features = expand_features(entry, default_features, features);
+  entry.deps.iter()
+    .filter(|dep| dep.kind.as_ref().map(|k| k != "dev").unwrap_or(true))
+    .filter(|dep| !dep.optional || enabled_features.optional_dependencies.contains(&dep.name))
+    .map(|dep| {
+      let dependency_features = enabled_features.dependency_features.iter()
+        .filter(|&&(ref dependency_name, _)| dependency_name == &dep.name)
+        .map(|&(_, ref feature)| feature.clone())
+        .collect();
+      (dep, dependency_features)
+    })
+    .collect()
+}
+
+/** What a crate's enabled features turn on. */
+struct EnabledFeatures {
+  optional_dependencies: HashSet<String>,
+  // Features of dependencies, as (dependency, feature), in the order they were found
+  dependency_features: Vec<(String, String)>,
+}
+
+/**
+ * Expands the provided features (and "default", if enabled) into the optional dependencies that
+ * they enable, and the features that they ask of dependencies.
+ */
+fn expand_features(entry: &cargo::IndexEntry, default_features: bool, features: &[String]) -> EnabledFeatures {
+  let mut enabled_dependencies = HashSet::new();
+  let mut dependency_features = Vec::new();
+  let mut visited_features = HashSet::new();
+  let mut pending_features = features.to_vec();
+  if default_features {
+    pending_features.push("default".to_owned());
+  }
+  while let Some(feature) = pending_features.pop() {
+    if !visited_features.insert(feature.clone()) {
+      continue
+    }
+    let members = match entry.features.get(&feature) {
+      Some(members) => members,
+      None => {
+        // Not a declared feature, so it may be an optional dependency
+        if feature != "default" {
+          enabled_dependencies.insert(feature);
+        }
+        continue
+      },
+    };
+    for member in members.iter() {
+      if let Some(slash_idx) = member.find('/') {
+        // "dep/feature" enables the optional dependency "dep"
+        enabled_dependencies.insert(member[0..slash_idx].to_owned());
+        dependency_features.push((member[0..slash_idx].to_owned(), member[slash_idx + 1..].to_owned()));
+      } else if entry.features.contains_key(member) {
+        pending_features.push(member.clone());
+      } else {
+        enabled_dependencies.insert(member.clone());
+      }
+    }
+  }
+  EnabledFeatures {
+    optional_dependencies: enabled_dependencies,
+    dependency_features: dependency_features,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use cargo;
+  use index::testing::dependency_entry;
+  use index::testing::index_entry;
+  use resolver::candidates;
+  use std::collections::HashMap;
+
+  fn dependency(name: &str, optional: bool, kind: &str) -> cargo::DependencyEntry {
+    cargo::DependencyEntry {
+      optional: optional,
+      kind: Some(kind.to_owned()),
+      ..dependency_entry(name, "^1.0")
+    }
+  }
+
+  #[test]
+  fn test_active_dependencies_follow_default_features() {
+    let mut features = HashMap::new();
+    features.insert("default".to_owned(), vec!["std".to_owned()]);
+    features.insert("std".to_owned(), vec!["serde/std".to_owned()]);
+    let entry = cargo::IndexEntry {
+      deps: vec![
+        dependency("libc", false, "normal"),
+        dependency("serde", true, "normal"),
+        dependency("rand", true, "normal"),
+        dependency("cc", false, "build"),
+        dependency("quickcheck", false, "dev"),
+      ],
+      features: features,
+      ..index_entry("a", "1.0.0", &[])
+    };
+
+    let active = candidates::get_active_dependencies(&entry).into_iter()
+      .map(|dep| dep.name.clone())
+      .collect::<Vec<_>>();
+    assert_eq!(active, vec!["libc".to_owned(), "serde".to_owned(), "cc".to_owned()]);
+
+    let active = candidates::get_active_dependencies_with_features(&entry, &["rand".to_owned()]).into_iter()
+      .map(|dep| dep.name.clone())
+      .collect::<Vec<_>>();
+    assert_eq!(active, vec!["libc".to_owned(), "serde".to_owned(), "rand".to_owned(), "cc".to_owned()]);
+
+    let requested = candidates::get_requested_dependencies(&entry, true /* default_features */, &[]).into_iter()
+      .map(|(dep, features)| (dep.name.clone(), features))
+      .collect::<Vec<_>>();
+    assert_eq!(requested[1], ("serde".to_owned(), vec!["std".to_owned()]));
+    let requested = candidates::get_requested_dependencies(&entry, false /* default_features */, &[]).into_iter()
+      .map(|(dep, _)| dep.name.clone())
+      .collect::<Vec<_>>();
+    assert_eq!(requested, vec!["libc".to_owned(), "cc".to_owned()]);
+  }
+}
@@ -0,0 +1,424 @@
+use cargo;
+use snapshot::CrateSnapshot;
+use snapshot::WorkspaceSnapshot;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+
+/**
+ * The features that every crate of a snapshot ends up built with, once Cargo has unified the
+ * features that each of its dependents enable.
+ */
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct FeatureUnification {
+  pub snapshot_version: String,
+  // One entry per crate that building the members reaches, in name order
+  pub crates: Vec<UnifiedCrate>,
+  // Crates in the snapshot without an index entry (for their version), whose features are unknown
+  pub crates_without_entries: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct UnifiedCrate {
+  pub name: String,
+  pub version: String,
+  // Every enabled feature, in name order. As in Cargo, enabled optional dependencies are features.
+  pub features: Vec<UnifiedFeature>,
+  // Optional dependencies that the unified features enable, but that are not in the snapshot
+  pub missing_dependencies: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct UnifiedFeature {
+  pub name: String,
+  // Whoever enables the feature, directly or through another of the crate's features
+  pub enabled_by: Vec<FeatureEnabler>,
+  // The optional dependencies of the crate that the feature enables, directly or transitively
+  pub added_crates: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum FeatureEnabler {
+  // The defaults and configured features of members
+  Workspace,
+  // A crate that depends on this one
+  Dependent(String),
+}
+
+impl FeatureUnification {
+  pub fn get_crate(&self, name: &str) -> Option<&UnifiedCrate> {
+    self.crates.iter().find(|unified_crate| unified_crate.name == name)
+  }
+
+  /** Yields every enabled feature that pulls optional dependencies into the build. */
+  pub fn get_features_adding_crates(&self) -> Vec<(&UnifiedCrate, &UnifiedFeature)> {
+    let mut features = Vec::new();
+    for unified_crate in self.crates.iter() {
+      for feature in unified_crate.features.iter().filter(|feature| !feature.added_crates.is_empty()) {
+        features.push((unified_crate, feature));
+      }
+    }
+    features
+  }
+}
+
+impl UnifiedCrate {
+  pub fn get_feature_names(&self) -> Vec<&str> {
+    self.features.iter().map(|feature| feature.name.as_str()).collect()
+  }
+}
+
+/**
+ * Computes the unified feature set of every crate that building the members of the snapshot
+ * reaches, as Cargo would.
+ *
+ * Members are built with their default features and those the snapshot enables for them. From
+ * there, each dependency enables the `features` it lists (and "default", unless it sets
+ * `default_features` to false), features enable the features, optional dependencies and
+ * "dep/feature"s they list, and enabled optional dependencies are followed in turn. The dev
+ * dependencies of members whose tests are built are followed too, as Cargo unifies them with
+ * everything else.
+ *
+ * The index entries are matched to the snapshot by name and version; others are ignored.
+ */
+pub fn unify_features(snapshot: &WorkspaceSnapshot, entries: &[cargo::IndexEntry]) -> FeatureUnification {
+  let snapshot_crates = snapshot.details.iter()
+    .map(|details| (details.name.as_str(), details))
+    .collect::<HashMap<_, _>>();
+  let entries = entries.iter()
+    .filter(|entry| snapshot_crates.get(entry.name.as_str()).map(|d| d.version == entry.vers).unwrap_or(false))
+    .map(|entry| (entry.name.as_str(), entry))
+    .collect::<HashMap<_, _>>();
+  let mut unifier = FeatureUnifier {
+    snapshot_crates: snapshot_crates,
+    entries: entries,
+    members: snapshot.members.iter().map(|member| member.as_str()).collect(),
+    activated_crates: BTreeSet::new(),
+    enablers: BTreeMap::new(),
+    missing_dependencies: BTreeMap::new(),
+    pending: Vec::new(),
+  };
+
+  for member in snapshot.members.iter() {
+    unifier.pending.push(Activation::Feature(member.clone(), "default".to_owned(), FeatureEnabler::Workspace));
+    if let Some(details) = unifier.snapshot_crates.get(member.as_str()) {
+      for feature in details.features.iter() {
+        unifier.enable_feature_member(member, feature, FeatureEnabler::Workspace);
+      }
+    }
+  }
+  unifier.run();
+  unifier.into_unification(snapshot)
+}
+
+/** A unit of work for the unifier. */
+enum Activation {
+  // The crate is built, so its mandatory dependencies are too
+  Crate(String),
+  // The crate, the feature, and whoever enabled it
+  Feature(String, String, FeatureEnabler),
+}
+
+struct FeatureUnifier<'a> {
+  snapshot_crates: HashMap<&'a str, &'a CrateSnapshot>,
+  entries: HashMap<&'a str, &'a cargo::IndexEntry>,
+  members: BTreeSet<&'a str>,
+  activated_crates: BTreeSet<String>,
+  // Crate name => feature name => enablers
+  enablers: BTreeMap<String, BTreeMap<String, BTreeSet<FeatureEnabler>>>,
+  missing_dependencies: BTreeMap<String, BTreeSet<String>>,
+  pending: Vec<Activation>,
+}
+
+impl<'a> FeatureUnifier<'a> {
+  fn run(&mut self) {
+    while let Some(activation) = self.pending.pop() {
+      match activation {
+        Activation::Crate(name) => self.activate_crate(name),
+        Activation::Feature(name, feature, enabler) => self.activate_feature(name, feature, enabler),
+      }
+    }
+  }
+
+  fn activate_crate(&mut self, name: String) {
+    if !self.activated_crates.insert(name.clone()) {
+      return
+    }
+    for dependency in self.get_dependencies(&name).into_iter().filter(|dep| !dep.optional) {
+      self.activate_dependency(&name, dependency);
+    }
+  }
+
+  fn activate_feature(&mut self, name: String, feature: String, enabler: FeatureEnabler) {
+    self.pending.push(Activation::Crate(name.clone()));
+    let entry = match self.entries.get(name.as_str()) {
+      Some(entry) => *entry,
+      None => return,
+    };
+    let optional_dependencies = self.get_dependencies(&name).into_iter()
+      .filter(|dep| dep.optional && dep.name == feature)
+      .collect::<Vec<_>>();
+    if feature == "default" && !entry.features.contains_key("default") {
+      return
+    }
+
+    let is_new_enabler = self.enablers.entry(name.clone())
+      .or_insert_with(BTreeMap::new)
+      .entry(feature.clone())
+      .or_insert_with(BTreeSet::new)
+      .insert(enabler.clone());
+    if !is_new_enabler {
+      return
+    }
+
+    match entry.features.get(&feature) {
+      Some(members) => {
+        for member in members.iter() {
+          self.enable_feature_member(&name, member, enabler.clone());
+        }
+      },
+      None => {
+        // Not a declared feature, so it may be an optional dependency
+        for dependency in optional_dependencies.into_iter() {
+          self.activate_dependency(&name, dependency);
+        }
+      },
+    }
+  }
+
+  /** Enables an entry of a feature's list: a feature, an optional dependency, or a "dep/feature". */
+  fn enable_feature_member(&mut self, name: &str, member: &str, enabler: FeatureEnabler) {
+    if let Some(slash_idx) = member.find('/') {
+      let dependency_name = &member[0..slash_idx];
+      let dependency_feature = &member[slash_idx + 1..];
+      let is_optional = self.get_dependencies(name).iter()
+        .any(|dep| dep.optional && dep.name == dependency_name);
+      if is_optional {
+        self.pending.push(Activation::Feature(name.to_owned(), dependency_name.to_owned(), enabler));
+      }
+      if self.snapshot_crates.contains_key(dependency_name) {
+        self.pending.push(Activation::Feature(dependency_name.to_owned(),
+                                              dependency_feature.to_owned(),
+                                              FeatureEnabler::Dependent(name.to_owned())));
+      }
+    } else {
+      self.pending.push(Activation::Feature(name.to_owned(), member.to_owned(), enabler));
+    }
+  }
+
+  /** Builds the dependency, with the features that the dependent asks of it. */
+  fn activate_dependency(&mut self, dependent: &str, dependency: &cargo::DependencyEntry) {
+    if !self.snapshot_crates.contains_key(dependency.name.as_str()) {
+      self.missing_dependencies.entry(dependent.to_owned())
+        .or_insert_with(BTreeSet::new)
+        .insert(dependency.name.clone());
+      return
+    }
+    let enabler = FeatureEnabler::Dependent(dependent.to_owned());
+    self.pending.push(Activation::Crate(dependency.name.clone()));
+    if dependency.default_features {
+      self.pending.push(Activation::Feature(dependency.name.clone(), "default".to_owned(), enabler.clone()));
+    }
+    for feature in dependency.features.iter() {
+      self.pending.push(Activation::Feature(dependency.name.clone(), feature.clone(), enabler.clone()));
+    }
+  }
+
+  /**
+   * Yields the normal and build dependencies of the crate (for every target), plus its dev
+   * dependencies if it is a member whose tests are built.
+   */
+  fn get_dependencies(&self, name: &str) -> Vec<&'a cargo::DependencyEntry> {
+    let entry = match self.entries.get(name) {
+      Some(entry) => *entry,
+      None => return Vec::new(),
+    };
+    let dev_dependencies = match self.snapshot_crates.get(name) {
+      Some(details) if self.members.contains(name) => details.dev_dependencies.as_slice(),
+      _ => &[],
+    };
+    entry.deps.iter()
+      .filter(|dep| dep.kind.as_ref().map(|k| k != "dev").unwrap_or(true) || dev_dependencies.contains(&dep.name))
+      .collect()
+  }
+
+  fn into_unification(self, snapshot: &WorkspaceSnapshot) -> FeatureUnification {
+    let mut crates = Vec::new();
+    for name in self.activated_crates.iter() {
+      let details = match self.snapshot_crates.get(name.as_str()) {
+        Some(details) => details,
+        None => continue,
+      };
+      let optional_dependencies = self.get_dependencies(name).into_iter()
+        .filter(|dep| dep.optional)
+        .map(|dep| dep.name.as_str())
+        .collect::<BTreeSet<_>>();
+      let features = match (self.enablers.get(name), self.entries.get(name.as_str())) {
+        (Some(enablers), Some(entry)) => enablers.iter()
+          .map(|(feature, enablers)| UnifiedFeature {
+            name: feature.clone(),
+            enabled_by: enablers.iter().cloned().collect(),
+            added_crates: get_added_crates(entry, &optional_dependencies, feature),
+          })
+          .collect(),
+        _ => Vec::new(),
+      };
+      crates.push(UnifiedCrate {
+        name: name.clone(),
+        version: details.version.clone(),
+        features: features,
+        missing_dependencies: self.missing_dependencies.get(name)
+          .map(|missing| missing.iter().cloned().collect())
+          .unwrap_or_else(Vec::new),
+      });
+    }
+
+    let mut crates_without_entries = snapshot.details.iter()
+      .filter(|details| !self.entries.contains_key(details.name.as_str()))
+      .map(|details| details.name.clone())
+      .collect::<Vec<_>>();
+    crates_without_entries.sort();
+    FeatureUnification {
+      snapshot_version: snapshot.version.clone(),
+      crates: crates,
+      crates_without_entries: crates_without_entries,
+    }
+  }
+}
+
+/** Expands a single feature of the crate into the optional dependencies that it enables. */
+fn get_added_crates(entry: &cargo::IndexEntry, optional_dependencies: &BTreeSet<&str>, feature: &str) -> Vec<String> {
+  let mut added_crates = BTreeSet::new();
+  let mut visited_features = BTreeSet::new();
+  let mut pending_features = vec![feature.to_owned()];
+  while let Some(feature) = pending_features.pop() {
+    if !visited_features.insert(feature.clone()) {
+      continue
+    }
+    let members = match entry.features.get(&feature) {
+      Some(members) => members,
+      None => {
+        if optional_dependencies.contains(feature.as_str()) {
+          added_crates.insert(feature);
+        }
+        continue
+      },
+    };
+    for member in members.iter() {
+      match member.find('/') {
+        // "dep/feature" enables "dep", if it is optional
+        Some(slash_idx) => pending_features.push(member[0..slash_idx].to_owned()),
+        None => pending_features.push(member.clone()),
+      }
+    }
+  }
+  added_crates.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use cargo;
+  use index::testing::dependency_entry;
+  use index::testing::index_entry;
+  use resolver::features::FeatureEnabler;
+  use resolver::features;
+  use snapshot::CrateSnapshot;
+  use snapshot::WorkspaceSnapshot;
+  use snapshot::testing::crate_snapshot;
+  use std::collections::HashMap;
+
+  fn feature_table(features: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+    features.iter()
+      .map(|&(feature, members)| (feature.to_owned(), members.iter().map(|m| m.to_string()).collect()))
+      .collect()
+  }
+
+  fn dependent(name: &str) -> FeatureEnabler {
+    FeatureEnabler::Dependent(name.to_owned())
+  }
+
+  #[test]
+  fn test_features_are_unified_across_dependents() {
+    let optional_dependency = |name: &str| cargo::DependencyEntry {
+      optional: true,
+      ..dependency_entry(name, "^1.0")
+    };
+    let entries = vec![
+      cargo::IndexEntry {
+        deps: vec![
+          cargo::DependencyEntry {
+            default_features: false,
+            features: vec!["alloc".to_owned()],
+            ..dependency_entry("serde", "^1.0")
+          },
+          optional_dependency("serde_json"),
+          optional_dependency("log"),
+          optional_dependency("unused"),
+        ],
+        features: feature_table(&[("default", &["std"]), ("std", &[]), ("json", &["serde_json", "serde/derive"])]),
+        ..index_entry("app", "1.0.0", &[])
+      },
+      cargo::IndexEntry {
+        deps: vec![optional_dependency("serde_derive")],
+        features: feature_table(&[("default", &["std"]), ("std", &[]), ("alloc", &[]), ("derive", &["serde_derive"])]),
+        ..index_entry("serde", "1.0.0", &[])
+      },
+      cargo::IndexEntry {
+        deps: vec![cargo::DependencyEntry {
+          features: vec!["std".to_owned()],
+          ..dependency_entry("serde", "^1.0")
+        }],
+        ..index_entry("serde_json", "1.0.0", &[])
+      },
+      index_entry("serde_derive", "1.0.0", &[]),
+    ];
+    let snapshot = WorkspaceSnapshot {
+      version: "nightly-2017-11-20".to_owned(),
+      members: vec!["app".to_owned()],
+      details: vec![
+        CrateSnapshot {
+          features: vec!["json".to_owned(), "log".to_owned()],
+          ..crate_snapshot("app", "1.0.0", &[])
+        },
+        crate_snapshot("serde", "1.0.0", &[]),
+        crate_snapshot("serde_derive", "1.0.0", &[]),
+        crate_snapshot("serde_json", "1.0.0", &[]),
+        crate_snapshot("unused", "1.0.0", &[]),
+        crate_snapshot("unindexed", "1.0.0", &[]),
+      ],
+    };
+
+    let unification = features::unify_features(&snapshot, &entries);
+
+    let crate_names = unification.crates.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(crate_names, vec!["app", "serde", "serde_derive", "serde_json"]);
+    assert_eq!(unification.crates_without_entries, vec!["unindexed".to_owned(), "unused".to_owned()]);
+
+    let app = unification.get_crate("app").unwrap();
+    assert_eq!(app.get_feature_names(), vec!["default", "json", "log", "serde_json", "std"]);
+    assert_eq!(app.missing_dependencies, vec!["log".to_owned()]);
+
+    let serde = unification.get_crate("serde").unwrap();
+    assert_eq!(serde.get_feature_names(), vec!["alloc", "default", "derive", "serde_derive", "std"]);
+    let enablers = serde.features.iter().map(|f| (f.name.as_str(), f.enabled_by.clone())).collect::<Vec<_>>();
+    assert_eq!(enablers, vec![
+      ("alloc", vec![dependent("app")]),
+      ("default", vec![dependent("serde_json")]),
+      ("derive", vec![dependent("app")]),
+      ("serde_derive", vec![dependent("app")]),
+      ("std", vec![dependent("serde_json")]),
+    ]);
+
+    let adding_crates = unification.get_features_adding_crates().into_iter()
+      .map(|(c, f)| (c.name.as_str(), f.name.as_str(), f.added_crates.clone()))
+      .collect::<Vec<_>>();
+    assert_eq!(adding_crates, vec![
+      ("app", "json", vec!["serde_json".to_owned()]),
+      ("app", "log", vec!["log".to_owned()]),
+      ("app", "serde_json", vec!["serde_json".to_owned()]),
+      ("serde", "derive", vec!["serde_derive".to_owned()]),
+      ("serde", "serde_derive", vec!["serde_derive".to_owned()]),
+    ]);
+  }
+}
@@ -0,0 +1,237 @@
+pub mod candidates;
+pub mod features;
+pub mod report;
+mod solver;
+
+use resolver::report::ConflictReport;
+use semver::Version;
+use semver::VersionReq;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+
+/** The number of versions the resolver will decide on before giving up. */
+pub const DEFAULT_MAX_RESOLUTION_STEPS: u64 = 100000;
+
+/** A dependency on some range of versions of a crate. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Requirement {
+  pub name: String,
+  pub req: VersionReq,
+}
+
+/** A single version of a crate that could be selected, and what selecting it requires. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+  pub version: Version,
+  pub dependencies: Vec<Requirement>,
+  // Only present when the crate's tests must also be buildable
+  pub dev_dependencies: Vec<Requirement>,
+}
+
+/**
+ * A trait for anything that can enumerate the usable versions of a crate.
+ *
+ * Sources should omit versions that must never be selected (such as yanked versions).
+ */
+pub trait CandidateSource {
+  /** Yields every usable version of the named crate, in any order. */
+  fn get_candidates(&self, crate_name: &str) -> Vec<Candidate>;
+}
+
+/** A consistent selection of exactly one version per crate. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolution {
+  pub versions: BTreeMap<String, Version>,
+  // The names of the crates that each selected crate depends on
+  pub dependencies: BTreeMap<String, BTreeSet<String>>,
+  pub dev_dependencies: BTreeMap<String, BTreeSet<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResolutionErr {
+  // No selection satisfies every requirement. Explains why.
+  NoSolution(ConflictReport),
+  // The search was abandoned after deciding on this many versions
+  StepLimitExceeded(u64),
+}
+
+/**
+ * Selects one version of each root crate and each of their transitive dependencies, such that
+ * every dependency requirement is satisfied.
+ *
+ * Newer versions are always tried first, so the resolution prefers the newest mutually compatible
+ * versions. When no such selection exists, the error explains which requirements conflict.
+ */
+pub fn resolve(source: &CandidateSource, roots: &[Requirement]) -> Result<Resolution, ResolutionErr> {
+  resolve_with_preferences(source, roots, &BTreeMap::new())
+}
+
+/**
+ * As resolve, but selects the preferred version of a crate whenever it is allowed, rather than the
+ * newest. This keeps an existing selection stable while something is added to it.
+ */
+pub fn resolve_with_preferences(source: &CandidateSource,
+                                roots: &[Requirement],
+                                preferred_versions: &BTreeMap<String, Version>) -> Result<Resolution, ResolutionErr> {
+  solver::Solver::new(source, roots, preferred_versions, DEFAULT_MAX_RESOLUTION_STEPS).solve()
+}
+
+pub mod testing {
+  use resolver::Candidate;
+  use resolver::CandidateSource;
+  use resolver::Requirement;
+  use semver::Version;
+  use semver::VersionReq;
+  use std::collections::HashMap;
+
+  /**
+   * A CandidateSource backed by an in-memory list of (name, version, [(dep name, dep req)]).
+   *
+   * Dependency names prefixed with "dev:" are dev dependencies.
+   */
+  pub struct FakeCandidateSource {
+    candidates: HashMap<String, Vec<Candidate>>,
+  }
+
+  impl FakeCandidateSource {
+    pub fn new(crates: &[(&str, &str, &[(&str, &str)])]) -> FakeCandidateSource {
+      let mut candidates = HashMap::new();
+      for &(name, version, dependencies) in crates.iter() {
+        candidates.entry(name.to_owned())
+          .or_insert_with(Vec::new)
+          .push(Candidate {
+            version: Version::parse(version).unwrap(),
+            dependencies: dependencies.iter()
+              .filter(|&&(dep_name, _)| !dep_name.starts_with("dev:"))
+              .map(|&(dep_name, dep_req)| requirement(dep_name, dep_req))
+              .collect(),
+            dev_dependencies: dependencies.iter()
+              .filter(|&&(dep_name, _)| dep_name.starts_with("dev:"))
+              .map(|&(dep_name, dep_req)| requirement(&dep_name["dev:".len()..], dep_req))
+              .collect(),
+          });
+      }
+      FakeCandidateSource {
+        candidates: candidates,
+      }
+    }
+  }
+
+  impl CandidateSource for FakeCandidateSource {
+    fn get_candidates(&self, crate_name: &str) -> Vec<Candidate> {
+      self.candidates.get(crate_name).cloned().unwrap_or_default()
+    }
+  }
+
+  pub fn requirement(name: &str, req: &str) -> Requirement {
+    Requirement {
+      name: name.to_owned(),
+      req: VersionReq::parse(req).unwrap(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use resolver::ResolutionErr;
+  use resolver::testing::FakeCandidateSource;
+  use resolver::testing::requirement;
+  use resolver;
+  use semver::Version;
+  use std::collections::BTreeMap;
+
+  #[test]
+  fn test_prefers_newest_versions() {
+    let source = FakeCandidateSource::new(&[
+      ("a", "1.0.0", &[("b", "^1.0")]),
+      ("a", "1.1.0", &[("b", "^1.1")]),
+      ("b", "1.0.0", &[]),
+      ("b", "1.2.0", &[]),
+      ("b", "2.0.0", &[]),
+    ]);
+    let resolution = resolver::resolve(&source, &[requirement("a", "*")]).unwrap();
+    assert_eq!(resolution.versions.get("a"), Some(&Version::parse("1.1.0").unwrap()));
+    assert_eq!(resolution.versions.get("b"), Some(&Version::parse("1.2.0").unwrap()));
+    assert!(resolution.dependencies.get("a").unwrap().contains("b"));
+  }
+
+  #[test]
+  fn test_preferred_versions_are_kept_when_allowed() {
+    let source = FakeCandidateSource::new(&[
+      ("a", "1.0.0", &[("b", "^1.0")]),
+      ("b", "1.0.0", &[]),
+      ("b", "1.2.0", &[]),
+      ("c", "1.0.0", &[]),
+      ("c", "2.0.0", &[]),
+    ]);
+    let mut preferred_versions = BTreeMap::new();
+    preferred_versions.insert("b".to_owned(), Version::parse("1.0.0").unwrap());
+    preferred_versions.insert("c".to_owned(), Version::parse("1.0.0").unwrap());
+    let roots = [requirement("a", "*"), requirement("c", "^2.0")];
+    let resolution = resolver::resolve_with_preferences(&source, &roots, &preferred_versions).unwrap();
+    assert_eq!(resolution.versions.get("b"), Some(&Version::parse("1.0.0").unwrap()));
+    assert_eq!(resolution.versions.get("c"), Some(&Version::parse("2.0.0").unwrap()));
+  }
+
+  #[test]
+  fn test_backtracks_to_older_versions_when_needed() {
+    // The newest "a" needs "c" 2.x, but "b" pins "c" to 1.x
+    let source = FakeCandidateSource::new(&[
+      ("a", "1.0.0", &[("c", "^1.0")]),
+      ("a", "2.0.0", &[("c", "^2.0")]),
+      ("b", "1.0.0", &[("c", "^1.0")]),
+      ("c", "1.0.0", &[]),
+      ("c", "2.0.0", &[]),
+    ]);
+    let resolution = resolver::resolve(&source, &[requirement("a", "*"), requirement("b", "*")]).unwrap();
+    assert_eq!(resolution.versions.get("a"), Some(&Version::parse("1.0.0").unwrap()));
+    assert_eq!(resolution.versions.get("c"), Some(&Version::parse("1.0.0").unwrap()));
+  }
+
+  #[test]
+  fn test_dev_dependencies_constrain_the_resolution() {
+    let source = FakeCandidateSource::new(&[
+      ("a", "1.0.0", &[("b", "^1.0"), ("dev:b", "~1.0.0"), ("dev:c", "^1.0")]),
+      ("b", "1.0.5", &[]),
+      ("b", "1.1.0", &[]),
+      ("c", "1.0.0", &[]),
+    ]);
+    let resolution = resolver::resolve(&source, &[requirement("a", "*")]).unwrap();
+    assert_eq!(resolution.versions.get("b"), Some(&Version::parse("1.0.5").unwrap()));
+    assert_eq!(resolution.versions.get("c"), Some(&Version::parse("1.0.0").unwrap()));
+    assert!(!resolution.dependencies.get("a").unwrap().contains("c"));
+    assert!(resolution.dev_dependencies.get("a").unwrap().contains("c"));
+  }
+
+  #[test]
+  fn test_reports_unsatisfiable_requirements() {
+    let source = FakeCandidateSource::new(&[
+      ("a", "1.0.0", &[("missing", "^1.0")]),
+    ]);
+    match resolver::resolve(&source, &[requirement("a", "*")]) {
+      Err(ResolutionErr::NoSolution(report)) => {
+        assert!(report.explanation.iter().any(|line| line.contains("missing")))
+      },
+      other => panic!("Expected no solution, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn test_explains_conflicting_requirements() {
+    let source = FakeCandidateSource::new(&[
+      ("foo", "2.0.0", &[("bar", "^0.3")]),
+      ("baz", "1.4.0", &[("bar", "^0.4")]),
+      ("bar", "0.3.0", &[]),
+      ("bar", "0.4.0", &[]),
+    ]);
+    match resolver::resolve(&source, &[requirement("foo", "*"), requirement("baz", "*")]) {
+      Err(ResolutionErr::NoSolution(report)) => {
+        assert_eq!(report.explanation[0],
+                   "Because baz depends on bar ^0.4 and foo depends on bar ^0.3, \
+                    baz is incompatible with foo.");
+        assert_eq!(report.derivation.cause, "derived");
+      },
+      other => panic!("Expected no solution, got {:?}", other),
+    }
+  }
+}
@@ -0,0 +1,239 @@
+use resolver::solver::Cause;
+use resolver::solver::Incompatibility;
+use resolver::solver::ROOT_PACKAGE;
+use resolver::solver::Solver;
+use resolver::solver::Term;
+use semver::Version;
+use std::collections::BTreeSet;
+use std::collections::HashSet;
+
+/**
+ * An explanation of why no resolution exists.
+ *
+ * The explanation is a human readable derivation, one step per line. The derivation tree carries
+ * the same information in a structured form, for reports.
+ */
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct ConflictReport {
+  pub explanation: Vec<String>,
+  pub derivation: DerivationNode,
+}
+
+/**
+ * A single fact established during resolution.
+ *
+ * Facts are either external (from the dependency metadata) or derived from two earlier facts. A
+ * fact that is derived more than once in the tree only lists its premises the first time.
+ */
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct DerivationNode {
+  pub conclusion: String,
+  // One of "root", "dependency", "unavailable", or "derived"
+  pub cause: String,
+  // The crate selections that cannot all hold at once
+  pub terms: Vec<ReportTerm>,
+  pub derived_from: Vec<DerivationNode>,
+}
+
+/** A statement about the version selected for a crate. */
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct ReportTerm {
+  pub crate_name: String,
+  // Whether the term requires the crate to be selected at all
+  pub positive: bool,
+  // The versions that the term allows
+  pub versions: Vec<String>,
+}
+
+/** Builds the report for a failed resolution, whose final derived incompatibility is provided. */
+pub fn build_conflict_report(solver: &Solver, incompatibility_id: usize) -> ConflictReport {
+  let mut explanation = Vec::new();
+  let mut explained_ids = HashSet::new();
+  explain(solver, incompatibility_id, &mut explanation, &mut explained_ids);
+
+  let mut expanded_ids = HashSet::new();
+  ConflictReport {
+    explanation: explanation,
+    derivation: build_derivation_node(solver, incompatibility_id, &mut expanded_ids),
+  }
+}
+
+/** Appends the lines that establish the incompatibility, skipping any already established. */
+fn explain(solver: &Solver, id: usize, lines: &mut Vec<String>, explained_ids: &mut HashSet<usize>) {
+  if !explained_ids.insert(id) {
+    return
+  }
+  let incompatibility = solver.get_incompatibility(id);
+  let conclusion = describe(solver, incompatibility);
+  let (first_id, second_id) = match incompatibility.cause {
+    Cause::Derived(first_id, second_id) => (first_id, second_id),
+    _ => {
+      // Only reached when an external fact alone rules out the workspace
+      lines.push(format!("Because {}, {}.", conclusion, describe_failure()));
+      return
+    },
+  };
+
+  let first = solver.get_incompatibility(first_id);
+  let second = solver.get_incompatibility(second_id);
+  let first_is_derived = is_derived(first);
+  let second_is_derived = is_derived(second);
+  let line = if first_is_derived && second_is_derived {
+    explain(solver, first_id, lines, explained_ids);
+    explain(solver, second_id, lines, explained_ids);
+    format!("Because {} and {}, {}.",
+            describe(solver, first),
+            describe(solver, second),
+            conclusion)
+  } else if first_is_derived || second_is_derived {
+    let (derived_id, external) = if first_is_derived { (first_id, second) } else { (second_id, first) };
+    explain(solver, derived_id, lines, explained_ids);
+    format!("And because {}, {}.", describe(solver, external), conclusion)
+  } else {
+    format!("Because {} and {}, {}.",
+            describe(solver, first),
+            describe(solver, second),
+            conclusion)
+  };
+  lines.push(line);
+}
+
+fn build_derivation_node(solver: &Solver, id: usize, expanded_ids: &mut HashSet<usize>) -> DerivationNode {
+  let incompatibility = solver.get_incompatibility(id);
+  let (cause, derived_from) = match incompatibility.cause {
+    Cause::Root => ("root", Vec::new()),
+    Cause::Dependency { .. } => ("dependency", Vec::new()),
+    Cause::Unavailable { .. } => ("unavailable", Vec::new()),
+    Cause::Derived(first_id, second_id) => {
+      let derived_from = if expanded_ids.insert(id) {
+        vec![
+          build_derivation_node(solver, first_id, expanded_ids),
+          build_derivation_node(solver, second_id, expanded_ids),
+        ]
+      } else {
+        Vec::new()
+      };
+      ("derived", derived_from)
+    },
+  };
+
+  DerivationNode {
+    conclusion: describe(solver, incompatibility),
+    cause: cause.to_owned(),
+    terms: incompatibility.terms.iter()
+      .map(|term| ReportTerm {
+        crate_name: term.package.clone(),
+        positive: term.is_positive(),
+        versions: term.versions.iter().map(|v| v.to_string()).collect(),
+      })
+      .collect(),
+    derived_from: derived_from,
+  }
+}
+
+fn is_derived(incompatibility: &Incompatibility) -> bool {
+  match incompatibility.cause {
+    Cause::Derived(_, _) => true,
+    _ => false,
+  }
+}
+
+fn describe_failure() -> String {
+  "no selection of versions satisfies the workspace".to_owned()
+}
+
+/** Renders the incompatibility as a clause, such as "foo 2.0.0 depends on bar ^0.3". */
+fn describe(solver: &Solver, incompatibility: &Incompatibility) -> String {
+  let terms = &incompatibility.terms;
+  match incompatibility.cause {
+    Cause::Root => return "the workspace is required".to_owned(),
+    Cause::Dependency { ref dependency, ref req } => {
+      if let Some(depender) = terms.iter().find(|t| &t.package != dependency) {
+        return format!("{} depends on {} {}", describe_selection(solver, depender), dependency, req)
+      }
+    },
+    Cause::Unavailable { ref dependency, ref req } => {
+      if let Some(depender) = terms.iter().find(|t| &t.package != dependency) {
+        return format!("{} depends on {} {} (which matches no available version)",
+                       describe_selection(solver, depender),
+                       dependency,
+                       req)
+      }
+    },
+    Cause::Derived(_, _) => {},
+  }
+
+  let positive_terms = terms.iter().filter(|t| t.is_positive()).collect::<Vec<_>>();
+  let negative_terms = terms.iter().filter(|t| !t.is_positive()).collect::<Vec<_>>();
+  // The workspace is always selected, so it can be left out of the clause
+  let non_root_terms = terms.iter()
+    .filter(|t| !(t.package == ROOT_PACKAGE && t.is_positive()))
+    .collect::<Vec<_>>();
+  if non_root_terms.is_empty() {
+    return describe_failure()
+  }
+  if non_root_terms.len() == 1 {
+    let term = non_root_terms[0];
+    if term.is_positive() {
+      return format!("{} cannot be selected", describe_selection(solver, term))
+    }
+    return format!("{} is required", describe_requirement(solver, term))
+  }
+  if positive_terms.len() == 1 && negative_terms.len() == 1 {
+    return format!("{} requires {}",
+                   describe_selection(solver, positive_terms[0]),
+                   describe_requirement(solver, negative_terms[0]))
+  }
+  if negative_terms.is_empty() && positive_terms.len() == 2 {
+    return format!("{} is incompatible with {}",
+                   describe_selection(solver, positive_terms[0]),
+                   describe_selection(solver, positive_terms[1]))
+  }
+
+  let clauses = terms.iter()
+    .map(|term| if term.is_positive() {
+      describe_selection(solver, term)
+    } else {
+      format!("not {}", describe_requirement(solver, term))
+    })
+    .collect::<Vec<_>>();
+  format!("{} are incompatible", clauses.join(", "))
+}
+
+/** Describes the selection a positive term allows, such as "foo 1.0.0 through 1.2.0". */
+fn describe_selection(solver: &Solver, term: &Term) -> String {
+  if term.package == ROOT_PACKAGE {
+    return "the workspace".to_owned()
+  }
+  describe_versions(&term.package, &term.versions, &solver.get_known_versions(&term.package))
+}
+
+/** Describes the selection a negative term forbids the absence of, such as "bar 0.3.0". */
+fn describe_requirement(solver: &Solver, term: &Term) -> String {
+  let known_versions = solver.get_known_versions(&term.package);
+  let required_versions = known_versions.difference(&term.versions).cloned().collect::<BTreeSet<_>>();
+  describe_versions(&term.package, &required_versions, &known_versions)
+}
+
+fn describe_versions(package: &str, versions: &BTreeSet<Version>, known_versions: &BTreeSet<Version>) -> String {
+  if versions.is_empty() {
+    return format!("no version of {}", package)
+  }
+  if versions == known_versions {
+    return package.to_owned()
+  }
+  if versions.len() == 1 {
+    return format!("{} {}", package, versions.iter().next().unwrap())
+  }
+
+  let lowest = versions.iter().next().unwrap();
+  let highest = versions.iter().next_back().unwrap();
+  let contiguous_count = known_versions.iter()
+    .filter(|v| *v >= lowest && *v <= highest)
+    .count();
+  if contiguous_count == versions.len() {
+    return format!("{} {} through {}", package, lowest, highest)
+  }
+  let rendered_versions = versions.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+  format!("{} {}", package, rendered_versions.join(" | "))
+}
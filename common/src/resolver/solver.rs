@@ -0,0 +1,598 @@
+use resolver::Candidate;
+use resolver::CandidateSource;
+use resolver::Requirement;
+use resolver::Resolution;
+use resolver::ResolutionErr;
+use resolver::report;
+use semver::Version;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+/** The name of the virtual package whose only version depends on each root requirement. */
+pub const ROOT_PACKAGE: &'static str = "$workspace";
+
+/**
+ * A statement about the version selected for a package.
+ *
+ * Every package has a finite universe of known versions, so a term is the set of outcomes that it
+ * allows: some subset of the universe, and possibly the outcome where the package is not selected
+ * at all. A "positive" term is one that requires the package to be selected.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Term {
+  pub package: String,
+  pub versions: BTreeSet<Version>,
+  pub allows_none: bool,
+}
+
+impl Term {
+  pub fn is_positive(&self) -> bool {
+    !self.allows_none
+  }
+
+  fn is_subset_of(&self, other: &Term) -> bool {
+    self.versions.is_subset(&other.versions) && (!self.allows_none || other.allows_none)
+  }
+
+  fn is_disjoint_from(&self, other: &Term) -> bool {
+    self.versions.is_disjoint(&other.versions) && !(self.allows_none && other.allows_none)
+  }
+
+  fn intersect(&self, other: &Term) -> Term {
+    Term {
+      package: self.package.clone(),
+      versions: self.versions.intersection(&other.versions).cloned().collect(),
+      allows_none: self.allows_none && other.allows_none,
+    }
+  }
+
+  fn union(&self, other: &Term) -> Term {
+    Term {
+      package: self.package.clone(),
+      versions: self.versions.union(&other.versions).cloned().collect(),
+      allows_none: self.allows_none || other.allows_none,
+    }
+  }
+}
+
+/** Why an incompatibility is known to hold. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cause {
+  // The root package must be selected
+  Root,
+  // The first term's versions depend on the dependency
+  Dependency { dependency: String, req: String },
+  // As above, but no version of the dependency matches at all
+  Unavailable { dependency: String, req: String },
+  // Derived from two earlier incompatibilities during conflict resolution
+  Derived(usize, usize),
+}
+
+/** A set of terms that cannot all hold at once. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Incompatibility {
+  pub terms: Vec<Term>,
+  pub cause: Cause,
+}
+
+#[derive(Debug, Clone)]
+struct Assignment {
+  term: Term,
+  decision_level: usize,
+  // The incompatibility this was derived from, or None for decisions
+  cause: Option<usize>,
+}
+
+enum Relation {
+  Satisfied,
+  AlmostSatisfied(usize),
+  Contradicted,
+  Inconclusive,
+}
+
+struct PackageInfo {
+  // Newest first
+  candidates: Vec<Candidate>,
+  universe: BTreeSet<Version>,
+}
+
+/**
+ * An incompatibility-driven solver, following PubGrub.
+ *
+ * Each decision is followed by unit propagation over the known incompatibilities. Conflicts are
+ * resolved by deriving new incompatibilities (which are learned, so that the same conflict is never
+ * revisited) and backjumping. When the derivation reaches an incompatibility that rules out the
+ * root, the chain of derivations explains why no solution exists.
+ */
+pub struct Solver<'a> {
+  source: &'a CandidateSource,
+  packages: HashMap<String, PackageInfo>,
+  incompatibilities: Vec<Incompatibility>,
+  incompatibilities_by_package: HashMap<String, Vec<usize>>,
+  added_dependencies: HashSet<(String, String, String, bool)>,
+  assignments: Vec<Assignment>,
+  accumulated_terms: HashMap<String, Term>,
+  decisions: BTreeMap<String, Version>,
+  // Versions to select whenever they are allowed, in lieu of the newest version
+  preferred_versions: BTreeMap<String, Version>,
+  decision_level: usize,
+  max_decisions: u64,
+  decision_count: u64,
+}
+
+impl<'a> Solver<'a> {
+  pub fn new(source: &'a CandidateSource,
+             roots: &[Requirement],
+             preferred_versions: &BTreeMap<String, Version>,
+             max_decisions: u64) -> Solver<'a> {
+    let mut solver = Solver {
+      source: source,
+      packages: HashMap::new(),
+      incompatibilities: Vec::new(),
+      incompatibilities_by_package: HashMap::new(),
+      added_dependencies: HashSet::new(),
+      assignments: Vec::new(),
+      accumulated_terms: HashMap::new(),
+      decisions: BTreeMap::new(),
+      preferred_versions: preferred_versions.clone(),
+      decision_level: 0,
+      max_decisions: max_decisions,
+      decision_count: 0,
+    };
+
+    let root_version = root_version();
+    let mut root_universe = BTreeSet::new();
+    root_universe.insert(root_version.clone());
+    solver.packages.insert(ROOT_PACKAGE.to_owned(), PackageInfo {
+      candidates: vec![Candidate {
+        version: root_version,
+        dependencies: roots.to_vec(),
+        dev_dependencies: Vec::new(),
+      }],
+      universe: root_universe,
+    });
+    solver
+  }
+
+  pub fn solve(&mut self) -> Result<Resolution, ResolutionErr> {
+    let root = self.positive_term(ROOT_PACKAGE);
+    let not_root = self.negate(&root);
+    self.add_incompatibility(Incompatibility {
+      terms: vec![not_root],
+      cause: Cause::Root,
+    });
+
+    let mut next_package = ROOT_PACKAGE.to_owned();
+    loop {
+      try!(self.propagate(next_package));
+      match try!(self.decide()) {
+        Some(package) => next_package = package,
+        None => return Ok(self.build_resolution()),
+      }
+    }
+  }
+
+  fn build_resolution(&self) -> Resolution {
+    let mut resolution = Resolution {
+      versions: BTreeMap::new(),
+      dependencies: BTreeMap::new(),
+      dev_dependencies: BTreeMap::new(),
+    };
+    for (package, version) in self.decisions.iter() {
+      if package == ROOT_PACKAGE {
+        continue
+      }
+      let candidate = self.get_candidate(package, version).unwrap();
+      resolution.versions.insert(package.clone(), version.clone());
+      resolution.dependencies.insert(package.clone(), get_names(&candidate.dependencies));
+      resolution.dev_dependencies.insert(package.clone(), get_names(&candidate.dev_dependencies));
+    }
+    resolution
+  }
+
+  /**
+   * Yields the versions of a package that the solver has seen.
+   *
+   * Packages that have never been referenced have no known versions.
+   */
+  pub fn get_known_versions(&self, package: &str) -> BTreeSet<Version> {
+    self.packages.get(package)
+      .map(|info| info.universe.clone())
+      .unwrap_or_default()
+  }
+
+  /** Yields an incompatibility that was recorded during solving. */
+  pub fn get_incompatibility(&self, id: usize) -> &Incompatibility {
+    &self.incompatibilities[id]
+  }
+
+  /** Yields the versions the package could ever be, loading them from the source if needed. */
+  fn get_universe(&mut self, package: &str) -> &BTreeSet<Version> {
+    self.load_package(package);
+    &self.packages.get(package).unwrap().universe
+  }
+
+  fn load_package(&mut self, package: &str) {
+    if self.packages.contains_key(package) {
+      return
+    }
+    let mut candidates = self.source.get_candidates(package);
+    candidates.sort_by(|a, b| b.version.cmp(&a.version));
+    candidates.dedup_by(|a, b| a.version == b.version);
+    let universe = candidates.iter().map(|c| c.version.clone()).collect();
+    self.packages.insert(package.to_owned(), PackageInfo {
+      candidates: candidates,
+      universe: universe,
+    });
+  }
+
+  fn get_candidate(&self, package: &str, version: &Version) -> Option<&Candidate> {
+    self.packages.get(package)
+      .and_then(|info| info.candidates.iter().find(|c| &c.version == version))
+  }
+
+  fn full_term(&mut self, package: &str) -> Term {
+    Term {
+      package: package.to_owned(),
+      versions: self.get_universe(package).clone(),
+      allows_none: true,
+    }
+  }
+
+  fn positive_term(&mut self, package: &str) -> Term {
+    Term {
+      package: package.to_owned(),
+      versions: self.get_universe(package).clone(),
+      allows_none: false,
+    }
+  }
+
+  pub fn negate(&mut self, term: &Term) -> Term {
+    let universe = self.get_universe(&term.package).clone();
+    Term {
+      package: term.package.clone(),
+      versions: universe.difference(&term.versions).cloned().collect(),
+      allows_none: !term.allows_none,
+    }
+  }
+
+  fn is_full(&mut self, term: &Term) -> bool {
+    let universe_len = self.get_universe(&term.package).len();
+    term.allows_none && term.versions.len() == universe_len
+  }
+
+  /**
+   * Records an incompatibility, merging terms that refer to the same package and dropping terms
+   * that always hold. Yields its id.
+   */
+  fn add_incompatibility(&mut self, incompatibility: Incompatibility) -> usize {
+    let mut merged_terms: Vec<Term> = Vec::new();
+    for term in incompatibility.terms.into_iter() {
+      let existing_idx = merged_terms.iter().position(|t| t.package == term.package);
+      match existing_idx {
+        Some(idx) => {
+          let merged = merged_terms[idx].intersect(&term);
+          merged_terms[idx] = merged;
+        },
+        None => merged_terms.push(term),
+      }
+    }
+    let mut terms = Vec::new();
+    for term in merged_terms.into_iter() {
+      if !self.is_full(&term) {
+        terms.push(term);
+      }
+    }
+
+    let id = self.incompatibilities.len();
+    for term in terms.iter() {
+      self.incompatibilities_by_package.entry(term.package.clone())
+        .or_insert_with(Vec::new)
+        .push(id);
+    }
+    self.incompatibilities.push(Incompatibility {
+      terms: terms,
+      cause: incompatibility.cause,
+    });
+    id
+  }
+
+  fn get_accumulated_term(&mut self, package: &str) -> Term {
+    match self.accumulated_terms.get(package) {
+      Some(term) => return term.clone(),
+      None => {},
+    }
+    self.full_term(package)
+  }
+
+  fn assign(&mut self, term: Term, cause: Option<usize>) {
+    let accumulated = self.get_accumulated_term(&term.package).intersect(&term);
+    self.accumulated_terms.insert(term.package.clone(), accumulated);
+    self.assignments.push(Assignment {
+      term: term,
+      decision_level: self.decision_level,
+      cause: cause,
+    });
+  }
+
+  fn relation(&mut self, incompatibility_id: usize) -> Relation {
+    let terms = self.incompatibilities[incompatibility_id].terms.clone();
+    let mut unsatisfied_idx = None;
+    for (idx, term) in terms.iter().enumerate() {
+      let accumulated = self.get_accumulated_term(&term.package);
+      if accumulated.is_subset_of(term) {
+        continue
+      }
+      if accumulated.is_disjoint_from(term) {
+        return Relation::Contradicted
+      }
+      if unsatisfied_idx.is_some() {
+        return Relation::Inconclusive
+      }
+      unsatisfied_idx = Some(idx);
+    }
+    match unsatisfied_idx {
+      Some(idx) => Relation::AlmostSatisfied(idx),
+      None => Relation::Satisfied,
+    }
+  }
+
+  fn propagate(&mut self, package: String) -> Result<(), ResolutionErr> {
+    let mut changed = vec![package];
+    while let Some(package) = changed.pop() {
+      let incompatibility_ids = self.incompatibilities_by_package.get(&package)
+        .cloned()
+        .unwrap_or_default();
+      for id in incompatibility_ids.into_iter().rev() {
+        match self.relation(id) {
+          Relation::Satisfied => {
+            let root_cause = try!(self.resolve_conflict(id));
+            match self.relation(root_cause) {
+              Relation::AlmostSatisfied(term_idx) => {
+                let term = self.incompatibilities[root_cause].terms[term_idx].clone();
+                let derived = self.negate(&term);
+                self.assign(derived, Some(root_cause));
+                changed.clear();
+                changed.push(term.package);
+              },
+              _ => panic!("A learned incompatibility must be almost satisfied after backjumping"),
+            }
+            break
+          },
+          Relation::AlmostSatisfied(term_idx) => {
+            let term = self.incompatibilities[id].terms[term_idx].clone();
+            let derived = self.negate(&term);
+            self.assign(derived, Some(id));
+            changed.push(term.package);
+          },
+          Relation::Contradicted | Relation::Inconclusive => {},
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /** Yields the index of the earliest assignment after which the term is satisfied. */
+  fn find_satisfier(&mut self, term: &Term, before_idx: usize, initial: Option<Term>) -> Option<usize> {
+    let mut accumulated = match initial {
+      Some(initial) => initial,
+      None => self.full_term(&term.package),
+    };
+    for idx in 0..before_idx {
+      if self.assignments[idx].term.package != term.package {
+        continue
+      }
+      accumulated = accumulated.intersect(&self.assignments[idx].term);
+      if accumulated.is_subset_of(term) {
+        return Some(idx)
+      }
+    }
+    None
+  }
+
+  fn is_terminal(&self, incompatibility: &Incompatibility) -> bool {
+    incompatibility.terms.is_empty()
+      || (incompatibility.terms.len() == 1
+          && incompatibility.terms[0].package == ROOT_PACKAGE
+          && incompatibility.terms[0].is_positive())
+  }
+
+  /**
+   * Derives an incompatibility that explains the conflict, and backjumps to the point where it is
+   * almost satisfied. Yields the learned incompatibility, or the explanation if the root itself is
+   * ruled out.
+   */
+  fn resolve_conflict(&mut self, conflict_id: usize) -> Result<usize, ResolutionErr> {
+    let mut incompatibility_id = conflict_id;
+    loop {
+      if self.is_terminal(&self.incompatibilities[incompatibility_id]) {
+        return Err(ResolutionErr::NoSolution(report::build_conflict_report(self, incompatibility_id)))
+      }
+
+      let terms = self.incompatibilities[incompatibility_id].terms.clone();
+      let assignment_count = self.assignments.len();
+      let mut satisfier_idx = 0;
+      let mut satisfied_term_idx = 0;
+      let mut term_satisfier_idxs = Vec::new();
+      for (term_idx, term) in terms.iter().enumerate() {
+        let idx = self.find_satisfier(term, assignment_count, None)
+          .expect("Conflicting incompatibility must be satisfied");
+        term_satisfier_idxs.push(idx);
+        if idx >= satisfier_idx {
+          satisfier_idx = idx;
+          satisfied_term_idx = term_idx;
+        }
+      }
+
+      let satisfier = self.assignments[satisfier_idx].clone();
+      let term = terms[satisfied_term_idx].clone();
+      let mut previous_level = 0;
+      for (term_idx, idx) in term_satisfier_idxs.iter().enumerate() {
+        if term_idx != satisfied_term_idx {
+          previous_level = ::std::cmp::max(previous_level, self.assignments[*idx].decision_level);
+        }
+      }
+      let satisfier_alone_satisfies = satisfier.term.is_subset_of(&term);
+      if !satisfier_alone_satisfies {
+        let previous_idx = self.find_satisfier(&term, satisfier_idx, Some(satisfier.term.clone()))
+          .expect("Terms not satisfied by their satisfier alone must have a previous satisfier");
+        previous_level = ::std::cmp::max(previous_level, self.assignments[previous_idx].decision_level);
+      }
+
+      if satisfier.cause.is_none() || previous_level < satisfier.decision_level {
+        self.backtrack(previous_level);
+        return Ok(incompatibility_id)
+      }
+
+      let cause_id = satisfier.cause.unwrap();
+      let mut prior_terms = Vec::new();
+      for (term_idx, other_term) in terms.iter().enumerate() {
+        if term_idx != satisfied_term_idx {
+          prior_terms.push(other_term.clone());
+        }
+      }
+      for cause_term in self.incompatibilities[cause_id].terms.iter() {
+        if cause_term.package != satisfier.term.package {
+          prior_terms.push(cause_term.clone());
+        }
+      }
+      if !satisfier_alone_satisfies {
+        let not_satisfier = self.negate(&satisfier.term);
+        prior_terms.push(not_satisfier.union(&term));
+      }
+
+      incompatibility_id = self.add_incompatibility(Incompatibility {
+        terms: prior_terms,
+        cause: Cause::Derived(incompatibility_id, cause_id),
+      });
+    }
+  }
+
+  fn backtrack(&mut self, decision_level: usize) {
+    while self.assignments.last().map(|a| a.decision_level > decision_level).unwrap_or(false) {
+      let assignment = self.assignments.pop().unwrap();
+      if assignment.cause.is_none() {
+        self.decisions.remove(&assignment.term.package);
+      }
+    }
+    self.decision_level = decision_level;
+
+    self.accumulated_terms.clear();
+    let assignments = self.assignments.clone();
+    for assignment in assignments.iter() {
+      let accumulated = self.get_accumulated_term(&assignment.term.package).intersect(&assignment.term);
+      self.accumulated_terms.insert(assignment.term.package.clone(), accumulated);
+    }
+  }
+
+  /**
+   * Selects the preferred (or else the newest) allowed version of the undecided package with the
+   * fewest allowed versions, and records its dependencies. Yields None once every required package
+   * has been decided.
+   */
+  fn decide(&mut self) -> Result<Option<String>, ResolutionErr> {
+    let mut next = None;
+    for (package, term) in self.accumulated_terms.iter() {
+      if !term.is_positive() || self.decisions.contains_key(package) {
+        continue
+      }
+      let is_better = match next {
+        None => true,
+        Some((ref best_package, best_count)) =>
+          (term.versions.len(), package) < (best_count, best_package),
+      };
+      if is_better {
+        next = Some((package.clone(), term.versions.len()));
+      }
+    }
+    let package = match next {
+      Some((package, _)) => package,
+      None => return Ok(None),
+    };
+
+    self.decision_count = self.decision_count + 1;
+    if self.decision_count > self.max_decisions {
+      return Err(ResolutionErr::StepLimitExceeded(self.max_decisions))
+    }
+
+    // Accumulated positive terms are never empty, as propagation never derives a contradiction
+    let version = {
+      let allowed_versions = &self.accumulated_terms.get(&package).unwrap().versions;
+      match self.preferred_versions.get(&package) {
+        Some(preferred_version) if allowed_versions.contains(preferred_version) => preferred_version.clone(),
+        _ => allowed_versions.iter().next_back().cloned().unwrap(),
+      }
+    };
+    self.add_dependency_incompatibilities(&package, &version);
+
+    if package != ROOT_PACKAGE {
+      self.decision_level = self.decision_level + 1;
+    }
+    let mut versions = BTreeSet::new();
+    versions.insert(version.clone());
+    self.assign(Term { package: package.clone(), versions: versions, allows_none: false }, None);
+    self.decisions.insert(package.clone(), version);
+    Ok(Some(package))
+  }
+
+  fn add_dependency_incompatibilities(&mut self, package: &str, version: &Version) {
+    let candidate = self.get_candidate(package, version).unwrap().clone();
+    let requirements = candidate.dependencies.iter()
+      .map(|r| (r, false))
+      .chain(candidate.dev_dependencies.iter().map(|r| (r, true)))
+      .collect::<Vec<_>>();
+
+    for (requirement, is_dev) in requirements.into_iter() {
+      let req_string = requirement.req.to_string();
+      let key = (package.to_owned(), requirement.name.clone(), req_string.clone(), is_dev);
+      if !self.added_dependencies.insert(key) {
+        continue
+      }
+
+      // Every version of the package that shares this exact requirement
+      let depending_versions = self.packages.get(package).unwrap().candidates.iter()
+        .filter(|c| {
+          let requirements = if is_dev { &c.dev_dependencies } else { &c.dependencies };
+          requirements.iter().any(|r| r.name == requirement.name && r.req.to_string() == req_string)
+        })
+        .map(|c| c.version.clone())
+        .collect::<BTreeSet<_>>();
+      let matching_versions = self.get_universe(&requirement.name).iter()
+        .filter(|v| requirement.req.matches(v))
+        .cloned()
+        .collect::<BTreeSet<_>>();
+
+      let depender = Term {
+        package: package.to_owned(),
+        versions: depending_versions,
+        allows_none: false,
+      };
+      let cause = if matching_versions.is_empty() {
+        Cause::Unavailable { dependency: requirement.name.clone(), req: req_string }
+      } else {
+        Cause::Dependency { dependency: requirement.name.clone(), req: req_string }
+      };
+      let not_dependency = self.negate(&Term {
+        package: requirement.name.clone(),
+        versions: matching_versions,
+        allows_none: false,
+      });
+      self.add_incompatibility(Incompatibility {
+        terms: vec![depender, not_dependency],
+        cause: cause,
+      });
+    }
+  }
+}
+
+fn root_version() -> Version {
+  Version::parse("0.0.0").unwrap()
+}
+
+fn get_names(requirements: &[Requirement]) -> BTreeSet<String> {
+  requirements.iter()
+    .map(|requirement| requirement.name.clone())
+    .collect()
+}
@@ -0,0 +1,292 @@
+use checksum::to_hex;
+use ring::digest;
+use ring::rand::SystemRandom;
+use ring::signature::Ed25519KeyPair;
+use ring::signature;
+use serde_json::Value;
+use serde_json;
+use snapshot::WorkspaceSnapshot;
+use untrusted::Input;
+
+/**
+ * A detached signature over a snapshot, published alongside it.
+ *
+ * The signature covers the canonical serialization of the snapshot as it was published, so it is
+ * independent of the format the snapshot is stored in.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SnapshotSignature {
+  pub snapshot_version: String,
+  // Hex encoded SHA-256 of the canonical serialization
+  pub sha256: String,
+  // Identifies the public key that verifies the signature
+  pub key_id: String,
+  // Hex encoded Ed25519 signature of the canonical serialization
+  pub signature: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerificationErr {
+  // No signature accompanies the snapshot
+  Unsigned,
+  // The signature was made for a snapshot with another version
+  VersionMismatch { expected: String, actual: String },
+  // The snapshot contents do not match the digest, so it has been modified
+  DigestMismatch { expected: String, actual: String },
+  // The signature was not made by any of the trusted keys
+  UntrustedKey(String),
+  // The signature is malformed, or was not made over this snapshot by the claimed key
+  BadSignature,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SigningErr {
+  InvalidKey,
+  KeyGenerationFailed,
+}
+
+/** A private key that snapshots are signed with. */
+pub struct SigningKey {
+  key_pair: Ed25519KeyPair,
+}
+
+/** A public key that snapshot signatures can be checked against. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicKey {
+  bytes: Vec<u8>,
+}
+
+impl SigningKey {
+  /** Loads a key from an unencrypted PKCS#8 (v2) document. */
+  pub fn from_pkcs8(pkcs8_bytes: &[u8]) -> Result<SigningKey, SigningErr> {
+    Ed25519KeyPair::from_pkcs8(Input::from(pkcs8_bytes))
+      .map(|key_pair| SigningKey { key_pair: key_pair })
+      .map_err(|_| SigningErr::InvalidKey)
+  }
+
+  /** Generates a fresh key, as a PKCS#8 document suitable for SigningKey::from_pkcs8. */
+  pub fn generate_pkcs8() -> Result<Vec<u8>, SigningErr> {
+    Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+      .map(|pkcs8_bytes| pkcs8_bytes.to_vec())
+      .map_err(|_| SigningErr::KeyGenerationFailed)
+  }
+
+  pub fn get_public_key(&self) -> PublicKey {
+    PublicKey {
+      bytes: self.key_pair.public_key_bytes().to_vec(),
+    }
+  }
+}
+
+impl PublicKey {
+  /** Parses a hex encoded Ed25519 public key. */
+  pub fn from_hex(raw: &str) -> Result<PublicKey, SigningErr> {
+    match from_hex(raw.trim()) {
+      Some(ref bytes) if bytes.len() == 32 => Ok(PublicKey { bytes: bytes.clone() }),
+      _ => Err(SigningErr::InvalidKey),
+    }
+  }
+
+  pub fn to_hex(&self) -> String {
+    to_hex(&self.bytes)
+  }
+
+  /** A short identifier for the key: the first 8 bytes of its SHA-256, hex encoded. */
+  pub fn get_key_id(&self) -> String {
+    to_hex(&digest::digest(&digest::SHA256, &self.bytes).as_ref()[0..8])
+  }
+}
+
+/**
+ * Serializes the snapshot into the canonical form that digests and signatures are computed over.
+ *
+ * This is compact JSON with object keys in sorted order.
+ */
+pub fn canonicalize(snapshot: &WorkspaceSnapshot) -> Vec<u8> {
+  canonicalize_value(&serde_json::to_value(snapshot).expect("Snapshots are always representable as JSON"))
+}
+
+/**
+ * As canonicalize, but for a snapshot as it was published (parsed without its schema).
+ *
+ * Verification must use this form: fields added to WorkspaceSnapshot since the snapshot was
+ * published would otherwise appear in its canonical form, and change its digest.
+ */
+pub fn canonicalize_value(published_snapshot: &Value) -> Vec<u8> {
+  // Values hold their object keys in a BTreeMap, so this sorts every object by key
+  serde_json::to_vec(published_snapshot).expect("JSON values are always serializable")
+}
+
+/** Yields the hex encoded SHA-256 of the snapshot's canonical form. */
+pub fn get_digest(snapshot: &WorkspaceSnapshot) -> String {
+  to_hex(digest::digest(&digest::SHA256, &canonicalize(snapshot)).as_ref())
+}
+
+/** As get_digest, but for a snapshot as it was published. */
+pub fn get_published_digest(published_snapshot: &Value) -> String {
+  to_hex(digest::digest(&digest::SHA256, &canonicalize_value(published_snapshot)).as_ref())
+}
+
+pub fn sign_snapshot(snapshot: &WorkspaceSnapshot, key: &SigningKey) -> SnapshotSignature {
+  let canonical_bytes = canonicalize(snapshot);
+  SnapshotSignature {
+    snapshot_version: snapshot.version.clone(),
+    sha256: to_hex(digest::digest(&digest::SHA256, &canonical_bytes).as_ref()),
+    key_id: key.get_public_key().get_key_id(),
+    signature: to_hex(key.key_pair.sign(&canonical_bytes).as_ref()),
+  }
+}
+
+/**
+ * Checks that the snapshot, as it was published, is unmodified and signed by one of the trusted
+ * keys.
+ *
+ * A missing signature is an error: consumers should never fall back to unsigned snapshots.
+ */
+pub fn verify_snapshot(published_snapshot: &Value,
+                       signature: Option<&SnapshotSignature>,
+                       trusted_keys: &[PublicKey]) -> Result<(), VerificationErr> {
+  let signature = match signature {
+    Some(signature) => signature,
+    None => return Err(VerificationErr::Unsigned),
+  };
+  let version = published_snapshot.get("version").and_then(|version| version.as_str()).unwrap_or("");
+  if signature.snapshot_version != version {
+    return Err(VerificationErr::VersionMismatch {
+      expected: signature.snapshot_version.clone(),
+      actual: version.to_owned(),
+    })
+  }
+  try!(verify_digest(published_snapshot, &signature.sha256));
+
+  let key = match trusted_keys.iter().find(|key| key.get_key_id() == signature.key_id) {
+    Some(key) => key,
+    None => return Err(VerificationErr::UntrustedKey(signature.key_id.clone())),
+  };
+  let signature_bytes = try!(from_hex(&signature.signature).ok_or(VerificationErr::BadSignature));
+  signature::verify(&signature::ED25519,
+                    Input::from(&key.bytes[..]),
+                    Input::from(&canonicalize_value(published_snapshot)[..]),
+                    Input::from(&signature_bytes[..]))
+    .map_err(|_| VerificationErr::BadSignature)
+}
+
+/** Checks the published snapshot against a digest that was pinned elsewhere, such as in a project config. */
+pub fn verify_digest(published_snapshot: &Value, expected_sha256: &str) -> Result<(), VerificationErr> {
+  let actual_sha256 = get_published_digest(published_snapshot);
+  if actual_sha256 != expected_sha256.to_lowercase() {
+    return Err(VerificationErr::DigestMismatch {
+      expected: expected_sha256.to_owned(),
+      actual: actual_sha256,
+    })
+  }
+  Ok(())
+}
+
+fn from_hex(raw: &str) -> Option<Vec<u8>> {
+  let chars = raw.chars().collect::<Vec<_>>();
+  if chars.len() % 2 != 0 {
+    return None
+  }
+  let mut bytes = Vec::new();
+  for pair in chars.chunks(2) {
+    match (pair[0].to_digit(16), pair[1].to_digit(16)) {
+      (Some(high), Some(low)) => bytes.push((high * 16 + low) as u8),
+      _ => return None,
+    }
+  }
+  Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::Value;
+  use serde_json;
+  use serde_yaml;
+  use signing::PublicKey;
+  use signing::SigningKey;
+  use signing::SnapshotSignature;
+  use signing::VerificationErr;
+  use signing;
+  use snapshot::WorkspaceSnapshot;
+
+  // A snapshot as published before CrateSnapshot.features and ResolutionType.path were added
+  const OLDER_SCHEMA_SNAPSHOT: &'static str = "---
+version: nightly-2017-11-20
+members:
+  - libc
+details:
+  - name: libc
+    version: \"0.2.33\"
+    maintainer: someone@example.com
+    dependencies: []
+    dev_dependencies: []
+    dev_only: false
+    resolution_type:
+      crates_io: true
+      git: ~
+";
+
+  fn snapshot() -> WorkspaceSnapshot {
+    WorkspaceSnapshot {
+      version: "nightly-2017-11-20".to_owned(),
+      members: vec!["libc".to_owned()],
+      details: Vec::new(),
+    }
+  }
+
+  fn published(snapshot: &WorkspaceSnapshot) -> Value {
+    serde_json::to_value(snapshot).unwrap()
+  }
+
+  #[test]
+  fn test_signed_snapshots_verify() {
+    let key = SigningKey::from_pkcs8(&SigningKey::generate_pkcs8().unwrap()).unwrap();
+    let signature = signing::sign_snapshot(&snapshot(), &key);
+
+    assert_eq!(signing::verify_snapshot(&published(&snapshot()), Some(&signature), &[key.get_public_key()]),
+               Ok(()));
+    assert_eq!(signing::verify_snapshot(&published(&snapshot()), None, &[key.get_public_key()]),
+               Err(VerificationErr::Unsigned));
+    let other_key = SigningKey::from_pkcs8(&SigningKey::generate_pkcs8().unwrap()).unwrap();
+    assert_eq!(signing::verify_snapshot(&published(&snapshot()), Some(&signature), &[other_key.get_public_key()]),
+               Err(VerificationErr::UntrustedKey(signature.key_id.clone())));
+  }
+
+  #[test]
+  fn test_tampered_snapshots_are_rejected() {
+    let key = SigningKey::from_pkcs8(&SigningKey::generate_pkcs8().unwrap()).unwrap();
+    let signature = signing::sign_snapshot(&snapshot(), &key);
+
+    let mut tampered_snapshot = snapshot();
+    tampered_snapshot.members.push("evil".to_owned());
+    match signing::verify_snapshot(&published(&tampered_snapshot), Some(&signature), &[key.get_public_key()]) {
+      Err(VerificationErr::DigestMismatch { .. }) => {},
+      other => panic!("Expected a digest mismatch, got {:?}", other),
+    }
+
+    // A digest that matches the tampered contents doesn't help without a matching signature
+    let mut forged_signature = signature.clone();
+    forged_signature.sha256 = signing::get_digest(&tampered_snapshot);
+    assert_eq!(signing::verify_snapshot(&published(&tampered_snapshot), Some(&forged_signature), &[key.get_public_key()]),
+               Err(VerificationErr::BadSignature));
+  }
+
+  #[test]
+  fn test_snapshots_signed_under_an_older_schema_verify() {
+    let key = PublicKey::from_hex("62850059c89a098618978ef19bc1f5bcfd006c49cfc8ec63759cf418df631024").unwrap();
+    let signature = SnapshotSignature {
+      snapshot_version: "nightly-2017-11-20".to_owned(),
+      sha256: "cb313d0a24de907450d71b5ae0b284bb0758c7b823f77e4706e5b11a3866d407".to_owned(),
+      key_id: "03a3f2b92b2bd458".to_owned(),
+      signature: "3886f913e047816b2991555e65f0f0be4b4dc6a1110373b555325428e4a2833a\
+                  c2ae301bf26bb5460a886d5329594a9eb2e1c30f45da98e28e7a458d38889801".to_owned(),
+    };
+    let published_snapshot = serde_yaml::from_str::<Value>(OLDER_SCHEMA_SNAPSHOT).unwrap();
+
+    assert_eq!(signing::verify_snapshot(&published_snapshot, Some(&signature), &[key.clone()]), Ok(()));
+    // Read into the current schema, the snapshot gains fields, and so would no longer match
+    let snapshot = serde_yaml::from_str::<WorkspaceSnapshot>(OLDER_SCHEMA_SNAPSHOT).unwrap();
+    assert!(signing::get_digest(&snapshot) != signature.sha256);
+  }
+}
@@ -0,0 +1,303 @@
+use semver::Version;
+use snapshot::CrateSnapshot;
+use snapshot::ResolutionType;
+use snapshot::WorkspaceSnapshot;
+use std::collections::BTreeMap;
+
+/** Everything that changed between two snapshots, with crates in name order. */
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct SnapshotDiff {
+  pub from_version: String,
+  pub to_version: String,
+  pub added: Vec<CrateVersion>,
+  pub removed: Vec<CrateVersion>,
+  pub version_changes: Vec<VersionChange>,
+  pub maintainer_changes: Vec<MaintainerChange>,
+  pub resolution_changes: Vec<ResolutionChange>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct CrateVersion {
+  pub name: String,
+  pub version: String,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct VersionChange {
+  pub name: String,
+  pub from: String,
+  pub to: String,
+  pub kind: BumpKind,
+  // Filled in by callers that can read the crate's sources
+  pub changelog: Option<ChangelogExcerpt>,
+}
+
+/** How a version change would affect dependents, by Cargo's reading of semver. */
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BumpKind {
+  Patch,
+  Minor,
+  // Incompatible: the major version changed, or the minor version of a 0.x crate
+  Major,
+  Downgrade,
+  // Either version is not valid semver
+  Unknown,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct MaintainerChange {
+  pub name: String,
+  pub from: Option<String>,
+  pub to: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct ResolutionChange {
+  pub name: String,
+  pub from: Option<ResolutionType>,
+  pub to: Option<ResolutionType>,
+}
+
+/** The parts of a changelog file that cover a version change. */
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct ChangelogExcerpt {
+  pub file_name: String,
+  pub excerpt: String,
+}
+
+/** Compares two snapshots, crate by crate. */
+pub fn diff_snapshots(from: &WorkspaceSnapshot, to: &WorkspaceSnapshot) -> SnapshotDiff {
+  let from_crates = get_crates_by_name(from);
+  let to_crates = get_crates_by_name(to);
+
+  let mut diff = SnapshotDiff {
+    from_version: from.version.clone(),
+    to_version: to.version.clone(),
+    added: Vec::new(),
+    removed: Vec::new(),
+    version_changes: Vec::new(),
+    maintainer_changes: Vec::new(),
+    resolution_changes: Vec::new(),
+  };
+
+  for (name, from_crate) in from_crates.iter() {
+    if !to_crates.contains_key(name) {
+      diff.removed.push(CrateVersion {
+        name: name.to_string(),
+        version: from_crate.version.clone(),
+      });
+    }
+  }
+
+  for (name, to_crate) in to_crates.iter() {
+    let from_crate = match from_crates.get(name) {
+      Some(from_crate) => from_crate,
+      None => {
+        diff.added.push(CrateVersion {
+          name: name.to_string(),
+          version: to_crate.version.clone(),
+        });
+        continue
+      },
+    };
+
+    if from_crate.version != to_crate.version {
+      diff.version_changes.push(VersionChange {
+        name: name.to_string(),
+        from: from_crate.version.clone(),
+        to: to_crate.version.clone(),
+        kind: classify_bump(&from_crate.version, &to_crate.version),
+        changelog: None,
+      });
+    }
+    if from_crate.maintainer != to_crate.maintainer {
+      diff.maintainer_changes.push(MaintainerChange {
+        name: name.to_string(),
+        from: from_crate.maintainer.clone(),
+        to: to_crate.maintainer.clone(),
+      });
+    }
+    if from_crate.resolution_type != to_crate.resolution_type {
+      diff.resolution_changes.push(ResolutionChange {
+        name: name.to_string(),
+        from: from_crate.resolution_type.clone(),
+        to: to_crate.resolution_type.clone(),
+      });
+    }
+  }
+
+  diff
+}
+
+/** Classifies a change from one version to another. */
+pub fn classify_bump(raw_from: &str, raw_to: &str) -> BumpKind {
+  let (from, to) = match (Version::parse(raw_from), Version::parse(raw_to)) {
+    (Ok(from), Ok(to)) => (from, to),
+    _ => return BumpKind::Unknown,
+  };
+
+  if to < from {
+    BumpKind::Downgrade
+  } else if get_compatibility_class(raw_from) != get_compatibility_class(raw_to) {
+    BumpKind::Major
+  } else if from.minor != to.minor {
+    BumpKind::Minor
+  } else {
+    BumpKind::Patch
+  }
+}
+
+/**
+ * Yields the part of a version that Cargo considers when deciding whether two versions are
+ * compatible: the major version, or the first nonzero component for 0.x versions.
+ */
+pub fn get_compatibility_class(raw_version: &str) -> String {
+  match Version::parse(raw_version) {
+    Ok(ref version) if version.major > 0 => version.major.to_string(),
+    Ok(ref version) if version.minor > 0 => format!("0.{}", version.minor),
+    Ok(ref version) => format!("0.0.{}", version.patch),
+    Err(_) => raw_version.to_owned(),
+  }
+}
+
+/**
+ * Pulls the sections of a changelog that cover the versions after `from`, up to and including
+ * `to`.
+ *
+ * Sections start at headings (either "#" style, or underlined with "=" or "-") that mention a
+ * version. Yields None if no such section was found.
+ */
+pub fn extract_changelog_excerpt(contents: &str, raw_from: &str, raw_to: &str) -> Option<String> {
+  let (from, to) = match (Version::parse(raw_from), Version::parse(raw_to)) {
+    (Ok(from), Ok(to)) => (from, to),
+    _ => return None,
+  };
+
+  let lines = contents.lines().collect::<Vec<_>>();
+  let mut excerpt_lines = Vec::new();
+  let mut in_relevant_section = false;
+  for (idx, line) in lines.iter().enumerate() {
+    let is_heading = line.starts_with('#') || lines.get(idx + 1).map(|next| is_underline(next)).unwrap_or(false);
+    if is_heading {
+      if let Some(version) = find_version(line) {
+        in_relevant_section = from < version && version <= to;
+      }
+    }
+    if in_relevant_section {
+      excerpt_lines.push(*line);
+    }
+  }
+
+  if excerpt_lines.is_empty() {
+    return None
+  }
+  Some(excerpt_lines.join("\n").trim().to_owned())
+}
+
+fn get_crates_by_name(snapshot: &WorkspaceSnapshot) -> BTreeMap<&str, &CrateSnapshot> {
+  snapshot.details.iter()
+    .map(|details| (details.name.as_str(), details))
+    .collect()
+}
+
+fn is_underline(line: &str) -> bool {
+  let trimmed = line.trim();
+  trimmed.len() >= 3 && (trimmed.chars().all(|c| c == '=') || trimmed.chars().all(|c| c == '-'))
+}
+
+/** Finds the first word in a heading that is a version, such as "1.2.3", "v1.2.3" or "[1.2.3]". */
+fn find_version(heading: &str) -> Option<Version> {
+  heading.split(|c: char| c.is_whitespace() || "#[](),:".contains(c))
+    .map(|word| word.trim_left_matches('v'))
+    .filter_map(|word| Version::parse(word).ok())
+    .next()
+}
+
+#[cfg(test)]
+mod tests {
+  use snapshot::CrateSnapshot;
+  use snapshot::GitResolution;
+  use snapshot::ResolutionType;
+  use snapshot::WorkspaceSnapshot;
+  use snapshot::testing::crate_snapshot;
+  use snapshot_diff::BumpKind;
+  use snapshot_diff::CrateVersion;
+  use snapshot_diff;
+
+  #[test]
+  fn test_diff_classifies_every_change() {
+    let from = WorkspaceSnapshot {
+      version: "lts-1.0".to_owned(),
+      members: Vec::new(),
+      details: vec![
+        crate_snapshot("libc", "0.2.29", &[]),
+        crate_snapshot("old", "0.1.0", &[]),
+        CrateSnapshot {
+          maintainer: Some("a@example.com".to_owned()),
+          ..crate_snapshot("rand", "0.3.18", &[])
+        },
+        crate_snapshot("regex", "0.2.2", &[]),
+        crate_snapshot("serde", "1.0.15", &[]),
+        crate_snapshot("tiny", "0.0.1", &[]),
+      ],
+    };
+    let mut forked_regex = crate_snapshot("regex", "0.2.2", &[]);
+    forked_regex.resolution_type = Some(ResolutionType {
+      crates_io: None,
+      git: Some(GitResolution {
+        repository: "https://example.com/regex".to_owned(),
+        revision: "abc123".to_owned(),
+      }),
+      path: None,
+    });
+    let to = WorkspaceSnapshot {
+      version: "lts-1.1".to_owned(),
+      members: Vec::new(),
+      details: vec![
+        crate_snapshot("libc", "0.2.30", &[]),
+        crate_snapshot("new", "1.0.0", &[]),
+        CrateSnapshot {
+          maintainer: Some("b@example.com".to_owned()),
+          ..crate_snapshot("rand", "0.4.1", &[])
+        },
+        forked_regex,
+        crate_snapshot("serde", "1.1.0", &[]),
+        crate_snapshot("tiny", "0.0.2", &[]),
+      ],
+    };
+
+    let diff = snapshot_diff::diff_snapshots(&from, &to);
+    assert_eq!(diff.added, vec![CrateVersion { name: "new".to_owned(), version: "1.0.0".to_owned() }]);
+    assert_eq!(diff.removed, vec![CrateVersion { name: "old".to_owned(), version: "0.1.0".to_owned() }]);
+    let bumps = diff.version_changes.iter()
+      .map(|change| (change.name.as_str(), change.kind))
+      .collect::<Vec<_>>();
+    assert_eq!(bumps, vec![
+      ("libc", BumpKind::Patch),
+      ("rand", BumpKind::Major),
+      ("serde", BumpKind::Minor),
+      // Cargo considers every 0.0.x release incompatible with the others
+      ("tiny", BumpKind::Major),
+    ]);
+    assert_eq!(diff.maintainer_changes.len(), 1);
+    assert_eq!(diff.maintainer_changes[0].to, Some("b@example.com".to_owned()));
+    assert_eq!(diff.resolution_changes.len(), 1);
+    assert_eq!(diff.resolution_changes[0].name, "regex".to_owned());
+    assert_eq!(snapshot_diff::classify_bump("1.2.0", "1.1.0"), BumpKind::Downgrade);
+  }
+
+  #[test]
+  fn test_changelog_excerpts_cover_only_the_new_versions() {
+    let changelog = "# Changelog\n\n## [0.2.31]\n- Future\n\n## [0.2.30] - 2017-11-01\n- Added a thing\n\n\
+                     ## v0.2.29\n- Old news\n";
+    assert_eq!(snapshot_diff::extract_changelog_excerpt(changelog, "0.2.29", "0.2.30"),
+               Some("## [0.2.30] - 2017-11-01\n- Added a thing".to_owned()));
+
+    let releases = "Version 1.1.0 (2017-10-01)\n==========================\n\n- Faster\n\n\
+                    Version 1.0.0\n=============\n\n- First\n";
+    assert_eq!(snapshot_diff::extract_changelog_excerpt(releases, "1.0.0", "1.1.0"),
+               Some("Version 1.1.0 (2017-10-01)\n==========================\n\n- Faster".to_owned()));
+    assert_eq!(snapshot_diff::extract_changelog_excerpt("No versions here", "1.0.0", "1.1.0"), None);
+  }
+}
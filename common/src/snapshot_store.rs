@@ -0,0 +1,265 @@
+use chrono::NaiveDate;
+use serde_json::Value;
+use serde_yaml;
+use signing::PublicKey;
+use signing::SnapshotSignature;
+use signing::VerificationErr;
+use signing;
+use snapshot::WorkspaceSnapshot;
+use std::fmt;
+use std::fs::File;
+use std::fs;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use verification::VerificationReport;
+
+/** The directory, relative to the root of a snapshot repository, that holds every snapshot. */
+pub const SNAPSHOT_DIRECTORY: &'static str = "snapshots";
+
+/** The name of a published snapshot, which is also the name of its tag. */
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SnapshotName {
+  // nightly-YYYY-MM-DD
+  Nightly(NaiveDate),
+  // lts-N.M
+  Lts { major: u32, minor: u32 },
+}
+
+/** A stream of snapshots, named by its alias. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Channel {
+  Nightly,
+  Lts,
+}
+
+/** A reference to a snapshot, either by its exact name or as the latest of a channel. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotSelector {
+  Exact(SnapshotName),
+  Latest(Channel),
+}
+
+#[derive(Debug)]
+pub enum SnapshotStoreErr {
+  IoErr(io::Error),
+  YamlErr(serde_yaml::Error),
+  // Neither a snapshot name nor an alias
+  InvalidName(String),
+  // A valid name or alias, but no snapshot in the store matches it
+  UnknownSnapshot(String),
+  VerificationErr(VerificationErr),
+}
+
+define_from_error_boilerplate!(io::Error, SnapshotStoreErr, SnapshotStoreErr::IoErr);
+define_from_error_boilerplate!(serde_yaml::Error, SnapshotStoreErr, SnapshotStoreErr::YamlErr);
+define_from_error_boilerplate!(VerificationErr, SnapshotStoreErr, SnapshotStoreErr::VerificationErr);
+
+impl SnapshotName {
+  /** Parses a name of the form "nightly-YYYY-MM-DD" or "lts-N.M". */
+  pub fn parse(name: &str) -> Option<SnapshotName> {
+    if name.starts_with("nightly-") {
+      return NaiveDate::parse_from_str(&name["nightly-".len()..], "%Y-%m-%d").ok()
+        .map(SnapshotName::Nightly)
+    }
+    if name.starts_with("lts-") {
+      let mut parts = name["lts-".len()..].splitn(2, '.');
+      let major = parts.next().and_then(|part| parse_number(part));
+      let minor = parts.next().and_then(|part| parse_number(part));
+      return match (major, minor) {
+        (Some(major), Some(minor)) => Some(SnapshotName::Lts { major: major, minor: minor }),
+        _ => None,
+      }
+    }
+    None
+  }
+
+  pub fn get_channel(&self) -> Channel {
+    match *self {
+      SnapshotName::Nightly(_) => Channel::Nightly,
+      SnapshotName::Lts { .. } => Channel::Lts,
+    }
+  }
+
+  /** Yields the path of the snapshot's file, relative to the root of a snapshot repository. */
+  pub fn get_relative_path(&self) -> PathBuf {
+    PathBuf::from(SNAPSHOT_DIRECTORY).join(format!("{}.yaml", self))
+  }
+
+  /** As above, but for the snapshot's detached signature. */
+  pub fn get_signature_relative_path(&self) -> PathBuf {
+    PathBuf::from(SNAPSHOT_DIRECTORY).join(format!("{}.sig", self))
+  }
+
+  /** As above, but for the snapshot's verification report. */
+  pub fn get_verification_relative_path(&self) -> PathBuf {
+    PathBuf::from(SNAPSHOT_DIRECTORY).join(format!("{}.verification.yaml", self))
+  }
+}
+
+impl fmt::Display for SnapshotName {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      SnapshotName::Nightly(ref date) => write!(f, "nightly-{}", date.format("%Y-%m-%d")),
+      SnapshotName::Lts { major, minor } => write!(f, "lts-{}.{}", major, minor),
+    }
+  }
+}
+
+impl SnapshotSelector {
+  /** Parses either a snapshot name, or one of the channel aliases "nightly" and "lts". */
+  pub fn parse(name_or_alias: &str) -> Option<SnapshotSelector> {
+    match name_or_alias {
+      "nightly" => Some(SnapshotSelector::Latest(Channel::Nightly)),
+      "lts" => Some(SnapshotSelector::Latest(Channel::Lts)),
+      name => SnapshotName::parse(name).map(SnapshotSelector::Exact),
+    }
+  }
+
+  /** Picks the matching snapshot from those that are available. */
+  pub fn select(&self, available_names: &[SnapshotName]) -> Option<SnapshotName> {
+    match *self {
+      SnapshotSelector::Exact(ref name) => available_names.iter().find(|n| *n == name).cloned(),
+      SnapshotSelector::Latest(channel) => available_names.iter()
+        .filter(|n| n.get_channel() == channel)
+        .max()
+        .cloned(),
+    }
+  }
+}
+
+/**
+ * Lists the snapshots in a snapshot repository checkout, oldest first within each channel.
+ *
+ * Files that are not named like snapshots are ignored.
+ */
+pub fn list_snapshots<P: AsRef<Path>>(repository_root: P) -> Result<Vec<SnapshotName>, SnapshotStoreErr> {
+  let snapshot_directory = repository_root.as_ref().join(SNAPSHOT_DIRECTORY);
+  let dir_iter = match fs::read_dir(&snapshot_directory) {
+    Ok(dir_iter) => dir_iter,
+    Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+    Err(err) => return Err(SnapshotStoreErr::IoErr(err)),
+  };
+
+  let mut names = Vec::new();
+  for entry in dir_iter {
+    let path = try!(entry).path();
+    if path.extension().and_then(|ext| ext.to_str()) != Some("yaml") {
+      continue
+    }
+    if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()).and_then(SnapshotName::parse) {
+      names.push(name);
+    }
+  }
+  names.sort();
+  Ok(names)
+}
+
+/** Resolves a snapshot name or alias against the snapshots in a snapshot repository checkout. */
+pub fn resolve_name<P: AsRef<Path>>(repository_root: P, name_or_alias: &str) -> Result<SnapshotName, SnapshotStoreErr> {
+  let selector = try!(SnapshotSelector::parse(name_or_alias)
+    .ok_or_else(|| SnapshotStoreErr::InvalidName(name_or_alias.to_owned())));
+  let available_names = try!(list_snapshots(repository_root));
+  selector.select(&available_names)
+    .ok_or_else(|| SnapshotStoreErr::UnknownSnapshot(name_or_alias.to_owned()))
+}
+
+/** Reads a snapshot, by name or alias, from a snapshot repository checkout. */
+pub fn read_snapshot<P: AsRef<Path>>(repository_root: P, name_or_alias: &str) -> Result<WorkspaceSnapshot, SnapshotStoreErr> {
+  let name = try!(resolve_name(repository_root.as_ref(), name_or_alias));
+  let contents = try!(read_snapshot_contents(repository_root, &name));
+  Ok(try!(serde_yaml::from_str::<WorkspaceSnapshot>(&contents)))
+}
+
+/** Reads the snapshot file exactly as it was published. */
+fn read_snapshot_contents<P: AsRef<Path>>(repository_root: P, name: &SnapshotName) -> Result<String, SnapshotStoreErr> {
+  let mut contents = String::new();
+  try!(File::open(repository_root.as_ref().join(name.get_relative_path()))
+    .and_then(|mut f| f.read_to_string(&mut contents)));
+  Ok(contents)
+}
+
+/** Reads the signature of a snapshot from a snapshot repository checkout, if it was signed. */
+pub fn read_signature<P: AsRef<Path>>(repository_root: P, name: &SnapshotName) -> Result<Option<SnapshotSignature>, SnapshotStoreErr> {
+  let mut contents = String::new();
+  match File::open(repository_root.as_ref().join(name.get_signature_relative_path())) {
+    Ok(mut file) => try!(file.read_to_string(&mut contents)),
+    Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+    Err(err) => return Err(SnapshotStoreErr::IoErr(err)),
+  };
+  Ok(Some(try!(serde_yaml::from_str::<SnapshotSignature>(&contents))))
+}
+
+/** Reads the verification report of a snapshot from a snapshot repository checkout, if it was verified. */
+pub fn read_verification_report<P: AsRef<Path>>(repository_root: P, name: &SnapshotName) -> Result<Option<VerificationReport>, SnapshotStoreErr> {
+  let mut contents = String::new();
+  match File::open(repository_root.as_ref().join(name.get_verification_relative_path())) {
+    Ok(mut file) => try!(file.read_to_string(&mut contents)),
+    Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+    Err(err) => return Err(SnapshotStoreErr::IoErr(err)),
+  };
+  Ok(Some(try!(serde_yaml::from_str::<VerificationReport>(&contents))))
+}
+
+/**
+ * As read_snapshot, but rejects snapshots that are unsigned, modified, or not signed by one of the
+ * trusted keys.
+ *
+ * The signature is checked against the snapshot as it was published, rather than as it is read
+ * into the current WorkspaceSnapshot, so that adding fields does not invalidate older signatures.
+ */
+pub fn read_verified_snapshot<P: AsRef<Path>>(repository_root: P,
+                                              name_or_alias: &str,
+                                              trusted_keys: &[PublicKey]) -> Result<WorkspaceSnapshot, SnapshotStoreErr> {
+  let name = try!(resolve_name(repository_root.as_ref(), name_or_alias));
+  let contents = try!(read_snapshot_contents(repository_root.as_ref(), &name));
+  let signature = try!(read_signature(repository_root.as_ref(), &name));
+  try!(signing::verify_snapshot(&try!(serde_yaml::from_str::<Value>(&contents)), signature.as_ref(), trusted_keys));
+  Ok(try!(serde_yaml::from_str::<WorkspaceSnapshot>(&contents)))
+}
+
+fn parse_number(raw: &str) -> Option<u32> {
+  // Leading zeros (and signs) would make two spellings of the same name
+  if raw.is_empty() || (raw.len() > 1 && raw.starts_with('0')) || !raw.chars().all(|c| c.is_digit(10)) {
+    return None
+  }
+  raw.parse::<u32>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::NaiveDate;
+  use snapshot_store::Channel;
+  use snapshot_store::SnapshotName;
+  use snapshot_store::SnapshotSelector;
+
+  #[test]
+  fn test_names_round_trip() {
+    for raw_name in ["nightly-2017-11-20", "lts-1.0", "lts-12.3"].iter() {
+      assert_eq!(SnapshotName::parse(raw_name).unwrap().to_string(), *raw_name);
+    }
+    assert_eq!(SnapshotName::parse("nightly-2017-11-20"),
+               Some(SnapshotName::Nightly(NaiveDate::from_ymd(2017, 11, 20))));
+    for raw_name in ["nightly-2017-13-01", "lts-1", "lts-01.0", "lts-1.x", "beta-1.0"].iter() {
+      assert_eq!(SnapshotName::parse(raw_name), None);
+    }
+  }
+
+  #[test]
+  fn test_aliases_select_the_latest_of_their_channel() {
+    let available_names = ["lts-1.9", "lts-1.10", "nightly-2017-11-19", "nightly-2017-11-20"].iter()
+      .map(|raw_name| SnapshotName::parse(raw_name).unwrap())
+      .collect::<Vec<_>>();
+
+    assert_eq!(SnapshotSelector::parse("lts"), Some(SnapshotSelector::Latest(Channel::Lts)));
+    assert_eq!(SnapshotSelector::parse("lts").unwrap().select(&available_names),
+               SnapshotName::parse("lts-1.10"));
+    assert_eq!(SnapshotSelector::parse("nightly").unwrap().select(&available_names),
+               SnapshotName::parse("nightly-2017-11-20"));
+    assert_eq!(SnapshotSelector::parse("lts-1.9").unwrap().select(&available_names),
+               SnapshotName::parse("lts-1.9"));
+    assert_eq!(SnapshotSelector::parse("lts-2.0").unwrap().select(&available_names), None);
+  }
+}
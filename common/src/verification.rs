@@ -0,0 +1,77 @@
+/** The outcome of building every member of a snapshot, as published alongside the snapshot. */
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct VerificationReport {
+  pub snapshot_version: String,
+  // One entry per member, in name order
+  pub crates: Vec<CrateVerification>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CrateVerification {
+  pub name: String,
+  pub version: String,
+  // Failed if the member did not build, or if its tests failed unexpectedly
+  pub outcome: VerificationOutcome,
+  pub duration_millis: u64,
+  // The tail of the build's combined stdout and stderr
+  pub log: String,
+  // Builds with other combinations of features, if the verifier was configured to make them
+  #[serde(default)]
+  pub feature_builds: Vec<FeatureBuildVerification>,
+  // Only present for members whose tests were run
+  #[serde(default)]
+  pub tests: Option<TestVerification>,
+}
+
+/** The outcome of building a member with one combination of its features. */
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FeatureBuildVerification {
+  // Such as "no-default-features", "all-features" or "feature/$NAME"
+  pub features: String,
+  pub outcome: VerificationOutcome,
+  pub duration_millis: u64,
+  pub log: String,
+}
+
+/** The outcome of building and running a member's tests. */
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TestVerification {
+  pub outcome: VerificationOutcome,
+  pub duration_millis: u64,
+  // The tail of the log of building the tests
+  pub log: String,
+  // One entry per test binary that was built
+  pub binaries: Vec<TestBinaryVerification>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TestBinaryVerification {
+  // Such as "lib", "bin/$NAME" or "test/$NAME"
+  pub name: String,
+  pub outcome: VerificationOutcome,
+  pub duration_millis: u64,
+  pub log: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VerificationOutcome {
+  Passed,
+  Failed,
+  // Failed, but the workspace configuration expects it to, so it does not fail the snapshot
+  ExpectedFailure,
+}
+
+impl VerificationReport {
+  /** Whether every member of the snapshot built, and had no unexpected test failures. */
+  pub fn is_passing(&self) -> bool {
+    self.crates.iter().all(|verification| verification.outcome != VerificationOutcome::Failed)
+  }
+
+  /** Yields the verifications of the members that failed. */
+  pub fn get_failures(&self) -> Vec<&CrateVerification> {
+    self.crates.iter()
+      .filter(|verification| verification.outcome == VerificationOutcome::Failed)
+      .collect()
+  }
+}
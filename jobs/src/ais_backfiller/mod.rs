@@ -1,20 +1,16 @@
 use ::Job;
 use ::JobErr;
-use toml;
-use cargo::util::toml::TomlManifest;
 use common::cargo;
-use flate2::read::GzDecoder;
-use std::path::PathBuf;
 use index::KeyedByCrateKey;
 use index::augmented::AugmentedIndex;
+use index::augmented;
 use index::crates_io::CratesIoIndex;
 use lcs_fetcher::repository::LcsRepositorySource;
 use lcs_fetcher::repository::LocalFsLcsRepository;
-use std::collections::HashSet;
-use std::fs::File;
-use std::io::Read;
-use tar::Archive;
-use tempdir::TempDir;
+use std::time::Duration;
+
+pub mod pipeline;
+pub mod source_stats;
 
 mod flags {
   define_pub_cfg!(max_backfill_changes_per_commit,
@@ -33,6 +29,26 @@ mod flags {
                   bool,
                   false,
                   "Whether or not to backfill all keys, regardless of backfill status.");
+  define_pub_cfg!(backfill_fetch_parallelism,
+                  u32,
+                  8u32,
+                  "The number of crates to fetch from the LCS concurrently during backfill.");
+  define_pub_cfg!(backfill_extract_parallelism,
+                  u32,
+                  4u32,
+                  "The number of crate tarballs to decompress and scan concurrently during backfill.");
+  define_pub_cfg!(backfill_parse_parallelism,
+                  u32,
+                  2u32,
+                  "The number of crate manifests to parse concurrently during backfill.");
+  define_pub_cfg!(max_backfill_crates_in_flight,
+                  u32,
+                  64u32,
+                  "The maximum number of crates buffered between each backfill stage.");
+  define_pub_cfg!(backfill_progress_log_interval_secs,
+                  u64,
+                  30u64,
+                  "How often, in seconds, to log backfill throughput and ETA.");
 }
 
 #[derive(Clone, Builder)]
@@ -42,6 +58,11 @@ pub struct AisBackfillerParams {
   force_backfill_all_keys: bool,
   should_write_changes: bool,
   should_commit_changes: bool,
+  fetch_parallelism: u32,
+  extract_parallelism: u32,
+  parse_parallelism: u32,
+  max_crates_in_flight: u32,
+  progress_log_interval: Duration,
 }
 
 impl Default for AisBackfillerParams {
@@ -63,11 +84,30 @@ impl Default for AisBackfillerParams {
       panic!("--should_backfill_all requires `--max_changes_per_commit to be -1 (indicating no limit)`.");
     }
 
+    let fetch_parallelism =
+      flags::backfill_fetch_parallelism::CONFIG.get_value();
+    let extract_parallelism =
+      flags::backfill_extract_parallelism::CONFIG.get_value();
+    let parse_parallelism =
+      flags::backfill_parse_parallelism::CONFIG.get_value();
+    let max_crates_in_flight =
+      flags::max_backfill_crates_in_flight::CONFIG.get_value();
+
+    if fetch_parallelism == 0 || extract_parallelism == 0 || parse_parallelism == 0 || max_crates_in_flight == 0 {
+      panic!("Backfill parallelism and --max_backfill_crates_in_flight must all be at least 1.");
+    }
+
     AisBackfillerParams {
       should_write_changes: should_write_changes,
       should_commit_changes: should_commit_changes,
       force_backfill_all_keys: force_backfill_all_keys,
       max_changes_per_commit: max_changes_per_commit,
+      fetch_parallelism: fetch_parallelism,
+      extract_parallelism: extract_parallelism,
+      parse_parallelism: parse_parallelism,
+      max_crates_in_flight: max_crates_in_flight,
+      progress_log_interval: Duration::from_secs(
+        flags::backfill_progress_log_interval_secs::CONFIG.get_value()),
     }
   }
 }
@@ -93,7 +133,34 @@ impl AisBackfillerJob {
   }
 
   fn run_now(&mut self) -> Result<(), JobErr> {
-    let augmented_index_crate_keys = self.augmented_index.get_crate_keys().into_iter().collect::<HashSet<_>>();
+    let keys_to_backfill = try!(self.find_keys_to_backfill());
+
+    let mut backfilled_entries = Vec::new();
+    let mut failed_keys = Vec::new();
+    try!(pipeline::run_backfill_pipeline(&*self.lcs_source,
+                                         keys_to_backfill,
+                                         &self.params,
+                                         |key, result| {
+      match result {
+        Ok(entry) => backfilled_entries.push(entry),
+        Err(err) => {
+          warn!("Failed to backfill {}:{}: {:?}", key.name, key.version, err);
+          failed_keys.push(key);
+        },
+      }
+    }));
+
+    info!("Backfilled {} crates, {} failed", backfilled_entries.len(), failed_keys.len());
+    self.record_backfilled_entries(backfilled_entries)
+  }
+
+  /**
+   * Identifies the crates in the LCS that are missing from (or incomplete in) the augmented index.
+   *
+   * Incomplete entries for crates that are not in the LCS are left alone, since they could only
+   * fail to fetch (and would otherwise be retried ahead of real work on every run).
+   */
+  fn find_keys_to_backfill(&self) -> Result<Vec<cargo::CrateKey>, JobErr> {
     let lcs_crate_keys = try!(self.lcs_source.get_existing_crate_keys());
     let mut backfill_candidates = Vec::new();
 
@@ -101,71 +168,37 @@ impl AisBackfillerJob {
       backfill_candidates = lcs_crate_keys;
     } else {
       for lcs_crate_key in lcs_crate_keys.into_iter() {
-        if !augmented_index_crate_keys.contains(&lcs_crate_key) {
+        let is_complete = match self.augmented_index.get_entry(&lcs_crate_key) {
+          Some(item) => item.dev_dependencies.is_some()
+            && item.source_statistics.is_some()
+            && item.license.is_some(),
+          None => false,
+        };
+        if !is_complete {
           backfill_candidates.push(lcs_crate_key);
         }
       }
-
-      for augmented_index_key in augmented_index_crate_keys.into_iter() {
-        let item = self.augmented_index.get_entry(&augmented_index_key).unwrap();
-        if item.dev_dependencies.is_none() {
-          backfill_candidates.push(augmented_index_key.clone());
-        }
-      }
     }
-    backfill_candidates.sort_by_key(|k| k.name.to_lowercase());
+    backfill_candidates.sort_by_key(|k| (k.name.to_lowercase(), augmented::get_version_order_key(&k.version)));
     let keys_to_backfill = backfill_candidates.into_iter()
       .take(self.params.max_changes_per_commit as usize)
       .collect::<Vec<_>>();
 
-    let tempdir = try!(TempDir::new("local_crates_during_backfill"));
-    let tempdir_path = tempdir.path();
-    for key_to_backfill in keys_to_backfill.into_iter() {
-      let upstream_entry = self.upstream_index.get_entry(&key_to_backfill);
-      let augmented_entry = self.augmented_index.get_entry(&key_to_backfill);
-      let crate_filename = format!("{name}-{version}.crate",
-                                   name = key_to_backfill.name,
-                                   version = key_to_backfill.version);
-      try!(self.lcs_source.fetch_crate(&key_to_backfill, &tempdir_path));
-      let crate_path = tempdir_path.join(crate_filename);
-
-      let file = try!(File::open(crate_path));
-      let gz = try!(GzDecoder::new(file));
-      let mut tar = Archive::new(gz);
-      let mut found_file = false;
-      let mut toml_contents = String::new();
-      for entry_res in try!(tar.entries()) {
-        let mut entry = try!(entry_res);
-        {
-          let file_path = try!(entry.path());
-          // TODO(acmcarther): This is likely inefficient
-          if !(file_path == PathBuf::from("Cargo.toml")) {
-            continue
-          }
-        }
-
-        found_file = true;
-        entry.read_to_string(&mut toml_contents);
-        break;
-      }
-
-      if !found_file {
-        return Err(JobErr::OtherErr(format!("{}:{} did not have a valid Cargo.toml",
-                                            key_to_backfill.name,
-                                            key_to_backfill.version)))
-      }
-
-      if toml_contents.is_empty() {
-        return Err(JobErr::OtherErr(format!("{}:{} has a Cargo.toml but it is empty",
-                                            key_to_backfill.name,
-                                            key_to_backfill.version)))
-      }
-
-      let manifest = try!(toml::from_str::<TomlManifest>(&toml_contents));
+    Ok(keys_to_backfill)
+  }
 
-      // TODO(acmcarther): Extract useful information from the manifest
+  /** Writes (and optionally commits) the backfilled entries into the augmented index. */
+  fn record_backfilled_entries(&mut self, entries: Vec<cargo::AugmentedIndexEntry>) -> Result<(), JobErr> {
+    if entries.is_empty() || !self.params.should_write_changes {
+      return Ok(())
     }
 
+    let entry_count = entries.len();
+    let written_paths = try!(self.augmented_index.write_entries(entries));
+    if self.params.should_commit_changes {
+      let message = format!("Backfill {} augmented index entries", entry_count);
+      try!(self.augmented_index.commit_changes(&written_paths, &message));
+    }
     Ok(())
   }
 }
@@ -177,20 +210,52 @@ impl Job for AisBackfillerJob {
 }
 
 pub mod testing {
+  use common::cargo::CrateKey;
+  use flate2::Compression;
+  use flate2::write::GzEncoder;
+  use tar::Builder;
+  use tar::Header;
+
+  /**
+   * Builds the bytes of a gzipped crate tarball containing the provided files.
+   *
+   * Paths are relative to the crate root, and are nested under "$NAME-$VERSION/" the way cargo
+   * packages them.
+   */
+  pub fn build_crate_tarball(key: &CrateKey, files: &[(&str, &str)]) -> Vec<u8> {
+    let encoder = GzEncoder::new(Vec::new(), Compression::Default);
+    let mut builder = Builder::new(encoder);
+    for &(path, contents) in files.iter() {
+      let mut header = Header::new_gnu();
+      header.set_path(format!("{}-{}/{}", key.name, key.version, path)).unwrap();
+      header.set_size(contents.len() as u64);
+      header.set_mode(0o644);
+      header.set_cksum();
+      builder.append(&header, contents.as_bytes()).unwrap();
+    }
+    builder.into_inner().unwrap().finish().unwrap()
+  }
 }
 
 #[cfg(test)]
 mod tests {
   use ais_backfiller::AisBackfillerJobBuilder;
   use ais_backfiller::AisBackfillerParamsBuilder;
+  use ais_backfiller::testing;
   use common::cargo;
   use index::GenericIndexParamsBuilder;
+  use index::KeyedByCrateKey;
   use index::augmented::AugmentedIndex;
   use index::augmented;
   use index::crates_io::CratesIoIndex;
   use index::crates_io;
   use index;
   use lcs_fetcher::repository::LocalFsLcsRepository;
+  use lcs_fetcher::repository::testing::TestingCrate;
+  use lcs_fetcher::repository;
+  use serde_json;
+  use std::fs::File;
+  use std::io::Read;
   use std::str::FromStr;
   use url::Url;
 
@@ -208,4 +273,130 @@ mod tests {
 
     ais_backfiller_job.run_now().unwrap();
   }
+
+  #[test]
+  fn test_backfiller_records_dev_dependencies_in_key_order() {
+    let keys = vec![
+      cargo::CrateKey { name: "zeta".to_owned(), version: "0.1.0".to_owned() },
+      cargo::CrateKey { name: "alpha".to_owned(), version: "0.2.0".to_owned() },
+      cargo::CrateKey { name: "alpha".to_owned(), version: "0.1.0".to_owned() },
+    ];
+    let testing_crates = keys.iter()
+      .map(|key| TestingCrate {
+        key: key.clone(),
+        contents: testing::build_crate_tarball(key, &[
+          ("Cargo.toml", &format!("[package]\nname = \"{}\"\nversion = \"{}\"\n\n[dev-dependencies]\nrand = \"0.3\"\n",
+                                  key.name,
+                                  key.version)),
+          ("src/lib.rs", ""),
+        ]),
+      })
+      .collect::<Vec<_>>();
+    let source_fs_lcs = repository::testing::create_localfs_for_testing(&testing_crates).unwrap();
+
+    let mut ais_backfiller_job =
+      AisBackfillerJobBuilder::default()
+        .augmented_index(augmented::testing::get_minimum_index())
+        .upstream_index(crates_io::testing::get_minimum_index())
+        .lcs_source(Box::new(source_fs_lcs))
+        .params(AisBackfillerParamsBuilder::default()
+          .max_changes_per_commit(-1)
+          .should_write_changes(true)
+          .should_commit_changes(true)
+          .fetch_parallelism(2)
+          .extract_parallelism(2)
+          .parse_parallelism(2)
+          .max_crates_in_flight(1)
+          .build()
+          .unwrap())
+        .build()
+        .unwrap();
+
+    ais_backfiller_job.run_now().unwrap();
+
+    for key in keys.iter() {
+      let entry = ais_backfiller_job.augmented_index.get_entry(key).unwrap();
+      let dev_dependencies = entry.dev_dependencies.clone().unwrap();
+      assert_eq!(dev_dependencies.len(), 1);
+      assert_eq!(dev_dependencies[0].name, "rand".to_owned());
+      let source_statistics = entry.source_statistics.clone().unwrap();
+      assert_eq!(source_statistics.file_counts_by_extension.get("rs"), Some(&1));
+      assert_eq!(source_statistics.file_counts_by_extension.get("toml"), Some(&1));
+    }
+
+    let repository = ais_backfiller_job.augmented_index.get_repository();
+    let workdir = repository.workdir().unwrap();
+    let mut alpha_contents = String::new();
+    File::open(workdir.join(index::get_path_for_crate("alpha"))).unwrap()
+      .read_to_string(&mut alpha_contents)
+      .unwrap();
+    let alpha_versions = alpha_contents.lines()
+      .map(|line| serde_json::from_str::<cargo::AugmentedIndexEntry>(line).unwrap().vers)
+      .collect::<Vec<_>>();
+    assert_eq!(alpha_versions, vec!["0.1.0".to_owned(), "0.2.0".to_owned()]);
+
+    let head_commit = repository.find_commit(repository.head().unwrap().target().unwrap()).unwrap();
+    assert_eq!(head_commit.message(), Some("Backfill 3 augmented index entries"));
+  }
+
+  #[test]
+  fn test_backfiller_skips_crates_without_manifests() {
+    let key = cargo::CrateKey { name: "broken".to_owned(), version: "0.1.0".to_owned() };
+    let testing_crates = vec![
+      TestingCrate {
+        key: key.clone(),
+        contents: testing::build_crate_tarball(&key, &[("src/lib.rs", "")]),
+      }
+    ];
+    let source_fs_lcs = repository::testing::create_localfs_for_testing(&testing_crates).unwrap();
+
+    let mut ais_backfiller_job =
+      AisBackfillerJobBuilder::default()
+        .augmented_index(augmented::testing::get_minimum_index())
+        .upstream_index(crates_io::testing::get_minimum_index())
+        .lcs_source(Box::new(source_fs_lcs))
+        .build()
+        .unwrap();
+
+    ais_backfiller_job.run_now().unwrap();
+
+    assert_eq!(ais_backfiller_job.augmented_index.get_entry(&key), None);
+  }
+
+  #[test]
+  fn test_backfiller_only_revisits_incomplete_entries_in_the_lcs() {
+    let key = cargo::CrateKey { name: "present".to_owned(), version: "0.1.0".to_owned() };
+    let testing_crates = vec![
+      TestingCrate {
+        key: key.clone(),
+        contents: testing::build_crate_tarball(&key, &[
+          ("Cargo.toml", "[package]\nname = \"present\"\nversion = \"0.1.0\"\n"),
+          ("src/lib.rs", ""),
+        ]),
+      }
+    ];
+    let source_fs_lcs = repository::testing::create_localfs_for_testing(&testing_crates).unwrap();
+    // Incomplete, and sorted first, but not in the LCS
+    let absent_entry = cargo::AugmentedIndexEntry {
+      name: "absent".to_owned(),
+      vers: "0.1.0".to_owned(),
+      dev_dependencies: Some(Vec::new()),
+      source_statistics: None,
+      license: None,
+    };
+
+    let ais_backfiller_job =
+      AisBackfillerJobBuilder::default()
+        .augmented_index(augmented::testing::get_seeded_index(vec![absent_entry]))
+        .upstream_index(crates_io::testing::get_minimum_index())
+        .lcs_source(Box::new(source_fs_lcs))
+        .params(AisBackfillerParamsBuilder::default()
+          .max_changes_per_commit(1)
+          .build()
+          .unwrap())
+        .build()
+        .unwrap();
+
+    assert_eq!(ais_backfiller_job.find_keys_to_backfill().unwrap(), vec![key]);
+  }
 }
@@ -0,0 +1,425 @@
+use ::JobErr;
+use ais_backfiller::AisBackfillerParams;
+use ais_backfiller::source_stats::SourceStatisticsCollector;
+use common::cargo::CrateKey;
+use common::cargo;
+use common::license;
+use common::manifest::CargoManifest;
+use common::manifest::DependencyKind;
+use flate2::read::GzDecoder;
+use lcs_fetcher::repository::LcsRepositorySource;
+use scoped_threadpool::Pool;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::SyncSender;
+use std::sync::mpsc;
+use std::time::Duration;
+use std::time::Instant;
+use tar::Archive;
+use tempdir::TempDir;
+
+/** A crate tarball that has been fetched from the LCS into scratch space. */
+pub struct FetchedCrate {
+  pub crate_path: PathBuf,
+}
+
+/** The interesting contents of a crate tarball, extracted in a single pass over the archive. */
+pub struct ExtractedCrate {
+  pub cargo_toml: String,
+  pub source_statistics: cargo::SourceStatistics,
+  // License-like files anywhere in the crate, plus any small file at its root, keyed by path
+  pub license_candidates: BTreeMap<String, String>,
+}
+
+/** Root-level files larger than this are assumed not to be a license-file. */
+const MAX_ROOT_LICENSE_FILE_BYTES: u64 = 64 * 1024;
+
+/** A unit of work moving between stages, tagged with its position in the original key order. */
+type StageItem<T> = (usize, CrateKey, Result<T, JobErr>);
+
+/**
+ * Fetches, extracts and parses the provided crates concurrently, invoking `on_result` once per
+ * crate in the order the keys were provided.
+ *
+ * Each stage runs on its own bounded thread pool, and the stages are connected by bounded
+ * channels so that fetching cannot run arbitrarily far ahead of extraction. Results may complete
+ * out of order; they are buffered and released in key order so that whatever the caller does
+ * with them (such as writing and committing the index) is deterministic.
+ */
+pub fn run_backfill_pipeline<F>(lcs_source: &LcsRepositorySource,
+                                keys: Vec<CrateKey>,
+                                params: &AisBackfillerParams,
+                                mut on_result: F) -> Result<(), JobErr>
+    where F: FnMut(CrateKey, Result<cargo::AugmentedIndexEntry, JobErr>) {
+  let scratch_dir = try!(TempDir::new("local_crates_during_backfill"));
+  let scratch_path = scratch_dir.path();
+  let total_crates = keys.len();
+  let mut progress = BackfillProgress::new(total_crates, params.progress_log_interval);
+
+  let channel_bound = params.max_crates_in_flight as usize;
+  let (fetched_sender, fetched_receiver) = mpsc::sync_channel::<StageItem<FetchedCrate>>(channel_bound);
+  let (extracted_sender, extracted_receiver) = mpsc::sync_channel::<StageItem<ExtractedCrate>>(channel_bound);
+  let (parsed_sender, parsed_receiver) = mpsc::sync_channel::<StageItem<cargo::AugmentedIndexEntry>>(channel_bound);
+  let fetched_receiver = Mutex::new(fetched_receiver);
+  let extracted_receiver = Mutex::new(extracted_receiver);
+
+  let mut fetch_pool = Pool::new(params.fetch_parallelism);
+  let mut extract_pool = Pool::new(params.extract_parallelism);
+  let mut parse_pool = Pool::new(params.parse_parallelism);
+
+  info!("Backfilling {} crates (fetch: {}, extract: {}, parse: {} threads)",
+        total_crates,
+        params.fetch_parallelism,
+        params.extract_parallelism,
+        params.parse_parallelism);
+
+  fetch_pool.scoped(|fetch_scope| {
+    extract_pool.scoped(|extract_scope| {
+      parse_pool.scoped(|parse_scope| {
+        for (position, key) in keys.into_iter().enumerate() {
+          let fetched_sender = fetched_sender.clone();
+          fetch_scope.execute(move || {
+            let result = fetch_crate(lcs_source, &key, scratch_path);
+            let _ = fetched_sender.send((position, key, result));
+          });
+        }
+        drop(fetched_sender);
+
+        for _ in 0..params.extract_parallelism {
+          let receiver = &fetched_receiver;
+          let sender = extracted_sender.clone();
+          extract_scope.execute(move || {
+            run_stage_worker(receiver, sender, |key, fetched| extract_crate(key, &fetched))
+          });
+        }
+        drop(extracted_sender);
+
+        for _ in 0..params.parse_parallelism {
+          let receiver = &extracted_receiver;
+          let sender = parsed_sender.clone();
+          parse_scope.execute(move || {
+            run_stage_worker(receiver, sender, |key, extracted| parse_crate(key, &extracted))
+          });
+        }
+        // Workers hold the only remaining senders, so the merge below ends once they finish
+        drop(parsed_sender);
+
+        let mut pending_results = BTreeMap::new();
+        let mut next_position = 0;
+        for (position, key, result) in parsed_receiver.iter() {
+          pending_results.insert(position, (key, result));
+          while let Some((key, result)) = pending_results.remove(&next_position) {
+            progress.record(result.is_ok());
+            on_result(key, result);
+            next_position = next_position + 1;
+          }
+          progress.maybe_log();
+        }
+      });
+    });
+  });
+
+  progress.log();
+  Ok(())
+}
+
+/**
+ * Pulls items from the previous stage until it is exhausted, transforming successful items and
+ * forwarding failures untouched.
+ */
+fn run_stage_worker<I, O, F>(receiver: &Mutex<Receiver<StageItem<I>>>,
+                             sender: SyncSender<StageItem<O>>,
+                             transform: F)
+    where F: Fn(&CrateKey, I) -> Result<O, JobErr> {
+  loop {
+    let next_item = receiver.lock().unwrap().recv();
+    let (position, key, result) = match next_item {
+      Ok(item) => item,
+      // The previous stage is finished
+      Err(_) => return,
+    };
+
+    let transformed = result.and_then(|input| transform(&key, input));
+    if sender.send((position, key, transformed)).is_err() {
+      return
+    }
+  }
+}
+
+/** Copies the crate tarball out of the LCS into the scratch directory. */
+fn fetch_crate(lcs_source: &LcsRepositorySource, key: &CrateKey, scratch_path: &Path) -> Result<FetchedCrate, JobErr> {
+  let crate_filename = format!("{name}-{version}.crate",
+                               name = key.name,
+                               version = key.version);
+  try!(lcs_source.fetch_crate(key, scratch_path));
+  Ok(FetchedCrate {
+    crate_path: scratch_path.join(crate_filename),
+  })
+}
+
+/** Decompresses the fetched tarball, and pulls out the files needed for backfilling. */
+fn extract_crate(key: &CrateKey, fetched: &FetchedCrate) -> Result<ExtractedCrate, JobErr> {
+  let extraction_result = extract_crate_contents(key, &fetched.crate_path);
+  // Minor optimization -- remove file early if possible
+  let _ = fs::remove_file(&fetched.crate_path);
+  extraction_result
+}
+
+fn extract_crate_contents(key: &CrateKey, crate_path: &Path) -> Result<ExtractedCrate, JobErr> {
+  let compressed_size = try!(fs::metadata(crate_path)).len();
+  let mut statistics_collector = SourceStatisticsCollector::new(compressed_size);
+  let file = try!(File::open(crate_path));
+  let gz = try!(GzDecoder::new(file));
+  let mut tar = Archive::new(gz);
+  let mut toml_contents = None;
+  let mut license_candidates = BTreeMap::new();
+  for entry_res in try!(tar.entries()) {
+    let mut entry = try!(entry_res);
+    if !entry.header().entry_type().is_file() {
+      continue
+    }
+    // Crate tarballs nest everything within a "$NAME-$VERSION/" directory
+    let path_in_crate = try!(entry.path()).components().skip(1).collect::<PathBuf>();
+    let size = try!(entry.header().size());
+
+    if path_in_crate == PathBuf::from("Cargo.toml") {
+      let mut contents = String::new();
+      try!(entry.read_to_string(&mut contents));
+      toml_contents = Some(contents);
+      statistics_collector.record_file(&path_in_crate, size);
+    } else if path_in_crate.extension().map(|e| e == "rs").unwrap_or(false) {
+      let mut bytes = Vec::new();
+      try!(entry.read_to_end(&mut bytes));
+      statistics_collector.record_rust_file(&path_in_crate, size, &String::from_utf8_lossy(&bytes));
+    } else if is_license_candidate(&path_in_crate, size) {
+      let mut bytes = Vec::new();
+      try!(entry.read_to_end(&mut bytes));
+      license_candidates.insert(path_in_crate.to_string_lossy().into_owned(),
+                                String::from_utf8_lossy(&bytes).into_owned());
+      statistics_collector.record_file(&path_in_crate, size);
+    } else {
+      statistics_collector.record_file(&path_in_crate, size);
+    }
+  }
+
+  match toml_contents {
+    None => Err(JobErr::OtherErr(format!("{}:{} did not have a valid Cargo.toml",
+                                         key.name,
+                                         key.version))),
+    Some(ref contents) if contents.is_empty() =>
+      Err(JobErr::OtherErr(format!("{}:{} has a Cargo.toml but it is empty",
+                                   key.name,
+                                   key.version))),
+    Some(contents) => Ok(ExtractedCrate {
+      cargo_toml: contents,
+      source_statistics: statistics_collector.finish(),
+      license_candidates: license_candidates,
+    }),
+  }
+}
+
+/**
+ * Whether a file might hold license text.
+ *
+ * The manifest's license-file is not known until the manifest is parsed, so small root-level files
+ * are kept as well, since that is where a license-file with an unusual name almost always lives.
+ */
+fn is_license_candidate(path_in_crate: &Path, size: u64) -> bool {
+  let is_license_named = path_in_crate.file_name()
+    .map(|name| license::is_license_file_name(&name.to_string_lossy()))
+    .unwrap_or(false);
+  let is_small_root_file =
+    path_in_crate.components().count() == 1 && size <= MAX_ROOT_LICENSE_FILE_BYTES;
+  is_license_named || is_small_root_file
+}
+
+/** Interprets the declared license, and identifies each license file in the crate. */
+fn build_license_info(manifest: &CargoManifest, license_candidates: &BTreeMap<String, String>) -> cargo::LicenseInfo {
+  let package = manifest.get_package();
+  let declared = package
+    .and_then(|p| p.license.as_ref())
+    .map(|raw| license::normalize_declared_license(raw));
+  let license_file = package
+    .and_then(|p| p.license_file.as_ref())
+    .map(|path| path.trim_left_matches("./").to_owned());
+
+  let detected_files = license_candidates.iter()
+    .filter(|&(path, _)| {
+      let file_name = path.rsplit('/').next().unwrap_or(path);
+      license::is_license_file_name(file_name) || license_file.as_ref() == Some(path)
+    })
+    .map(|(path, contents)| license::detect_license_file(path, contents))
+    .collect();
+
+  cargo::LicenseInfo {
+    declared: declared,
+    license_file: license_file,
+    detected_files: detected_files,
+  }
+}
+
+/** Parses the extracted manifest, and derives the augmented index entry from it. */
+fn parse_crate(key: &CrateKey, extracted: &ExtractedCrate) -> Result<cargo::AugmentedIndexEntry, JobErr> {
+  let manifest = try!(CargoManifest::from_str(&extracted.cargo_toml));
+
+  Ok(cargo::AugmentedIndexEntry {
+    name: key.name.clone(),
+    vers: key.version.clone(),
+    dev_dependencies: Some(manifest.get_dependency_entries(DependencyKind::Dev)),
+    source_statistics: Some(extracted.source_statistics.clone()),
+    license: Some(build_license_info(&manifest, &extracted.license_candidates)),
+  })
+}
+
+/** Tracks how far along a backfill is, and periodically logs throughput and remaining time. */
+pub struct BackfillProgress {
+  total: usize,
+  completed: usize,
+  failed: usize,
+  started_at: Instant,
+  last_logged_at: Instant,
+  log_interval: Duration,
+}
+
+impl BackfillProgress {
+  pub fn new(total: usize, log_interval: Duration) -> BackfillProgress {
+    let now = Instant::now();
+    BackfillProgress {
+      total: total,
+      completed: 0,
+      failed: 0,
+      started_at: now,
+      last_logged_at: now,
+      log_interval: log_interval,
+    }
+  }
+
+  /** Records the completion of a single crate. */
+  pub fn record(&mut self, succeeded: bool) {
+    self.completed = self.completed + 1;
+    if !succeeded {
+      self.failed = self.failed + 1;
+    }
+  }
+
+  /** Logs progress if the log interval has elapsed since the last log. */
+  pub fn maybe_log(&mut self) {
+    if self.last_logged_at.elapsed() >= self.log_interval {
+      self.log();
+    }
+  }
+
+  pub fn log(&mut self) {
+    let elapsed = self.started_at.elapsed();
+    let eta = match estimate_remaining_time(self.completed, self.total, elapsed) {
+      Some(remaining) => format_duration(remaining),
+      None => "unknown".to_owned(),
+    };
+    info!("Backfilled {}/{} crates ({} failed) in {}: {:.2} crates/sec, ETA {}",
+          self.completed,
+          self.total,
+          self.failed,
+          format_duration(elapsed),
+          crates_per_second(self.completed, elapsed),
+          eta);
+    self.last_logged_at = Instant::now();
+  }
+}
+
+fn duration_as_secs_f64(duration: Duration) -> f64 {
+  duration.as_secs() as f64 + (duration.subsec_nanos() as f64 / 1_000_000_000f64)
+}
+
+fn crates_per_second(completed: usize, elapsed: Duration) -> f64 {
+  let elapsed_secs = duration_as_secs_f64(elapsed);
+  if elapsed_secs == 0f64 {
+    return 0f64
+  }
+  completed as f64 / elapsed_secs
+}
+
+/** Extrapolates the time remaining from the throughput observed so far. */
+pub fn estimate_remaining_time(completed: usize, total: usize, elapsed: Duration) -> Option<Duration> {
+  if completed == 0 {
+    return None
+  }
+  let remaining = total.saturating_sub(completed);
+  let secs_per_crate = duration_as_secs_f64(elapsed) / completed as f64;
+  Some(Duration::from_secs((secs_per_crate * remaining as f64).ceil() as u64))
+}
+
+/** Renders a duration as "1h02m03s", omitting leading zero units. */
+pub fn format_duration(duration: Duration) -> String {
+  let total_secs = duration.as_secs();
+  let hours = total_secs / 3600;
+  let minutes = (total_secs % 3600) / 60;
+  let seconds = total_secs % 60;
+  if hours > 0 {
+    format!("{}h{:02}m{:02}s", hours, minutes, seconds)
+  } else if minutes > 0 {
+    format!("{}m{:02}s", minutes, seconds)
+  } else {
+    format!("{}s", seconds)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use ais_backfiller::pipeline;
+  use common::cargo::LicenseConfidence;
+  use common::manifest::CargoManifest;
+  use std::collections::BTreeMap;
+  use std::time::Duration;
+
+  #[test]
+  fn test_remaining_time_is_extrapolated_from_throughput() {
+    assert_eq!(pipeline::estimate_remaining_time(0, 100, Duration::from_secs(10)), None);
+    assert_eq!(pipeline::estimate_remaining_time(10, 100, Duration::from_secs(10)),
+               Some(Duration::from_secs(90)));
+    assert_eq!(pipeline::estimate_remaining_time(100, 100, Duration::from_secs(10)),
+               Some(Duration::from_secs(0)));
+  }
+
+  #[test]
+  fn test_durations_are_formatted_compactly() {
+    assert_eq!(pipeline::format_duration(Duration::from_secs(5)), "5s".to_owned());
+    assert_eq!(pipeline::format_duration(Duration::from_secs(65)), "1m05s".to_owned());
+    assert_eq!(pipeline::format_duration(Duration::from_secs(3723)), "1h02m03s".to_owned());
+  }
+
+  #[test]
+  fn test_license_info_covers_declared_license_and_license_files() {
+    let manifest = CargoManifest::from_str(r#"
+      [package]
+      name = "example"
+      version = "0.1.0"
+      license = "MIT/Apache-2.0"
+      license-file = "./TERMS"
+    "#).unwrap();
+    let mut license_candidates = BTreeMap::new();
+    license_candidates.insert("LICENSE-MIT".to_owned(),
+                              "Permission is hereby granted, free of charge, to any person obtaining a copy".to_owned());
+    license_candidates.insert("TERMS".to_owned(), "Do not redistribute.".to_owned());
+    license_candidates.insert("README.md".to_owned(), "# example".to_owned());
+
+    let license_info = pipeline::build_license_info(&manifest, &license_candidates);
+
+    let declared = license_info.declared.unwrap();
+    assert_eq!(declared.spdx_expression, Some("MIT OR Apache-2.0".to_owned()));
+    assert_eq!(declared.confidence, LicenseConfidence::Normalized);
+    assert_eq!(license_info.license_file, Some("TERMS".to_owned()));
+    let detected = license_info.detected_files.iter()
+      .map(|f| (f.path.clone(), f.spdx_id.clone(), f.confidence))
+      .collect::<Vec<_>>();
+    assert_eq!(detected, vec![
+      ("LICENSE-MIT".to_owned(), Some("MIT".to_owned()), LicenseConfidence::High),
+      ("TERMS".to_owned(), None, LicenseConfidence::Unknown),
+    ]);
+  }
+}
@@ -0,0 +1,407 @@
+use common::cargo::SourceStatistics;
+use std::path::Path;
+
+/**
+ * Accumulates SourceStatistics over the files of a single crate tarball.
+ *
+ * The analysis is purely lexical: comments and string literals are skipped, but no parsing is
+ * performed. As a result, `unsafe fn` pointer types are counted as unsafe functions.
+ */
+pub struct SourceStatisticsCollector {
+  statistics: SourceStatistics,
+}
+
+impl SourceStatisticsCollector {
+  pub fn new(compressed_size_bytes: u64) -> SourceStatisticsCollector {
+    let mut statistics = SourceStatistics::default();
+    statistics.compressed_size_bytes = compressed_size_bytes;
+    SourceStatisticsCollector {
+      statistics: statistics,
+    }
+  }
+
+  /** Records a non-Rust file, by size and extension only. */
+  pub fn record_file(&mut self, path_in_crate: &Path, size_bytes: u64) {
+    self.statistics.uncompressed_size_bytes = self.statistics.uncompressed_size_bytes + size_bytes;
+    let extension = path_in_crate.extension()
+      .map(|e| e.to_string_lossy().to_lowercase())
+      .unwrap_or_else(|| "(none)".to_owned());
+    *self.statistics.file_counts_by_extension.entry(extension).or_insert(0) += 1;
+  }
+
+  /** Records a Rust source file, scanning its contents. */
+  pub fn record_rust_file(&mut self, path_in_crate: &Path, size_bytes: u64, source: &str) {
+    self.record_file(path_in_crate, size_bytes);
+
+    let scan = scan_rust_source(source);
+    self.statistics.rust_lines_of_code = self.statistics.rust_lines_of_code + scan.lines_of_code;
+    self.statistics.unsafe_usage.blocks = self.statistics.unsafe_usage.blocks + scan.unsafe_blocks;
+    self.statistics.unsafe_usage.functions = self.statistics.unsafe_usage.functions + scan.unsafe_functions;
+    self.statistics.unsafe_usage.impls = self.statistics.unsafe_usage.impls + scan.unsafe_impls;
+    self.statistics.unsafe_usage.traits = self.statistics.unsafe_usage.traits + scan.unsafe_traits;
+    if is_crate_root(path_in_crate) && scan.forbids_unsafe_code {
+      self.statistics.forbids_unsafe_code = true;
+    }
+  }
+
+  pub fn finish(self) -> SourceStatistics {
+    self.statistics
+  }
+}
+
+/** Whether the path is the default library or binary crate root. */
+fn is_crate_root(path_in_crate: &Path) -> bool {
+  path_in_crate == Path::new("src/lib.rs") || path_in_crate == Path::new("src/main.rs")
+}
+
+/** The results of lexically scanning a single Rust source file. */
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RustSourceScan {
+  pub lines_of_code: u64,
+  pub unsafe_blocks: u64,
+  pub unsafe_functions: u64,
+  pub unsafe_impls: u64,
+  pub unsafe_traits: u64,
+  pub forbids_unsafe_code: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+  Ident(String),
+  Punct(char),
+  Literal,
+}
+
+/** Scans a Rust source file for lines of code, unsafe usage and `#![forbid(unsafe_code)]`. */
+pub fn scan_rust_source(source: &str) -> RustSourceScan {
+  let (tokens, lines_of_code) = tokenize(source);
+  let mut scan = RustSourceScan::default();
+  scan.lines_of_code = lines_of_code;
+
+  for (idx, token) in tokens.iter().enumerate() {
+    if *token == Token::Ident("unsafe".to_owned()) {
+      match next_qualified_item(&tokens[idx + 1..]) {
+        Some(&Token::Punct('{')) => scan.unsafe_blocks = scan.unsafe_blocks + 1,
+        Some(&Token::Ident(ref ident)) if ident == "fn" => scan.unsafe_functions = scan.unsafe_functions + 1,
+        Some(&Token::Ident(ref ident)) if ident == "impl" => scan.unsafe_impls = scan.unsafe_impls + 1,
+        Some(&Token::Ident(ref ident)) if ident == "trait" => scan.unsafe_traits = scan.unsafe_traits + 1,
+        _ => {},
+      }
+    }
+
+    if is_forbid_unsafe_code_attribute(&tokens[idx..]) {
+      scan.forbids_unsafe_code = true;
+    }
+  }
+
+  scan
+}
+
+/** Skips an `extern "ABI"` qualifier, yielding the token that `unsafe` applies to. */
+fn next_qualified_item(tokens: &[Token]) -> Option<&Token> {
+  let mut remaining = tokens.iter();
+  match remaining.next() {
+    Some(&Token::Ident(ref ident)) if ident == "extern" => {
+      match remaining.next() {
+        Some(&Token::Literal) => remaining.next(),
+        other => other,
+      }
+    },
+    other => other,
+  }
+}
+
+/** Whether the tokens begin with an inner attribute like `#![forbid(..., unsafe_code, ...)]`. */
+fn is_forbid_unsafe_code_attribute(tokens: &[Token]) -> bool {
+  let prefix = [
+    Token::Punct('#'),
+    Token::Punct('!'),
+    Token::Punct('['),
+    Token::Ident("forbid".to_owned()),
+    Token::Punct('('),
+  ];
+  if tokens.len() < prefix.len() || &tokens[0..prefix.len()] != &prefix[..] {
+    return false
+  }
+
+  for token in tokens[prefix.len()..].iter() {
+    match *token {
+      Token::Punct(')') => return false,
+      Token::Ident(ref ident) if ident == "unsafe_code" => return true,
+      _ => {},
+    }
+  }
+  false
+}
+
+/**
+ * Splits Rust source into coarse tokens, skipping whitespace, comments and the contents of
+ * literals.
+ *
+ * Also yields the number of lines containing anything other than whitespace and comments.
+ */
+fn tokenize(source: &str) -> (Vec<Token>, u64) {
+  let chars = source.chars().collect::<Vec<_>>();
+  let mut tokens = Vec::new();
+  let mut lines = LineCounter::new();
+  let mut idx = 0;
+
+  while idx < chars.len() {
+    let c = chars[idx];
+    let next = chars.get(idx + 1).cloned();
+
+    if c == '\n' {
+      lines.newline();
+      idx = idx + 1;
+    } else if c.is_whitespace() {
+      idx = idx + 1;
+    } else if c == '/' && next == Some('/') {
+      while idx < chars.len() && chars[idx] != '\n' {
+        idx = idx + 1;
+      }
+    } else if c == '/' && next == Some('*') {
+      idx = skip_block_comment(&chars, idx, &mut lines);
+    } else if c == '"' {
+      lines.mark_code();
+      idx = skip_string(&chars, idx + 1, &mut lines);
+      tokens.push(Token::Literal);
+    } else if c == '\'' {
+      lines.mark_code();
+      match char_literal_end(&chars, idx) {
+        Some(end) => {
+          idx = end;
+          tokens.push(Token::Literal);
+        },
+        // A lifetime or label, which is just an identifier with a tick
+        None => {
+          idx = idx + 1;
+        },
+      }
+    } else if c.is_alphanumeric() || c == '_' {
+      lines.mark_code();
+      let start = idx;
+      while idx < chars.len() && (chars[idx].is_alphanumeric() || chars[idx] == '_') {
+        idx = idx + 1;
+      }
+      let ident = chars[start..idx].iter().cloned().collect::<String>();
+      match literal_prefix_end(&chars, idx, &ident) {
+        Some(literal_start) => {
+          idx = literal_start;
+          if chars[idx] == '\'' {
+            idx = char_literal_end(&chars, idx).unwrap_or(idx + 1);
+          } else {
+            idx = skip_raw_or_plain_string(&chars, idx, &mut lines);
+          }
+          tokens.push(Token::Literal);
+        },
+        None => tokens.push(Token::Ident(ident)),
+      }
+    } else {
+      lines.mark_code();
+      tokens.push(Token::Punct(c));
+      idx = idx + 1;
+    }
+  }
+
+  (tokens, lines.count)
+}
+
+/** Counts lines that contain code, marking each line at most once. */
+struct LineCounter {
+  count: u64,
+  current_line_marked: bool,
+}
+
+impl LineCounter {
+  fn new() -> LineCounter {
+    LineCounter {
+      count: 0,
+      current_line_marked: false,
+    }
+  }
+
+  fn mark_code(&mut self) {
+    if !self.current_line_marked {
+      self.count = self.count + 1;
+      self.current_line_marked = true;
+    }
+  }
+
+  fn newline(&mut self) {
+    self.current_line_marked = false;
+  }
+}
+
+/** Skips a (possibly nested) block comment starting at idx, yielding the index after it. */
+fn skip_block_comment(chars: &[char], start: usize, lines: &mut LineCounter) -> usize {
+  let mut depth = 0;
+  let mut idx = start;
+  while idx < chars.len() {
+    let next = chars.get(idx + 1).cloned();
+    if chars[idx] == '/' && next == Some('*') {
+      depth = depth + 1;
+      idx = idx + 2;
+    } else if chars[idx] == '*' && next == Some('/') {
+      depth = depth - 1;
+      idx = idx + 2;
+      if depth == 0 {
+        return idx
+      }
+    } else {
+      if chars[idx] == '\n' {
+        lines.newline();
+      }
+      idx = idx + 1;
+    }
+  }
+  idx
+}
+
+/** Skips the body of a plain string starting just after its opening quote. */
+fn skip_string(chars: &[char], start: usize, lines: &mut LineCounter) -> usize {
+  let mut idx = start;
+  while idx < chars.len() {
+    match chars[idx] {
+      '\\' => idx = idx + 2,
+      '"' => return idx + 1,
+      '\n' => {
+        lines.newline();
+        lines.mark_code();
+        idx = idx + 1;
+      },
+      _ => idx = idx + 1,
+    }
+  }
+  idx
+}
+
+/**
+ * If the identifier just lexed is a literal prefix (b"", b'', r"", r#""#, br"") immediately
+ * followed by the literal, yields the index where the literal's quoting begins.
+ */
+fn literal_prefix_end(chars: &[char], idx: usize, ident: &str) -> Option<usize> {
+  let next = chars.get(idx).cloned();
+  match (ident, next) {
+    ("b", Some('"')) | ("b", Some('\'')) => Some(idx),
+    ("r", Some('"')) | ("br", Some('"')) => Some(idx),
+    ("r", Some('#')) | ("br", Some('#')) => {
+      let mut lookahead = idx;
+      while chars.get(lookahead) == Some(&'#') {
+        lookahead = lookahead + 1;
+      }
+      if chars.get(lookahead) == Some(&'"') { Some(idx) } else { None }
+    },
+    _ => None,
+  }
+}
+
+/** Skips a raw string (r#"..."#) or plain string beginning at its hashes or opening quote. */
+fn skip_raw_or_plain_string(chars: &[char], start: usize, lines: &mut LineCounter) -> usize {
+  let mut hashes = 0;
+  let mut idx = start;
+  while chars.get(idx) == Some(&'#') {
+    hashes = hashes + 1;
+    idx = idx + 1;
+  }
+  // Step past the opening quote
+  idx = idx + 1;
+
+  while idx < chars.len() {
+    if chars[idx] == '"' && (0..hashes).all(|offset| chars.get(idx + 1 + offset) == Some(&'#')) {
+      return idx + 1 + hashes
+    }
+    if chars[idx] == '\n' {
+      lines.newline();
+      lines.mark_code();
+    }
+    idx = idx + 1;
+  }
+  idx
+}
+
+/**
+ * If a char literal begins at idx (which holds a tick), yields the index after it. Yields None
+ * for lifetimes and labels.
+ */
+fn char_literal_end(chars: &[char], start: usize) -> Option<usize> {
+  match chars.get(start + 1).cloned() {
+    Some('\\') => {
+      let mut idx = start + 2;
+      while idx < chars.len() && chars[idx] != '\'' && chars[idx] != '\n' {
+        idx = idx + 1;
+      }
+      Some(idx + 1)
+    },
+    Some(_) if chars.get(start + 2) == Some(&'\'') => Some(start + 3),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use ais_backfiller::source_stats::RustSourceScan;
+  use ais_backfiller::source_stats::SourceStatisticsCollector;
+  use ais_backfiller::source_stats::scan_rust_source;
+  use std::path::Path;
+
+  #[test]
+  fn test_unsafe_usage_is_counted_by_kind() {
+    let scan = scan_rust_source(r#"
+      pub unsafe fn raw() {}
+      unsafe extern "C" fn callback() {}
+      unsafe impl Send for Thing {}
+      unsafe trait Zeroable {}
+      fn safe() {
+        unsafe { raw() }
+      }
+    "#);
+
+    assert_eq!(scan, RustSourceScan {
+      lines_of_code: 7,
+      unsafe_blocks: 1,
+      unsafe_functions: 2,
+      unsafe_impls: 1,
+      unsafe_traits: 1,
+      forbids_unsafe_code: false,
+    });
+  }
+
+  #[test]
+  fn test_comments_and_literals_are_ignored() {
+    let scan = scan_rust_source(r###"
+      // unsafe { line comment }
+      /* unsafe { block /* nested */ comment } */
+      const A: &'static str = "unsafe { string }";
+      const B: &'static str = r#"unsafe { "raw" string }"#;
+      const C: char = '{';
+      fn lifetime<'a>(x: &'a u8) -> &'a u8 { x }
+    "###);
+
+    assert_eq!(scan.lines_of_code, 4);
+    assert_eq!(scan.unsafe_blocks, 0);
+  }
+
+  #[test]
+  fn test_forbid_unsafe_code_is_detected() {
+    assert!(scan_rust_source("#![forbid(unsafe_code)]").forbids_unsafe_code);
+    assert!(scan_rust_source("#![forbid(missing_docs, unsafe_code)]\nfn main() {}").forbids_unsafe_code);
+    assert!(!scan_rust_source("#![deny(unsafe_code)]").forbids_unsafe_code);
+    assert!(!scan_rust_source("// #![forbid(unsafe_code)]").forbids_unsafe_code);
+  }
+
+  #[test]
+  fn test_collector_only_honors_forbid_in_crate_roots() {
+    let mut collector = SourceStatisticsCollector::new(10);
+    collector.record_rust_file(Path::new("src/module.rs"), 23, "#![forbid(unsafe_code)]");
+    collector.record_file(Path::new("README.md"), 5);
+    collector.record_file(Path::new("LICENSE"), 7);
+    let statistics = collector.finish();
+
+    assert!(!statistics.forbids_unsafe_code);
+    assert_eq!(statistics.compressed_size_bytes, 10);
+    assert_eq!(statistics.uncompressed_size_bytes, 35);
+    assert_eq!(statistics.file_counts_by_extension.get("rs"), Some(&1));
+    assert_eq!(statistics.file_counts_by_extension.get("md"), Some(&1));
+    assert_eq!(statistics.file_counts_by_extension.get("(none)"), Some(&1));
+  }
+}
@@ -12,6 +12,9 @@ extern crate common;
 
 use jobs::LcsFetcherJob;
 use jobs::AisBackfillerJob;
+use jobs::SnapshotPlannerJob;
+use jobs::SnapshotPromoterJob;
+use jobs::SnapshotVerifierJob;
 use jobs::Job;
 use std::collections::HashMap;
 use std::ops::Deref;
@@ -36,6 +39,9 @@ lazy_static! {
     let mut jobs: HashMap<&'static str, fn() -> Box<Job>> = HashMap::new();
     jobs.insert("lcs-fetcher", get_lcs_fetcher);
     jobs.insert("ais-backfiller", get_ais_backfiller);
+    jobs.insert("snapshot-planner", get_snapshot_planner);
+    jobs.insert("snapshot-promoter", get_snapshot_promoter);
+    jobs.insert("snapshot-verifier", get_snapshot_verifier);
     jobs
   };
 
@@ -73,3 +79,15 @@ fn get_lcs_fetcher() -> Box<Job> {
 fn get_ais_backfiller() -> Box<Job> {
   Box::new(AisBackfillerJob::for_upstream_indexes().unwrap())
 }
+
+fn get_snapshot_planner() -> Box<Job> {
+  Box::new(SnapshotPlannerJob::for_upstream_indexes().unwrap())
+}
+
+fn get_snapshot_promoter() -> Box<Job> {
+  Box::new(SnapshotPromoterJob::for_configured_repository().unwrap())
+}
+
+fn get_snapshot_verifier() -> Box<Job> {
+  Box::new(SnapshotVerifierJob::for_configured_repository().unwrap())
+}
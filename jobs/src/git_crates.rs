@@ -0,0 +1,511 @@
+use ::JobErr;
+use common::cargo::CrateKey;
+use common::cargo;
+use common::checksum;
+use common::manifest::CargoManifest;
+use common::snapshot::GitResolution;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use git2::Repository;
+use git2::build::CheckoutBuilder;
+use std::fs::File;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+use tar::Builder;
+use tar::Header;
+use toml;
+
+// The number of characters of the revision (or digest) that qualify a packaged crate's version
+const SHORT_REVISION_LENGTH: usize = 7;
+
+// The tables of a manifest (or of a target within it) that hold dependencies
+const DEPENDENCY_TABLES: &'static [&'static str] = &[
+  "dependencies",
+  "dev-dependencies",
+  "dev_dependencies",
+  "build-dependencies",
+  "build_dependencies",
+];
+
+// The keys of a dependency that point somewhere other than a registry
+const LOCAL_SOURCE_KEYS: &'static [&'static str] = &["path", "git", "branch", "tag", "rev"];
+
+/** A crate that was packaged from a git repository at a specific revision, or from a local path. */
+pub struct GitCrate {
+  // Built from the crate's manifest, as crates.io would, with a git (or path) qualified version
+  pub index_entry: cargo::IndexEntry,
+  // The packaged .crate file, within the scratch directory it was packaged into
+  pub crate_path: PathBuf,
+}
+
+impl GitCrate {
+  /** The key that the crate is stored in the LCS under. */
+  pub fn get_crate_key(&self) -> CrateKey {
+    CrateKey::from(self.index_entry.clone())
+  }
+
+  /** Yields the crate's dev dependencies, which are kept in the entry as crates.io does. */
+  pub fn get_dev_dependencies(&self) -> Vec<&cargo::DependencyEntry> {
+    self.index_entry.deps.iter()
+      .filter(|dep| dep.kind.as_ref().map(|k| k == "dev").unwrap_or(false))
+      .collect()
+  }
+}
+
+/**
+ * Yields the version given to a crate built from git: its declared version, qualified by the
+ * revision as semver build metadata (such as "0.2.2+git.abc1234").
+ *
+ * Build metadata is ignored when matching requirements, so dependents resolve it like the declared
+ * version.
+ */
+pub fn get_git_version(declared_version: &str, revision: &str) -> String {
+  qualify_version(declared_version, "git", revision)
+}
+
+/**
+ * Yields the version given to a crate packaged from a local path: its declared version, qualified by
+ * the checksum of its contents (such as "0.2.2+path.1a2b3c4").
+ *
+ * Edits to a local crate rarely change its declared version, so the checksum keeps each edit
+ * distinct in the LCS.
+ */
+pub fn get_path_version(declared_version: &str, cksum: &str) -> String {
+  qualify_version(declared_version, "path", cksum)
+}
+
+fn qualify_version(declared_version: &str, source: &str, identifier: &str) -> String {
+  let release = declared_version.split('+').next().unwrap_or(declared_version);
+  format!("{}+{}.{}", release, source, identifier.chars().take(SHORT_REVISION_LENGTH).collect::<String>())
+}
+
+/**
+ * Clones the repository, checks out the revision, and packages the named crate (which may be in
+ * any directory of the repository) into a .crate within the scratch directory.
+ */
+pub fn package_git_crate(crate_name: &str,
+                         resolution: &GitResolution,
+                         scratch_path: &Path) -> Result<GitCrate, JobErr> {
+  let checkout_path = scratch_path.join(format!("{}-checkout", crate_name));
+  let repository = try!(Repository::clone(&resolution.repository, &checkout_path));
+  let commit = try!(repository.revparse_single(&format!("{}^{{commit}}", resolution.revision)));
+  try!(repository.checkout_tree(&commit, Some(CheckoutBuilder::new().force())));
+  try!(repository.set_head_detached(commit.id()));
+
+  let crate_directory = match try!(find_crate_directory(&checkout_path, crate_name)) {
+    Some(crate_directory) => crate_directory,
+    None => return Err(JobErr::OtherErr(format!("{} was not found in {} at {}",
+                                                crate_name,
+                                                resolution.repository,
+                                                resolution.revision))),
+  };
+  let packaged_manifest = try!(get_packaged_manifest(crate_name, &crate_directory));
+  let version = get_git_version(&try!(get_declared_version(&packaged_manifest)), &commit.id().to_string());
+  package_crate_directory(crate_name, &crate_directory, &packaged_manifest, &version, scratch_path)
+}
+
+/**
+ * Packages the named crate from a local directory (or from any directory beneath it) into a .crate
+ * within the scratch directory.
+ */
+pub fn package_path_crate(crate_name: &str, source_path: &Path, scratch_path: &Path) -> Result<GitCrate, JobErr> {
+  if !source_path.is_dir() {
+    return Err(JobErr::OtherErr(format!("{} resolves from {:?}, which is not a directory", crate_name, source_path)))
+  }
+  let crate_directory = match try!(find_crate_directory(source_path, crate_name)) {
+    Some(crate_directory) => crate_directory,
+    None => return Err(JobErr::OtherErr(format!("{} was not found in {:?}", crate_name, source_path))),
+  };
+  let packaged_manifest = try!(get_packaged_manifest(crate_name, &crate_directory));
+  let declared_version = try!(get_declared_version(&packaged_manifest));
+
+  // The contents are checksummed under the declared version, then packaged again under the
+  // version that the checksum qualifies
+  let draft = try!(package_crate_directory(crate_name,
+                                           &crate_directory,
+                                           &packaged_manifest,
+                                           &declared_version,
+                                           scratch_path));
+  try!(fs::remove_file(&draft.crate_path));
+  let version = get_path_version(&declared_version, &draft.index_entry.cksum);
+  package_crate_directory(crate_name, &crate_directory, &packaged_manifest, &version, scratch_path)
+}
+
+fn get_declared_version(packaged_manifest: &str) -> Result<String, JobErr> {
+  let manifest = try!(CargoManifest::from_str(packaged_manifest));
+  Ok(manifest.get_package().map(|p| p.version.clone()).unwrap_or_default())
+}
+
+/** Writes the crate directory into a .crate under the provided version, and builds its entry. */
+fn package_crate_directory(crate_name: &str,
+                           crate_directory: &Path,
+                           packaged_manifest: &str,
+                           version: &str,
+                           scratch_path: &Path) -> Result<GitCrate, JobErr> {
+  let crate_path = scratch_path.join(format!("{}-{}.crate", crate_name, version));
+  try!(write_crate_tarball(crate_directory,
+                           packaged_manifest,
+                           &format!("{}-{}", crate_name, version),
+                           &crate_path));
+  let mut crate_bytes = Vec::new();
+  try!(File::open(&crate_path).and_then(|mut f| f.read_to_end(&mut crate_bytes)));
+
+  let manifest = try!(CargoManifest::from_str(packaged_manifest));
+  let mut index_entry = manifest.to_index_entry(&checksum::get_sha256(&crate_bytes)).unwrap();
+  index_entry.vers = version.to_owned();
+  Ok(GitCrate {
+    index_entry: index_entry,
+    crate_path: crate_path,
+  })
+}
+
+/**
+ * Finds the shallowest directory whose Cargo.toml declares the named package.
+ *
+ * Manifests that don't parse (such as templates or test fixtures) are passed over, since they
+ * can't declare the package.
+ */
+fn find_crate_directory(root: &Path, crate_name: &str) -> Result<Option<PathBuf>, JobErr> {
+  let mut pending_directories = vec![root.to_path_buf()];
+  while !pending_directories.is_empty() {
+    let mut next_directories = Vec::new();
+    for directory in pending_directories.into_iter() {
+      if directory.join("Cargo.toml").is_file() {
+        match read_manifest(&directory) {
+          Ok(ref manifest) if manifest.get_package().map(|p| p.name == crate_name).unwrap_or(false) => {
+            return Ok(Some(directory))
+          },
+          Ok(_) => {},
+          Err(err) => debug!("Skipping the manifest in {:?}, which could not be read: {:?}", directory, err),
+        }
+      }
+      for entry in try!(get_packaged_entries(&directory)).into_iter() {
+        if entry.is_dir() {
+          next_directories.push(entry);
+        }
+      }
+    }
+    pending_directories = next_directories;
+  }
+  Ok(None)
+}
+
+fn read_manifest(directory: &Path) -> Result<CargoManifest, JobErr> {
+  let mut contents = String::new();
+  try!(File::open(directory.join("Cargo.toml")).and_then(|mut f| f.read_to_string(&mut contents)));
+  Ok(try!(CargoManifest::from_str(&contents)))
+}
+
+/**
+ * Yields the directory's entries in name order, less those that never belong in a package.
+ *
+ * Symlinks are left out too, as they may point outside of the repository (or back into it,
+ * forming a cycle).
+ */
+fn get_packaged_entries(directory: &Path) -> Result<Vec<PathBuf>, JobErr> {
+  let mut entries = Vec::new();
+  for entry in try!(fs::read_dir(directory)) {
+    let entry = try!(entry);
+    let path = entry.path();
+    let is_excluded = path.file_name()
+      .map(|name| name == ".git" || name == "target")
+      .unwrap_or(false);
+    if !is_excluded && !try!(entry.file_type()).is_symlink() {
+      entries.push(path);
+    }
+  }
+  entries.sort();
+  Ok(entries)
+}
+
+/**
+ * Yields the crate's manifest as it would be published: path and git dependencies keep only their
+ * version requirement, as `cargo package` leaves them.
+ *
+ * Dev dependencies without a version are dropped. Any other dependency without one is an error,
+ * since nothing outside of the repository could resolve it.
+ */
+fn get_packaged_manifest(crate_name: &str, crate_directory: &Path) -> Result<String, JobErr> {
+  let mut contents = String::new();
+  try!(File::open(crate_directory.join("Cargo.toml")).and_then(|mut f| f.read_to_string(&mut contents)));
+  let mut manifest = try!(contents.parse::<toml::Value>());
+  let mut unversioned_dependencies = Vec::new();
+  if let Some(table) = manifest.as_table_mut() {
+    unversioned_dependencies.extend(translate_local_dependencies(table));
+    if let Some(&mut toml::Value::Table(ref mut targets)) = table.get_mut("target") {
+      for target in targets.values_mut() {
+        if let Some(target_table) = target.as_table_mut() {
+          unversioned_dependencies.extend(translate_local_dependencies(target_table));
+        }
+      }
+    }
+  }
+  if !unversioned_dependencies.is_empty() {
+    return Err(JobErr::OtherErr(format!("{} depends on {} by path or git without a version, so it cannot be packaged",
+                                        crate_name,
+                                        unversioned_dependencies.join(", "))))
+  }
+
+  toml::to_string(&manifest)
+    .map_err(|err| JobErr::OtherErr(format!("Could not rewrite the manifest of {}: {}", crate_name, err)))
+}
+
+/**
+ * Strips the local source from each versioned dependency in the table's dependency tables, and
+ * drops unversioned dev dependencies. Yields the other unversioned dependencies, which are kept.
+ */
+fn translate_local_dependencies(table: &mut toml::value::Table) -> Vec<String> {
+  let mut unversioned_dependencies = Vec::new();
+  for key in DEPENDENCY_TABLES.iter() {
+    let is_dev_table = key.starts_with("dev");
+    if let Some(&mut toml::Value::Table(ref mut dependencies)) = table.get_mut(*key) {
+      let mut dropped_dependencies = Vec::new();
+      for (name, dependency) in dependencies.iter_mut() {
+        let details = match dependency.as_table_mut() {
+          Some(details) => details,
+          None => continue,
+        };
+        if !LOCAL_SOURCE_KEYS.iter().any(|source_key| details.contains_key(*source_key)) {
+          continue
+        }
+        if details.contains_key("version") {
+          for source_key in LOCAL_SOURCE_KEYS.iter() {
+            details.remove(*source_key);
+          }
+        } else if is_dev_table {
+          dropped_dependencies.push(name.clone());
+        } else {
+          unversioned_dependencies.push(name.clone());
+        }
+      }
+      for name in dropped_dependencies.iter() {
+        dependencies.remove(name);
+      }
+    }
+  }
+  unversioned_dependencies
+}
+
+/**
+ * Writes every file in the crate directory into a gzipped tarball, nested within a "$NAME-$VERSION/"
+ * directory as crates.io packages are. The provided manifest stands in for the crate's Cargo.toml.
+ *
+ * Subdirectories with a Cargo.toml of their own hold other packages, so they are left out as
+ * `cargo package` leaves them out.
+ *
+ * Entries are written in name order with fixed metadata, so the same tree always yields the same
+ * tarball (and checksum).
+ */
+fn write_crate_tarball(crate_directory: &Path,
+                       manifest_contents: &str,
+                       prefix: &str,
+                       destination: &Path) -> Result<(), JobErr> {
+  let encoder = GzEncoder::new(try!(File::create(destination)), Compression::Default);
+  let mut builder = Builder::new(encoder);
+  let mut pending_paths = try!(get_packaged_entries(crate_directory));
+  pending_paths.reverse();
+  while let Some(path) = pending_paths.pop() {
+    if path.is_dir() {
+      if path.join("Cargo.toml").is_file() {
+        continue
+      }
+      let mut children = try!(get_packaged_entries(&path));
+      children.reverse();
+      pending_paths.extend(children.into_iter());
+      continue
+    }
+    let relative_path = path.strip_prefix(crate_directory).unwrap();
+    let mut header = Header::new_gnu();
+    try!(header.set_path(Path::new(prefix).join(relative_path)));
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    if relative_path == Path::new("Cargo.toml") {
+      header.set_size(manifest_contents.len() as u64);
+      header.set_cksum();
+      try!(builder.append(&header, manifest_contents.as_bytes()));
+    } else {
+      let mut file = try!(File::open(&path));
+      header.set_size(try!(file.metadata()).len());
+      header.set_cksum();
+      try!(builder.append(&header, &mut file));
+    }
+  }
+  try!(try!(builder.into_inner()).finish());
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use common::snapshot::GitResolution;
+  use flate2::read::GzDecoder;
+  use git2::Repository;
+  use git_crates;
+  use git_util;
+  use std::fs::File;
+  use std::fs;
+  use std::io::Read;
+  use std::io::Write;
+  use std::os::unix::fs::symlink;
+  use std::path::PathBuf;
+  use tar::Archive;
+  use tempdir::TempDir;
+
+  fn write_file(path: PathBuf, contents: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    File::create(path).unwrap().write_all(contents.as_bytes()).unwrap();
+  }
+
+  fn commit_all(repository: &Repository, paths: &[&str]) -> String {
+    let paths = paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+    git_util::commit_paths(repository, &paths, "Commit").unwrap().to_string()
+  }
+
+  #[test]
+  fn test_packages_a_workspace_member_at_a_revision() {
+    let repository_dir = TempDir::new("git-crate-repository").unwrap();
+    let repository = Repository::init(repository_dir.path()).unwrap();
+    write_file(repository_dir.path().join("Cargo.toml"), "[workspace]\nmembers = [\"forked\"]\n");
+    write_file(repository_dir.path().join("forked/Cargo.toml"),
+               "[package]\nname = \"forked\"\nversion = \"0.2.2\"\n\n\
+                [dependencies]\nlibc = \"0.2\"\n\n[dev-dependencies]\nrand = \"0.3\"\n\n\
+                [features]\ndefault = []\nextra = [\"libc/extra\"]\n");
+    write_file(repository_dir.path().join("forked/src/lib.rs"), "pub fn forked() {}\n");
+    // A package nested within the crate, which is not part of it
+    write_file(repository_dir.path().join("forked/forked-syntax/Cargo.toml"),
+               "[package]\nname = \"forked-syntax\"\nversion = \"0.4.1\"\n");
+    write_file(repository_dir.path().join("forked/forked-syntax/src/lib.rs"), "\n");
+    let revision = git_util::commit_paths(&repository,
+                                          &[PathBuf::from("Cargo.toml"),
+                                            PathBuf::from("forked/Cargo.toml"),
+                                            PathBuf::from("forked/src/lib.rs"),
+                                            PathBuf::from("forked/forked-syntax/Cargo.toml"),
+                                            PathBuf::from("forked/forked-syntax/src/lib.rs")],
+                                          "Fork").unwrap().to_string();
+    // Later commits must not leak into the package
+    write_file(repository_dir.path().join("forked/src/later.rs"), "\n");
+    git_util::commit_paths(&repository, &[PathBuf::from("forked/src/later.rs")], "Later").unwrap();
+
+    let scratch_dir = TempDir::new("git-crate-scratch").unwrap();
+    let resolution = GitResolution {
+      repository: repository_dir.path().to_string_lossy().into_owned(),
+      revision: revision.clone(),
+    };
+    let git_crate = git_crates::package_git_crate("forked", &resolution, scratch_dir.path()).unwrap();
+
+    let expected_version = format!("0.2.2+git.{}", &revision[0..7]);
+    assert_eq!(git_crate.index_entry.vers, expected_version);
+    assert_eq!(git_crate.get_crate_key().version, expected_version);
+    assert_eq!(git_crate.index_entry.cksum.len(), 64);
+    let deps = git_crate.index_entry.deps.iter()
+      .map(|dep| (dep.name.as_str(), dep.kind.as_ref().map(|k| k.as_str())))
+      .collect::<Vec<_>>();
+    assert_eq!(deps, vec![("libc", Some("normal")), ("rand", Some("dev"))]);
+    assert_eq!(git_crate.get_dev_dependencies().len(), 1);
+    assert_eq!(git_crate.index_entry.features.get("extra"), Some(&vec!["libc/extra".to_owned()]));
+
+    let mut archive = Archive::new(GzDecoder::new(File::open(&git_crate.crate_path).unwrap()).unwrap());
+    let paths = archive.entries().unwrap()
+      .map(|entry| entry.unwrap().path().unwrap().to_string_lossy().into_owned())
+      .collect::<Vec<_>>();
+    assert_eq!(paths, vec![
+      format!("forked-{}/Cargo.toml", expected_version),
+      format!("forked-{}/src/lib.rs", expected_version),
+    ]);
+  }
+
+  #[test]
+  fn test_packages_path_dependencies_as_registry_dependencies() {
+    let repository_dir = TempDir::new("git-crate-repository").unwrap();
+    let repository = Repository::init(repository_dir.path()).unwrap();
+    write_file(repository_dir.path().join("Cargo.toml"),
+               "[workspace]\nmembers = [\"forked\", \"forked_core\"]\n");
+    // A template whose manifest does not parse, which is shallower than the crate
+    write_file(repository_dir.path().join("template/Cargo.toml"), "[package\nname = {{name}}\n");
+    write_file(repository_dir.path().join("forked_core/Cargo.toml"),
+               "[package]\nname = \"forked_core\"\nversion = \"0.2.2\"\n");
+    write_file(repository_dir.path().join("nested/forked/Cargo.toml"),
+               "[package]\nname = \"forked\"\nversion = \"0.2.2\"\n\n\
+                [dependencies]\nforked_core = { path = \"../../forked_core\", version = \"0.2\" }\n\n\
+                [dev-dependencies]\ntest_helpers = { path = \"../../test_helpers\" }\n");
+    write_file(repository_dir.path().join("nested/forked/src/lib.rs"), "\n");
+    // A link out of the checkout, which must neither be searched nor packaged
+    symlink(repository_dir.path(), repository_dir.path().join("nested/forked/src/outside")).unwrap();
+    let revision = commit_all(&repository,
+                              &["Cargo.toml",
+                                "template/Cargo.toml",
+                                "forked_core/Cargo.toml",
+                                "nested/forked/Cargo.toml",
+                                "nested/forked/src/lib.rs",
+                                "nested/forked/src/outside"]);
+
+    let scratch_dir = TempDir::new("git-crate-scratch").unwrap();
+    let resolution = GitResolution {
+      repository: repository_dir.path().to_string_lossy().into_owned(),
+      revision: revision.clone(),
+    };
+    let git_crate = git_crates::package_git_crate("forked", &resolution, scratch_dir.path()).unwrap();
+
+    let deps = git_crate.index_entry.deps.iter()
+      .map(|dep| (dep.name.as_str(), dep.req.as_str()))
+      .collect::<Vec<_>>();
+    assert_eq!(deps, vec![("forked_core", "0.2")]);
+
+    let mut archive = Archive::new(GzDecoder::new(File::open(&git_crate.crate_path).unwrap()).unwrap());
+    let mut packaged_manifest = String::new();
+    let mut paths = Vec::new();
+    for entry in archive.entries().unwrap() {
+      let mut entry = entry.unwrap();
+      let path = entry.path().unwrap().to_string_lossy().into_owned();
+      if path.ends_with("/Cargo.toml") {
+        entry.read_to_string(&mut packaged_manifest).unwrap();
+      }
+      paths.push(path);
+    }
+    assert_eq!(paths.len(), 2);
+    assert!(!packaged_manifest.contains("path"));
+    assert!(!packaged_manifest.contains("test_helpers"));
+    assert!(packaged_manifest.contains("forked_core"));
+  }
+
+  #[test]
+  fn test_rejects_unversioned_path_dependencies() {
+    let repository_dir = TempDir::new("git-crate-repository").unwrap();
+    let repository = Repository::init(repository_dir.path()).unwrap();
+    write_file(repository_dir.path().join("Cargo.toml"),
+               "[package]\nname = \"forked\"\nversion = \"0.2.2\"\n\n\
+                [dependencies]\nforked_core = { path = \"forked_core\" }\n");
+    write_file(repository_dir.path().join("src/lib.rs"), "\n");
+    let revision = commit_all(&repository, &["Cargo.toml", "src/lib.rs"]);
+
+    let scratch_dir = TempDir::new("git-crate-scratch").unwrap();
+    let resolution = GitResolution {
+      repository: repository_dir.path().to_string_lossy().into_owned(),
+      revision: revision,
+    };
+    assert!(git_crates::package_git_crate("forked", &resolution, scratch_dir.path()).is_err());
+  }
+
+  #[test]
+  fn test_packages_a_local_crate_under_a_checksum_qualified_version() {
+    let source_dir = TempDir::new("path-crate-source").unwrap();
+    write_file(source_dir.path().join("local/Cargo.toml"), "[package]\nname = \"local\"\nversion = \"0.1.0\"\n");
+    write_file(source_dir.path().join("local/src/lib.rs"), "\n");
+    let scratch_dir = TempDir::new("path-crate-scratch").unwrap();
+
+    let packaged = git_crates::package_path_crate("local", source_dir.path(), scratch_dir.path()).unwrap();
+    assert!(packaged.index_entry.vers.starts_with("0.1.0+path."));
+    assert_eq!(packaged.crate_path,
+               scratch_dir.path().join(format!("local-{}.crate", packaged.index_entry.vers)));
+    assert_eq!(fs::read_dir(scratch_dir.path()).unwrap().count(), 1);
+    let repackaged = git_crates::package_path_crate("local", source_dir.path(), scratch_dir.path()).unwrap();
+    assert_eq!(repackaged.index_entry.vers, packaged.index_entry.vers);
+
+    write_file(source_dir.path().join("local/src/lib.rs"), "pub fn edited() {}\n");
+    let edited = git_crates::package_path_crate("local", source_dir.path(), scratch_dir.path()).unwrap();
+    assert!(edited.index_entry.vers.starts_with("0.1.0+path."));
+    assert!(edited.index_entry.vers != packaged.index_entry.vers);
+    assert!(git_crates::package_path_crate("local", &source_dir.path().join("missing"), scratch_dir.path()).is_err());
+  }
+}
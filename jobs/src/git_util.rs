@@ -0,0 +1,38 @@
+use ::JobErr;
+use git2::Oid;
+use git2::Repository;
+use git2::Signature;
+use std::path::PathBuf;
+
+/** The identity used when committing to (or tagging) a repository that stockpile maintains. */
+pub const COMMITTER_NAME: &'static str = "stockpile";
+pub const COMMITTER_EMAIL: &'static str = "stockpile@localhost";
+
+pub fn get_signature() -> Result<Signature<'static>, JobErr> {
+  Ok(try!(Signature::now(COMMITTER_NAME, COMMITTER_EMAIL)))
+}
+
+/** Stages the provided paths (relative to the repository root) and commits them onto HEAD. */
+pub fn commit_paths(repository: &Repository, paths: &[PathBuf], message: &str) -> Result<Oid, JobErr> {
+  let mut index = try!(repository.index());
+  for path in paths.iter() {
+    try!(index.add_path(path));
+  }
+  try!(index.write());
+  let tree = try!(repository.find_tree(try!(index.write_tree())));
+  let signature = try!(get_signature());
+  // A freshly initialized repository has no HEAD yet
+  let parent = match repository.head().ok().and_then(|head| head.target()) {
+    Some(oid) => Some(try!(repository.find_commit(oid))),
+    None => None,
+  };
+  let parents = parent.iter().collect::<Vec<_>>();
+
+  let oid = try!(repository.commit(Some("HEAD"),
+                                   &signature,
+                                   &signature,
+                                   message,
+                                   &tree,
+                                   parents.as_slice()));
+  Ok(oid)
+}
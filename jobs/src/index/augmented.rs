@@ -1,10 +1,14 @@
 use ::JobErr;
 use common::cargo::CrateKey;
 use common::cargo;
+use git2::Repository;
 use index::GenericIndexLoader;
 use index::GenericIndexParams;
 use index::GenericIndexArtifacts;
 use index::KeyedByCrateKey;
+use semver::Version;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use url::Url;
@@ -70,6 +74,63 @@ impl AugmentedIndex {
   }
 }
 
+impl AugmentedIndex {
+  /**
+   * Inserts or replaces the provided entries, and rewrites the index file of every affected
+   * crate.
+   *
+   * Entries within a crate file are written in semver order so that repeated backfills of the
+   * same data produce identical files. Yields the paths of the rewritten files, relative to the
+   * root of the index.
+   */
+  pub fn write_entries(&mut self, entries: Vec<cargo::AugmentedIndexEntry>) -> Result<Vec<PathBuf>, JobErr> {
+    let mut affected_crate_names = BTreeSet::new();
+    for entry in entries.into_iter() {
+      affected_crate_names.insert(entry.name.clone());
+      self.contents.insert(cargo::CrateKey::from(entry.clone()), entry);
+    }
+
+    let mut entries_by_crate_name = BTreeMap::new();
+    for entry in self.contents.values() {
+      if affected_crate_names.contains(&entry.name) {
+        entries_by_crate_name.entry(entry.name.clone())
+          .or_insert_with(Vec::new)
+          .push(entry.clone());
+      }
+    }
+
+    let mut written_paths = Vec::new();
+    for (crate_name, mut crate_entries) in entries_by_crate_name.into_iter() {
+      crate_entries.sort_by_key(|entry| get_version_order_key(&entry.vers));
+      written_paths.push(try!(self.loader_artifacts.write_crate_file(&crate_name, &crate_entries)));
+    }
+
+    Ok(written_paths)
+  }
+
+  /** Yields the git repository backing this index. */
+  pub fn get_repository(&self) -> &Repository {
+    &*self.loader_artifacts.repository
+  }
+
+  /** Commits the provided (previously written) index files to the index repository. */
+  pub fn commit_changes(&self, paths: &[PathBuf], message: &str) -> Result<(), JobErr> {
+    let oid = try!(self.loader_artifacts.commit_paths(paths, message));
+    info!("Committed {} augmented index files as {}", paths.len(), oid);
+    Ok(())
+  }
+}
+
+/**
+ * Orders versions by semver precedence, rather than lexically (which puts 0.10.0 before 0.9.0).
+ *
+ * Versions that don't parse come first, and the raw version breaks ties between versions that
+ * differ only in build metadata, so the order is always total.
+ */
+pub fn get_version_order_key(version: &str) -> (Option<Version>, String) {
+  (Version::parse(version).ok(), version.to_owned())
+}
+
 impl KeyedByCrateKey for AugmentedIndex {
   type Item = cargo::AugmentedIndexEntry;
 
@@ -88,6 +149,7 @@ pub mod testing {
   use index::GenericIndexParams;
   use index::augmented::AugmentedIndex;
   use index::augmented::AugmentedIndexParams;
+  use std::sync::Arc;
   use url::Url;
 
   pub fn get_seeded_index(entries: Vec<cargo::AugmentedIndexEntry>) -> AugmentedIndex {
@@ -99,7 +161,10 @@ pub mod testing {
       }
     };
 
-    AugmentedIndex::new(params).unwrap()
+    let mut index = AugmentedIndex::new(params).unwrap();
+    // Keep the seeded directory alive for as long as the index is
+    index.loader_artifacts.tempdir = Arc::new(Some(tempdir));
+    index
   }
 
   pub fn get_minimum_index() -> AugmentedIndex {
@@ -114,6 +179,9 @@ mod tests {
   use url::Url;
   use index::augmented;
   use index::KeyedByCrateKey;
+  use serde_json;
+  use std::fs::File;
+  use std::io::Read;
 
   #[test]
   fn test_empty_local_index_works() {
@@ -127,6 +195,8 @@ mod tests {
       name: "a".to_owned(),
       vers: "0.0.1".to_owned(),
       dev_dependencies: Some(Vec::new()),
+      source_statistics: None,
+      license: None,
     };
     let index = augmented::testing::get_seeded_index(vec![index_entry]);
 
@@ -136,4 +206,27 @@ mod tests {
                  version: "0.0.1".to_owned()
                }]);
   }
+
+  #[test]
+  fn test_writes_entries_in_semver_order() {
+    let mut index = augmented::testing::get_minimum_index();
+    let entries = ["0.10.0", "0.9.0", "0.9.0-alpha", "0.2.0"].iter()
+      .map(|version| cargo::AugmentedIndexEntry {
+        name: "a".to_owned(),
+        vers: version.to_string(),
+        dev_dependencies: Some(Vec::new()),
+        source_statistics: None,
+        license: None,
+      })
+      .collect::<Vec<_>>();
+    let written_paths = index.write_entries(entries).unwrap();
+
+    let mut contents = String::new();
+    let index_root = index.get_repository().workdir().unwrap().to_path_buf();
+    File::open(index_root.join(&written_paths[0])).unwrap().read_to_string(&mut contents).unwrap();
+    let versions = contents.lines()
+      .map(|line| serde_json::from_str::<cargo::AugmentedIndexEntry>(line).unwrap().vers)
+      .collect::<Vec<_>>();
+    assert_eq!(versions, vec!["0.2.0", "0.9.0-alpha", "0.9.0", "0.10.0"]);
+  }
 }
@@ -1,11 +1,16 @@
 use ::JobErr;
 use common::cargo::CrateKey;
 use common::cargo;
+use common::index::IndexEntrySource;
 use index::GenericIndexLoader;
 use index::GenericIndexParams;
 use index::GenericIndexArtifacts;
 use index::KeyedByCrateKey;
+use index::revisions::RevisionHistory;
+use index::revisions;
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::io;
 use std::path::PathBuf;
 use url::Url;
 
@@ -19,12 +24,18 @@ mod flags {
                   ::zcfg::NoneableCfg<String>,
                   None,
                   "The path to the crates.io index to use in lieu of pulling a fresh copy.");
+
+  define_pub_cfg!(crates_io_revisions_directory,
+                  ::zcfg::NoneableCfg<String>,
+                  None,
+                  "The path to a directory of metadata revisions to apply on top of the crates.io index.");
 }
 
 /** The parameters required to load and use a crates.io metadata index. */
 #[derive(Builder, Clone)]
 pub struct CratesIoIndexParams {
   generic_params: GenericIndexParams,
+  revisions_path: Option<PathBuf>,
 }
 
 impl CratesIoIndexParams {
@@ -33,21 +44,30 @@ impl CratesIoIndexParams {
     let url = Url::parse(&flags::crates_io_index_url::CONFIG.get_value()).unwrap();
     let pre_pulled_index_path = flags::pre_pulled_crates_io_index_directory::CONFIG.get_value().inner()
       .map(PathBuf::from);
+    let revisions_path = flags::crates_io_revisions_directory::CONFIG.get_value().inner()
+      .map(PathBuf::from);
     CratesIoIndexParams {
       generic_params: GenericIndexParams {
         url: url,
         pre_pulled_index_path: pre_pulled_index_path,
       },
+      revisions_path: revisions_path,
     }
   }
 }
 
-/** A loaded, ready-to-use crates.io metadata index. */
+/**
+ * A loaded, ready-to-use crates.io metadata index.
+ *
+ * Entries reflect any metadata revisions that were provided, rather than the upstream entries.
+ */
 #[derive(Clone)]
 pub struct CratesIoIndex {
   params: CratesIoIndexParams,
   loader_artifacts: GenericIndexArtifacts,
   contents: HashMap<cargo::CrateKey, cargo::IndexEntry>,
+  broken_keys: HashSet<cargo::CrateKey>,
+  revision_histories: HashMap<cargo::CrateKey, RevisionHistory>,
 }
 
 impl CratesIoIndex {
@@ -59,19 +79,42 @@ impl CratesIoIndex {
   pub fn new(params: CratesIoIndexParams) -> Result<CratesIoIndex, JobErr> {
     let loader = GenericIndexLoader::new(params.generic_params.clone());
     let (artifacts, contents) = try!(loader.load_index::<cargo::IndexEntry>());
-    let keyed_contents = contents.into_iter()
+    let mut keyed_contents = contents.into_iter()
       .map(|content| {
         let key = ::std::convert::From::from(content.clone());
         (key, content)
       })
       .collect::<HashMap<_, _>>();
 
+    let revisions = match params.revisions_path {
+      Some(ref revisions_path) => try!(revisions::load_revisions(revisions_path)),
+      None => Vec::new(),
+    };
+    debug!("Applying {} metadata revisions to the crates.io index", revisions.len());
+    let revised_contents = try!(revisions::apply_revisions(&mut keyed_contents, revisions));
+
     Ok(CratesIoIndex {
       params: params,
       loader_artifacts: artifacts,
-      contents: keyed_contents
+      contents: keyed_contents,
+      broken_keys: revised_contents.broken_keys,
+      revision_histories: revised_contents.histories,
     })
   }
+
+  /** Whether a metadata revision has marked the crate version as broken. */
+  pub fn is_broken(&self, key: &CrateKey) -> bool {
+    self.broken_keys.contains(key)
+  }
+
+  /**
+   * Yields the upstream entry, and every revision applied on top of it, for a crate version.
+   *
+   * Yields None if the crate version has not been revised.
+   */
+  pub fn get_revision_history(&self, key: &CrateKey) -> Option<&RevisionHistory> {
+    self.revision_histories.get(key)
+  }
 }
 
 impl KeyedByCrateKey for CratesIoIndex {
@@ -86,24 +129,47 @@ impl KeyedByCrateKey for CratesIoIndex {
   }
 }
 
+/** Scans the whole index, so this is only suitable for occasional lookups. */
+impl IndexEntrySource for CratesIoIndex {
+  fn get_entries(&self, crate_name: &str) -> io::Result<Vec<cargo::IndexEntry>> {
+    Ok(self.contents.iter()
+      .filter(|&(key, _)| key.name == crate_name)
+      .map(|(_, entry)| entry.clone())
+      .collect())
+  }
+}
+
 pub mod testing {
   use common::cargo;
   use index;
   use index::GenericIndexParams;
   use index::crates_io::CratesIoIndex;
   use index::crates_io::CratesIoIndexParams;
+  use index::revisions::Revision;
+  use index::revisions;
+  use std::sync::Arc;
   use url::Url;
 
   pub fn get_seeded_index(entries: Vec<cargo::IndexEntry>) -> CratesIoIndex {
+    get_seeded_index_with_revisions(entries, &[])
+  }
+
+  /** Seeds an index, and applies the provided metadata revisions on top of it. */
+  pub fn get_seeded_index_with_revisions(entries: Vec<cargo::IndexEntry>, revisions: &[Revision]) -> CratesIoIndex {
     let tempdir = index::testing::seed_index_with_contents::<cargo::IndexEntry>(entries);
+    let revisions_tempdir = revisions::testing::seed_revisions(revisions);
     let params = CratesIoIndexParams {
       generic_params: GenericIndexParams {
         url: Url::parse("http://not-resolvable").unwrap(),
         pre_pulled_index_path: Some(tempdir.path().to_path_buf()),
-      }
+      },
+      revisions_path: Some(revisions_tempdir.path().to_path_buf()),
     };
 
-    CratesIoIndex::new(params).unwrap()
+    let mut index = CratesIoIndex::new(params).unwrap();
+    // Keep the seeded directory alive for as long as the index is
+    index.loader_artifacts.tempdir = Arc::new(Some(tempdir));
+    index
   }
 
   pub fn get_minimum_index() -> CratesIoIndex {
@@ -118,6 +184,8 @@ mod tests {
   use url::Url;
   use index::crates_io;
   use index::KeyedByCrateKey;
+  use index::revisions::Revision;
+  use index::revisions::RevisionAction;
 
   #[test]
   fn test_empty_local_index_works() {
@@ -143,4 +211,32 @@ mod tests {
                  version: "0.0.1".to_owned()
                }]);
   }
+
+  #[test]
+  fn test_revisions_are_applied_on_load() {
+    let key = cargo::CrateKey { name: "a".to_owned(), version: "0.0.1".to_owned() };
+    let index_entry = cargo::IndexEntry {
+      name: "a".to_owned(),
+      vers: "0.0.1".to_owned(),
+      deps: Vec::new(),
+      cksum: "111".to_owned(),
+      features: HashMap::new(),
+      yanked: None,
+    };
+    let mark_broken = Revision {
+      crate_name: "a".to_owned(),
+      version: "0.0.1".to_owned(),
+      revision: 1,
+      author: "someone@example.com".to_owned(),
+      reason: "Fails to build on any supported rustc".to_owned(),
+      action: RevisionAction::MarkBroken,
+    };
+    let index = crates_io::testing::get_seeded_index_with_revisions(vec![index_entry.clone()],
+                                                                    &[mark_broken.clone()]);
+
+    assert!(index.is_broken(&key));
+    let history = index.get_revision_history(&key).unwrap();
+    assert_eq!(history.original_entry, index_entry);
+    assert_eq!(history.applied_revisions[0].revision, mark_broken);
+  }
 }
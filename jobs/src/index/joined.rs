@@ -0,0 +1,130 @@
+use common::cargo::CrateKey;
+use common::cargo;
+use index::KeyedByCrateKey;
+use std::collections::HashMap;
+
+/**
+ * A combined view over the crates.io-index and the augmented index.
+ *
+ * The crates.io-index is authoritative for which crates exist, so every entry in the joined index
+ * corresponds to an upstream entry, with the augmented entry attached where one exists. Keys found
+ * on only one side are retained so that they can be reported (and backfilled, or cleaned up).
+ */
+#[derive(Clone)]
+pub struct JoinedIndex {
+  contents: HashMap<CrateKey, cargo::JoinedIndexEntry>,
+  keys_missing_augmentation: Vec<CrateKey>,
+  keys_missing_upstream: Vec<CrateKey>,
+}
+
+impl JoinedIndex {
+  /** Joins an upstream index with an augmented index on CrateKey. */
+  pub fn new<U, A>(upstream_index: &U, augmented_index: &A) -> JoinedIndex
+      where U: KeyedByCrateKey<Item=cargo::IndexEntry>,
+            A: KeyedByCrateKey<Item=cargo::AugmentedIndexEntry> {
+    let mut contents = HashMap::new();
+    let mut keys_missing_augmentation = Vec::new();
+    for key in upstream_index.get_crate_keys().into_iter() {
+      let index_entry = upstream_index.get_entry(key).unwrap().clone();
+      let augmented_entry = augmented_index.get_entry(key).cloned();
+      if augmented_entry.is_none() {
+        keys_missing_augmentation.push(key.clone());
+      }
+      contents.insert(key.clone(), cargo::JoinedIndexEntry {
+        index_entry: index_entry,
+        augmented_entry: augmented_entry,
+      });
+    }
+
+    let mut keys_missing_upstream = augmented_index.get_crate_keys().into_iter()
+      .filter(|key| upstream_index.get_entry(key).is_none())
+      .cloned()
+      .collect::<Vec<_>>();
+
+    keys_missing_augmentation.sort();
+    keys_missing_upstream.sort();
+
+    JoinedIndex {
+      contents: contents,
+      keys_missing_augmentation: keys_missing_augmentation,
+      keys_missing_upstream: keys_missing_upstream,
+    }
+  }
+
+  /** Yields the (sorted) keys present upstream that have no augmented index entry. */
+  pub fn get_keys_missing_augmentation(&self) -> &Vec<CrateKey> {
+    &self.keys_missing_augmentation
+  }
+
+  /** Yields the (sorted) keys present in the augmented index that are not known upstream. */
+  pub fn get_keys_missing_upstream(&self) -> &Vec<CrateKey> {
+    &self.keys_missing_upstream
+  }
+}
+
+impl KeyedByCrateKey for JoinedIndex {
+  type Item = cargo::JoinedIndexEntry;
+
+  fn get_crate_keys(&self) -> Vec<&CrateKey> {
+    self.contents.keys().collect()
+  }
+
+  fn get_entry(&self, key: &CrateKey) -> Option<&cargo::JoinedIndexEntry> {
+    self.contents.get(key)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use common::cargo;
+  use common::index::testing::dependency_entry;
+  use common::index::testing::index_entry;
+  use index::KeyedByCrateKey;
+  use index::augmented;
+  use index::crates_io;
+  use index::joined::JoinedIndex;
+
+  fn augmented_entry(name: &str, dev_dependencies: Vec<cargo::DependencyEntry>) -> cargo::AugmentedIndexEntry {
+    cargo::AugmentedIndexEntry {
+      name: name.to_owned(),
+      vers: "0.1.0".to_owned(),
+      dev_dependencies: Some(dev_dependencies),
+      source_statistics: None,
+      license: None,
+    }
+  }
+
+  fn key(name: &str) -> cargo::CrateKey {
+    cargo::CrateKey { name: name.to_owned(), version: "0.1.0".to_owned() }
+  }
+
+  #[test]
+  fn test_joins_entries_and_reports_one_sided_keys() {
+    let upstream_index = crates_io::testing::get_seeded_index(vec![
+      index_entry("both", "0.1.0", &[("libc", "0.1")]),
+      index_entry("upstream-only", "0.1.0", &[]),
+    ]);
+    let augmented_index = augmented::testing::get_seeded_index(vec![
+      augmented_entry("both", vec![cargo::DependencyEntry {
+        kind: Some("dev".to_owned()),
+        ..dependency_entry("rand", "0.1")
+      }]),
+      augmented_entry("augmented-only", Vec::new()),
+    ]);
+
+    let joined_index = JoinedIndex::new(&upstream_index, &augmented_index);
+
+    let mut keys = joined_index.get_crate_keys();
+    keys.sort();
+    assert_eq!(keys, vec![&key("both"), &key("upstream-only")]);
+
+    let both = joined_index.get_entry(&key("both")).unwrap();
+    assert_eq!(both.get_deps()[0].name, "libc".to_owned());
+    assert_eq!(both.get_dev_dependencies().unwrap()[0].name, "rand".to_owned());
+    let upstream_only = joined_index.get_entry(&key("upstream-only")).unwrap();
+    assert_eq!(upstream_only.get_dev_dependencies(), None);
+
+    assert_eq!(joined_index.get_keys_missing_augmentation(), &vec![key("upstream-only")]);
+    assert_eq!(joined_index.get_keys_missing_upstream(), &vec![key("augmented-only")]);
+  }
+}
@@ -1,13 +1,17 @@
 use common::cargo::CrateKey;
 use ::JobErr;
 use common::iter_util;
+use git2::Oid;
 use git2::Repository;
+use git_util;
 use rayon::prelude::*;
+use serde::Serialize;
 use serde::de::DeserializeOwned;
 use serde_json;
 use std::fs::File;
 use std::fs;
 use std::io::Read;
+use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -16,6 +20,10 @@ use url::Url;
 
 pub mod crates_io;
 pub mod augmented;
+pub mod joined;
+pub mod revisions;
+
+pub use common::index::get_path_for_crate;
 
 /**
  * A trait that is applicable to any object that has data keyed by CrateKey.
@@ -155,39 +163,51 @@ pub struct GenericIndexArtifacts {
   pub tempdir: Arc<Option<TempDir>>
 }
 
+impl GenericIndexArtifacts {
+  /**
+   * Replaces the index file for the given crate name with the provided contents, one serialized
+   * entry per line.
+   *
+   * Yields the path of the written file, relative to the root of the index.
+   */
+  pub fn write_crate_file<T: Serialize>(&self, crate_name: &str, entries: &[T]) -> Result<PathBuf, JobErr> {
+    let workdir = try!(self.repository.workdir()
+      .map(Path::to_path_buf)
+      .ok_or(JobErr::OtherErr("Cannot write into a bare index repository".to_owned())));
+    let relative_path = get_path_for_crate(crate_name);
+    let full_path = workdir.join(&relative_path);
+    if let Some(parent) = full_path.parent() {
+      try!(fs::create_dir_all(parent));
+    }
+
+    let mut lines = Vec::new();
+    for entry in entries.iter() {
+      lines.push(try!(serde_json::to_string(entry)));
+    }
+    let mut file = try!(File::create(&full_path));
+    try!(file.write_all(lines.join("\n").as_bytes()));
+    Ok(relative_path)
+  }
+
+  /** Stages the provided paths (relative to the index root) and commits them onto HEAD. */
+  pub fn commit_paths(&self, paths: &[PathBuf], message: &str) -> Result<Oid, JobErr> {
+    git_util::commit_paths(&*self.repository, paths, message)
+  }
+}
+
 pub mod testing {
   use common::cargo;
   use git2::Repository;
   use index;
   use serde::Serialize;
   use serde_json;
+  use std::collections::BTreeMap;
   use std::fs::File;
   use std::fs;
   use std::io::Write;
-  use std::path::PathBuf;
   use tempdir::TempDir;
 
-  /**
-   * Constructs an "index-like" directory path for the given crate name.
-   *
-   * If the crate name is one character, the path is 1/$CRATE_NAME
-   * If the crate name is two characters, the path is 2/$CRATE_NAME
-   * If the crate name is three characters, the path is 3/$CRATE_NAME
-   * If the crate name is four or more characters, the path is
-   *   $FIRST_TWO_CHARS/$NEXT_TWO_CHARS/$CRATE_NAME
-   */
-  pub fn get_path_for_crate(crate_name: &str) -> PathBuf {
-    match crate_name.len() {
-      0 => panic!("Can't generate a path for an empty string"),
-      1 => PathBuf::from(format!("1/{}", crate_name)),
-      2 => PathBuf::from(format!("2/{}", crate_name)),
-      3 => PathBuf::from(format!("3/{}", crate_name)),
-      _ => PathBuf::from(format!("{}/{}/{}",
-                                 crate_name[0..2].to_owned(),
-                                 crate_name[2..4].to_owned(),
-                                 crate_name)),
-    }
-  }
+  pub use index::get_path_for_crate;
 
   /** Constructs a basic index directory with no contents. */
   pub fn seed_minimum_index() -> TempDir {
@@ -201,16 +221,23 @@ pub mod testing {
       where cargo::CrateKey: ::std::convert::From<T> {
     let index_tempdir = index::testing::seed_minimum_index();
 
+    // Every version of a crate shares a single file, one entry per line
+    let mut lines_by_crate_name = BTreeMap::new();
     for entry in index_entries.iter() {
       let key = cargo::CrateKey::from(entry.clone());
-      let path = index::testing::get_path_for_crate(&key.name);
+      lines_by_crate_name.entry(key.name)
+        .or_insert_with(Vec::new)
+        .push(serde_json::to_string(&entry).unwrap());
+    }
+
+    for (crate_name, lines) in lines_by_crate_name.into_iter() {
+      let path = index::testing::get_path_for_crate(&crate_name);
       let path_from_index = index_tempdir.path().join(path);
       if let Some(ref parent) = path_from_index.parent() {
         fs::create_dir_all(parent).unwrap();
       };
       let mut crate_file = File::create(path_from_index).unwrap();
-      let json = serde_json::to_string(&entry).unwrap();
-      crate_file.write_all(json.as_bytes()).unwrap();
+      crate_file.write_all(lines.join("\n").as_bytes()).unwrap();
     }
 
     index_tempdir
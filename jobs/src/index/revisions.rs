@@ -0,0 +1,301 @@
+use ::JobErr;
+use common::cargo::CrateKey;
+use common::cargo;
+use serde_yaml;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs::File;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+
+/**
+ * A curated change to a single crates.io-index entry, in the style of Hackage's metadata
+ * revisions.
+ *
+ * Upstream entries cannot be modified in place (modifications are lost on sync), so revisions are
+ * kept in their own version-controlled files and applied on top of the upstream entry whenever the
+ * index is loaded.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Revision {
+  #[serde(rename = "crate")]
+  pub crate_name: String,
+  pub version: String,
+  // Revisions of the same crate version are applied in ascending order of this number, which
+  // starts at 1 and must be unique per crate version
+  pub revision: u32,
+  pub author: String,
+  pub reason: String,
+  pub action: RevisionAction,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RevisionAction {
+  // Replaces the version requirement of a dependency, typically with a narrower one
+  TightenDependency { name: String, req: String },
+  // Removes a dependency (of every kind and target) entirely
+  DropDependency { name: String },
+  // Marks the version as unusable, without yanking it
+  MarkBroken,
+}
+
+impl Revision {
+  pub fn get_crate_key(&self) -> CrateKey {
+    CrateKey {
+      name: self.crate_name.clone(),
+      version: self.version.clone(),
+    }
+  }
+}
+
+/** A revision, alongside the state of the entry once it (and all before it) had been applied. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppliedRevision {
+  pub revision: Revision,
+  pub entry: cargo::IndexEntry,
+  pub broken: bool,
+}
+
+/** The full revision history of a single crate version. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RevisionHistory {
+  pub original_entry: cargo::IndexEntry,
+  pub applied_revisions: Vec<AppliedRevision>,
+}
+
+/**
+ * Reads every revision from the "*.yaml" files within the provided directory (recursively).
+ *
+ * Each file contains a list of revisions. Files are read in path order, although the order in
+ * which revisions are applied depends only on their crate, version and revision number.
+ */
+pub fn load_revisions<P: AsRef<Path>>(revisions_path: P) -> Result<Vec<Revision>, JobErr> {
+  let mut revision_files = Vec::new();
+  try!(find_revision_files(revisions_path.as_ref(), &mut revision_files));
+  revision_files.sort();
+
+  let mut revisions = Vec::new();
+  for revision_file in revision_files.iter() {
+    let mut contents = String::new();
+    try!(File::open(revision_file).and_then(|mut f| f.read_to_string(&mut contents)));
+    // An empty file deserializes as None
+    let file_revisions = try!(serde_yaml::from_str::<Option<Vec<Revision>>>(&contents)
+      .map_err(|e| JobErr::OtherErr(format!("Invalid revision file {:?}: {}", revision_file, e))));
+    revisions.append(&mut file_revisions.unwrap_or_default());
+  }
+  Ok(revisions)
+}
+
+fn find_revision_files(path: &Path, revision_files: &mut Vec<PathBuf>) -> Result<(), JobErr> {
+  for entry_res in try!(fs::read_dir(path)) {
+    let entry = try!(entry_res);
+    let entry_path = entry.path();
+    if try!(entry.file_type()).is_dir() {
+      if !entry_path.ends_with(".git") {
+        try!(find_revision_files(&entry_path, revision_files));
+      }
+    } else if entry_path.extension().map(|e| e == "yaml" || e == "yml").unwrap_or(false) {
+      revision_files.push(entry_path);
+    }
+  }
+  Ok(())
+}
+
+/** The result of applying revisions to a set of index entries. */
+pub struct RevisedContents {
+  pub broken_keys: HashSet<CrateKey>,
+  pub histories: HashMap<CrateKey, RevisionHistory>,
+}
+
+/**
+ * Applies the revisions to the provided entries in place.
+ *
+ * Application is deterministic: revisions are grouped by crate version and applied in revision
+ * number order. Revisions for versions that are not in the index are skipped with a warning, as
+ * the index may predate them. Duplicate revision numbers, or revisions naming a dependency that
+ * does not exist, are errors.
+ */
+pub fn apply_revisions(contents: &mut HashMap<CrateKey, cargo::IndexEntry>,
+                       revisions: Vec<Revision>) -> Result<RevisedContents, JobErr> {
+  let mut revisions_by_key = BTreeMap::new();
+  for revision in revisions.into_iter() {
+    revisions_by_key.entry(revision.get_crate_key())
+      .or_insert_with(Vec::new)
+      .push(revision);
+  }
+
+  let mut revised_contents = RevisedContents {
+    broken_keys: HashSet::new(),
+    histories: HashMap::new(),
+  };
+  for (key, mut key_revisions) in revisions_by_key.into_iter() {
+    key_revisions.sort_by_key(|r| r.revision);
+    for pair in key_revisions.windows(2) {
+      if pair[0].revision == pair[1].revision {
+        return Err(JobErr::OtherErr(format!("{}:{} has more than one revision {}",
+                                            key.name,
+                                            key.version,
+                                            pair[0].revision)));
+      }
+    }
+
+    let entry = match contents.get_mut(&key) {
+      Some(entry) => entry,
+      None => {
+        warn!("Skipping {} revision(s) for {}:{}, which is not in the index",
+              key_revisions.len(),
+              key.name,
+              key.version);
+        continue
+      },
+    };
+
+    let mut history = RevisionHistory {
+      original_entry: entry.clone(),
+      applied_revisions: Vec::new(),
+    };
+    let mut broken = false;
+    for revision in key_revisions.into_iter() {
+      try!(apply_revision(entry, &mut broken, &revision));
+      history.applied_revisions.push(AppliedRevision {
+        revision: revision,
+        entry: entry.clone(),
+        broken: broken,
+      });
+    }
+
+    if broken {
+      revised_contents.broken_keys.insert(key.clone());
+    }
+    revised_contents.histories.insert(key, history);
+  }
+
+  Ok(revised_contents)
+}
+
+fn apply_revision(entry: &mut cargo::IndexEntry, broken: &mut bool, revision: &Revision) -> Result<(), JobErr> {
+  match revision.action {
+    RevisionAction::TightenDependency { ref name, ref req } => {
+      let mut found = false;
+      for dep in entry.deps.iter_mut().filter(|d| &d.name == name) {
+        dep.req = req.clone();
+        found = true;
+      }
+      if !found {
+        return Err(missing_dependency_err(entry, revision, name));
+      }
+    },
+    RevisionAction::DropDependency { ref name } => {
+      let original_len = entry.deps.len();
+      entry.deps.retain(|d| &d.name != name);
+      if entry.deps.len() == original_len {
+        return Err(missing_dependency_err(entry, revision, name));
+      }
+    },
+    RevisionAction::MarkBroken => *broken = true,
+  }
+  Ok(())
+}
+
+fn missing_dependency_err(entry: &cargo::IndexEntry, revision: &Revision, name: &str) -> JobErr {
+  JobErr::OtherErr(format!("Revision {} of {}:{} refers to dependency {}, which it does not have",
+                           revision.revision,
+                           entry.name,
+                           entry.vers,
+                           name))
+}
+
+pub mod testing {
+  use index::revisions::Revision;
+  use serde_yaml;
+  use std::fs::File;
+  use std::io::Write;
+  use tempdir::TempDir;
+
+  /** Writes the provided revisions into a fresh revisions directory. */
+  pub fn seed_revisions(revisions: &[Revision]) -> TempDir {
+    let tempdir = TempDir::new("revisions").unwrap();
+    let mut file = File::create(tempdir.path().join("revisions.yaml")).unwrap();
+    file.write_all(serde_yaml::to_string(&revisions).unwrap().as_bytes()).unwrap();
+    tempdir
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use common::cargo::CrateKey;
+  use common::cargo;
+  use common::index::testing::dependency_entry;
+  use common::index::testing::index_entry;
+  use index::revisions::Revision;
+  use index::revisions::RevisionAction;
+  use index::revisions;
+  use std::collections::HashMap;
+
+  fn key() -> CrateKey {
+    CrateKey { name: "a".to_owned(), version: "0.1.0".to_owned() }
+  }
+
+  fn contents() -> HashMap<CrateKey, cargo::IndexEntry> {
+    let mut contents = HashMap::new();
+    contents.insert(key(), index_entry("a", "0.1.0", &[("libc", "0.2"), ("log", "0.3")]));
+    contents
+  }
+
+  fn revision(number: u32, action: RevisionAction) -> Revision {
+    Revision {
+      crate_name: "a".to_owned(),
+      version: "0.1.0".to_owned(),
+      revision: number,
+      author: "someone@example.com".to_owned(),
+      reason: "testing".to_owned(),
+      action: action,
+    }
+  }
+
+  #[test]
+  fn test_revisions_are_applied_in_revision_order() {
+    let mut contents = contents();
+    let revised = revisions::apply_revisions(&mut contents, vec![
+      revision(2, RevisionAction::DropDependency { name: "log".to_owned() }),
+      revision(1, RevisionAction::TightenDependency { name: "libc".to_owned(), req: "~0.2.20".to_owned() }),
+      revision(3, RevisionAction::MarkBroken),
+    ]).unwrap();
+
+    let entry = contents.get(&key()).unwrap();
+    assert_eq!(entry.deps, vec![dependency_entry("libc", "~0.2.20")]);
+    assert!(revised.broken_keys.contains(&key()));
+
+    let history = revised.histories.get(&key()).unwrap();
+    assert_eq!(history.original_entry.deps.len(), 2);
+    let numbers_and_states = history.applied_revisions.iter()
+      .map(|a| (a.revision.revision, a.entry.deps.len(), a.broken))
+      .collect::<Vec<_>>();
+    assert_eq!(numbers_and_states, vec![(1, 2, false), (2, 1, false), (3, 1, true)]);
+  }
+
+  #[test]
+  fn test_invalid_revisions_are_rejected() {
+    assert!(revisions::apply_revisions(&mut contents(), vec![
+      revision(1, RevisionAction::MarkBroken),
+      revision(1, RevisionAction::MarkBroken),
+    ]).is_err());
+    assert!(revisions::apply_revisions(&mut contents(), vec![
+      revision(1, RevisionAction::DropDependency { name: "serde".to_owned() }),
+    ]).is_err());
+  }
+
+  #[test]
+  fn test_revisions_round_trip_through_files() {
+    let original = vec![
+      revision(1, RevisionAction::TightenDependency { name: "libc".to_owned(), req: "~0.2.20".to_owned() }),
+      revision(2, RevisionAction::MarkBroken),
+    ];
+    let tempdir = revisions::testing::seed_revisions(&original);
+    assert_eq!(revisions::load_revisions(tempdir.path()).unwrap(), original);
+  }
+}
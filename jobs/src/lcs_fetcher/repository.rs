@@ -2,6 +2,7 @@ use aws::SimpleS3Client;
 use aws::SimpleS3ClientParams;
 use aws_sdk_rust::aws::s3::object::ListObjectsRequest;
 use common::cargo::CrateKey;
+use common::index;
 use hyper::Client;
 use hyper::header::Connection;
 use ::JobErr;
@@ -43,8 +44,12 @@ pub trait LcsBase {
   fn get_existing_crate_keys(&self) -> Result<Vec<CrateKey>, JobErr>;
 }
 
-/** A "LocalCrateService" repository source, which can furnish crates tarballs. */
-pub trait LcsRepositorySource: LcsBase + __LcsRepositorySource_BoxClone {
+/**
+ * A "LocalCrateService" repository source, which can furnish crates tarballs.
+ *
+ * Sources must be shareable between threads, as crates may be fetched concurrently.
+ */
+pub trait LcsRepositorySource: LcsBase + __LcsRepositorySource_BoxClone + Send + Sync {
   /**
    * Retrieves the provided CrateKey from the internal repository, and writes it into the 
    * destination directory.
@@ -110,28 +115,6 @@ impl LocalFsLcsRepository {
   fn get_index_path(&self) -> PathBuf {
     self.crates_path.join("index.txt")
   }
-
-  /**
-   * Constructs filesystem-safe directory path for the given crate name.
-   *
-   * If the crate name is one character, the path is 1/$CRATE_NAME
-   * If the crate name is two characters, the path is 2/$CRATE_NAME
-   * If the crate name is three characters, the path is 3/$CRATE_NAME
-   * If the crate name is four or more characters, the path is
-   *   $FIRST_TWO_CHARS/$NEXT_TWO_CHARS/
-   */
-  fn get_directory_for_crate(crate_name: &str) -> PathBuf {
-    let lower_crate_name = crate_name.to_lowercase();
-    match lower_crate_name.len() {
-      0 => panic!("Can't generate a path for an empty string"),
-      1 => PathBuf::from("1/"),
-      2 => PathBuf::from("2/"),
-      3 => PathBuf::from("3/"),
-      _ => PathBuf::from(format!("{}/{}/",
-                                 lower_crate_name[0..2].to_owned(),
-                                 lower_crate_name[2..4].to_owned())),
-    }
-  }
 }
 
 impl LcsBase for LocalFsLcsRepository {
@@ -166,10 +149,7 @@ impl LcsRepositorySource for LocalFsLcsRepository {
     let crate_filename = format!("{name}-{version}.crate",
                                  name = key.name,
                                  version = key.version);
-    let crate_subdirectory = LocalFsLcsRepository::get_directory_for_crate(&key.name);
-    let crate_path = self.crates_path
-      .join(crate_subdirectory)
-      .join(&crate_filename);
+    let crate_path = self.crates_path.join(index::get_lcs_path_for_crate(&key.name, &key.version));
 
     let destination_crate = destination.join(crate_filename);
 
@@ -182,20 +162,15 @@ impl LcsRepositorySink for LocalFsLcsRepository {
   /** Inserts a crate into the local directory, and appends it into the index. */
   fn upload_crate(&mut self, key: &CrateKey, path: &Path) -> Result <(), JobErr> {
     // TODO(acmcarther): Clean this API up. Its super unsafe
-    let crate_filename = format!("{name}-{version}.crate",
-                                 name = key.name,
-                                 version = key.version);
-    let crate_subdirectory = LocalFsLcsRepository::get_directory_for_crate(&key.name);
     let index_path = self.crates_path.join("index.txt");
-    let crate_path = self.crates_path
-      .join(crate_subdirectory)
-      .join(&crate_filename);
+    let crate_path = self.crates_path.join(index::get_lcs_path_for_crate(&key.name, &key.version));
 
     try!(fs::create_dir_all(crate_path.parent().unwrap()));
     try!(fs::copy(path, crate_path));
 
     let mut index_file = try!(OpenOptions::new()
       .append(true)
+      .create(true)
       .open(index_path));
 
     try!(index_file.write_all(format!("{name}:{version}\n",
@@ -1,6 +1,7 @@
 #![feature(used)]
 #![allow(dead_code)]
 extern crate cargo;
+extern crate chrono;
 extern crate flate2;
 extern crate toml;
 extern crate tar;
@@ -9,23 +10,38 @@ extern crate aws_sdk_rust;
 extern crate git2;
 #[macro_use] extern crate derive_builder;
 extern crate hyper;
-#[macro_use(log, debug, info, warn)] extern crate log;
+extern crate libc;
+#[macro_use(log, debug, error, info, warn)] extern crate log;
 extern crate serde_json;
 extern crate rayon;
 extern crate scoped_threadpool;
+extern crate semver;
 extern crate serde;
+#[macro_use] extern crate serde_derive;
+extern crate serde_yaml;
 #[macro_use] extern crate lazy_static;
 extern crate tempdir;
 extern crate url;
 #[macro_use] extern crate zcfg;
 
 mod aws;
+mod git_crates;
+mod git_util;
 mod index;
 mod lcs_fetcher;
+mod sandbox;
 mod ais_backfiller;
+mod snapshot_planner;
+mod snapshot_promoter;
+mod snapshot_repository;
+mod snapshot_verifier;
 
 use std::io;
 use aws_sdk_rust::aws::errors::s3::S3Error;
+use common::configuration::ConfigurationErr;
+use common::resolver::ResolutionErr;
+use common::signing::SigningErr;
+use common::snapshot_store::SnapshotStoreErr;
 
 #[derive(Debug)]
 pub enum JobErr {
@@ -35,6 +51,11 @@ pub enum JobErr {
   S3Err(S3Error),
   GitErr(git2::Error),
   TomlErr(toml::de::Error),
+  YamlErr(serde_yaml::Error),
+  ResolutionErr(ResolutionErr),
+  SnapshotStoreErr(SnapshotStoreErr),
+  SigningErr(SigningErr),
+  ConfigurationErr(ConfigurationErr),
   OtherErr(String),
   UnsupportedOperation,
 }
@@ -44,6 +65,11 @@ define_from_error_boilerplate!(serde_json::Error, JobErr, JobErr::SerdeJsonErr);
 define_from_error_boilerplate!(git2::Error, JobErr, JobErr::GitErr);
 define_from_error_boilerplate!(S3Error, JobErr, JobErr::S3Err);
 define_from_error_boilerplate!(toml::de::Error, JobErr, JobErr::TomlErr);
+define_from_error_boilerplate!(serde_yaml::Error, JobErr, JobErr::YamlErr);
+define_from_error_boilerplate!(ResolutionErr, JobErr, JobErr::ResolutionErr);
+define_from_error_boilerplate!(SnapshotStoreErr, JobErr, JobErr::SnapshotStoreErr);
+define_from_error_boilerplate!(SigningErr, JobErr, JobErr::SigningErr);
+define_from_error_boilerplate!(ConfigurationErr, JobErr, JobErr::ConfigurationErr);
 
 pub trait Job {
   fn run(&mut self);
@@ -51,3 +77,6 @@ pub trait Job {
 
 pub use lcs_fetcher::LcsFetcherJob;
 pub use ais_backfiller::AisBackfillerJob;
+pub use snapshot_planner::SnapshotPlannerJob;
+pub use snapshot_promoter::SnapshotPromoterJob;
+pub use snapshot_verifier::SnapshotVerifierJob;
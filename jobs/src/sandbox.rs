@@ -0,0 +1,433 @@
+use ::JobErr;
+use libc;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Child;
+use std::process::Command;
+use std::process::ExitStatus;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use tempdir::TempDir;
+
+mod flags {
+  define_pub_cfg!(sandbox_timeout_seconds,
+                  u64,
+                  3600u64,
+                  "The wall-clock time that a sandboxed step may run for before it is killed.");
+  define_pub_cfg!(sandbox_cpu_seconds,
+                  u64,
+                  3600u64,
+                  "The CPU time that each process of a sandboxed step may use.");
+  define_pub_cfg!(sandbox_memory_megabytes,
+                  u64,
+                  8192u64,
+                  "The address space that each process of a sandboxed step may use.");
+  define_pub_cfg!(sandbox_max_processes,
+                  u64,
+                  1024u64,
+                  "The number of processes that the user running sandboxed steps may have.");
+  define_pub_cfg!(sandbox_max_file_megabytes,
+                  u64,
+                  4096u64,
+                  "The largest file that a sandboxed step may write.");
+}
+
+// The environment passed through to sandboxed steps, so that toolchains can still be found. The
+// host's CARGO_HOME is deliberately absent, as it holds registry credentials and installed binaries.
+const PASSED_ENVIRONMENT: &'static [&'static str] = &["PATH", "RUSTUP_HOME", "RUSTUP_TOOLCHAIN"];
+
+// How often a running step is checked on
+const POLL_INTERVAL_MILLIS: u64 = 50;
+
+// How long to wait for a step's output once it has exited, as processes that escaped it may still
+// hold its pipes open
+const OUTPUT_GRACE_MILLIS: u64 = 1000;
+
+/** Limits on the resources that a sandboxed step may use. */
+#[derive(Debug, Clone, Builder)]
+#[builder(default)]
+pub struct SandboxLimits {
+  pub timeout: Duration,
+  pub cpu_seconds: u64,
+  pub memory_bytes: u64,
+  pub max_processes: u64,
+  pub max_file_bytes: u64,
+}
+
+impl Default for SandboxLimits {
+  fn default() -> SandboxLimits {
+    SandboxLimits {
+      timeout: Duration::from_secs(flags::sandbox_timeout_seconds::CONFIG.get_value()),
+      cpu_seconds: flags::sandbox_cpu_seconds::CONFIG.get_value(),
+      memory_bytes: flags::sandbox_memory_megabytes::CONFIG.get_value() * 1024 * 1024,
+      max_processes: flags::sandbox_max_processes::CONFIG.get_value(),
+      max_file_bytes: flags::sandbox_max_file_megabytes::CONFIG.get_value() * 1024 * 1024,
+    }
+  }
+}
+
+/** A program to run in the sandbox, with its arguments and environment. */
+#[derive(Debug, Clone)]
+pub struct SandboxStep {
+  program: PathBuf,
+  args: Vec<String>,
+  // The step's fresh scratch directory is used if unset
+  current_dir: Option<PathBuf>,
+  env: Vec<(String, OsString)>,
+}
+
+impl SandboxStep {
+  pub fn new<P: AsRef<Path>>(program: P) -> SandboxStep {
+    SandboxStep {
+      program: program.as_ref().to_path_buf(),
+      args: Vec::new(),
+      current_dir: None,
+      env: Vec::new(),
+    }
+  }
+
+  pub fn args(mut self, args: &[String]) -> SandboxStep {
+    self.args.extend(args.iter().cloned());
+    self
+  }
+
+  pub fn current_dir<P: AsRef<Path>>(mut self, current_dir: P) -> SandboxStep {
+    self.current_dir = Some(current_dir.as_ref().to_path_buf());
+    self
+  }
+
+  pub fn env<V: Into<OsString>>(mut self, key: &str, value: V) -> SandboxStep {
+    self.env.push((key.to_owned(), value.into()));
+    self
+  }
+
+  /** Describes the step as a shell command, for logs. */
+  pub fn describe(&self) -> String {
+    let mut words = vec![self.program.to_string_lossy().into_owned()];
+    words.extend(self.args.iter().cloned());
+    words.join(" ")
+  }
+}
+
+/** What a sandboxed step did. */
+#[derive(Debug)]
+pub struct SandboxOutput {
+  // Unset if the step was killed for running past its timeout
+  pub status: Option<ExitStatus>,
+  pub stdout: Vec<u8>,
+  pub stderr: Vec<u8>,
+  pub duration: Duration,
+}
+
+impl SandboxOutput {
+  pub fn success(&self) -> bool {
+    self.status.map(|status| status.success()).unwrap_or(false)
+  }
+
+  pub fn timed_out(&self) -> bool {
+    self.status.is_none()
+  }
+}
+
+/**
+ * Runs steps (such as the builds of unaudited crates) with as little access to the host as can be
+ * arranged without privileges.
+ *
+ * Each step:
+ *   - Gets a fresh scratch directory, which is its HOME and TMPDIR (and holds its CARGO_HOME), and
+ *     is removed afterwards
+ *   - Starts from an empty environment, but for PASSED_ENVIRONMENT and the step's own variables
+ *   - Has no network, if `unshare` can create a network namespace here
+ *   - Runs in a pid namespace of its own where possible, so that no process outlives the step
+ *   - Is limited in CPU time, address space, processes and file size, by rlimits
+ *   - Is killed (with every process it started) if it runs past its timeout
+ */
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+  limits: SandboxLimits,
+  // The `unshare` arguments that isolate the network (and processes), if it can be isolated
+  unshare_args: Option<Vec<&'static str>>,
+}
+
+impl Default for Sandbox {
+  fn default() -> Sandbox {
+    Sandbox::new(SandboxLimits::default())
+  }
+}
+
+impl Sandbox {
+  /** Creates a sandbox with the provided limits, isolating the network if this host allows it. */
+  pub fn new(limits: SandboxLimits) -> Sandbox {
+    let unshare_args = find_unshare_args();
+    if unshare_args.is_none() {
+      warn!("Network namespaces are unavailable, so sandboxed steps will have network access");
+    }
+    Sandbox {
+      limits: limits,
+      unshare_args: unshare_args,
+    }
+  }
+
+  pub fn isolates_network(&self) -> bool {
+    self.unshare_args.is_some()
+  }
+
+  /** Runs the step to completion (or its timeout), capturing its output. */
+  pub fn run(&self, step: &SandboxStep) -> Result<SandboxOutput, JobErr> {
+    let scratch_dir = try!(TempDir::new("sandbox_step"));
+    let cargo_home = scratch_dir.path().join(".cargo");
+    try!(fs::create_dir_all(&cargo_home));
+    let mut command = match self.unshare_args {
+      Some(ref unshare_args) => {
+        let mut command = Command::new("unshare");
+        command.args(unshare_args).arg("--").arg(&step.program);
+        command
+      },
+      None => Command::new(&step.program),
+    };
+    command.args(&step.args)
+      .current_dir(step.current_dir.as_ref().map(|p| p.as_path()).unwrap_or(scratch_dir.path()))
+      .env_clear()
+      .envs(get_passed_environment())
+      .env("HOME", scratch_dir.path())
+      .env("TMPDIR", scratch_dir.path())
+      .env("CARGO_HOME", &cargo_home)
+      .envs(step.env.iter().map(|&(ref key, ref value)| (key, value)))
+      .stdin(Stdio::null())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped());
+
+    let limits = self.limits.clone();
+    unsafe {
+      command.before_exec(move || {
+        // A process group of its own, so that the whole step can be killed at once
+        if libc::setpgid(0, 0) != 0 {
+          return Err(::std::io::Error::last_os_error())
+        }
+        let rlimits = [
+          (libc::RLIMIT_CPU, limits.cpu_seconds),
+          (libc::RLIMIT_AS, limits.memory_bytes),
+          (libc::RLIMIT_NPROC, limits.max_processes),
+          (libc::RLIMIT_FSIZE, limits.max_file_bytes),
+        ];
+        for &(resource, value) in rlimits.iter() {
+          let rlimit = libc::rlimit {
+            rlim_cur: value as libc::rlim_t,
+            rlim_max: value as libc::rlim_t,
+          };
+          if libc::setrlimit(resource, &rlimit) != 0 {
+            return Err(::std::io::Error::last_os_error())
+          }
+        }
+        Ok(())
+      });
+    }
+
+    let started_at = Instant::now();
+    let mut child = try!(command.spawn());
+    let stdout_reader = PipeReader::start(child.stdout.take());
+    let stderr_reader = PipeReader::start(child.stderr.take());
+    let status = try!(wait_with_timeout(&mut child, self.limits.timeout));
+    if status.is_none() {
+      warn!("Killed `{}` after its timeout of {:?}", step.describe(), self.limits.timeout);
+    }
+
+    let output_deadline = Instant::now() + Duration::from_millis(OUTPUT_GRACE_MILLIS);
+    Ok(SandboxOutput {
+      status: status,
+      stdout: stdout_reader.finish(output_deadline),
+      stderr: stderr_reader.finish(output_deadline),
+      duration: started_at.elapsed(),
+    })
+  }
+}
+
+/**
+ * Finds the arguments that let `unshare` create a network namespace, and a pid namespace if it
+ * can: none are needed with privileges, and a user namespace is needed without them.
+ *
+ * In a pid namespace, the step is its first process, so every process it started (even those that
+ * left its process group) is killed when it exits or is killed.
+ */
+fn find_unshare_args() -> Option<Vec<&'static str>> {
+  let candidates = vec![
+    vec!["--net", "--pid", "--fork"],
+    vec!["--user", "--map-root-user", "--net", "--pid", "--fork"],
+    vec!["--net"],
+    vec!["--user", "--map-root-user", "--net"],
+  ];
+  for unshare_args in candidates.into_iter() {
+    let probe = Command::new("unshare")
+      .args(&unshare_args)
+      .arg("--")
+      .arg("true")
+      .stdout(Stdio::null())
+      .stderr(Stdio::null())
+      .status();
+    if probe.map(|status| status.success()).unwrap_or(false) {
+      return Some(unshare_args)
+    }
+  }
+  None
+}
+
+/**
+ * Yields the variables in PASSED_ENVIRONMENT that are set. Rustup's toolchains are otherwise found
+ * through HOME, which steps do not share, so RUSTUP_HOME is made explicit.
+ */
+fn get_passed_environment() -> Vec<(String, OsString)> {
+  let mut passed_environment = PASSED_ENVIRONMENT.iter()
+    .filter_map(|key| env::var_os(key).map(|value| (key.to_string(), value)))
+    .collect::<Vec<_>>();
+  let is_rustup_home_set = passed_environment.iter().any(|&(ref key, _)| key == "RUSTUP_HOME");
+  if let Some(rustup_home) = env::home_dir().map(|home| home.join(".rustup")) {
+    if !is_rustup_home_set && rustup_home.is_dir() {
+      passed_environment.push(("RUSTUP_HOME".to_owned(), rustup_home.into_os_string()));
+    }
+  }
+  passed_environment
+}
+
+/**
+ * Reads a pipe of the step in the background.
+ *
+ * A process that escaped the step may hold the pipe open indefinitely, so the output is collected
+ * by a deadline rather than by joining the thread, which is abandoned if the pipe is still open.
+ */
+struct PipeReader {
+  bytes: Arc<Mutex<Vec<u8>>>,
+  // Disconnected once the pipe is closed
+  finished: Receiver<()>,
+}
+
+impl PipeReader {
+  fn start<R: Read + Send + 'static>(pipe: Option<R>) -> PipeReader {
+    let bytes = Arc::new(Mutex::new(Vec::new()));
+    let (finished_sender, finished) = mpsc::channel::<()>();
+    let thread_bytes = bytes.clone();
+    thread::spawn(move || {
+      let _finished_sender = finished_sender;
+      if let Some(mut pipe) = pipe {
+        let mut buffer = [0u8; 8192];
+        loop {
+          match pipe.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(len) => thread_bytes.lock().unwrap().extend_from_slice(&buffer[0..len]),
+          }
+        }
+      }
+    });
+    PipeReader {
+      bytes: bytes,
+      finished: finished,
+    }
+  }
+
+  /** Yields everything read from the pipe once it closes, or whatever was read by the deadline. */
+  fn finish(self, deadline: Instant) -> Vec<u8> {
+    let now = Instant::now();
+    if deadline > now {
+      let _ = self.finished.recv_timeout(deadline - now);
+    }
+    let bytes = self.bytes.lock().unwrap();
+    bytes.clone()
+  }
+}
+
+/** Waits for the child to exit, or kills its process group once the timeout passes. */
+fn wait_with_timeout(child: &mut Child, timeout: Duration) -> Result<Option<ExitStatus>, JobErr> {
+  let started_at = Instant::now();
+  loop {
+    if let Some(status) = try!(child.try_wait()) {
+      return Ok(Some(status))
+    }
+    if started_at.elapsed() >= timeout {
+      unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+      }
+      try!(child.wait());
+      return Ok(None)
+    }
+    thread::sleep(Duration::from_millis(POLL_INTERVAL_MILLIS));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use sandbox::Sandbox;
+  use sandbox::SandboxLimitsBuilder;
+  use sandbox::SandboxStep;
+  use std::env;
+  use std::time::Duration;
+
+  fn sandbox_with_timeout(timeout: Duration) -> Sandbox {
+    Sandbox::new(SandboxLimitsBuilder::default()
+      .timeout(timeout)
+      .cpu_seconds(60)
+      .memory_bytes(1024 * 1024 * 1024)
+      .max_processes(1024)
+      .max_file_bytes(1024 * 1024)
+      .build()
+      .unwrap())
+  }
+
+  fn shell(script: &str) -> SandboxStep {
+    SandboxStep::new("sh").args(&["-c".to_owned(), script.to_owned()])
+  }
+
+  #[test]
+  fn test_steps_run_in_a_fresh_directory_with_a_clear_environment() {
+    env::set_var("STOCKPILE_SANDBOX_TEST_SECRET", "secret");
+    env::set_var("CARGO_HOME", "/host/cargo/home");
+    let sandbox = sandbox_with_timeout(Duration::from_secs(30));
+    let output = sandbox.run(&shell("pwd; echo \"$HOME\"; echo \"$CARGO_HOME\"; \
+                                     echo \"[$STOCKPILE_SANDBOX_TEST_SECRET]\"; echo \"$PASSED\"; echo oops >&2")
+                                .env("PASSED", "passed"))
+      .unwrap();
+    assert!(output.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], lines[1]);
+    assert!(lines[0].contains("sandbox_step"));
+    assert_eq!(lines[2], format!("{}/.cargo", lines[0]));
+    assert_eq!(&lines[3..], &["[]", "passed"]);
+    assert_eq!(output.stderr, b"oops\n".to_vec());
+    if sandbox.isolates_network() {
+      // Only the loopback interface exists in a fresh network namespace
+      let output = sandbox.run(&shell("tail -n +3 /proc/net/dev | cut -d: -f1")).unwrap();
+      assert_eq!(String::from_utf8(output.stdout).unwrap().trim(), "lo");
+    }
+  }
+
+  #[test]
+  fn test_steps_are_limited() {
+    let sandbox = sandbox_with_timeout(Duration::from_millis(500));
+    let output = sandbox.run(&shell("sleep 30 & sleep 30")).unwrap();
+    assert!(output.timed_out());
+    assert!(output.duration < Duration::from_secs(10));
+
+    // Processes that leave the step's process group do not hold it up either
+    let output = sandbox.run(&shell("setsid sleep 30 & sleep 30")).unwrap();
+    assert!(output.timed_out());
+    assert!(output.duration < Duration::from_secs(10));
+    let output = sandbox.run(&shell("setsid sleep 30 & echo done")).unwrap();
+    assert!(output.success());
+    assert_eq!(output.stdout, b"done\n".to_vec());
+    assert!(output.duration < Duration::from_secs(10));
+
+    let output = sandbox.run(&shell("head -c 2000000 /dev/zero > big")).unwrap();
+    assert!(!output.success());
+    assert!(!output.timed_out());
+  }
+}
@@ -0,0 +1,92 @@
+use common::resolver::Candidate;
+use common::resolver::CandidateSource;
+use common::resolver::candidates;
+use git_crates::GitCrate;
+use index::KeyedByCrateKey;
+use index::crates_io::CratesIoIndex;
+use index::joined::JoinedIndex;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+/**
+ * A CandidateSource over the joined index.
+ *
+ * Yanked versions, versions marked broken by a metadata revision, and versions whose metadata
+ * cannot be parsed as semver are never candidates. Dev dependencies (from the augmented index) are
+ * only included for the crates whose tests must be buildable, and members may enable features
+ * (and so optional dependencies) beyond their defaults.
+ */
+pub struct IndexCandidateSource {
+  candidates: HashMap<String, Vec<Candidate>>,
+}
+
+impl IndexCandidateSource {
+  pub fn new(joined_index: &JoinedIndex,
+             upstream_index: &CratesIoIndex,
+             crates_needing_dev_dependencies: &HashSet<String>,
+             member_features: &HashMap<String, Vec<String>>) -> IndexCandidateSource {
+    let mut candidates = HashMap::new();
+    for key in joined_index.get_crate_keys().into_iter() {
+      if upstream_index.is_broken(key) {
+        continue
+      }
+      let entry = joined_index.get_entry(key).unwrap();
+      if entry.is_yanked() {
+        continue
+      }
+      let dev_dependencies = if crates_needing_dev_dependencies.contains(&key.name) {
+        match entry.get_dev_dependencies() {
+          Some(dev_dependencies) => dev_dependencies.iter().collect(),
+          None => {
+            warn!("{}:{} has not had its dev dependencies backfilled, so they are ignored",
+                  key.name,
+                  key.version);
+            Vec::new()
+          },
+        }
+      } else {
+        Vec::new()
+      };
+
+      let features = member_features.get(&key.name).map(|features| features.as_slice()).unwrap_or(&[]);
+      match candidates::to_candidate_with_features(&entry.index_entry, dev_dependencies, features) {
+        Some(candidate) => candidates.entry(key.name.clone())
+          .or_insert_with(Vec::new)
+          .push(candidate),
+        None => debug!("Skipping {}:{}, which has unparseable version metadata", key.name, key.version),
+      }
+    }
+
+    IndexCandidateSource {
+      candidates: candidates,
+    }
+  }
+}
+
+impl IndexCandidateSource {
+  /**
+   * Makes a crate packaged from git (or a local path) the only candidate for its name.
+   *
+   * Its dev dependencies are included if its tests must be buildable.
+   */
+  pub fn add_git_crate(&mut self, git_crate: &GitCrate, include_dev_dependencies: bool, features: &[String]) {
+    let dev_dependencies = if include_dev_dependencies {
+      git_crate.get_dev_dependencies()
+    } else {
+      Vec::new()
+    };
+    let name = git_crate.index_entry.name.clone();
+    match candidates::to_candidate_with_features(&git_crate.index_entry, dev_dependencies, features) {
+      Some(candidate) => {
+        self.candidates.insert(name, vec![candidate]);
+      },
+      None => warn!("Skipping the packaged {}, which has unparseable version metadata", name),
+    }
+  }
+}
+
+impl CandidateSource for IndexCandidateSource {
+  fn get_candidates(&self, crate_name: &str) -> Vec<Candidate> {
+    self.candidates.get(crate_name).cloned().unwrap_or_default()
+  }
+}
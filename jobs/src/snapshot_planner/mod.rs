@@ -0,0 +1,613 @@
+use ::Job;
+use ::JobErr;
+use chrono::Utc;
+use common::configuration::ConfigurationErr;
+use common::configuration::WorkspaceConfiguration;
+use common::configuration;
+use common::resolver::CandidateSource;
+use common::resolver::Requirement;
+use common::resolver::ResolutionErr;
+use common::resolver::report::ConflictReport;
+use common::resolver;
+use common::snapshot::CrateSnapshot;
+use common::snapshot::ResolutionType;
+use common::snapshot::WorkspaceSnapshot;
+use git_crates::GitCrate;
+use git_crates;
+use index::augmented::AugmentedIndex;
+use index::crates_io::CratesIoIndex;
+use index::joined::JoinedIndex;
+use lcs_fetcher::repository::LcsRepositorySink;
+use lcs_fetcher::repository::LocalFsLcsRepository;
+use semver::VersionReq;
+use serde_json;
+use serde_yaml;
+use snapshot_planner::candidates::IndexCandidateSource;
+use snapshot_repository::SnapshotRepository;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use tempdir::TempDir;
+
+pub mod candidates;
+
+mod flags {
+  define_pub_cfg!(workspace_manifest_path,
+                  String,
+                  "manifest.yaml",
+                  "The path to the workspace configuration that the snapshot is planned for.");
+  define_pub_cfg!(snapshot_output_path,
+                  String,
+                  "snapshot.yaml",
+                  "The path to write the planned snapshot to.");
+  define_pub_cfg!(conflict_report_path,
+                  String,
+                  "conflict_report.json",
+                  "The path to write an explanation to when the workspace cannot be resolved.");
+  define_pub_cfg!(git_crate_lcs_directory,
+                  ::zcfg::NoneableCfg<String>,
+                  None,
+                  "The path to a local LCS to store crates that are packaged from git (or local paths) into.");
+  define_pub_cfg!(snapshot_version,
+                  ::zcfg::NoneableCfg<String>,
+                  None,
+                  "The version to give the planned snapshot. Defaults to nightly-YYYY-MM-DD for today.");
+}
+
+#[derive(Clone, Builder)]
+#[builder(default)]
+pub struct SnapshotPlannerParams {
+  manifest_path: PathBuf,
+  output_path: PathBuf,
+  conflict_report_path: PathBuf,
+  snapshot_version: String,
+}
+
+impl Default for SnapshotPlannerParams {
+  fn default() -> SnapshotPlannerParams {
+    let snapshot_version = flags::snapshot_version::CONFIG.get_value().inner()
+      .unwrap_or_else(|| format!("nightly-{}", Utc::now().format("%Y-%m-%d")));
+
+    SnapshotPlannerParams {
+      manifest_path: PathBuf::from(flags::workspace_manifest_path::CONFIG.get_value()),
+      output_path: PathBuf::from(flags::snapshot_output_path::CONFIG.get_value()),
+      conflict_report_path: PathBuf::from(flags::conflict_report_path::CONFIG.get_value()),
+      snapshot_version: snapshot_version,
+    }
+  }
+}
+
+/**
+ * A Job that selects a consistent set of crate versions for a workspace configuration.
+ *
+ * Every crate in the configuration's crate sets, along with all of their transitive dependencies,
+ * is pinned to exactly one version. The newest mutually compatible versions are preferred.
+ *
+ * Members that resolve from git (or a local path) are packaged from there, and stored in the LCS
+ * under their git (or path) qualified version. An LCS must be configured if there are any, since the
+ * snapshot could not be verified without them.
+ *
+ * If a snapshot repository is configured, the snapshot is also published into it.
+ */
+#[derive(Builder)]
+pub struct SnapshotPlannerJob {
+  upstream_index: CratesIoIndex,
+  augmented_index: AugmentedIndex,
+  #[builder(default)]
+  snapshot_repository: Option<SnapshotRepository>,
+  #[builder(default)]
+  lcs_sink: Option<Box<LcsRepositorySink>>,
+  #[builder(default)]
+  params: SnapshotPlannerParams,
+}
+
+impl SnapshotPlannerJob {
+  pub fn for_upstream_indexes() -> Result<SnapshotPlannerJob, JobErr> {
+    Ok(SnapshotPlannerJobBuilder::default()
+      .upstream_index(try!(CratesIoIndex::upstream_index()))
+      .augmented_index(try!(AugmentedIndex::upstream_index()))
+      .snapshot_repository(try!(SnapshotRepository::from_flags()))
+      .lcs_sink(flags::git_crate_lcs_directory::CONFIG.get_value().inner()
+        .map(|path| Box::new(LocalFsLcsRepository::new(path)) as Box<LcsRepositorySink>))
+      .build()
+      .unwrap())
+  }
+
+  fn run_now(&mut self) -> Result<(), JobErr> {
+    let configuration = match configuration::load_configuration(&self.params.manifest_path, &self.upstream_index) {
+      Ok(configuration) => configuration,
+      Err(ConfigurationErr::InvalidConfiguration(problems)) => {
+        error!("The workspace configuration is invalid:");
+        for problem in problems.iter() {
+          error!("  {}", problem);
+        }
+        return Err(JobErr::ConfigurationErr(ConfigurationErr::InvalidConfiguration(problems)))
+      },
+      Err(err) => return Err(JobErr::ConfigurationErr(err)),
+    };
+
+    let packaged_member_names = get_packaged_member_names(&configuration);
+    if !packaged_member_names.is_empty() && self.lcs_sink.is_none() {
+      return Err(JobErr::OtherErr(format!("{} resolve from git or a path, so --git_crate_lcs_directory must \
+                                           be set to store them where the verifier can fetch them",
+                                          packaged_member_names.join(", "))))
+    }
+    let scratch_dir = try!(TempDir::new("snapshot_planner_git_crates"));
+    // Paths are relative to the workspace configuration
+    let configuration_directory = self.params.manifest_path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let git_crates = try!(package_members(&configuration, &configuration_directory, scratch_dir.path()));
+    if let Some(ref mut lcs_sink) = self.lcs_sink {
+      for git_crate in git_crates.iter() {
+        let key = git_crate.get_crate_key();
+        try!(lcs_sink.upload_crate(&key, &git_crate.crate_path));
+        info!("Stored the packaged {}:{} in the LCS", key.name, key.version);
+      }
+    }
+
+    let crates_needing_dev_dependencies = get_crates_needing_dev_dependencies(&configuration);
+    let member_features = get_member_features(&configuration);
+    let joined_index = JoinedIndex::new(&self.upstream_index, &self.augmented_index);
+    let mut candidate_source = IndexCandidateSource::new(&joined_index,
+                                                         &self.upstream_index,
+                                                         &crates_needing_dev_dependencies,
+                                                         &member_features);
+    for git_crate in git_crates.iter() {
+      let name = &git_crate.index_entry.name;
+      candidate_source.add_git_crate(git_crate,
+                                     crates_needing_dev_dependencies.contains(name),
+                                     member_features.get(name).map(|f| f.as_slice()).unwrap_or(&[]));
+    }
+    let snapshot = match plan_snapshot(&configuration, &candidate_source, &self.params.snapshot_version) {
+      Ok(snapshot) => snapshot,
+      Err(JobErr::ResolutionErr(ResolutionErr::NoSolution(report))) => {
+        try!(self.write_conflict_report(&report));
+        return Err(JobErr::ResolutionErr(ResolutionErr::NoSolution(report)))
+      },
+      Err(err) => return Err(err),
+    };
+    info!("Planned snapshot {} with {} members and {} crates in total",
+          snapshot.version,
+          snapshot.members.len(),
+          snapshot.details.len());
+
+    let mut output_file = try!(File::create(&self.params.output_path));
+    try!(output_file.write_all(try!(serde_yaml::to_string(&snapshot)).as_bytes()));
+    info!("Wrote snapshot to {:?}", self.params.output_path);
+
+    if let Some(ref snapshot_repository) = self.snapshot_repository {
+      try!(snapshot_repository.publish(&snapshot));
+    }
+    Ok(())
+  }
+
+  /** Logs why the workspace could not be resolved, and writes the full report alongside. */
+  fn write_conflict_report(&self, report: &ConflictReport) -> Result<(), JobErr> {
+    error!("The workspace could not be resolved:");
+    for line in report.explanation.iter() {
+      error!("  {}", line);
+    }
+
+    let mut report_file = try!(File::create(&self.params.conflict_report_path));
+    try!(report_file.write_all(try!(serde_json::to_string_pretty(report)).as_bytes()));
+    info!("Wrote conflict report to {:?}", self.params.conflict_report_path);
+    Ok(())
+  }
+}
+
+impl Job for SnapshotPlannerJob {
+  fn run(&mut self) {
+    self.run_now().unwrap()
+  }
+}
+
+/** Yields the members whose dev dependencies must be in the snapshot, so their tests can build. */
+pub fn get_crates_needing_dev_dependencies(configuration: &WorkspaceConfiguration) -> HashSet<String> {
+  configuration.crate_sets.iter()
+    .flat_map(|crate_set| crate_set.crates.iter())
+    .map(|entry| entry.get_name().to_owned())
+    .filter(|crate_name| !configuration.skip_dev_dependencies.contains(crate_name))
+    .collect()
+}
+
+/** Yields the features that each member enables beyond its defaults, for members that have any. */
+pub fn get_member_features(configuration: &WorkspaceConfiguration) -> HashMap<String, Vec<String>> {
+  configuration.crate_sets.iter()
+    .flat_map(|crate_set| crate_set.crates.iter())
+    .filter(|entry| !entry.get_features().is_empty())
+    .map(|entry| (entry.get_name().to_owned(), entry.get_features().to_vec()))
+    .collect()
+}
+
+/** Yields the names of the members that resolve from git or a local path, which are packaged. */
+pub fn get_packaged_member_names(configuration: &WorkspaceConfiguration) -> Vec<String> {
+  configuration.crate_sets.iter()
+    .flat_map(|crate_set| crate_set.crates.iter())
+    .filter(|entry| match entry.get_resolution() {
+      Some(&ResolutionType { git: Some(_), .. }) | Some(&ResolutionType { path: Some(_), .. }) => true,
+      _ => false,
+    })
+    .map(|entry| entry.get_name().to_owned())
+    .collect()
+}
+
+/**
+ * Packages every member that resolves from git or a local path, so that it can be resolved and
+ * stored. Relative paths are taken from the provided directory.
+ */
+pub fn package_members(configuration: &WorkspaceConfiguration,
+                       configuration_directory: &Path,
+                       scratch_path: &Path) -> Result<Vec<GitCrate>, JobErr> {
+  let mut git_crates = Vec::new();
+  for entry in configuration.crate_sets.iter().flat_map(|crate_set| crate_set.crates.iter()) {
+    match entry.get_resolution() {
+      Some(&ResolutionType { git: Some(ref git_resolution), .. }) => {
+        info!("Packaging {} from {} at {}", entry.get_name(), git_resolution.repository, git_resolution.revision);
+        git_crates.push(try!(git_crates::package_git_crate(entry.get_name(), git_resolution, scratch_path)));
+      },
+      Some(&ResolutionType { path: Some(ref path), .. }) => {
+        let source_path = configuration_directory.join(path);
+        info!("Packaging {} from {:?}", entry.get_name(), source_path);
+        git_crates.push(try!(git_crates::package_path_crate(entry.get_name(), &source_path, scratch_path)));
+      },
+      _ => {},
+    }
+  }
+  Ok(git_crates)
+}
+
+/**
+ * Resolves the members of the configuration (and their dependencies) into a snapshot.
+ *
+ * Members may be any non-prerelease version that satisfies their version requirement, if they have
+ * one. Crates are listed in name order, and those that are only needed for the tests of members are
+ * marked as dev-only. Members with a resolution override are recorded with it, and must resolve to
+ * the version they were packaged under (see package_members) if it is git or a path.
+ */
+pub fn plan_snapshot(configuration: &WorkspaceConfiguration,
+                     candidate_source: &CandidateSource,
+                     snapshot_version: &str) -> Result<WorkspaceSnapshot, JobErr> {
+  // Member name -> (maintainer, entry)
+  let mut members = BTreeMap::new();
+  for crate_set in configuration.crate_sets.iter() {
+    for entry in crate_set.crates.iter() {
+      members.entry(entry.get_name().to_owned())
+        .or_insert_with(|| (crate_set.maintainer.clone(), entry));
+    }
+  }
+
+  let any_release = VersionReq::parse(">= 0.0.0").unwrap();
+  let mut roots = Vec::new();
+  for (name, &(_, entry)) in members.iter() {
+    let req = match entry.get_version_req() {
+      Some(raw_req) => try!(VersionReq::parse(raw_req)
+        .map_err(|_| JobErr::OtherErr(format!("{} has an invalid version requirement \"{}\"", name, raw_req)))),
+      None => any_release.clone(),
+    };
+    roots.push(Requirement {
+      name: name.clone(),
+      req: req,
+    });
+  }
+  let resolution = try!(resolver::resolve(candidate_source, &roots));
+
+  // A packaged member at any other version would be built from crates.io, despite its label
+  for (name, &(_, entry)) in members.iter() {
+    let source = match entry.get_resolution() {
+      Some(&ResolutionType { git: Some(_), .. }) => "git",
+      Some(&ResolutionType { path: Some(_), .. }) => "path",
+      _ => continue,
+    };
+    let version = resolution.versions.get(name).map(|v| v.to_string()).unwrap_or_default();
+    if !version.contains(&format!("+{}.", source)) {
+      return Err(JobErr::OtherErr(format!("{} resolves from {}, but was planned at {}, which was not packaged from it",
+                                          name,
+                                          source,
+                                          version)))
+    }
+  }
+
+  // Anything reachable from a member without passing through a dev dependency is needed to build
+  let mut non_dev_crates = BTreeSet::new();
+  let mut pending_crates = members.keys().cloned().collect::<Vec<_>>();
+  while let Some(crate_name) = pending_crates.pop() {
+    if !non_dev_crates.insert(crate_name.clone()) {
+      continue
+    }
+    if let Some(dependency_names) = resolution.dependencies.get(&crate_name) {
+      pending_crates.extend(dependency_names.iter().cloned());
+    }
+  }
+
+  let details = resolution.versions.iter()
+    .map(|(name, version)| CrateSnapshot {
+      name: name.clone(),
+      version: version.to_string(),
+      maintainer: members.get(name).map(|&(ref maintainer, _)| maintainer.clone()),
+      dependencies: resolution.dependencies.get(name)
+        .map(|names| names.iter().cloned().collect())
+        .unwrap_or_else(Vec::new),
+      dev_dependencies: resolution.dev_dependencies.get(name)
+        .map(|names| names.iter().cloned().collect())
+        .unwrap_or_else(Vec::new),
+      dev_only: !non_dev_crates.contains(name),
+      features: members.get(name).map(|&(_, entry)| entry.get_features().to_vec()).unwrap_or_else(Vec::new),
+      resolution_type: Some(members.get(name)
+        .and_then(|&(_, entry)| entry.get_resolution().cloned())
+        .unwrap_or_else(|| ResolutionType {
+          crates_io: Some(true),
+          git: None,
+          path: None,
+        })),
+    })
+    .collect();
+
+  Ok(WorkspaceSnapshot {
+    version: snapshot_version.to_owned(),
+    members: members.keys().cloned().collect(),
+    details: details,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use ::JobErr;
+  use common::cargo;
+  use common::configuration::CrateConstraints;
+  use common::configuration::CrateEntry;
+  use common::configuration::MaintainerConfiguration;
+  use common::configuration::WorkspaceConfiguration;
+  use common::index::testing::index_entry;
+  use common::snapshot::GitResolution;
+  use common::snapshot::ResolutionType;
+  use git2::Repository;
+  use git_util;
+  use index::augmented;
+  use index::crates_io;
+  use index::joined::JoinedIndex;
+  use index::revisions::Revision;
+  use index::revisions::RevisionAction;
+  use snapshot_planner::candidates::IndexCandidateSource;
+  use snapshot_planner;
+  use std::collections::HashMap;
+  use std::collections::HashSet;
+  use std::fs::File;
+  use std::fs;
+  use std::io::Write;
+  use std::path::PathBuf;
+  use tempdir::TempDir;
+
+  fn write_file(path: PathBuf, contents: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    File::create(path).unwrap().write_all(contents.as_bytes()).unwrap();
+  }
+
+
+  fn configuration(crates: &[&str]) -> WorkspaceConfiguration {
+    WorkspaceConfiguration {
+      crate_sets: vec![MaintainerConfiguration {
+        maintainer: "someone@example.com".to_owned(),
+        crates: crates.iter().map(|c| CrateEntry::Name((*c).to_owned())).collect(),
+      }],
+      skip_dev_dependencies: Vec::new(),
+      expected_test_failures: Vec::new(),
+    }
+  }
+
+  fn augmented_entry(name: &str, version: &str, dev_dependencies: &[(&str, &str)]) -> cargo::AugmentedIndexEntry {
+    cargo::AugmentedIndexEntry {
+      name: name.to_owned(),
+      vers: version.to_owned(),
+      dev_dependencies: Some(index_entry(name, version, dev_dependencies).deps),
+      source_statistics: None,
+      license: None,
+    }
+  }
+
+  #[test]
+  fn test_plans_newest_compatible_versions() {
+    let mut yanked = index_entry("libc", "0.2.31", &[]);
+    yanked.yanked = Some(true);
+    let upstream_index = crates_io::testing::get_seeded_index_with_revisions(
+      vec![
+        index_entry("memchr", "1.0.2", &[("libc", "^0.2.18")]),
+        index_entry("memchr", "2.0.1", &[("libc", "^0.2.30")]),
+        index_entry("libc", "0.2.29", &[]),
+        index_entry("libc", "0.2.30", &[]),
+        yanked,
+        index_entry("libc", "0.3.0", &[]),
+      ],
+      &[Revision {
+        crate_name: "libc".to_owned(),
+        version: "0.3.0".to_owned(),
+        revision: 1,
+        author: "someone@example.com".to_owned(),
+        reason: "Does not build".to_owned(),
+        action: RevisionAction::MarkBroken,
+      }]);
+    let augmented_index = augmented::testing::get_minimum_index();
+    let joined_index = JoinedIndex::new(&upstream_index, &augmented_index);
+    let candidate_source = IndexCandidateSource::new(&joined_index, &upstream_index, &HashSet::new(), &HashMap::new());
+
+    let snapshot = snapshot_planner::plan_snapshot(&configuration(&["memchr", "libc"]),
+                                                   &candidate_source,
+                                                   "nightly-2017-11-20").unwrap();
+
+    assert_eq!(snapshot.version, "nightly-2017-11-20".to_owned());
+    assert_eq!(snapshot.members, vec!["libc".to_owned(), "memchr".to_owned()]);
+    let details = snapshot.details.iter()
+      .map(|d| (d.name.clone(), d.version.clone(), d.maintainer.clone(), d.dependencies.clone()))
+      .collect::<Vec<_>>();
+    assert_eq!(details, vec![
+      ("libc".to_owned(), "0.2.30".to_owned(), Some("someone@example.com".to_owned()), Vec::new()),
+      ("memchr".to_owned(), "2.0.1".to_owned(), Some("someone@example.com".to_owned()), vec!["libc".to_owned()]),
+    ]);
+  }
+
+  #[test]
+  fn test_planning_fails_for_unknown_members() {
+    let upstream_index = crates_io::testing::get_minimum_index();
+    let augmented_index = augmented::testing::get_minimum_index();
+    let joined_index = JoinedIndex::new(&upstream_index, &augmented_index);
+    let candidate_source = IndexCandidateSource::new(&joined_index, &upstream_index, &HashSet::new(), &HashMap::new());
+
+    assert!(snapshot_planner::plan_snapshot(&configuration(&["missing"]),
+                                            &candidate_source,
+                                            "nightly-2017-11-20").is_err());
+  }
+
+  #[test]
+  fn test_dev_dependencies_of_members_are_planned_unless_skipped() {
+    let upstream_index = crates_io::testing::get_seeded_index(vec![
+      index_entry("memchr", "2.0.1", &[("libc", "^0.2")]),
+      index_entry("regex", "0.2.2", &[("memchr", "^2.0")]),
+      index_entry("libc", "0.2.30", &[]),
+      index_entry("quickcheck", "0.4.1", &[("rand", "^0.3")]),
+      index_entry("rand", "0.3.17", &[("libc", "^0.2")]),
+      index_entry("lazy_static", "0.2.9", &[]),
+    ]);
+    let augmented_index = augmented::testing::get_seeded_index(vec![
+      augmented_entry("memchr", "2.0.1", &[("lazy_static", "^0.2")]),
+      augmented_entry("regex", "0.2.2", &[("quickcheck", "^0.4")]),
+    ]);
+    let joined_index = JoinedIndex::new(&upstream_index, &augmented_index);
+    let mut configuration = configuration(&["regex", "memchr"]);
+    configuration.skip_dev_dependencies = vec!["memchr".to_owned()];
+    let candidate_source = IndexCandidateSource::new(
+      &joined_index,
+      &upstream_index,
+      &snapshot_planner::get_crates_needing_dev_dependencies(&configuration),
+      &snapshot_planner::get_member_features(&configuration));
+
+    let snapshot = snapshot_planner::plan_snapshot(&configuration,
+                                                   &candidate_source,
+                                                   "nightly-2017-11-20").unwrap();
+
+    let details = snapshot.details.iter()
+      .map(|d| (d.name.clone(), d.dev_only))
+      .collect::<Vec<_>>();
+    assert_eq!(details, vec![
+      ("libc".to_owned(), false),
+      ("memchr".to_owned(), false),
+      ("quickcheck".to_owned(), true),
+      ("rand".to_owned(), true),
+      ("regex".to_owned(), false),
+    ]);
+    let regex = snapshot.details.iter().find(|d| d.name == "regex").unwrap();
+    assert_eq!(regex.dev_dependencies, vec!["quickcheck".to_owned()]);
+  }
+
+  #[test]
+  fn test_member_constraints_are_honoured() {
+    let mut serde = index_entry("serde", "1.0.15", &[("serde_derive", "^1.0")]);
+    serde.deps[0].optional = true;
+    let upstream_index = crates_io::testing::get_seeded_index(vec![
+      index_entry("memchr", "1.0.2", &[]),
+      index_entry("memchr", "2.0.1", &[]),
+      serde,
+      index_entry("serde_derive", "1.0.15", &[]),
+    ]);
+    let augmented_index = augmented::testing::get_minimum_index();
+    let joined_index = JoinedIndex::new(&upstream_index, &augmented_index);
+
+    // A fork of memchr in git, and an unpublished crate beside the configuration
+    let repository_dir = TempDir::new("forked-memchr").unwrap();
+    let repository = Repository::init(repository_dir.path()).unwrap();
+    write_file(repository_dir.path().join("Cargo.toml"), "[package]\nname = \"memchr\"\nversion = \"1.0.2\"\n");
+    write_file(repository_dir.path().join("src/lib.rs"), "\n");
+    let revision = git_util::commit_paths(&repository,
+                                          &[PathBuf::from("Cargo.toml"), PathBuf::from("src/lib.rs")],
+                                          "Fork").unwrap().to_string();
+    let configuration_dir = TempDir::new("configuration").unwrap();
+    write_file(configuration_dir.path().join("local/Cargo.toml"), "[package]\nname = \"local\"\nversion = \"0.1.0\"\n");
+    write_file(configuration_dir.path().join("local/src/lib.rs"), "\n");
+
+    let forked_memchr = ResolutionType {
+      crates_io: None,
+      git: Some(GitResolution {
+        repository: repository_dir.path().to_string_lossy().into_owned(),
+        revision: revision.clone(),
+      }),
+      path: None,
+    };
+    let local = ResolutionType {
+      crates_io: None,
+      git: None,
+      path: Some("local".to_owned()),
+    };
+    let configuration = WorkspaceConfiguration {
+      crate_sets: vec![MaintainerConfiguration {
+        maintainer: "someone@example.com".to_owned(),
+        crates: vec![
+          CrateEntry::Constrained(CrateConstraints {
+            name: "memchr".to_owned(),
+            version: Some("=1.0.2".to_owned()),
+            features: Vec::new(),
+            resolution: Some(forked_memchr.clone()),
+            notes: None,
+          }),
+          CrateEntry::Constrained(CrateConstraints {
+            name: "serde".to_owned(),
+            version: None,
+            features: vec!["serde_derive".to_owned()],
+            resolution: None,
+            notes: Some("Verified with derive".to_owned()),
+          }),
+          CrateEntry::Constrained(CrateConstraints {
+            name: "local".to_owned(),
+            version: None,
+            features: Vec::new(),
+            resolution: Some(local.clone()),
+            notes: None,
+          }),
+        ],
+      }],
+      skip_dev_dependencies: Vec::new(),
+      expected_test_failures: Vec::new(),
+    };
+    assert_eq!(snapshot_planner::get_packaged_member_names(&configuration),
+               vec!["memchr".to_owned(), "local".to_owned()]);
+    let mut candidate_source = IndexCandidateSource::new(
+      &joined_index,
+      &upstream_index,
+      &HashSet::new(),
+      &snapshot_planner::get_member_features(&configuration));
+
+    // Unpackaged, memchr would resolve from crates.io despite its override
+    let mut unpackaged_configuration = configuration.clone();
+    unpackaged_configuration.crate_sets[0].crates.retain(|entry| entry.get_name() != "local");
+    match snapshot_planner::plan_snapshot(&unpackaged_configuration, &candidate_source, "nightly-2017-11-20") {
+      Err(JobErr::OtherErr(ref message)) => assert!(message.starts_with("memchr resolves from git")),
+      other => panic!("Expected memchr to be rejected, but got {:?}", other),
+    }
+
+    let scratch_dir = TempDir::new("packaged-members").unwrap();
+    let git_crates = snapshot_planner::package_members(&configuration,
+                                                       configuration_dir.path(),
+                                                       scratch_dir.path()).unwrap();
+    for git_crate in git_crates.iter() {
+      candidate_source.add_git_crate(git_crate, false, &[]);
+    }
+    let snapshot = snapshot_planner::plan_snapshot(&configuration,
+                                                   &candidate_source,
+                                                   "nightly-2017-11-20").unwrap();
+
+    let local_version = git_crates[1].index_entry.vers.clone();
+    assert!(local_version.starts_with("0.1.0+path."));
+    let details = snapshot.details.iter()
+      .map(|d| (d.name.clone(), d.version.clone(), d.features.clone()))
+      .collect::<Vec<_>>();
+    assert_eq!(details, vec![
+      ("local".to_owned(), local_version, Vec::new()),
+      ("memchr".to_owned(), format!("1.0.2+git.{}", &revision[0..7]), Vec::new()),
+      ("serde".to_owned(), "1.0.15".to_owned(), vec!["serde_derive".to_owned()]),
+      ("serde_derive".to_owned(), "1.0.15".to_owned(), Vec::new()),
+    ]);
+    assert_eq!(snapshot.details[0].resolution_type, Some(local));
+    assert_eq!(snapshot.details[1].resolution_type, Some(forked_memchr));
+  }
+}
@@ -0,0 +1,62 @@
+use ::Job;
+use ::JobErr;
+use snapshot_repository::SnapshotRepository;
+
+mod flags {
+  define_pub_cfg!(promoted_snapshot,
+                  String,
+                  "nightly",
+                  "The nightly snapshot (or alias) to promote to the next LTS snapshot.");
+  define_pub_cfg!(promote_to_new_major,
+                  bool,
+                  false,
+                  "Whether the promoted snapshot starts a new LTS major version, instead of a new minor version.");
+}
+
+#[derive(Clone, Builder)]
+#[builder(default)]
+pub struct SnapshotPromoterParams {
+  promoted_snapshot: String,
+  new_major: bool,
+}
+
+impl Default for SnapshotPromoterParams {
+  fn default() -> SnapshotPromoterParams {
+    SnapshotPromoterParams {
+      promoted_snapshot: flags::promoted_snapshot::CONFIG.get_value(),
+      new_major: flags::promote_to_new_major::CONFIG.get_value(),
+    }
+  }
+}
+
+/** A Job that republishes a nightly snapshot as the next LTS snapshot. */
+#[derive(Builder)]
+pub struct SnapshotPromoterJob {
+  snapshot_repository: SnapshotRepository,
+  #[builder(default)]
+  params: SnapshotPromoterParams,
+}
+
+impl SnapshotPromoterJob {
+  pub fn for_configured_repository() -> Result<SnapshotPromoterJob, JobErr> {
+    let snapshot_repository = try!(try!(SnapshotRepository::from_flags())
+      .ok_or_else(|| JobErr::OtherErr("--snapshot_repository_directory must be specified".to_owned())));
+    Ok(SnapshotPromoterJobBuilder::default()
+      .snapshot_repository(snapshot_repository)
+      .build()
+      .unwrap())
+  }
+
+  fn run_now(&mut self) -> Result<(), JobErr> {
+    let snapshot = try!(self.snapshot_repository.promote_to_lts(&self.params.promoted_snapshot,
+                                                                self.params.new_major));
+    info!("Published {}", snapshot.version);
+    Ok(())
+  }
+}
+
+impl Job for SnapshotPromoterJob {
+  fn run(&mut self) {
+    self.run_now().unwrap()
+  }
+}
@@ -0,0 +1,291 @@
+use ::JobErr;
+use common::signing::SigningKey;
+use common::signing;
+use common::snapshot::WorkspaceSnapshot;
+use common::snapshot_store::Channel;
+use common::snapshot_store::SnapshotName;
+use common::snapshot_store;
+use common::verification::VerificationReport;
+use git2::ObjectType;
+use git2::Oid;
+use git2::Repository;
+use git_util;
+use serde_yaml;
+use std::fs::File;
+use std::fs;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+mod flags {
+  define_pub_cfg!(snapshot_repository_directory,
+                  ::zcfg::NoneableCfg<String>,
+                  None,
+                  "The path to the git repository that snapshots are published into.");
+
+  define_pub_cfg!(snapshot_signing_key_path,
+                  ::zcfg::NoneableCfg<String>,
+                  None,
+                  "The path to the PKCS#8 Ed25519 key that published snapshots are signed with.");
+}
+
+/**
+ * A git repository holding every published snapshot.
+ *
+ * Each snapshot is written to snapshots/$NAME.yaml in its own commit, and that commit is tagged
+ * with the snapshot's name. Published snapshots are never rewritten, so the repository can be
+ * mirrored with a plain git clone or fetch. Reading is done with common::snapshot_store, so that
+ * clients can read a mirror without this job library.
+ *
+ * If the repository has a signing key, each snapshot is published with a detached signature in
+ * snapshots/$NAME.sig.
+ *
+ * Verification reports are committed to snapshots/$NAME.verification.yaml once a snapshot has been
+ * built. Unlike the snapshot, a report may be replaced by a later verification.
+ */
+#[derive(Clone)]
+pub struct SnapshotRepository {
+  repository: Arc<Repository>,
+  path: PathBuf,
+  signing_key: Option<Arc<SigningKey>>,
+}
+
+impl SnapshotRepository {
+  /** Opens the repository named by the binary flags, if there is one. */
+  pub fn from_flags() -> Result<Option<SnapshotRepository>, JobErr> {
+    match flags::snapshot_repository_directory::CONFIG.get_value().inner() {
+      Some(path) => {
+        let repository = try!(SnapshotRepository::open_or_init(path));
+        match flags::snapshot_signing_key_path::CONFIG.get_value().inner() {
+          Some(key_path) => {
+            let mut key_bytes = Vec::new();
+            try!(File::open(&key_path).and_then(|mut f| f.read_to_end(&mut key_bytes)));
+            Ok(Some(repository.with_signing_key(try!(SigningKey::from_pkcs8(&key_bytes)))))
+          },
+          None => {
+            warn!("No --snapshot_signing_key_path was provided, so snapshots will be published unsigned");
+            Ok(Some(repository))
+          },
+        }
+      },
+      None => Ok(None),
+    }
+  }
+
+  /** Opens the repository at the provided path, creating an empty one if there is none. */
+  pub fn open_or_init<P: AsRef<Path>>(path: P) -> Result<SnapshotRepository, JobErr> {
+    let repository = match Repository::open(path.as_ref()) {
+      Ok(repository) => repository,
+      Err(_) => {
+        info!("Initializing a new snapshot repository in {:?}", path.as_ref());
+        try!(fs::create_dir_all(path.as_ref()));
+        try!(Repository::init(path.as_ref()))
+      },
+    };
+    Ok(SnapshotRepository {
+      repository: Arc::new(repository),
+      path: path.as_ref().to_path_buf(),
+      signing_key: None,
+    })
+  }
+
+  /** Signs every snapshot published from now on with the provided key. */
+  pub fn with_signing_key(self, signing_key: SigningKey) -> SnapshotRepository {
+    SnapshotRepository {
+      signing_key: Some(Arc::new(signing_key)),
+      ..self
+    }
+  }
+
+  pub fn list(&self) -> Result<Vec<SnapshotName>, JobErr> {
+    Ok(try!(snapshot_store::list_snapshots(&self.path)))
+  }
+
+  /** Reads a snapshot by its name, or by a channel alias such as "lts". */
+  pub fn fetch(&self, name_or_alias: &str) -> Result<WorkspaceSnapshot, JobErr> {
+    Ok(try!(snapshot_store::read_snapshot(&self.path, name_or_alias)))
+  }
+
+  /**
+   * Commits the snapshot (and its signature) under its version, and tags the commit with it.
+   *
+   * The version must be a valid snapshot name that has not already been published.
+   */
+  pub fn publish(&self, snapshot: &WorkspaceSnapshot) -> Result<Oid, JobErr> {
+    let name = try!(SnapshotName::parse(&snapshot.version)
+      .ok_or_else(|| JobErr::OtherErr(format!("{} is not a valid snapshot name", snapshot.version))));
+    if try!(self.list()).contains(&name) {
+      return Err(JobErr::OtherErr(format!("Snapshot {} has already been published", name)))
+    }
+
+    let relative_path = name.get_relative_path();
+    try!(self.write_file(&relative_path, &try!(serde_yaml::to_string(snapshot))));
+    let mut published_paths = vec![relative_path];
+    if let Some(ref signing_key) = self.signing_key {
+      let signature = signing::sign_snapshot(snapshot, signing_key);
+      let signature_path = name.get_signature_relative_path();
+      try!(self.write_file(&signature_path, &try!(serde_yaml::to_string(&signature))));
+      published_paths.push(signature_path);
+    }
+
+    let commit_oid = try!(git_util::commit_paths(&self.repository,
+                                                 &published_paths,
+                                                 &format!("Publish snapshot {}", name)));
+    let commit = try!(self.repository.find_object(commit_oid, Some(ObjectType::Commit)));
+    try!(self.repository.tag(&name.to_string(),
+                             &commit,
+                             &try!(git_util::get_signature()),
+                             &format!("Snapshot {}", name),
+                             false /* force */));
+    info!("Published snapshot {} as {}", name, commit_oid);
+    Ok(commit_oid)
+  }
+
+  /** Commits the verification report of a published snapshot, replacing any earlier report. */
+  pub fn attach_verification_report(&self, report: &VerificationReport) -> Result<Oid, JobErr> {
+    let name = try!(SnapshotName::parse(&report.snapshot_version)
+      .ok_or_else(|| JobErr::OtherErr(format!("{} is not a valid snapshot name", report.snapshot_version))));
+    if !try!(self.list()).contains(&name) {
+      return Err(JobErr::OtherErr(format!("Snapshot {} has not been published", name)))
+    }
+
+    let relative_path = name.get_verification_relative_path();
+    try!(self.write_file(&relative_path, &try!(serde_yaml::to_string(report))));
+    let commit_oid = try!(git_util::commit_paths(&self.repository,
+                                                 &[relative_path],
+                                                 &format!("Verify snapshot {}", name)));
+    info!("Attached the verification report of {} as {}", name, commit_oid);
+    Ok(commit_oid)
+  }
+
+  /**
+   * Republishes a nightly snapshot as the next LTS snapshot.
+   *
+   * The new LTS is the next minor version of the latest LTS, or the next major version if
+   * requested. The first LTS is lts-1.0.
+   */
+  pub fn promote_to_lts(&self, nightly_name_or_alias: &str, new_major: bool) -> Result<WorkspaceSnapshot, JobErr> {
+    let nightly_name = try!(snapshot_store::resolve_name(&self.path, nightly_name_or_alias));
+    if nightly_name.get_channel() != Channel::Nightly {
+      return Err(JobErr::OtherErr(format!("Only nightly snapshots can be promoted, not {}", nightly_name)))
+    }
+
+    let latest_lts = try!(self.list()).into_iter()
+      .filter(|name| name.get_channel() == Channel::Lts)
+      .max();
+    let lts_name = match (latest_lts, new_major) {
+      (Some(SnapshotName::Lts { major, .. }), true) => SnapshotName::Lts { major: major + 1, minor: 0 },
+      (Some(SnapshotName::Lts { major, minor }), false) => SnapshotName::Lts { major: major, minor: minor + 1 },
+      _ => SnapshotName::Lts { major: 1, minor: 0 },
+    };
+
+    let mut snapshot = try!(self.fetch(&nightly_name.to_string()));
+    snapshot.version = lts_name.to_string();
+    try!(self.publish(&snapshot));
+    info!("Promoted {} to {}", nightly_name, lts_name);
+    Ok(snapshot)
+  }
+
+  fn write_file(&self, relative_path: &Path, contents: &str) -> Result<(), JobErr> {
+    let full_path = self.path.join(relative_path);
+    if let Some(parent) = full_path.parent() {
+      try!(fs::create_dir_all(parent));
+    }
+    let mut file = try!(File::create(&full_path));
+    try!(file.write_all(contents.as_bytes()));
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use common::signing::SigningKey;
+  use common::snapshot::WorkspaceSnapshot;
+  use common::snapshot_store::SnapshotName;
+  use common::snapshot_store;
+  use common::verification::VerificationReport;
+  use snapshot_repository::SnapshotRepository;
+  use std::fs::File;
+  use std::io::Write;
+  use tempdir::TempDir;
+
+  fn snapshot(version: &str) -> WorkspaceSnapshot {
+    WorkspaceSnapshot {
+      version: version.to_owned(),
+      members: vec!["libc".to_owned()],
+      details: Vec::new(),
+    }
+  }
+
+  #[test]
+  fn test_published_snapshots_can_be_fetched_by_alias() {
+    let tempdir = TempDir::new("snapshot_repository").unwrap();
+    let repository = SnapshotRepository::open_or_init(tempdir.path()).unwrap();
+    repository.publish(&snapshot("nightly-2017-11-19")).unwrap();
+    repository.publish(&snapshot("nightly-2017-11-20")).unwrap();
+
+    assert_eq!(repository.list().unwrap(),
+               vec![SnapshotName::parse("nightly-2017-11-19").unwrap(),
+                    SnapshotName::parse("nightly-2017-11-20").unwrap()]);
+    assert_eq!(repository.fetch("nightly").unwrap(), snapshot("nightly-2017-11-20"));
+    assert!(repository.repository.find_reference("refs/tags/nightly-2017-11-19").is_ok());
+    // Published snapshots are immutable
+    assert!(repository.publish(&snapshot("nightly-2017-11-20")).is_err());
+    assert!(repository.publish(&snapshot("2017-11-21")).is_err());
+  }
+
+  #[test]
+  fn test_verification_reports_are_attached_to_published_snapshots() {
+    let tempdir = TempDir::new("snapshot_repository").unwrap();
+    let repository = SnapshotRepository::open_or_init(tempdir.path()).unwrap();
+    let report = VerificationReport {
+      snapshot_version: "nightly-2017-11-20".to_owned(),
+      crates: Vec::new(),
+    };
+    assert!(repository.attach_verification_report(&report).is_err());
+
+    repository.publish(&snapshot("nightly-2017-11-20")).unwrap();
+    repository.attach_verification_report(&report).unwrap();
+    let name = SnapshotName::parse("nightly-2017-11-20").unwrap();
+    assert_eq!(snapshot_store::read_verification_report(tempdir.path(), &name).unwrap(), Some(report));
+    // The report is not mistaken for a snapshot
+    assert_eq!(repository.list().unwrap(), vec![name]);
+  }
+
+  #[test]
+  fn test_promotion_creates_the_next_lts() {
+    let tempdir = TempDir::new("snapshot_repository").unwrap();
+    let repository = SnapshotRepository::open_or_init(tempdir.path()).unwrap();
+    repository.publish(&snapshot("nightly-2017-11-20")).unwrap();
+
+    assert_eq!(repository.promote_to_lts("nightly", false).unwrap().version, "lts-1.0");
+    assert_eq!(repository.promote_to_lts("nightly-2017-11-20", false).unwrap().version, "lts-1.1");
+    assert_eq!(repository.promote_to_lts("nightly", true).unwrap().version, "lts-2.0");
+    assert_eq!(repository.fetch("lts").unwrap(), snapshot("lts-2.0"));
+    assert!(repository.promote_to_lts("lts", false).is_err());
+  }
+
+  #[test]
+  fn test_signed_snapshots_can_be_verified() {
+    let tempdir = TempDir::new("snapshot_repository").unwrap();
+    let signing_key = SigningKey::from_pkcs8(&SigningKey::generate_pkcs8().unwrap()).unwrap();
+    let public_key = signing_key.get_public_key();
+    let repository = SnapshotRepository::open_or_init(tempdir.path()).unwrap()
+      .with_signing_key(signing_key);
+    repository.publish(&snapshot("nightly-2017-11-20")).unwrap();
+
+    assert_eq!(snapshot_store::read_verified_snapshot(tempdir.path(), "nightly", &[public_key.clone()]).unwrap(),
+               snapshot("nightly-2017-11-20"));
+
+    let snapshot_path = tempdir.path()
+      .join(SnapshotName::parse("nightly-2017-11-20").unwrap().get_relative_path());
+    let mut tampered_snapshot = snapshot("nightly-2017-11-20");
+    tampered_snapshot.members.push("evil".to_owned());
+    File::create(snapshot_path).unwrap()
+      .write_all(::serde_yaml::to_string(&tampered_snapshot).unwrap().as_bytes())
+      .unwrap();
+    assert!(snapshot_store::read_verified_snapshot(tempdir.path(), "nightly", &[public_key]).is_err());
+  }
+}
@@ -0,0 +1,692 @@
+use ::Job;
+use ::JobErr;
+use common::cargo::IndexEntry;
+use common::configuration::WorkspaceConfiguration;
+use common::configuration;
+use common::snapshot::CrateSnapshot;
+use common::snapshot::WorkspaceSnapshot;
+use common::verification::CrateVerification;
+use common::verification::FeatureBuildVerification;
+use common::verification::TestBinaryVerification;
+use common::verification::TestVerification;
+use common::verification::VerificationOutcome;
+use common::verification::VerificationReport;
+use lcs_fetcher::repository::HttpLcsRepository;
+use lcs_fetcher::repository::LcsRepositorySource;
+use lcs_fetcher::repository::LocalFsLcsRepository;
+use sandbox::Sandbox;
+use sandbox::SandboxOutput;
+use sandbox::SandboxStep;
+use serde_json::Value;
+use serde_json;
+use snapshot_planner;
+use snapshot_repository::SnapshotRepository;
+use snapshot_verifier::workspace::VerificationWorkspace;
+use std::collections::HashSet;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+pub mod workspace;
+
+mod flags {
+  define_pub_cfg!(verified_snapshot,
+                  String,
+                  "nightly",
+                  "The snapshot (or alias) whose members should be built.");
+  define_pub_cfg!(verification_lcs_directory,
+                  ::zcfg::NoneableCfg<String>,
+                  None,
+                  "The path to a local LCS to vendor crates from. Defaults to the upstream crate server.");
+  define_pub_cfg!(verification_cargo_path,
+                  String,
+                  "cargo",
+                  "The cargo binary that members are built with.");
+  define_pub_cfg!(verify_member_tests,
+                  bool,
+                  false,
+                  "Whether to also build and run the tests of members, unless their dev dependencies are skipped.");
+  define_pub_cfg!(verification_manifest_path,
+                  ::zcfg::NoneableCfg<String>,
+                  None,
+                  "The workspace configuration that the snapshot was planned from. Needed to run member tests.");
+  define_pub_cfg!(verification_feature_matrix,
+                  String,
+                  "",
+                  "A comma separated list of the feature combinations that members are also built with: no-default-features, all-features, and each-feature.");
+  define_pub_cfg!(max_features_built_alone,
+                  u32,
+                  8u32,
+                  "Members with more features than this are not built with each feature on its own.");
+}
+
+// Only the end of a build log is kept, as that is where the errors are
+const MAX_LOG_BYTES: usize = 64 * 1024;
+
+/** A combination of features that members are built with, beyond their defaults. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeatureMatrixEntry {
+  NoDefaultFeatures,
+  AllFeatures,
+  // Each feature (but "default") on its own, without the default features
+  EachFeature,
+}
+
+impl FeatureMatrixEntry {
+  pub fn parse(raw: &str) -> Option<FeatureMatrixEntry> {
+    match raw.trim() {
+      "no-default-features" => Some(FeatureMatrixEntry::NoDefaultFeatures),
+      "all-features" => Some(FeatureMatrixEntry::AllFeatures),
+      "each-feature" => Some(FeatureMatrixEntry::EachFeature),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Clone, Builder)]
+#[builder(default)]
+pub struct SnapshotVerifierParams {
+  verified_snapshot: String,
+  cargo_path: PathBuf,
+  run_tests: bool,
+  feature_matrix: Vec<FeatureMatrixEntry>,
+  max_features_built_alone: usize,
+}
+
+impl Default for SnapshotVerifierParams {
+  fn default() -> SnapshotVerifierParams {
+    SnapshotVerifierParams {
+      verified_snapshot: flags::verified_snapshot::CONFIG.get_value(),
+      cargo_path: PathBuf::from(flags::verification_cargo_path::CONFIG.get_value()),
+      run_tests: flags::verify_member_tests::CONFIG.get_value(),
+      feature_matrix: flags::verification_feature_matrix::CONFIG.get_value()
+        .split(',')
+        .filter(|raw| !raw.trim().is_empty())
+        .map(|raw| FeatureMatrixEntry::parse(raw)
+          .expect(&format!("Unknown --verification_feature_matrix entry \"{}\"", raw)))
+        .collect(),
+      max_features_built_alone: flags::max_features_built_alone::CONFIG.get_value() as usize,
+    }
+  }
+}
+
+/**
+ * A Job that builds every member of a published snapshot, and attaches a report of the results to
+ * the snapshot.
+ *
+ * Every crate in the snapshot is vendored from the LCS into a scratch workspace that replaces
+ * crates.io, and each member is built there with `--frozen`, so that Cargo never reaches for the
+ * network. Every step runs in the sandbox, as the crates are unaudited code.
+ *
+ * Members may also be built with other combinations of their features, as listed by the feature
+ * matrix. Each feature is only built on its own for members with few enough of them.
+ *
+ * If requested, the tests of members are then built and run (one test binary at a time), for the
+ * members that the workspace configuration does not skip the dev dependencies of. Failures that the
+ * configuration expects are recorded, but do not fail the member.
+ */
+#[derive(Builder)]
+pub struct SnapshotVerifierJob {
+  snapshot_repository: SnapshotRepository,
+  lcs_source: Box<LcsRepositorySource>,
+  #[builder(default)]
+  configuration: Option<WorkspaceConfiguration>,
+  #[builder(default)]
+  sandbox: Sandbox,
+  #[builder(default)]
+  params: SnapshotVerifierParams,
+}
+
+impl SnapshotVerifierJob {
+  pub fn for_configured_repository() -> Result<SnapshotVerifierJob, JobErr> {
+    let snapshot_repository = try!(try!(SnapshotRepository::from_flags())
+      .ok_or_else(|| JobErr::OtherErr("--snapshot_repository_directory must be specified".to_owned())));
+    let lcs_source: Box<LcsRepositorySource> = match flags::verification_lcs_directory::CONFIG.get_value().inner() {
+      Some(path) => Box::new(LocalFsLcsRepository::new(path)),
+      None => Box::new(HttpLcsRepository::default()),
+    };
+    let configuration = match flags::verification_manifest_path::CONFIG.get_value().inner() {
+      Some(path) => Some(try!(configuration::read_unvalidated_configuration(path))),
+      None => None,
+    };
+    if flags::verify_member_tests::CONFIG.get_value() && configuration.is_none() {
+      return Err(JobErr::OtherErr("--verification_manifest_path must be specified to run member tests".to_owned()))
+    }
+    Ok(SnapshotVerifierJobBuilder::default()
+      .snapshot_repository(snapshot_repository)
+      .lcs_source(lcs_source)
+      .configuration(configuration)
+      .build()
+      .unwrap())
+  }
+
+  fn run_now(&mut self) -> Result<(), JobErr> {
+    let snapshot = try!(self.snapshot_repository.fetch(&self.params.verified_snapshot));
+    let report = try!(self.verify(&snapshot));
+    for failure in report.get_failures().into_iter() {
+      warn!("{}:{} failed verification:\n{}", failure.name, failure.version, failure.log);
+    }
+    try!(self.snapshot_repository.attach_verification_report(&report));
+    info!("Verified {}: {} of {} members passed",
+          snapshot.version,
+          report.crates.len() - report.get_failures().len(),
+          report.crates.len());
+    Ok(())
+  }
+
+  /** Builds (and possibly tests) every member of the snapshot, in name order. */
+  pub fn verify(&self, snapshot: &WorkspaceSnapshot) -> Result<VerificationReport, JobErr> {
+    let workspace = try!(VerificationWorkspace::prepare(snapshot, self.lcs_source.as_ref()));
+    let tested_members = match self.configuration {
+      Some(ref configuration) if self.params.run_tests =>
+        snapshot_planner::get_crates_needing_dev_dependencies(configuration),
+      _ => HashSet::new(),
+    };
+    let mut members = snapshot.details.iter()
+      .filter(|details| snapshot.members.contains(&details.name))
+      .collect::<Vec<_>>();
+    members.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut crates = Vec::new();
+    for details in members.into_iter() {
+      info!("Building {}:{}", details.name, details.version);
+      let mut verification = try!(self.verify_member(&workspace, details));
+      if verification.outcome == VerificationOutcome::Passed {
+        let member_entry = try!(workspace.get_member_entry(&details.name)
+          .ok_or_else(|| JobErr::OtherErr(format!("{} was not prepared as a member", details.name))));
+        for (features, args) in self.get_feature_builds(member_entry).into_iter() {
+          info!("Building {}:{} with {}", details.name, details.version, features);
+          let feature_build = try!(self.verify_feature_build(&workspace, details, features, &args));
+          if feature_build.outcome == VerificationOutcome::Failed {
+            verification.outcome = VerificationOutcome::Failed;
+          }
+          verification.feature_builds.push(feature_build);
+        }
+      }
+      if verification.outcome == VerificationOutcome::Passed && tested_members.contains(&details.name) {
+        info!("Testing {}:{}", details.name, details.version);
+        let tests = try!(self.verify_member_tests(&workspace, details));
+        if tests.outcome == VerificationOutcome::Failed {
+          verification.outcome = VerificationOutcome::Failed;
+        }
+        verification.tests = Some(tests);
+      }
+      crates.push(verification);
+    }
+    Ok(VerificationReport {
+      snapshot_version: snapshot.version.clone(),
+      crates: crates,
+    })
+  }
+
+  /**
+   * Locks the member's dependencies against the vendored crates, and then builds it with the
+   * features the snapshot enables for it.
+   */
+  fn verify_member(&self, workspace: &VerificationWorkspace, details: &CrateSnapshot) -> Result<CrateVerification, JobErr> {
+    let member_path = workspace.get_member_path(&details.name);
+    let build_args = get_cargo_args(&["build", "--frozen"], details);
+
+    let started_at = Instant::now();
+    let mut log = String::new();
+    let mut outcome = VerificationOutcome::Passed;
+    for args in vec![vec!["generate-lockfile".to_owned()], build_args].into_iter() {
+      let output = try!(self.run_cargo(workspace, &member_path, &args));
+      log.push_str(&format!("$ cargo {}\n", args.join(" ")));
+      log.push_str(&get_output_log(&output, true /* include_stdout */));
+      if !output.success() {
+        outcome = VerificationOutcome::Failed;
+        break
+      }
+    }
+
+    Ok(CrateVerification {
+      name: details.name.clone(),
+      version: details.version.clone(),
+      outcome: outcome,
+      duration_millis: get_millis_since(started_at),
+      log: get_log_tail(&log).to_owned(),
+      feature_builds: Vec::new(),
+      tests: None,
+    })
+  }
+
+  /**
+   * Yields the name and the cargo arguments of each build in the feature matrix for the member,
+   * whose features are read from its index entry.
+   */
+  fn get_feature_builds(&self, member_entry: &IndexEntry) -> Vec<(String, Vec<String>)> {
+    let mut feature_builds = Vec::new();
+    for entry in self.params.feature_matrix.iter() {
+      match *entry {
+        FeatureMatrixEntry::NoDefaultFeatures =>
+          feature_builds.push(("no-default-features".to_owned(), vec!["--no-default-features".to_owned()])),
+        FeatureMatrixEntry::AllFeatures =>
+          feature_builds.push(("all-features".to_owned(), vec!["--all-features".to_owned()])),
+        FeatureMatrixEntry::EachFeature => {
+          let mut features = member_entry.features.keys()
+            .filter(|feature| feature.as_str() != "default")
+            .cloned()
+            .collect::<Vec<_>>();
+          features.sort();
+          if features.len() > self.params.max_features_built_alone {
+            info!("Not building each of the {} features of {} on its own", features.len(), member_entry.name);
+            continue
+          }
+          for feature in features.into_iter() {
+            feature_builds.push((format!("feature/{}", feature),
+                                 vec!["--no-default-features".to_owned(), "--features".to_owned(), feature]));
+          }
+        },
+      }
+    }
+    feature_builds
+  }
+
+  /** Builds the member with one combination of its features, against its existing lockfile. */
+  fn verify_feature_build(&self,
+                          workspace: &VerificationWorkspace,
+                          details: &CrateSnapshot,
+                          features: String,
+                          feature_args: &[String]) -> Result<FeatureBuildVerification, JobErr> {
+    let mut args = vec!["build".to_owned(), "--frozen".to_owned()];
+    args.extend(feature_args.iter().cloned());
+    let output = try!(self.run_cargo(workspace, &workspace.get_member_path(&details.name), &args));
+    let log = format!("$ cargo {}\n{}", args.join(" "), get_output_log(&output, true /* include_stdout */));
+    Ok(FeatureBuildVerification {
+      features: features,
+      outcome: if output.success() { VerificationOutcome::Passed } else { VerificationOutcome::Failed },
+      duration_millis: get_millis(output.duration),
+      log: get_log_tail(&log).to_owned(),
+    })
+  }
+
+  /**
+   * Builds the member's test binaries, and then runs each of them from the member's directory, as
+   * `cargo test` would. Doc tests are not run.
+   */
+  fn verify_member_tests(&self, workspace: &VerificationWorkspace, details: &CrateSnapshot) -> Result<TestVerification, JobErr> {
+    let member_path = workspace.get_member_path(&details.name);
+    let args = get_cargo_args(&["test", "--frozen", "--no-run", "--message-format=json"], details);
+    let started_at = Instant::now();
+    let output = try!(self.run_cargo(workspace, &member_path, &args));
+    // Stdout only holds the build's JSON messages
+    let log = format!("$ cargo {}\n{}", args.join(" "), get_output_log(&output, false /* include_stdout */));
+    if !output.success() {
+      return Ok(TestVerification {
+        outcome: self.get_test_failure_outcome(&details.name, None),
+        duration_millis: get_millis_since(started_at),
+        log: get_log_tail(&log).to_owned(),
+        binaries: Vec::new(),
+      })
+    }
+
+    let mut binaries = Vec::new();
+    for (binary_name, binary_path) in get_test_binaries(&String::from_utf8_lossy(&output.stdout)).into_iter() {
+      let binary_output = try!(self.sandbox.run(&SandboxStep::new(&binary_path)
+        .current_dir(&member_path)
+        .env("CARGO_MANIFEST_DIR", member_path.as_os_str())));
+      let binary_log = get_output_log(&binary_output, true /* include_stdout */);
+      let outcome = if binary_output.success() {
+        VerificationOutcome::Passed
+      } else {
+        self.get_test_failure_outcome(&details.name, Some(&binary_name))
+      };
+      binaries.push(TestBinaryVerification {
+        name: binary_name,
+        outcome: outcome,
+        duration_millis: get_millis(binary_output.duration),
+        log: get_log_tail(&binary_log).to_owned(),
+      });
+    }
+
+    let has_failure = binaries.iter().any(|binary| binary.outcome == VerificationOutcome::Failed);
+    Ok(TestVerification {
+      outcome: if has_failure { VerificationOutcome::Failed } else { VerificationOutcome::Passed },
+      duration_millis: get_millis_since(started_at),
+      log: get_log_tail(&log).to_owned(),
+      binaries: binaries,
+    })
+  }
+
+  fn get_test_failure_outcome(&self, crate_name: &str, test: Option<&str>) -> VerificationOutcome {
+    let is_expected = self.configuration.as_ref()
+      .map(|configuration| configuration.is_test_failure_expected(crate_name, test))
+      .unwrap_or(false);
+    if is_expected {
+      VerificationOutcome::ExpectedFailure
+    } else {
+      VerificationOutcome::Failed
+    }
+  }
+
+  fn run_cargo(&self, workspace: &VerificationWorkspace, member_path: &Path, args: &[String]) -> Result<SandboxOutput, JobErr> {
+    self.sandbox.run(&SandboxStep::new(&self.params.cargo_path)
+      .args(args)
+      .current_dir(member_path)
+      .env("CARGO_TARGET_DIR", workspace.get_target_path()))
+  }
+}
+
+impl Job for SnapshotVerifierJob {
+  fn run(&mut self) {
+    self.run_now().unwrap()
+  }
+}
+
+/** Prefixes the member's features (if it has any) with the provided cargo arguments. */
+fn get_cargo_args(args: &[&str], details: &CrateSnapshot) -> Vec<String> {
+  let mut cargo_args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+  if !details.features.is_empty() {
+    cargo_args.push("--features".to_owned());
+    cargo_args.push(details.features.join(" "));
+  }
+  cargo_args
+}
+
+fn get_millis_since(started_at: Instant) -> u64 {
+  get_millis(started_at.elapsed())
+}
+
+fn get_millis(duration: Duration) -> u64 {
+  duration.as_secs() * 1000 + (duration.subsec_nanos() / 1_000_000) as u64
+}
+
+/** Combines a step's output into a log, noting if it was killed for running too long. */
+fn get_output_log(output: &SandboxOutput, include_stdout: bool) -> String {
+  let mut log = String::new();
+  if include_stdout {
+    log.push_str(&String::from_utf8_lossy(&output.stdout));
+  }
+  log.push_str(&String::from_utf8_lossy(&output.stderr));
+  if output.timed_out() {
+    log.push_str(&format!("(killed after {} seconds)\n", output.duration.as_secs()));
+  }
+  log
+}
+
+/**
+ * Finds the test binaries in the JSON messages of `cargo test --no-run`, named like "lib" or
+ * "test/$NAME". They are yielded in name order, as Cargo builds them in no particular order.
+ */
+fn get_test_binaries(messages: &str) -> Vec<(String, PathBuf)> {
+  let mut binaries = Vec::new();
+  for message in messages.lines().filter_map(|line| serde_json::from_str::<Value>(line).ok()) {
+    let is_test_artifact = message["reason"].as_str() == Some("compiler-artifact")
+      && message["profile"]["test"].as_bool() == Some(true);
+    if !is_test_artifact {
+      continue
+    }
+    let binary_path = match message["filenames"].get(0).and_then(|filename| filename.as_str()) {
+      Some(filename) => PathBuf::from(filename),
+      None => continue,
+    };
+    let kinds = message["target"]["kind"].as_array().cloned().unwrap_or_default();
+    let kind = kinds.get(0).and_then(|kind| kind.as_str()).unwrap_or("unknown");
+    let binary_name = if kinds.iter().any(|kind| kind.as_str() == Some("lib")) {
+      "lib".to_owned()
+    } else {
+      format!("{}/{}", kind, message["target"]["name"].as_str().unwrap_or("unknown"))
+    };
+    binaries.push((binary_name, binary_path));
+  }
+  binaries.sort();
+  binaries
+}
+
+/** Yields at most the last MAX_LOG_BYTES of the log, starting on a line if possible. */
+fn get_log_tail(log: &str) -> &str {
+  if log.len() <= MAX_LOG_BYTES {
+    return log
+  }
+  let mut start_idx = log.len() - MAX_LOG_BYTES;
+  while !log.is_char_boundary(start_idx) {
+    start_idx = start_idx + 1;
+  }
+  let tail = &log[start_idx..];
+  match tail.find('\n') {
+    Some(newline_idx) => &tail[newline_idx + 1..],
+    None => tail,
+  }
+}
+
+pub mod testing {
+  use common::cargo::CrateKey;
+  use flate2::Compression;
+  use flate2::write::GzEncoder;
+  use lcs_fetcher::repository::testing::TestingCrate;
+  use tar::Builder;
+  use tar::Header;
+
+  /** Packages a library crate with the provided dependencies (as name and requirement) for the LCS. */
+  pub fn create_testing_crate(name: &str, version: &str, dependencies: &[(&str, &str)], lib_rs: &str) -> TestingCrate {
+    let mut manifest_sections = "[dependencies]\n".to_owned();
+    for &(dependency, req) in dependencies.iter() {
+      manifest_sections.push_str(&format!("{} = \"{}\"\n", dependency, req));
+    }
+    package_testing_crate(name, version, &manifest_sections, &[("src/lib.rs", lib_rs)])
+  }
+
+  /** Packages a crate from the provided files, with the sections after [package] in its manifest. */
+  pub fn package_testing_crate(name: &str, version: &str, manifest_sections: &str, files: &[(&str, &str)]) -> TestingCrate {
+    let manifest = format!("[package]\nname = \"{}\"\nversion = \"{}\"\nauthors = []\n\n{}",
+                           name,
+                           version,
+                           manifest_sections);
+    let mut all_files = vec![("Cargo.toml", manifest.as_str())];
+    all_files.extend(files.iter().cloned());
+
+    let mut builder = Builder::new(GzEncoder::new(Vec::new(), Compression::Default));
+    for &(path, contents) in all_files.iter() {
+      let mut header = Header::new_gnu();
+      header.set_path(format!("{}-{}/{}", name, version, path)).unwrap();
+      header.set_size(contents.len() as u64);
+      header.set_mode(0o644);
+      header.set_cksum();
+      builder.append(&header, contents.as_bytes()).unwrap();
+    }
+
+    TestingCrate {
+      key: CrateKey {
+        name: name.to_owned(),
+        version: version.to_owned(),
+      },
+      contents: builder.into_inner().unwrap().finish().unwrap(),
+    }
+  }
+
+}
+
+#[cfg(test)]
+mod tests {
+  use common::configuration::CrateEntry;
+  use common::configuration::ExpectedTestFailure;
+  use common::configuration::MaintainerConfiguration;
+  use common::configuration::WorkspaceConfiguration;
+  use common::snapshot::WorkspaceSnapshot;
+  use common::snapshot::testing::crate_snapshot;
+  use common::verification::VerificationOutcome;
+  use lcs_fetcher::repository::LocalFsLcsRepository;
+  use lcs_fetcher::repository::testing;
+  use snapshot_repository::SnapshotRepository;
+  use snapshot_verifier::FeatureMatrixEntry;
+  use snapshot_verifier::SnapshotVerifierJob;
+  use snapshot_verifier::SnapshotVerifierJobBuilder;
+  use snapshot_verifier::SnapshotVerifierParamsBuilder;
+  use snapshot_verifier::testing::create_testing_crate;
+  use snapshot_verifier::testing::package_testing_crate;
+  use std::path::PathBuf;
+  use tempdir::TempDir;
+
+  fn verifier_job(repository_dir: &TempDir,
+                  lcs: LocalFsLcsRepository,
+                  configuration: Option<WorkspaceConfiguration>) -> SnapshotVerifierJob {
+    matrix_verifier_job(repository_dir, lcs, configuration, Vec::new())
+  }
+
+  fn matrix_verifier_job(repository_dir: &TempDir,
+                         lcs: LocalFsLcsRepository,
+                         configuration: Option<WorkspaceConfiguration>,
+                         feature_matrix: Vec<FeatureMatrixEntry>) -> SnapshotVerifierJob {
+    SnapshotVerifierJobBuilder::default()
+      .snapshot_repository(SnapshotRepository::open_or_init(repository_dir.path()).unwrap())
+      .lcs_source(Box::new(lcs))
+      .configuration(configuration)
+      .params(SnapshotVerifierParamsBuilder::default()
+        .verified_snapshot("nightly".to_owned())
+        .cargo_path(PathBuf::from("cargo"))
+        .run_tests(true)
+        .feature_matrix(feature_matrix)
+        .max_features_built_alone(8)
+        .build()
+        .unwrap())
+      .build()
+      .unwrap()
+  }
+
+  #[test]
+  fn test_members_are_built_offline_from_the_lcs() {
+    let lcs = testing::create_localfs_for_testing(&vec![
+      create_testing_crate("leaf", "1.0.0", &[], "pub fn leaf() -> u32 { 1 }\n"),
+      create_testing_crate("member", "0.1.0", &[("leaf", "^1.0")],
+                           "extern crate leaf;\npub fn member() -> u32 { leaf::leaf() }\n"),
+      create_testing_crate("broken", "0.1.0", &[], "pub fn broken() -> u32 { \"not a number\" }\n"),
+    ]).unwrap();
+    let snapshot = WorkspaceSnapshot {
+      version: "nightly-2017-11-20".to_owned(),
+      members: vec!["member".to_owned(), "broken".to_owned()],
+      details: vec![
+        crate_snapshot("broken", "0.1.0", &[]),
+        crate_snapshot("leaf", "1.0.0", &[]),
+        crate_snapshot("member", "0.1.0", &["leaf"]),
+      ],
+    };
+    let repository_dir = TempDir::new("snapshot_verifier").unwrap();
+
+    let report = verifier_job(&repository_dir, lcs, None).verify(&snapshot).unwrap();
+
+    let outcomes = report.crates.iter()
+      .map(|verification| (verification.name.as_str(), verification.outcome))
+      .collect::<Vec<_>>();
+    assert_eq!(outcomes, vec![("broken", VerificationOutcome::Failed), ("member", VerificationOutcome::Passed)]);
+    assert!(report.crates[0].log.contains("$ cargo build --frozen"));
+    assert!(report.crates[0].log.contains("mismatched types"));
+    // Without a configuration, no tests are run
+    assert_eq!(report.crates[1].tests, None);
+    assert!(!report.is_passing());
+  }
+
+  #[test]
+  fn test_optional_dependencies_outside_the_snapshot_do_not_fail_members() {
+    let lcs = testing::create_localfs_for_testing(&vec![
+      create_testing_crate("leaf", "1.0.0", &[], "pub fn leaf() -> u32 { 1 }\n"),
+      package_testing_crate("member", "0.1.0",
+                            "[dependencies]\nleaf = { version = \"^1.0\", optional = true }\n\
+                             absent = { version = \"^1.0\", optional = true }\n\n\
+                             [features]\ndefault = [\"leaf\"]\nextra = [\"absent\", \"absent/std\"]\n",
+                            &[("src/lib.rs", "extern crate leaf;\npub fn member() -> u32 { leaf::leaf() }\n")]),
+    ]).unwrap();
+    let snapshot = WorkspaceSnapshot {
+      version: "nightly-2017-11-20".to_owned(),
+      members: vec!["member".to_owned()],
+      details: vec![crate_snapshot("leaf", "1.0.0", &[]), crate_snapshot("member", "0.1.0", &["leaf"])],
+    };
+    let repository_dir = TempDir::new("snapshot_verifier").unwrap();
+
+    let report = verifier_job(&repository_dir, lcs, None).verify(&snapshot).unwrap();
+
+    assert_eq!(report.crates[0].outcome, VerificationOutcome::Passed, "{}", report.crates[0].log);
+  }
+
+  #[test]
+  fn test_member_tests_are_run_per_binary() {
+    let lcs = testing::create_localfs_for_testing(&vec![
+      create_testing_crate("leaf", "1.0.0", &[], "pub fn leaf() -> u32 { 1 }\n"),
+      package_testing_crate("tested", "0.1.0", "[dev-dependencies]\nleaf = \"^1.0\"\n", &[
+        ("src/lib.rs", "#[cfg(test)] extern crate leaf;\n#[test]\nfn passes() { assert_eq!(leaf::leaf(), 1); }\n"),
+        ("tests/flaky.rs", "#[test]\nfn flakes() { panic!(\"flaked\"); }\n"),
+      ]),
+      // Its dev dependency is not in the snapshot, so it must be left out when building
+      package_testing_crate("untested", "0.1.0", "[dev-dependencies]\nmissing = \"1.0\"\n", &[
+        ("src/lib.rs", "pub fn untested() {}\n"),
+      ]),
+    ]).unwrap();
+    let mut tested = crate_snapshot("tested", "0.1.0", &[]);
+    tested.dev_dependencies = vec!["leaf".to_owned()];
+    let mut leaf = crate_snapshot("leaf", "1.0.0", &[]);
+    leaf.dev_only = true;
+    let snapshot = WorkspaceSnapshot {
+      version: "nightly-2017-11-20".to_owned(),
+      members: vec!["tested".to_owned(), "untested".to_owned()],
+      details: vec![leaf, tested, crate_snapshot("untested", "0.1.0", &[])],
+    };
+    let mut configuration = WorkspaceConfiguration {
+      crate_sets: vec![MaintainerConfiguration {
+        maintainer: "a@example.com".to_owned(),
+        crates: vec![CrateEntry::Name("tested".to_owned()), CrateEntry::Name("untested".to_owned())],
+      }],
+      skip_dev_dependencies: vec!["untested".to_owned()],
+      expected_test_failures: Vec::new(),
+    };
+    let repository_dir = TempDir::new("snapshot_verifier").unwrap();
+
+    let report = verifier_job(&repository_dir, lcs.clone(), Some(configuration.clone())).verify(&snapshot).unwrap();
+    assert_eq!(report.crates[0].outcome, VerificationOutcome::Failed);
+    let tests = report.crates[0].tests.clone().unwrap();
+    let binaries = tests.binaries.iter()
+      .map(|binary| (binary.name.as_str(), binary.outcome))
+      .collect::<Vec<_>>();
+    assert_eq!(binaries, vec![("lib", VerificationOutcome::Passed), ("test/flaky", VerificationOutcome::Failed)]);
+    assert!(tests.binaries[1].log.contains("flaked"));
+    assert_eq!(report.crates[1].outcome, VerificationOutcome::Passed);
+    assert_eq!(report.crates[1].tests, None);
+
+    configuration.expected_test_failures = vec![ExpectedTestFailure {
+      name: "tested".to_owned(),
+      test: Some("test/flaky".to_owned()),
+      notes: None,
+    }];
+    let report = verifier_job(&repository_dir, lcs, Some(configuration)).verify(&snapshot).unwrap();
+    assert!(report.is_passing());
+    let tests = report.crates[0].tests.clone().unwrap();
+    assert_eq!(tests.outcome, VerificationOutcome::Passed);
+    assert_eq!(tests.binaries[1].outcome, VerificationOutcome::ExpectedFailure);
+  }
+
+  #[test]
+  fn test_members_are_built_with_each_feature_combination() {
+    let lcs = testing::create_localfs_for_testing(&vec![
+      package_testing_crate("featured", "0.1.0", "[features]\ndefault = [\"std\"]\nstd = []\nextra = []\n", &[
+        ("src/lib.rs", "#[cfg(feature = \"std\")]\npub fn f() -> u32 { 1 }\n\
+                        #[cfg(not(feature = \"std\"))]\npub fn f() -> u32 { \"needs std\" }\n"),
+      ]),
+    ]).unwrap();
+    let snapshot = WorkspaceSnapshot {
+      version: "nightly-2017-11-20".to_owned(),
+      members: vec!["featured".to_owned()],
+      details: vec![crate_snapshot("featured", "0.1.0", &[])],
+    };
+    let repository_dir = TempDir::new("snapshot_verifier").unwrap();
+    let feature_matrix = vec![
+      FeatureMatrixEntry::NoDefaultFeatures,
+      FeatureMatrixEntry::AllFeatures,
+      FeatureMatrixEntry::EachFeature,
+    ];
+
+    let report = matrix_verifier_job(&repository_dir, lcs, None, feature_matrix).verify(&snapshot).unwrap();
+
+    let feature_builds = report.crates[0].feature_builds.iter()
+      .map(|build| (build.features.as_str(), build.outcome))
+      .collect::<Vec<_>>();
+    assert_eq!(feature_builds, vec![
+      ("no-default-features", VerificationOutcome::Failed),
+      ("all-features", VerificationOutcome::Passed),
+      ("feature/extra", VerificationOutcome::Failed),
+      ("feature/std", VerificationOutcome::Passed),
+    ]);
+    assert!(report.crates[0].feature_builds[0].log.contains("$ cargo build --frozen --no-default-features"));
+    assert_eq!(report.crates[0].outcome, VerificationOutcome::Failed);
+    assert_eq!(FeatureMatrixEntry::parse(" all-features"), Some(FeatureMatrixEntry::AllFeatures));
+  }
+}
@@ -0,0 +1,310 @@
+use ::JobErr;
+use common::cargo::CrateKey;
+use common::cargo::IndexEntry;
+use common::checksum;
+use common::manifest::CargoManifest;
+use common::snapshot::CrateSnapshot;
+use common::snapshot::WorkspaceSnapshot;
+use flate2::read::GzDecoder;
+use lcs_fetcher::repository::LcsRepositorySource;
+use serde_json;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs::File;
+use std::fs;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use tar::Archive;
+use tempdir::TempDir;
+use toml;
+
+// The name given to the directory source that replaces crates.io
+const REPLACEMENT_SOURCE_NAME: &'static str = "stockpile-snapshot";
+
+/**
+ * A scratch directory holding every crate of a snapshot, laid out so that Cargo builds members
+ * from it without a registry.
+ *
+ * The layout is:
+ *   .cargo/config           Replaces crates.io with the vendor directory
+ *   vendor/$NAME-$VERSION/  Every crate in the snapshot, as Cargo's directory sources expect
+ *   members/$NAME/          A copy of each member, for Cargo to build (and write a lockfile) in
+ *   target/                 Shared between members, so common dependencies are built once
+ *
+ * As the vendor directory holds exactly one version of each crate, members resolve to the versions
+ * in the snapshot. Cargo locks dev dependencies and every optional dependency even when it only
+ * builds, so members whose dev dependencies are not in the snapshot have them removed from their
+ * copy, as do optional dependencies that are not in the snapshot (along with the feature entries
+ * that name them).
+ */
+pub struct VerificationWorkspace {
+  tempdir: TempDir,
+  // The index entry of each member, as read from its packaged manifest
+  member_entries: HashMap<String, IndexEntry>,
+}
+
+/** The contents of .cargo-checksum.json, which Cargo requires of each crate in a directory source. */
+#[derive(Serialize)]
+struct CargoChecksum {
+  // Per-file checksums, which are left empty so that no file is checked
+  files: BTreeMap<String, String>,
+  package: String,
+}
+
+impl VerificationWorkspace {
+  /** Fetches every crate in the snapshot from the LCS, and lays out a workspace for its members. */
+  pub fn prepare(snapshot: &WorkspaceSnapshot,
+                 lcs_source: &LcsRepositorySource) -> Result<VerificationWorkspace, JobErr> {
+    let mut workspace = VerificationWorkspace {
+      tempdir: try!(TempDir::new("snapshot_verifier")),
+      member_entries: HashMap::new(),
+    };
+    let download_path = workspace.get_root().join("downloads");
+    try!(fs::create_dir_all(&download_path));
+    try!(fs::create_dir_all(workspace.get_vendor_path()));
+    try!(fs::create_dir_all(workspace.get_target_path()));
+
+    let mut checksums = HashMap::new();
+    for details in snapshot.details.iter() {
+      let key = CrateKey {
+        name: details.name.clone(),
+        version: details.version.clone(),
+      };
+      debug!("Vendoring {}:{}", key.name, key.version);
+      try!(lcs_source.fetch_crate(&key, &download_path));
+      let crate_path = download_path.join(format!("{}-{}.crate", key.name, key.version));
+      checksums.insert(key.name.clone(), try!(workspace.vendor_crate(&key, &crate_path)));
+      try!(fs::remove_file(&crate_path));
+    }
+
+    let snapshot_crates = snapshot.details.iter().map(|details| details.name.as_str()).collect::<HashSet<_>>();
+    for member in snapshot.members.iter() {
+      let details = try!(snapshot.details.iter()
+        .find(|details| &details.name == member)
+        .ok_or_else(|| JobErr::OtherErr(format!("Member {} has no details in the snapshot", member))));
+      let member_path = workspace.get_member_path(member);
+      try!(copy_directory(&workspace.get_vendored_crate_path(&details.name, &details.version),
+                          &member_path));
+      let member_entry = try!(try!(read_manifest(&member_path)).to_index_entry(&checksums[member])
+        .ok_or_else(|| JobErr::OtherErr(format!("Member {} has no [package] in its manifest", member))));
+      workspace.member_entries.insert(member.clone(), member_entry);
+      try!(workspace.rewrite_member_manifest(details, &snapshot_crates));
+    }
+
+    try!(workspace.write_cargo_config());
+    Ok(workspace)
+  }
+
+  pub fn get_root(&self) -> &Path {
+    self.tempdir.path()
+  }
+
+  pub fn get_vendor_path(&self) -> PathBuf {
+    self.get_root().join("vendor")
+  }
+
+  pub fn get_target_path(&self) -> PathBuf {
+    self.get_root().join("target")
+  }
+
+  /** Yields the directory that the member is built in. */
+  pub fn get_member_path(&self, member: &str) -> PathBuf {
+    self.get_root().join("members").join(member)
+  }
+
+  /** Yields the index entry of the member, as read from its packaged manifest. */
+  pub fn get_member_entry(&self, member: &str) -> Option<&IndexEntry> {
+    self.member_entries.get(member)
+  }
+
+  fn get_vendored_crate_path(&self, name: &str, version: &str) -> PathBuf {
+    self.get_vendor_path().join(format!("{}-{}", name, version))
+  }
+
+  /**
+   * Unpacks the .crate into the vendor directory, with the checksum Cargo expects alongside it, and
+   * yields that checksum.
+   */
+  fn vendor_crate(&self, key: &CrateKey, crate_path: &Path) -> Result<String, JobErr> {
+    let mut crate_bytes = Vec::new();
+    try!(File::open(crate_path).and_then(|mut f| f.read_to_end(&mut crate_bytes)));
+    // Crate tarballs nest everything within a "$NAME-$VERSION/" directory
+    let mut archive = Archive::new(try!(GzDecoder::new(crate_bytes.as_slice())));
+    try!(archive.unpack(self.get_vendor_path()));
+
+    let vendored_path = self.get_vendored_crate_path(&key.name, &key.version);
+    if !vendored_path.join("Cargo.toml").is_file() {
+      return Err(JobErr::OtherErr(format!("{}:{} did not unpack into {:?}", key.name, key.version, vendored_path)))
+    }
+    let checksum = CargoChecksum {
+      files: BTreeMap::new(),
+      package: checksum::get_sha256(&crate_bytes),
+    };
+    let mut checksum_file = try!(File::create(vendored_path.join(".cargo-checksum.json")));
+    try!(checksum_file.write_all(try!(serde_json::to_string(&checksum)).as_bytes()));
+    Ok(checksum.package)
+  }
+
+  /**
+   * Rewrites the copy of the member's manifest without the (target specific) dependencies that
+   * Cargo would fail to lock: dev dependencies, if the snapshot has none for the member, and
+   * optional dependencies that are not in the snapshot.
+   */
+  fn rewrite_member_manifest(&self, details: &CrateSnapshot, snapshot_crates: &HashSet<&str>) -> Result<(), JobErr> {
+    let manifest_path = self.get_member_path(&details.name).join("Cargo.toml");
+    let mut contents = String::new();
+    try!(File::open(&manifest_path).and_then(|mut f| f.read_to_string(&mut contents)));
+    let mut manifest = try!(contents.parse::<toml::Value>());
+    let remove_dev_dependencies = details.dev_dependencies.is_empty();
+    let mut removed_dependencies = HashSet::new();
+    if let Some(table) = manifest.as_table_mut() {
+      removed_dependencies.extend(
+        remove_unlockable_dependencies(table, remove_dev_dependencies, snapshot_crates));
+      if let Some(&mut toml::Value::Table(ref mut targets)) = table.get_mut("target") {
+        for target in targets.values_mut() {
+          if let Some(target_table) = target.as_table_mut() {
+            removed_dependencies.extend(
+              remove_unlockable_dependencies(target_table, remove_dev_dependencies, snapshot_crates));
+          }
+        }
+      }
+      if let Some(&mut toml::Value::Table(ref mut features)) = table.get_mut("features") {
+        for members in features.values_mut() {
+          if let Some(members) = members.as_array_mut() {
+            members.retain(|member| {
+              let name = member.as_str().and_then(|member| member.split('/').next()).unwrap_or("");
+              !removed_dependencies.contains(name)
+            });
+          }
+        }
+      }
+    }
+    if !removed_dependencies.is_empty() {
+      info!("Removed optional dependencies of {} that are not in the snapshot: {:?}",
+            details.name,
+            removed_dependencies);
+    }
+
+    let rewritten = try!(toml::to_string(&manifest)
+      .map_err(|err| JobErr::OtherErr(format!("Could not rewrite {:?}: {}", manifest_path, err))));
+    try!(File::create(&manifest_path).and_then(|mut f| f.write_all(rewritten.as_bytes())));
+    Ok(())
+  }
+
+  fn write_cargo_config(&self) -> Result<(), JobErr> {
+    let config_path = self.get_root().join(".cargo");
+    try!(fs::create_dir_all(&config_path));
+    let mut config_file = try!(File::create(config_path.join("config")));
+    try!(write!(config_file,
+                "[source.crates-io]\nreplace-with = \"{name}\"\n\n[source.{name}]\ndirectory = \"{directory}\"\n",
+                name = REPLACEMENT_SOURCE_NAME,
+                directory = self.get_vendor_path().to_string_lossy()));
+    Ok(())
+  }
+}
+
+fn read_manifest(crate_path: &Path) -> Result<CargoManifest, JobErr> {
+  let mut contents = String::new();
+  try!(File::open(crate_path.join("Cargo.toml")).and_then(|mut f| f.read_to_string(&mut contents)));
+  Ok(try!(CargoManifest::from_str(&contents)))
+}
+
+/**
+ * Removes the dev dependency tables (if asked to) and the optional dependencies that are not in the
+ * snapshot from a manifest (or [target]) table, and yields the names of the optional dependencies.
+ */
+fn remove_unlockable_dependencies(table: &mut toml::value::Table,
+                                  remove_dev_dependencies: bool,
+                                  snapshot_crates: &HashSet<&str>) -> Vec<String> {
+  if remove_dev_dependencies {
+    table.remove("dev-dependencies");
+    table.remove("dev_dependencies");
+  }
+  let mut removed_dependencies = Vec::new();
+  for key in ["dependencies", "build-dependencies", "build_dependencies"].iter() {
+    if let Some(&mut toml::Value::Table(ref mut dependencies)) = table.get_mut(*key) {
+      removed_dependencies.extend(remove_optional_dependencies(dependencies, snapshot_crates));
+    }
+  }
+  removed_dependencies
+}
+
+fn remove_optional_dependencies(dependencies: &mut toml::value::Table, snapshot_crates: &HashSet<&str>) -> Vec<String> {
+  let removed_dependencies = dependencies.iter()
+    .filter(|&(name, dependency)| {
+      let is_optional = dependency.get("optional").and_then(|optional| optional.as_bool()).unwrap_or(false);
+      is_optional && !snapshot_crates.contains(name.as_str())
+    })
+    .map(|(name, _)| name.clone())
+    .collect::<Vec<_>>();
+  for name in removed_dependencies.iter() {
+    dependencies.remove(name);
+  }
+  removed_dependencies
+}
+
+/** Recursively copies the contents of one directory into another, which is created if needed. */
+fn copy_directory(source: &Path, destination: &Path) -> Result<(), JobErr> {
+  try!(fs::create_dir_all(destination));
+  for entry in try!(fs::read_dir(source)) {
+    let path = try!(entry).path();
+    let destination_path = destination.join(path.file_name().unwrap());
+    if path.is_dir() {
+      try!(copy_directory(&path, &destination_path));
+    } else {
+      try!(fs::copy(&path, &destination_path));
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use common::snapshot::WorkspaceSnapshot;
+  use common::snapshot::testing::crate_snapshot;
+  use lcs_fetcher::repository::testing;
+  use serde_json::Value;
+  use snapshot_verifier::testing::create_testing_crate;
+  use snapshot_verifier::workspace::VerificationWorkspace;
+  use std::fs::File;
+  use std::io::Read;
+
+  fn read_file(path: ::std::path::PathBuf) -> String {
+    let mut contents = String::new();
+    File::open(path).unwrap().read_to_string(&mut contents).unwrap();
+    contents
+  }
+
+  #[test]
+  fn test_every_crate_is_vendored_and_members_are_copied() {
+    let lcs = testing::create_localfs_for_testing(&vec![
+      create_testing_crate("leaf", "1.0.0", &[], "pub fn leaf() {}\n"),
+      create_testing_crate("member", "0.1.0", &[("leaf", "^1.0")], "extern crate leaf;\n"),
+    ]).unwrap();
+    let snapshot = WorkspaceSnapshot {
+      version: "nightly-2017-11-20".to_owned(),
+      members: vec!["member".to_owned()],
+      details: vec![crate_snapshot("leaf", "1.0.0", &[]), crate_snapshot("member", "0.1.0", &["leaf"])],
+    };
+
+    let workspace = VerificationWorkspace::prepare(&snapshot, &lcs).unwrap();
+
+    let config = read_file(workspace.get_root().join(".cargo/config"));
+    assert!(config.contains("[source.crates-io]\nreplace-with = \"stockpile-snapshot\""));
+    assert!(config.contains(&format!("directory = \"{}\"", workspace.get_vendor_path().to_string_lossy())));
+    let checksum = ::serde_json::from_str::<Value>(
+      &read_file(workspace.get_vendor_path().join("leaf-1.0.0/.cargo-checksum.json"))).unwrap();
+    assert_eq!(checksum["package"].as_str().map(|package| package.len()), Some(64));
+    assert!(workspace.get_vendor_path().join("member-0.1.0/src/lib.rs").is_file());
+    assert!(workspace.get_member_path("member").join("Cargo.toml").is_file());
+    assert!(!workspace.get_member_path("leaf").exists());
+    let member_entry = workspace.get_member_entry("member").unwrap();
+    assert_eq!(member_entry.deps[0].name, "leaf".to_owned());
+    let member_checksum = ::serde_json::from_str::<Value>(
+      &read_file(workspace.get_vendor_path().join("member-0.1.0/.cargo-checksum.json"))).unwrap();
+    assert_eq!(member_checksum["package"].as_str(), Some(member_entry.cksum.as_str()));
+  }
+}